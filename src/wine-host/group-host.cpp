@@ -53,7 +53,8 @@ __cdecl
 #else
                   << yabridge_group_host_name
 #endif
-                  << " <unix_domain_socket>" << std::endl;
+                  << " <unix_domain_socket> (version " << yabridge_git_version
+                  << ")" << std::endl;
 
         return 1;
     }
@@ -52,7 +52,7 @@ __cdecl
             << yabridge_individual_host_name
 #endif
             << " <plugin_type> <plugin_location> <endpoint_base_directory>"
-            << std::endl;
+            << " (version " << yabridge_git_version << ")" << std::endl;
 
         return 1;
     }
@@ -67,6 +67,10 @@ __cdecl
               << " (32-bit compatibility mode)"
 #endif
               << std::endl;
+    if (yabridge_wine_version[0] != '\0') {
+        std::cout << "Compiled with Wine " << yabridge_wine_version
+                  << std::endl;
+    }
     std::cout << "Preparing to load " << plugin_type_to_string(plugin_type)
               << " plugin at '" << plugin_location << "'" << std::endl;
 
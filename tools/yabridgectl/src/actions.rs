@@ -16,31 +16,514 @@
 
 //! Handlers for the subcommands, just to keep `main.rs` clean.
 
-use anyhow::{Context, Result};
-use colored::Colorize;
+use anyhow::{anyhow, Context, Result};
+use colored::{Color, Colorize};
+use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
 use std::fs;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::time::Duration;
 
-use crate::config::{yabridge_vst3_home, Config, InstallationMethod, YabridgeFiles};
-use crate::files::{self, LibArchitecture, NativeFile, Plugin, Vst2Plugin};
+use crate::cache;
+use crate::config::{home_directory, nested_plugin_dirs, Config, InstallationMethod, KnownConfig};
+use crate::files::{
+    self, ClapPlugin, LibArchitecture, NativeFile, Plugin, PluginFormat, Vst2Plugin,
+};
+use crate::json::{self, JsonFormat};
 use crate::utils;
-use crate::utils::{verify_path_setup, verify_wine_setup};
+use crate::utils::HostStatus;
+use sync::{oversized_plugin_size, SkipReason};
 
 pub mod blacklist;
+pub mod config;
+pub mod doctor;
+pub mod healthcheck;
+pub mod host;
+pub mod profile;
+pub mod prune;
+pub mod reconcile;
+pub mod sync;
+pub mod watch;
 
-/// Add a direcotry to the plugin locations. Duplicates get ignord because we're using ordered sets.
-pub fn add_directory(config: &mut Config, path: PathBuf) -> Result<()> {
-    config.plugin_dirs.insert(path);
-    config.write()
+/// The outcome of a command that modifies the config or the installed plugins. Every one of those
+/// commands prints this as a single line when it finishes so wrapper scripts can easily parse the
+/// results, see [`Summary::print()`]. What is being counted depends on the command, for instance
+/// `yabridgectl sync` counts plugins and files while `yabridgectl add` counts directories.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Summary {
+    /// Things that were newly added.
+    #[serde(rename = "new")]
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    /// Things that did not need to be changed.
+    pub skipped: usize,
+    /// Things that could not be changed because of an error. If this is not zero, then yabridgectl
+    /// will exit with a non-zero exit code.
+    pub failed: usize,
+    /// How many plugins `yabridgectl sync` did not set up, and why. This is only included in the
+    /// JSON output, since the plain text summary line should stay the same for wrapper scripts.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub skip_reasons: BTreeMap<sync::SkipReason, usize>,
+}
+
+impl Summary {
+    pub fn added(added: usize) -> Summary {
+        Summary {
+            added,
+            ..Summary::default()
+        }
+    }
+
+    pub fn removed(removed: usize) -> Summary {
+        Summary {
+            removed,
+            ..Summary::default()
+        }
+    }
+
+    pub fn skipped(skipped: usize) -> Summary {
+        Summary {
+            skipped,
+            ..Summary::default()
+        }
+    }
+
+    /// Print the summary as a single line, either as JSON or in a `key: value` format.
+    pub fn print(&self, json: Option<JsonFormat>) -> Result<()> {
+        // Pretty printing would spread the summary out over multiple lines
+        if json.is_some() {
+            return json::print(self, JsonFormat::Compact);
+        }
+
+        println!(
+            "summary: new: {}, updated: {}, removed: {}, skipped: {}, failed: {}",
+            self.added, self.updated, self.removed, self.skipped, self.failed
+        );
+
+        Ok(())
+    }
+}
+
+/// The contents of the file written with `--summary-json`. This contains the same counts as
+/// [`Summary`], along with some information that's not part of the printed summary.
+#[derive(Serialize, Debug)]
+struct SummaryFile {
+    #[serde(flatten)]
+    summary: Summary,
+    /// How long the command took to run, in milliseconds.
+    duration_ms: u64,
+    /// The error message if the command failed. All counts will be zero in that case.
+    error: Option<String>,
+}
+
+/// Write the result of a command to `path` as JSON, for `--summary-json <path>`. The file is
+/// replaced atomically, so other programs reading the file will never see a partially written
+/// summary.
+pub fn write_summary_file(path: &Path, result: &Result<Summary>, duration: Duration) -> Result<()> {
+    let summary_file = SummaryFile {
+        summary: result.as_ref().cloned().unwrap_or_default(),
+        duration_ms: duration.as_millis() as u64,
+        error: result.as_ref().err().map(|err| format!("{:#}", err)),
+    };
+
+    utils::write_atomically(
+        path,
+        &(json::to_string(&summary_file, JsonFormat::Compact)? + "\n"),
+    )
+}
+
+/// The target directory used for plugin directories on read-only Windows partitions, relative to the
+/// home directory. Every directory gets its own subdirectory in here.
+const WINDOWS_PARTITION_TARGET_DIR: &str = ".vst/yabridge-windows";
+
+/// Add one or more direcotries to the plugin locations. Duplicates get ignord because we're using
+/// ordered sets. Directories on a Windows partition get a warning about the file system's quirks,
+/// and if the partition is mounted read-only, then those directories will get a writable target
+/// directory since VST2 plugins can't be set up next to their `.dll` files. With `interactive` the
+/// user can pick which of the new directories' subdirectories should be included, see
+/// [`select_subdirectories()`].
+pub fn add_directories(
+    config: &mut Config,
+    paths: Vec<PathBuf>,
+    interactive: bool,
+) -> Result<Summary> {
+    config.update(|config| {
+        let mut summary = Summary::default();
+        let mut added_paths: BTreeSet<PathBuf> = BTreeSet::new();
+        for path in paths {
+            if !config.plugin_dirs.insert(path.clone()) {
+                summary.skipped += 1;
+                continue;
+            }
+            summary.added += 1;
+            added_paths.insert(path.clone());
+
+            if let Some(mount) = utils::mount_info(&path) {
+                if mount.is_windows_file_system() {
+                    warn_windows_file_system(&path, &mount);
+                }
+                if mount.read_only && config.target_dir(&path)?.is_none() {
+                    let target = Path::new(WINDOWS_PARTITION_TARGET_DIR).join(
+                        path.file_name()
+                            .unwrap_or_else(|| mount.mount_point.as_os_str()),
+                    );
+                    utils::warnln!(
+                        "{}\n",
+                        utils::wrap(&format!(
+                            "'{}' is on a read-only file system, so its VST2 plugins will be set \
+                             up in '~/{}' instead. Use 'yabridgectl set {} --target <path>' to \
+                             change this.",
+                            path.display(),
+                            target.display(),
+                            path.display()
+                        ))
+                    );
+                    config.directory_overrides.entry(path).or_default().target = Some(target);
+                }
+            }
+        }
+
+        // Adding a directory that's inside of an existing plugin directory, or the other way around,
+        // would cause those plugins to be set up twice
+        let nested_dirs: Vec<(PathBuf, PathBuf)> = nested_plugin_dirs(&config.plugin_dirs)
+            .into_iter()
+            .filter(|(directory, parent)| {
+                added_paths.contains(*directory) || added_paths.contains(*parent)
+            })
+            .map(|(directory, parent)| (directory.to_owned(), parent.to_owned()))
+            .collect();
+        if !nested_dirs.is_empty() {
+            utils::errorln!(
+                "{}\n",
+                utils::wrap(&format!(
+                    "{}: Some plugin directories are inside of other plugin directories. The \
+                     plugins in there would be set up twice.",
+                    "WARNING".red()
+                ))
+            );
+            for (directory, parent) in &nested_dirs {
+                eprintln!(
+                    "- '{}' is inside of '{}'",
+                    directory.display(),
+                    parent.display()
+                );
+            }
+
+            let answer = promptly::prompt_opt::<String, &str>(
+                "\nWould you like to remove the nested directories from the plugin directories? \
+                 Entering anything other than YES will keep them",
+            );
+            if matches!(answer, Ok(Some(answer)) if answer == "YES") {
+                for (directory, _) in &nested_dirs {
+                    if !config.plugin_dirs.remove(directory) {
+                        continue;
+                    }
+                    config.directory_overrides.remove(directory);
+
+                    if added_paths.contains(directory) {
+                        summary.added -= 1;
+                        summary.skipped += 1;
+                    } else {
+                        summary.removed += 1;
+                    }
+                }
+            }
+        }
+
+        if interactive {
+            for path in &added_paths {
+                if config.plugin_dirs.contains(path) {
+                    select_subdirectories(config, path)?;
+                }
+            }
+        }
+
+        Ok(summary)
+    })
+}
+
+/// Let the user pick which subdirectories of a newly added plugin directory should be included,
+/// for `yabridgectl add --interactive`. The plugins in `directory` are grouped by the subdirectory
+/// directly under `directory` they're in, and the selected subdirectories are stored in the
+/// directory's settings. The other subdirectories will then be skipped. Plugins directly inside of
+/// `directory` are always included. See
+/// [`crate::config::DirectoryOverrides::subdirectories`].
+fn select_subdirectories(config: &mut Config, directory: &Path) -> Result<()> {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return Err(anyhow!(
+            "'--interactive' can only be used from a terminal, nothing has been changed"
+        ));
+    }
+
+    let search_results = files::index(directory, &config.index_blacklist(directory))
+        .without_ignored_files(&config.ignored_suffixes)
+        .search(&cache::PluginIndex::read())
+        .with_context(|| format!("Could not search '{}' for plugins", directory.display()))?;
+
+    let mut num_plugins_by_subdirectory: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    for plugin in &search_results.plugins {
+        if let Ok(relative_path) = plugin.original_path().strip_prefix(directory) {
+            let mut components = relative_path.components();
+            if let (Some(subdirectory), Some(_)) = (components.next(), components.next()) {
+                *num_plugins_by_subdirectory
+                    .entry(directory.join(subdirectory))
+                    .or_default() += 1;
+            }
+        }
+    }
+    if num_plugins_by_subdirectory.len() < 2 {
+        return Ok(());
+    }
+
+    let subdirectories: Vec<(PathBuf, usize)> = num_plugins_by_subdirectory.into_iter().collect();
+    println!(
+        "Found plugins in {} subdirectories of '{}':",
+        subdirectories.len(),
+        directory.display()
+    );
+    for (idx, (subdirectory, num_plugins)) in subdirectories.iter().enumerate() {
+        println!(
+            "{:>3}. {} ({} plugins)",
+            idx + 1,
+            subdirectory.strip_prefix(directory).unwrap().display(),
+            num_plugins
+        );
+    }
+    println!();
+
+    let selection = loop {
+        let answer = match promptly::prompt_opt::<String, &str>(
+            "Which subdirectories should be included? Enter their numbers separated by spaces or \
+             commas, ranges like 2-5 are also allowed. Leaving this empty will include all of \
+             them",
+        ) {
+            Ok(Some(answer)) => answer,
+            Ok(None) => break (0..subdirectories.len()).collect(),
+            Err(_) => return Err(anyhow!("Aborted, nothing has been changed")),
+        };
+
+        match parse_selection(&answer, subdirectories.len()) {
+            Some(selection) => break selection,
+            None => eprintln!(
+                "Invalid selection, enter numbers between 1 and {}",
+                subdirectories.len()
+            ),
+        }
+    };
+
+    // Subdirectories without any plugins are also skipped when only some subdirectories have been
+    // selected, so there's nothing to store when everything has been selected
+    if selection.len() < subdirectories.len() {
+        config
+            .directory_overrides
+            .entry(directory.to_owned())
+            .or_default()
+            .subdirectories = selection
+            .iter()
+            .map(|&idx| {
+                subdirectories[idx]
+                    .0
+                    .strip_prefix(directory)
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+
+        println!(
+            "\n{}\n",
+            utils::wrap(&format!(
+                "Only the {} selected subdirectories of '{}' will be searched for plugins. Use \
+                 'yabridgectl set {} --all-subdirectories' to include all of them again.",
+                selection.len(),
+                directory.display(),
+                directory.display()
+            ))
+        );
+    }
+
+    Ok(())
+}
+
+/// Parse the subdirectories selected in [`select_subdirectories()`]. The selection contains
+/// one-based numbers and ranges like `2-5`, separated by spaces or commas. Returns the zero-based
+/// indices of the selected subdirectories, or `None` if the selection is not valid.
+fn parse_selection(selection: &str, num_choices: usize) -> Option<BTreeSet<usize>> {
+    let parse_number = |number: &str| -> Option<usize> {
+        match number.trim().parse::<usize>() {
+            Ok(number) if (1..=num_choices).contains(&number) => Some(number - 1),
+            _ => None,
+        }
+    };
+
+    let mut indices = BTreeSet::new();
+    for item in selection
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+    {
+        match item.split_once('-') {
+            Some((start, end)) => indices.extend(parse_number(start)?..=parse_number(end)?),
+            None => {
+                indices.insert(parse_number(item)?);
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        None
+    } else {
+        Some(indices)
+    }
+}
+
+/// The TOML format used by `yabridgectl export --toml`. This uses the same key as the config file,
+/// so `yabridgectl import` can also read the plugin directories from another machine's config file.
+#[derive(Deserialize, Serialize, Debug)]
+struct DirectoryList {
+    plugin_dirs: Vec<PathBuf>,
+}
+
+/// Add all plugin directories listed in a file, or in STDIN if `path` is `-`. The file can either
+/// contain one directory per line, or it can be a TOML file written by `yabridgectl export --toml`.
+/// Directories that don't exist are skipped with a warning, and the other directories are added
+/// using [`add_directories()`].
+pub fn import_directories(config: &mut Config, path: &Path) -> Result<Summary> {
+    let contents = if path == Path::new("-") {
+        let mut contents = String::new();
+        io::stdin()
+            .read_to_string(&mut contents)
+            .context("Could not read the plugin directories from STDIN")?;
+        contents
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Could not read '{}'", path.display()))?
+    };
+
+    let mut num_missing = 0;
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for directory in parse_directory_list(&contents) {
+        let directory = utils::expand_path(&directory)?;
+        match directory.canonicalize() {
+            Ok(directory) if directory.is_dir() => {
+                if !paths.contains(&directory) {
+                    paths.push(directory);
+                }
+            }
+            _ => {
+                utils::warnln!(
+                    "{}",
+                    utils::wrap(&format!(
+                        "Warning: Skipping '{}' since that directory does not exist",
+                        directory.display()
+                    ))
+                );
+                num_missing += 1;
+            }
+        }
+    }
+
+    let mut summary = add_directories(config, paths, false)?;
+    summary.skipped += num_missing;
+
+    Ok(summary)
+}
+
+/// Parse the plugin directories for [`import_directories()`]. If `contents` is not a TOML file
+/// containing a `plugin_dirs` array, then every line is a directory. Empty lines and lines starting
+/// with a `#` are ignored.
+fn parse_directory_list(contents: &str) -> Vec<PathBuf> {
+    if let Ok(list) = toml::from_str::<DirectoryList>(contents) {
+        return list.plugin_dirs;
+    }
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Write the plugin directories to `path` in a format [`import_directories()`] can read, or to
+/// STDOUT if `path` is `None` or `-`. With `as_toml` the directories are written as a TOML array
+/// instead of one directory per line.
+pub fn export_directories(config: &Config, path: Option<&Path>, as_toml: bool) -> Result<()> {
+    let contents = if as_toml {
+        toml::to_string_pretty(&DirectoryList {
+            plugin_dirs: config.plugin_dirs.iter().cloned().collect(),
+        })
+        .context("Could not format TOML")?
+    } else {
+        config
+            .plugin_dirs
+            .iter()
+            .map(|directory| format!("{}\n", directory.display()))
+            .collect()
+    };
+
+    match path {
+        Some(path) if path != Path::new("-") => utils::write_atomically(path, &contents)
+            .with_context(|| format!("Could not write to '{}'", path.display())),
+        _ => {
+            print!("{}", contents);
+            Ok(())
+        }
+    }
+}
+
+/// Warn about the quirks of plugin directories on a Windows partition.
+fn warn_windows_file_system(path: &Path, mount: &utils::MountInfo) {
+    utils::warnln!(
+        "{}\n",
+        utils::wrap(&format!(
+            "{}: '{}' is on a Windows file system ({}). Plugins outside of a Wine prefix will \
+             use the default Wine prefix. These file systems don't store Linux permissions, and \
+             depending on the mount options every file may appear to be executable or the \
+             modification times may be shifted by your time zone, which causes plugins to be \
+             hashed again during the next sync. Avoid using Windows' fast startup feature, since \
+             that leaves the partition in a state where it can only be mounted read-only.",
+            "WARNING".red(),
+            path.display(),
+            mount.file_system
+        ))
+    );
 }
 
 /// Remove a direcotry to the plugin locations. The path is assumed to be part of
-/// `config.plugin_dirs`, otherwise this is silently ignored.
-pub fn remove_directory(config: &mut Config, path: &Path) -> Result<()> {
+/// `config.plugin_dirs`, otherwise this is silently ignored. With `prune`, the files yabridgectl set
+/// up for the plugins in this directory are removed first, see [`prune::find_directory_files()`].
+pub fn remove_directory(config: &mut Config, path: &Path, prune: bool) -> Result<Summary> {
+    // This needs to happen before the directory's target override is removed from the config
+    let mut summary = Summary::removed(1);
+    if prune {
+        let bridged_files = prune::find_directory_files(config, path)?;
+        if bridged_files.is_empty() {
+            println!("Found no files set up for plugins in this directory");
+        } else {
+            println!(
+                "Removing {} files set up for plugins in this directory:",
+                bridged_files.len()
+            );
+            for file in &bridged_files {
+                println!("- {}", file.path().display());
+            }
+
+            for file in &bridged_files {
+                match file {
+                    NativeFile::Directory(file_path) => utils::remove_dir_all(file_path)?,
+                    NativeFile::Regular(file_path) | NativeFile::Symlink(file_path) => {
+                        utils::remove_file(file_path)?
+                    }
+                }
+            }
+            summary.removed += bridged_files.len();
+        }
+    }
+
     // We've already verified that this path is in `config.plugin_dirs`
     config.plugin_dirs.remove(path);
+    config.directory_overrides.remove(path);
     config.write()?;
 
     // Ask the user to remove any leftover files to prevent possible future problems and out of date
@@ -66,16 +549,185 @@ pub fn remove_directory(config: &mut Config, path: &Path) -> Result<()> {
                 }
 
                 println!("\nRemoved {} files", orphan_files.len());
+                summary.removed += orphan_files.len();
+            }
+            _ => {
+                summary.skipped += orphan_files.len();
             }
-            _ => {}
         }
     }
 
-    Ok(())
+    Ok(summary)
+}
+
+/// Change the path of a plugin directory after it has been moved on disk, keeping its overrides and
+/// any blacklist and always-copy entries inside of it. The paths in yabridgectl's caches are updated
+/// as well, so the next sync recognizes the existing installations instead of setting everything up
+/// from scratch. `old_path` is assumed to be part of `config.plugin_dirs`.
+pub fn rename_directory(
+    config: &mut Config,
+    old_path: &Path,
+    new_path: PathBuf,
+) -> Result<Summary> {
+    if config.plugin_dirs.contains(&new_path) {
+        return Err(anyhow!(
+            "'{}' is already a plugin directory",
+            new_path.display()
+        ));
+    }
+    if !new_path.is_dir() {
+        return Err(anyhow!("'{}' is not a directory", new_path.display()));
+    }
+
+    let rebase = |path: &Path| match path.strip_prefix(old_path) {
+        Ok(suffix) if suffix.as_os_str().is_empty() => new_path.clone(),
+        Ok(suffix) => new_path.join(suffix),
+        Err(_) => path.to_owned(),
+    };
+
+    config.update(|config| {
+        config.plugin_dirs.remove(old_path);
+        config.plugin_dirs.insert(new_path.clone());
+        if let Some(overrides) = config.directory_overrides.remove(old_path) {
+            config
+                .directory_overrides
+                .insert(new_path.clone(), overrides);
+        }
+        config.blacklist = config.blacklist.iter().map(|path| rebase(path)).collect();
+        for profile in config.profiles.values_mut() {
+            profile.plugin_dirs = profile
+                .plugin_dirs
+                .iter()
+                .map(|path| rebase(path))
+                .collect();
+        }
+        config.always_copy = config.always_copy.iter().map(|path| rebase(path)).collect();
+        config.plugin_hosts = config
+            .plugin_hosts
+            .iter()
+            .map(|(plugin, directory)| (rebase(plugin), directory.clone()))
+            .collect();
+
+        Ok(())
+    })?;
+
+    let mut installed_plugins = cache::InstalledPlugins::read();
+    installed_plugins.plugins = installed_plugins
+        .plugins
+        .into_iter()
+        .map(|(plugin_path, mut installed)| {
+            installed.target = rebase(&installed.target);
+            (rebase(&plugin_path), installed)
+        })
+        .collect();
+    installed_plugins.write()?;
+
+    let mut extracted_archives = cache::ExtractedArchives::read();
+    extracted_archives.archives = extracted_archives
+        .archives
+        .into_iter()
+        .map(|(archive, mut extracted)| {
+            extracted.directory = rebase(&extracted.directory);
+            (rebase(&archive), extracted)
+        })
+        .collect();
+    extracted_archives.scanned = extracted_archives
+        .scanned
+        .into_iter()
+        .map(|(archive, scanned)| (rebase(&archive), scanned))
+        .collect();
+    extracted_archives.write()?;
+
+    // The plugin index and the copy hashes are keyed by the files' paths, and those files have not
+    // changed. This way the moved plugins don't need to be classified or hashed again.
+    let mut plugin_index = cache::PluginIndex::read();
+    plugin_index.files = plugin_index
+        .files
+        .into_iter()
+        .map(|(path, indexed)| (rebase(&path), indexed))
+        .collect();
+    plugin_index.write()?;
+
+    let mut copy_hashes = cache::CopyHashes::read();
+    copy_hashes.files = copy_hashes
+        .files
+        .into_iter()
+        .map(|(path, hashed)| (rebase(&path), hashed))
+        .collect();
+    copy_hashes.write()?;
+
+    // The scan cache is only used with `--fast`
+    if cache::ScanCache::exists() {
+        let mut scan_cache = cache::ScanCache::read();
+        let rebase_all = |paths: Vec<PathBuf>| -> Vec<PathBuf> {
+            paths.iter().map(|path| rebase(path)).collect()
+        };
+        scan_cache.directories = scan_cache
+            .directories
+            .into_iter()
+            .map(|(directory, contents)| {
+                (
+                    rebase(&directory),
+                    cache::ScannedDirectory {
+                        files: rebase_all(contents.files),
+                        symlinks: rebase_all(contents.symlinks),
+                        subdirectories: rebase_all(contents.subdirectories),
+                    },
+                )
+            })
+            .collect();
+        scan_cache.write()?;
+    }
+
+    println!(
+        "Renamed '{}' to '{}', rerun 'yabridgectl sync' to update the installed plugins",
+        old_path.display(),
+        new_path.display()
+    );
+
+    Ok(Summary {
+        updated: 1,
+        ..Summary::default()
+    })
+}
+
+/// Hash all plugins in `directory` and write the results to a hash manifest in that directory, see
+/// [`cache::HashManifest`]. This is meant to be run by an administrator for system-wide plugin
+/// directories, after installing or updating plugins.
+pub fn generate_hash_manifest(config: &Config, directory: &Path) -> Result<Summary> {
+    let search_results = files::index(directory, &config.index_blacklist(directory))
+        .without_ignored_files(&config.ignored_suffixes)
+        .search(&cache::PluginIndex::read())
+        .context("Failure while searching for plugins")?;
+    let plugin_files: Vec<PathBuf> = search_results
+        .plugins
+        .iter()
+        .map(|plugin| match plugin {
+            Plugin::Vst2(Vst2Plugin { path, .. }) | Plugin::Clap(ClapPlugin { path, .. }) => {
+                path.clone()
+            }
+            Plugin::Vst3(module) => module.original_module_path(),
+        })
+        .collect();
+
+    let manifest = cache::HashManifest::generate(directory, &plugin_files)?;
+    manifest.write(directory)?;
+
+    println!(
+        "Wrote the hashes for {} plugins to '{}'",
+        manifest.files.len(),
+        directory.join(cache::HASH_MANIFEST_FILE_NAME).display()
+    );
+
+    Ok(Summary::added(manifest.files.len()))
 }
 
-/// List the plugin locations.
-pub fn list_directories(config: &Config) -> Result<()> {
+/// List the plugin locations. With `--json` this prints a JSON array of paths instead.
+pub fn list_directories(config: &Config, json: Option<JsonFormat>) -> Result<()> {
+    if let Some(format) = json {
+        return json::print(&config.plugin_dirs, format);
+    }
+
     for directory in &config.plugin_dirs {
         println!("{}", directory.display());
     }
@@ -83,10 +735,256 @@ pub fn list_directories(config: &Config) -> Result<()> {
     Ok(())
 }
 
-/// Print the current configuration and the installation status for all found plugins.
-pub fn show_status(config: &Config) -> Result<()> {
+/// Print the versions of yabridgectl and yabridge, along with the paths to the yabridge files that
+/// are being used. This makes it possible to tell which of multiple yabridge installations
+/// yabridgectl is managing.
+pub fn show_info(config: &Config) -> Result<()> {
+    println!("yabridgectl version: {}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "yabridge path: {}",
+        config
+            .yabridge_home
+            .as_ref()
+            .map(|path| format!("'{}'", path.display()))
+            .unwrap_or_else(|| String::from("<auto>"))
+    );
+
+    let files = config.files().context("Could not find yabridge's files")?;
+    println!(
+        "libyabridge-vst2.so: '{}'",
+        files.libyabridge_vst2.display()
+    );
+    println!(
+        "libyabridge-vst3.so: {}",
+        files
+            .libyabridge_vst3
+            .as_ref()
+            .map(|path| format!("'{}'", path.display()))
+            .unwrap_or_else(|| "<not found>".red().to_string())
+    );
+    println!(
+        "libyabridge-clap.so: {}",
+        files
+            .libyabridge_clap
+            .as_ref()
+            .map(|path| format!("'{}'", path.display()))
+            .unwrap_or_else(|| "<not found>".red().to_string())
+    );
+    println!("yabridge-host.exe: '{}'", files.yabridge_host_exe.display());
+
+    // Older versions of yabridge don't print their version as part of the usage string
+    let host_version = match utils::yabridge_host_version(&files) {
+        Ok(Some(version)) => version,
+        Ok(None) => String::from("<unknown>"),
+        Err(err) => format!("<{}>", err).red().to_string(),
+    };
+    println!("yabridge host version: {}", host_version);
+
+    Ok(())
+}
+
+/// A single entry printed by `yabridgectl inventory`.
+#[derive(Serialize, Debug)]
+struct InventoryEntry<'a> {
+    format: &'static str,
+    architecture: LibArchitecture,
+    /// The Windows plugin's `.dll` file, `.vst3` module, or `.clap` file.
+    source: &'a Path,
+    /// The `.so` file for VST2 plugins, the merged bundle for VST3 plugins, or the native `.clap`
+    /// file for CLAP plugins.
+    target: PathBuf,
+}
+
+/// List every plugin yabridgectl will set up along with the location it will be set up at, as
+/// tab separated `<source>\t<target>` pairs or as JSON. This is meant to be used by other tools,
+/// such as package managers, so it does not contain any decorations.
+pub fn show_inventory(config: &Config, json: Option<JsonFormat>) -> Result<()> {
     let results = config
-        .search_directories()
+        .search_directories_indexed()
+        .context("Failure while searching for plugins")?;
+
+    let mut entries: Vec<InventoryEntry> = results
+        .iter()
+        .flat_map(|(path, search_results)| {
+            search_results
+                .plugins
+                .iter()
+                .filter(move |plugin| !config.is_blacklisted(path, plugin.original_path()))
+        })
+        .filter(|plugin| {
+            !config
+                .excluded_architectures
+                .contains(&plugin.architecture())
+                && config.is_format_enabled(plugin.format())
+        })
+        .map(|plugin| match plugin {
+            Plugin::Vst2(vst2_plugin) => InventoryEntry {
+                format: "vst2",
+                architecture: vst2_plugin.architecture,
+                source: &vst2_plugin.path,
+                target: vst2_plugin.target_path.clone(),
+            },
+            Plugin::Vst3(module) => InventoryEntry {
+                format: "vst3",
+                architecture: module.architecture,
+                source: module.original_path(),
+                target: module.target_bundle_home(),
+            },
+            Plugin::Clap(clap_plugin) => InventoryEntry {
+                format: "clap",
+                architecture: clap_plugin.architecture,
+                source: &clap_plugin.path,
+                target: clap_plugin.target_path(),
+            },
+        })
+        .collect();
+    entries.sort_by(|a, b| a.source.cmp(b.source));
+
+    if let Some(format) = json {
+        return json::print(&entries, format);
+    }
+
+    for entry in entries {
+        println!("{}\t{}", entry.source.display(), entry.target.display());
+    }
+
+    Ok(())
+}
+
+/// The version of the JSON output printed by `yabridgectl status --json`. This is only increased
+/// when fields are removed or when their meaning changes, adding new fields does not change the
+/// version.
+pub const STATUS_SCHEMA_VERSION: u32 = 1;
+
+/// The output of `yabridgectl status --json`.
+#[derive(Serialize, Debug)]
+struct StatusOutput {
+    schema_version: u32,
+    /// When the last successful sync happened as a Unix timestamp in seconds, if the plugins have
+    /// ever been synced.
+    last_sync: Option<u64>,
+    plugins: Vec<StatusEntry>,
+}
+
+/// The installation status for a single plugin in `yabridgectl status --json`.
+#[derive(Serialize, Debug)]
+struct StatusEntry {
+    /// The Windows plugin's `.dll` file, `.vst3` module, or `.clap` file.
+    path: PathBuf,
+    plugin_type: &'static str,
+    architecture: LibArchitecture,
+    /// Either `symlink` or `copy`. This is `null` if the plugin has not yet been set up.
+    installation_method: Option<&'static str>,
+    /// Whether the plugin has been set up using the current version of yabridge's libraries (or
+    /// the build from `config.plugin_hosts`). Plugins set up using symlinks to the actual libraries
+    /// instead of to the chainloaders are also not up to date.
+    up_to_date: bool,
+    /// The hash of the installed library, see [`utils::hash_file()`]. For symlinks this is the
+    /// hash of the file the symlink points to. This is `null` if the plugin has not yet been set
+    /// up.
+    target_hash: Option<i64>,
+}
+
+/// The hashes of the libraries plugins should be set up with, used to check whether a plugin's
+/// installation is up to date. These are the chainloaders if yabridge ships with them. Plugins using
+/// another build of yabridge from `config.plugin_hosts` are checked against that build's libraries.
+struct LibraryHashes<'a> {
+    vst2: Option<i64>,
+    vst3: Option<i64>,
+    clap: Option<i64>,
+    host_libraries: BTreeMap<&'a Path, Option<sync::HostLibraries>>,
+}
+
+impl<'a> LibraryHashes<'a> {
+    fn new(config: &Config) -> LibraryHashes<'a> {
+        let files = config.files().ok();
+        let hash_library =
+            |library: Option<&Path>| library.and_then(|path| utils::hash_file(path).ok());
+
+        LibraryHashes {
+            vst2: hash_library(files.as_ref().map(|files| files.vst2_library())),
+            vst3: hash_library(files.as_ref().and_then(|files| files.vst3_library())),
+            clap: hash_library(files.as_ref().and_then(|files| files.clap_library().ok())),
+            host_libraries: BTreeMap::new(),
+        }
+    }
+
+    /// The hash of the library `plugin` should be set up with, if that library exists.
+    fn get(&mut self, config: &'a Config, plugin_path: &Path, plugin: &Plugin) -> Option<i64> {
+        match config.plugin_hosts.get(plugin_path) {
+            Some(directory) => self
+                .host_libraries
+                .entry(directory)
+                .or_insert_with(|| sync::HostLibraries::new(config, directory).ok())
+                .as_ref()
+                .and_then(|host| match plugin {
+                    Plugin::Vst2(_) => Some(host.vst2_hash),
+                    Plugin::Vst3(_) => host.vst3.as_ref().map(|(_, hash)| *hash),
+                    Plugin::Clap(_) => host.clap.as_ref().map(|(_, hash)| *hash),
+                }),
+            None => match plugin {
+                Plugin::Vst2(_) => self.vst2,
+                Plugin::Vst3(_) => self.vst3,
+                Plugin::Clap(_) => self.clap,
+            },
+        }
+    }
+}
+
+/// Print the installation status for all found plugins as JSON for `yabridgectl status --json`.
+/// Unlike the regular output this only contains the plugins and the time of the last sync, the
+/// settings can be queried using other commands.
+pub fn show_status_json(config: &Config, format: JsonFormat) -> Result<()> {
+    let results = config
+        .search_directories_indexed()
+        .context("Failure while searching for plugins")?;
+
+    let mut library_hashes = LibraryHashes::new(config);
+    let mut plugins = Vec::new();
+    for search_results in results.values() {
+        for (plugin_path, (plugin, status)) in search_results.installation_status() {
+            let plugin_type = match plugin {
+                Plugin::Vst2(_) => "vst2",
+                Plugin::Vst3(_) => "vst3",
+                Plugin::Clap(_) => "clap",
+            };
+            let library_hash = library_hashes.get(config, &plugin_path, plugin);
+
+            let (installation_method, target_hash) = match &status {
+                Some(NativeFile::Regular(path)) => (Some("copy"), utils::hash_file(path).ok()),
+                Some(NativeFile::Symlink(path)) => (Some("symlink"), utils::hash_file(path).ok()),
+                Some(NativeFile::Directory(_)) | None => (None, None),
+            };
+
+            plugins.push(StatusEntry {
+                path: plugin_path,
+                plugin_type,
+                architecture: plugin.architecture(),
+                installation_method,
+                up_to_date: target_hash.is_some() && target_hash == library_hash,
+                target_hash,
+            });
+        }
+    }
+    plugins.sort_by(|a, b| a.path.cmp(&b.path));
+
+    json::print(
+        &StatusOutput {
+            schema_version: STATUS_SCHEMA_VERSION,
+            last_sync: config.last_sync,
+            plugins,
+        },
+        format,
+    )
+}
+
+/// Print the current configuration and the installation status for all found plugins. Plugin
+/// directories that don't contain any plugins are listed at the end, and they are returned so they
+/// can be removed with `yabridgectl status --clean-empty`, in which case `clean_empty` should be
+/// set.
+pub fn show_status(config: &Config, clean_empty: bool) -> Result<Vec<PathBuf>> {
+    let results = config
+        .search_directories_indexed()
         .context("Failure while searching for plugins")?;
 
     println!(
@@ -98,6 +996,10 @@ pub fn show_status(config: &Config) -> Result<()> {
             .unwrap_or_else(|| String::from("<auto>"))
     );
 
+    // When yabridge uses chainloaders, symlinks should point to those instead of to the actual
+    // libraries. Symlinks to the actual libraries are left over from older yabridge versions.
+    let mut legacy_symlink_targets: Vec<PathBuf> = Vec::new();
+    let mut has_vst3_support = true;
     match config.files() {
         Ok(files) => {
             println!(
@@ -105,398 +1007,651 @@ pub fn show_status(config: &Config) -> Result<()> {
                 files.libyabridge_vst2.display()
             );
             println!(
-                "libyabridge-vst3.so: {}\n",
+                "libyabridge-vst3.so: {}",
                 files
                     .libyabridge_vst3
+                    .as_ref()
                     .map(|path| format!("'{}'", path.display()))
                     .unwrap_or_else(|| "<not found>".red().to_string())
             );
+            println!(
+                "libyabridge-clap.so: {}",
+                files
+                    .libyabridge_clap
+                    .as_ref()
+                    .map(|path| format!("'{}'", path.display()))
+                    .unwrap_or_else(|| "<not found>".red().to_string())
+            );
+            has_vst3_support = files.libyabridge_vst3.is_some();
+            if files.uses_chainloader() {
+                println!("library setup: chainloader\n");
+                legacy_symlink_targets.push(files.libyabridge_vst2.clone());
+                if files.libyabridge_chainloader_vst3.is_some() {
+                    legacy_symlink_targets.extend(files.libyabridge_vst3.clone());
+                }
+                if files.libyabridge_chainloader_clap.is_some() {
+                    legacy_symlink_targets.extend(files.libyabridge_clap.clone());
+                }
+            } else {
+                println!("library setup: legacy\n");
+            }
         }
         Err(err) => {
             println!("Could not find yabridge's files files: {}\n", err);
         }
     }
     println!("installation method: {}", config.method);
+    println!(
+        "last sync: {}",
+        config
+            .last_sync
+            .map(utils::format_time_ago)
+            .unwrap_or_else(|| String::from("never"))
+    );
+    let disabled_formats: Vec<String> = PluginFormat::ALL
+        .iter()
+        .filter(|format| !config.is_format_enabled(**format))
+        .map(|format| format.to_string())
+        .collect();
+    if !disabled_formats.is_empty() {
+        println!("disabled formats: {}", disabled_formats.join(", ").yellow());
+    }
 
+    // The plugins are printed as a table when writing to a terminal
+    let is_terminal = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    let mut library_hashes = LibraryHashes::new(config);
+    let mut num_legacy_symlinks = 0;
+    let mut unused_directories: Vec<(PathBuf, bool)> = Vec::new();
+    // The reasons why the next sync would skip plugins, see `yabridgectl sync`. This only includes
+    // the reasons that don't depend on the options passed to `yabridgectl sync`.
+    let mut skip_reasons: BTreeMap<SkipReason, usize> = BTreeMap::new();
     for (path, search_results) in results {
+        if search_results.plugins.is_empty() {
+            unused_directories.push((path.to_owned(), path.is_dir()));
+        }
+        *skip_reasons.entry(SkipReason::NotAPlugin).or_default() +=
+            search_results.skipped_files.len();
+        *skip_reasons.entry(SkipReason::Shortcut).or_default() += search_results
+            .shortcut_files
+            .iter()
+            .filter(|path| files::read_plugin_shortcut_target(path).is_some())
+            .count();
+        let is_read_only = config.target_dir(path)?.is_none()
+            && matches!(
+                utils::mount_info(path),
+                Some(utils::MountInfo {
+                    read_only: true,
+                    ..
+                })
+            );
+
         // Always print these paths with trailing slashes for consistency's sake because paths can
         // be added both with and without a trailing slash
         println!("\n{}", path.join("").display());
+        if let Some(target) = config.target_dir(path)? {
+            println!("target: {}", target.join("").display());
+        }
+        for extra_target in config.extra_target_dirs(path)? {
+            println!("extra target: {}", extra_target.join("").display());
+        }
+        if let Some(wine) = config
+            .directory_overrides
+            .get(path)
+            .and_then(|overrides| overrides.wine.as_ref())
+        {
+            println!("wine: {}", wine.display());
+        }
+        let method = config.directory_method(path);
+        if method == config.method {
+            println!("installation method: {}", method);
+        } else {
+            println!(
+                "installation method: {} (overridden)",
+                method.to_string().yellow()
+            );
+        }
 
+        let mut rows: Vec<[StatusCell; 3]> = Vec::new();
         for (plugin_path, (plugin, status)) in search_results.installation_status() {
             let plugin_type = match plugin {
-                Plugin::Vst2(Vst2Plugin { architecture, .. }) => {
-                    format!("{}, {}", "VST2".cyan(), architecture)
+                Plugin::Vst2(Vst2Plugin { architecture, .. }) => StatusCell::new(
+                    format!("VST2, {}", architecture),
+                    format!("{}, {}", "VST2".cyan(), architecture),
+                ),
+                Plugin::Vst3(module) => StatusCell::new(
+                    format!("VST3, {}, {}", module.type_str(), module.architecture),
+                    format!(
+                        "{}, {}, {}",
+                        "VST3".magenta(),
+                        module.type_str(),
+                        module.architecture
+                    ),
+                ),
+                Plugin::Clap(ClapPlugin { architecture, .. }) => StatusCell::new(
+                    format!("CLAP, {}", architecture),
+                    format!("{}, {}", "CLAP".blue(), architecture),
+                ),
+            };
+
+            // Copies and symlinks that don't match the library they should be set up with will be
+            // updated during the next sync
+            let is_outdated = match &status {
+                Some(NativeFile::Regular(path)) | Some(NativeFile::Symlink(path)) => {
+                    utils::hash_file(path).ok() != library_hashes.get(config, &plugin_path, plugin)
                 }
-                Plugin::Vst3(module) => format!(
-                    "{}, {}, {}",
-                    "VST3".magenta(),
-                    module.type_str(),
-                    module.architecture
+                _ => false,
+            };
+            let (status_str, status_color) = match &status {
+                Some(NativeFile::Regular(_)) if is_outdated => {
+                    ("copy (outdated)", Some(Color::Yellow))
+                }
+                Some(NativeFile::Regular(_)) => ("copy", Some(Color::Green)),
+                Some(NativeFile::Symlink(path))
+                    if utils::read_symlink(path)
+                        .map(|target| legacy_symlink_targets.contains(&target))
+                        .unwrap_or(false) =>
+                {
+                    num_legacy_symlinks += 1;
+                    ("symlink (legacy)", Some(Color::Yellow))
+                }
+                Some(NativeFile::Symlink(_)) if is_outdated => {
+                    ("symlink (outdated)", Some(Color::Yellow))
+                }
+                Some(NativeFile::Symlink(_)) => ("symlink", Some(Color::Green)),
+                Some(NativeFile::Directory(_)) => ("invalid", Some(Color::Red)),
+                None => ("not yet installed", None),
+            };
+
+            let skip_reason = if config
+                .excluded_architectures
+                .contains(&plugin.architecture())
+            {
+                Some(SkipReason::ExcludedArchitecture)
+            } else if !config.is_format_enabled(plugin.format()) {
+                Some(SkipReason::DisabledFormat)
+            } else if config.is_blacklisted(path, &plugin_path) {
+                Some(SkipReason::Blacklisted)
+            } else if matches!(plugin, Plugin::Vst2(_)) && is_read_only {
+                Some(SkipReason::ReadOnlyDirectory)
+            } else if matches!(plugin, Plugin::Vst3(_)) && !has_vst3_support {
+                Some(SkipReason::NoVst3Support)
+            } else if oversized_plugin_size(config, plugin, config.max_plugin_size).is_some() {
+                Some(SkipReason::Oversized)
+            } else {
+                None
+            };
+            let (status_str, status_color) = match (skip_reason, status) {
+                (Some(reason), None) => (
+                    format!("skipped: {}", reason.description()),
+                    Some(Color::Yellow),
+                ),
+                (Some(reason), Some(_)) => (
+                    format!("{}, skipped: {}", status_str, reason.description()),
+                    Some(Color::Yellow),
                 ),
+                (None, _) => (status_str.to_owned(), status_color),
             };
+            if let Some(reason) = skip_reason {
+                *skip_reasons.entry(reason).or_default() += 1;
+            }
 
-            let status_str = match status {
-                Some(NativeFile::Regular(_)) => "copy".green(),
-                Some(NativeFile::Symlink(_)) => "symlink".green(),
-                Some(NativeFile::Directory(_)) => "invalid".red(),
-                None => "not yet installed".into(),
+            let host_str = match config.plugin_hosts.get(&plugin_path) {
+                Some(directory) => format!(", host: {}", directory.display()),
+                None => String::new(),
             };
 
+            let relative_path = plugin_path
+                .strip_prefix(path)
+                .unwrap_or(&plugin_path)
+                .display()
+                .to_string();
+            rows.push([
+                StatusCell::new(relative_path.clone(), relative_path),
+                plugin_type,
+                StatusCell::new(
+                    format!("{}{}", status_str, host_str),
+                    match status_color {
+                        Some(color) => format!("{}{}", status_str.color(color), host_str),
+                        None => format!("{}{}", status_str, host_str),
+                    },
+                ),
+            ]);
+        }
+
+        print_status_rows(&rows, is_terminal);
+    }
+
+    if num_legacy_symlinks > 0 {
+        utils::errorln!(
+            "\n{}",
+            utils::wrap(&format!(
+                "{}: {} plugins are set up using symlinks to yabridge's libraries, but this version \
+                 of yabridge uses chainloader libraries instead. Rerun 'yabridgectl sync' to \
+                 update these plugins.",
+                "WARNING".red(),
+                num_legacy_symlinks
+            ))
+        );
+    }
+
+    skip_reasons.retain(|_, count| *count > 0);
+    if !skip_reasons.is_empty() {
+        println!(
+            "\nThe next sync will skip: {}",
+            sync::format_skip_reasons(&skip_reasons)
+        );
+    }
+
+    if !unused_directories.is_empty() {
+        println!("\nPlugin directories without any plugins:");
+        for (path, exists) in &unused_directories {
+            if *exists {
+                println!("- {} (no plugins found)", path.display());
+            } else {
+                println!("- {} ({})", path.display(), "does not exist".red());
+            }
+        }
+        if !clean_empty {
+            println!("\nUse 'yabridgectl status --clean-empty' to remove these from the config.");
+        }
+    }
+
+    Ok(unused_directories
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect())
+}
+
+/// A cell in the plugin table printed by `yabridgectl status`. The plain text is used to compute the
+/// cell's width, since the colored text also contains escape codes.
+struct StatusCell {
+    plain: String,
+    colored: String,
+}
+
+impl StatusCell {
+    fn new(plain: String, colored: String) -> StatusCell {
+        StatusCell { plain, colored }
+    }
+
+    fn width(&self) -> usize {
+        self.plain.chars().count()
+    }
+}
+
+/// The minimum width of the path column in `yabridgectl status`. Paths are only shortened to fit the
+/// terminal up to this width.
+const MIN_STATUS_PATH_WIDTH: usize = 24;
+
+/// Print the plugins in a plugin directory for `yabridgectl status`. When writing to a terminal the
+/// paths, plugin types, and installation statuses are printed as aligned columns, and paths that
+/// don't fit are shortened in the middle so the file name stays visible. Otherwise every plugin is
+/// printed on a single line without any alignment so the output is easy to process.
+fn print_status_rows(rows: &[[StatusCell; 3]], is_terminal: bool) {
+    if !is_terminal {
+        for [path, plugin_type, status] in rows {
             println!(
                 "  {} :: {}, {}",
-                plugin_path
-                    .strip_prefix(path)
-                    .unwrap_or(&plugin_path)
-                    .display(),
-                plugin_type,
-                status_str
+                path.colored, plugin_type.colored, status.colored
             );
         }
+
+        return;
     }
 
-    Ok(())
+    let column_width = |column: usize| rows.iter().map(|row| row[column].width()).max();
+    let (type_width, status_width) = match (column_width(1), column_width(2)) {
+        (Some(type_width), Some(status_width)) => (type_width, status_width),
+        _ => return,
+    };
+    // The rows are indented by two spaces, and the columns are separated by two spaces
+    let path_width = column_width(0).unwrap_or(0).min(
+        textwrap::termwidth()
+            .saturating_sub(2 + 2 + type_width + 2 + status_width)
+            .max(MIN_STATUS_PATH_WIDTH),
+    );
+
+    for [path, plugin_type, status] in rows {
+        let path = utils::truncate_middle(&path.plain, path_width);
+        println!(
+            "  {}{}  {}{}  {}",
+            path,
+            " ".repeat(path_width.saturating_sub(path.chars().count())),
+            plugin_type.colored,
+            " ".repeat(type_width - plugin_type.width()),
+            status.colored
+        );
+    }
+}
+
+/// Remove the plugin directories without any plugins found by `yabridgectl status` from the config
+/// after asking for confirmation. Unlike `yabridgectl rm` this doesn't look for leftover `.so`
+/// files, since these directories contain no plugins that files could have been set up for.
+pub fn remove_unused_directories(
+    config: &mut Config,
+    directories: Vec<PathBuf>,
+) -> Result<Summary> {
+    if directories.is_empty() {
+        return Ok(Summary::default());
+    }
+
+    match promptly::prompt_opt::<String, &str>(
+        "\nWould you like to remove these directories from the config? Entering anything other \
+         than YES will leave the config unchanged",
+    ) {
+        Ok(Some(answer)) if answer == "YES" => config.update(|config| {
+            for directory in &directories {
+                config.plugin_dirs.remove(directory);
+                config.directory_overrides.remove(directory);
+            }
+
+            Ok(Summary::removed(directories.len()))
+        }),
+        _ => Ok(Summary {
+            skipped: directories.len(),
+            ..Summary::default()
+        }),
+    }
 }
 
 /// Options passed to `yabridgectl set`, see `main()` for the definitions of these options.
 pub struct SetOptions<'a> {
+    pub directory: Option<PathBuf>,
     pub method: Option<&'a str>,
     pub path: Option<PathBuf>,
     pub path_auto: bool,
+    pub target: Option<PathBuf>,
+    pub target_auto: bool,
+    /// An empty list removes all extra targets.
+    pub extra_targets: Option<Vec<PathBuf>>,
+    pub method_auto: bool,
+    pub wine: Option<PathBuf>,
+    pub wine_auto: bool,
+    pub all_subdirectories: bool,
+    pub exclude_arch: Option<BTreeSet<LibArchitecture>>,
+    pub formats: Option<BTreeSet<PluginFormat>>,
+    pub ignore_wine_errors: Option<Vec<String>>,
+    pub ignore_suffixes: Option<Vec<String>>,
+    pub always_copy: Vec<PathBuf>,
+    pub no_always_copy: Vec<PathBuf>,
+    /// `Some(None)` removes the size limit.
+    pub max_plugin_size: Option<Option<u64>>,
+    pub removal_limit: Option<usize>,
+    pub deduplicate_copies: Option<bool>,
+    pub link_host_binaries: Option<bool>,
+    pub relative_symlinks: Option<bool>,
     pub no_verify: Option<bool>,
+    pub verify_timeout: Option<u64>,
 }
 
 /// Change configuration settings. The actual options are defined in the clap [app](clap::App).
-pub fn set_settings(config: &mut Config, options: &SetOptions) -> Result<()> {
-    match options.method {
-        Some("copy") => config.method = InstallationMethod::Copy,
-        Some("symlink") => config.method = InstallationMethod::Symlink,
-        Some(s) => unimplemented!("Unexpected installation method '{}'", s),
-        None => (),
-    }
-
-    if let Some(path) = &options.path {
-        config.yabridge_home = Some(path.clone());
+pub fn set_settings(config: &mut Config, options: &SetOptions) -> Result<Summary> {
+    if let Some(directory) = &options.directory {
+        return set_directory_settings(config, directory, options);
     }
 
-    if options.path_auto {
-        config.yabridge_home = None;
-    }
+    config.update(|config| {
+        // Every setting that was passed counts as an updated setting
+        let mut summary = Summary::default();
 
-    if let Some(no_verify) = options.no_verify {
-        config.no_verify = no_verify;
-    }
+        match options.method {
+            Some("copy") => config.method = InstallationMethod::Copy,
+            Some("symlink") => config.method = InstallationMethod::Symlink,
+            Some(s) => return Err(anyhow!("Unexpected installation method '{}'", s)),
+            None => (),
+        }
+        if options.method.is_some() {
+            summary.updated += 1;
+        }
 
-    config.write()
-}
+        if let Some(path) = &options.path {
+            config.yabridge_home = Some(path.clone());
+            summary.updated += 1;
+        }
 
-/// Options passed to `yabridgectl sync`, see `main()` for the definitions of these options.
-pub struct SyncOptions {
-    pub force: bool,
-    pub no_verify: bool,
-    pub prune: bool,
-    pub verbose: bool,
-}
+        if options.path_auto {
+            config.yabridge_home = None;
+            summary.updated += 1;
+        }
 
-/// Set up yabridge for all Windows VST2 plugins in the plugin directories. Will also remove orphan
-/// `.so` files if the prune option is set.
-pub fn do_sync(config: &mut Config, options: &SyncOptions) -> Result<()> {
-    let files: YabridgeFiles = config.files()?;
-    let libyabridge_vst2_hash = utils::hash_file(&files.libyabridge_vst2)?;
-    let libyabridge_vst3_hash = match &files.libyabridge_vst3 {
-        Some(path) => Some(utils::hash_file(path)?),
-        None => None,
-    };
+        if let Some(exclude_arch) = &options.exclude_arch {
+            config.excluded_architectures = exclude_arch.clone();
+            summary.updated += 1;
+        }
 
-    if let Some(libyabridge_vst3_path) = &files.libyabridge_vst3 {
-        println!("Setting up VST2 and VST3 plugins using:");
-        println!("- {}", files.libyabridge_vst2.display());
-        println!("- {}\n", libyabridge_vst3_path.display());
-    } else {
-        println!("Setting up VST2 plugins using:");
-        println!("- {}\n", files.libyabridge_vst2.display());
-    }
+        if let Some(formats) = &options.formats {
+            config.formats = formats.clone();
+            summary.updated += 1;
+        }
 
-    let results = config
-        .search_directories()
-        .context("Failure while searching for plugins")?;
+        if let Some(ignore_wine_errors) = &options.ignore_wine_errors {
+            config.ignored_wine_errors = ignore_wine_errors.clone();
+            summary.updated += 1;
+        }
 
-    // Keep track of some global statistics
-    // The number of plugins we set up yabridge for
-    let mut num_installed = 0;
-    // The number of plugins we create a (new) copy of `libyabridge-{vst2,vst3}.so` for
-    let mut num_new = 0;
-    // The files we skipped during the scan because they turned out to not be plugins
-    let mut skipped_dll_files: Vec<PathBuf> = Vec::new();
-    // `.so` files and unused VST3 modules we found during scanning that didn't have a corresponding
-    // copy or symlink of `libyabridge-vst2.so`
-    let mut orphan_files: Vec<NativeFile> = Vec::new();
-    // All the VST3 modules we have set up yabridge for. We need this to detect leftover VST3
-    // modules in `~/.vst3/yabridge`.
-    let mut yabridge_vst3_bundles: BTreeMap<PathBuf, BTreeSet<LibArchitecture>> = BTreeMap::new();
-    for (path, search_results) in results {
-        num_installed += search_results.plugins.len();
-        orphan_files.extend(search_results.vst2_orphans().into_iter().cloned());
-        skipped_dll_files.extend(search_results.skipped_files);
-
-        if options.verbose {
-            // Always print these paths with trailing slashes for consistency's sake because paths
-            // can be added both with and without a trailing slash
-            println!("{}", path.join("").display());
-        }
-
-        for plugin in search_results.plugins {
-            // If verbose mode is enabled we'll print the path to the plugin after setting it up
-            let plugin_path: PathBuf = match plugin {
-                // We'll set up the copies or symlinks for VST2 plugins
-                Plugin::Vst2(Vst2Plugin {
-                    path: plugin_path, ..
-                }) => {
-                    let target_path = plugin_path.with_extension("so");
-
-                    // Since we skip some files, we'll also keep track of how many new file we've
-                    // actually set up
-                    if install_file(
-                        options.force,
-                        config.method,
-                        &files.libyabridge_vst2,
-                        Some(libyabridge_vst2_hash),
-                        &target_path,
-                    )? {
-                        num_new += 1;
-                    }
+        if let Some(ignore_suffixes) = &options.ignore_suffixes {
+            config.ignored_suffixes = ignore_suffixes.clone();
+            summary.updated += 1;
+        }
 
-                    plugin_path.clone()
-                }
-                // And then create merged bundles for the VST3 plugins:
-                // https://developer.steinberg.help/display/VST/Plug-in+Format+Structure#PluginFormatStructure-MergedBundle
-                Plugin::Vst3(module) => {
-                    // Only set up VST3 plugins when yabridge has been compiled with VST3 support
-                    if libyabridge_vst3_hash.is_none() {
-                        continue;
-                    }
+        for plugin_path in &options.always_copy {
+            if config.always_copy.insert(plugin_path.clone()) {
+                summary.added += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
 
-                    // 32-bit and 64-bit versions of the plugin cna live inside of the same
-                    // bundle), and show a warning if we come across any duplicates.
-                    let already_installed_architectures = yabridge_vst3_bundles
-                        .entry(module.target_bundle_home())
-                        .or_insert_with(BTreeSet::new);
-                    if !already_installed_architectures.insert(module.architecture) {
-                        eprintln!(
-                            "{}",
-                            utils::wrap(&format!(
-                            "{}: The {} version of '{}' has already been provided by another Wine \
-                             prefix, skipping '{}'\n",
-                            "WARNING".red(),
-                            module.architecture,
-                            module.target_bundle_home().display(),
-                            module.original_module_path().display(),
-                        ))
-                        );
+        for plugin_path in &options.no_always_copy {
+            if config.always_copy.remove(plugin_path) {
+                summary.removed += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
 
-                        continue;
-                    }
+        if let Some(max_plugin_size) = options.max_plugin_size {
+            config.max_plugin_size = max_plugin_size;
+            summary.updated += 1;
+        }
 
-                    // We're building a merged VST3 bundle containing both a copy or symlink to
-                    // `libyabridge-vst3.so` and the Windows VST3 plugin
-                    let native_module_path = module.target_native_module_path();
-                    utils::create_dir_all(native_module_path.parent().unwrap())?;
-                    if install_file(
-                        options.force,
-                        config.method,
-                        files.libyabridge_vst3.as_ref().unwrap(),
-                        libyabridge_vst3_hash,
-                        &native_module_path,
-                    )? {
-                        num_new += 1;
-                    }
+        if let Some(removal_limit) = options.removal_limit {
+            config.removal_limit = removal_limit;
+            summary.updated += 1;
+        }
 
-                    // We'll then symlink the Windows VST3 module to that bundle to create a merged
-                    // bundle: https://developer.steinberg.help/display/VST/Plug-in+Format+Structure#PluginFormatStructure-MergedBundle
-                    let windows_module_path = module.target_windows_module_path();
-                    utils::create_dir_all(windows_module_path.parent().unwrap())?;
-                    install_file(
-                        true,
-                        InstallationMethod::Symlink,
-                        &module.original_module_path(),
-                        None,
-                        &windows_module_path,
-                    )?;
-
-                    // If `module` is a bundle, then it may contain a `Resources` directory with
-                    // screenshots and documentation
-                    // TODO: Also symlink presets, but this is a bit more involved. See
-                    //       https://developer.steinberg.help/display/VST/Preset+Locations
-                    if let Some(original_resources_dir) = module.original_resources_dir() {
-                        install_file(
-                            false,
-                            InstallationMethod::Symlink,
-                            &original_resources_dir,
-                            None,
-                            &module.target_resources_dir(),
-                        )?;
-                    }
+        if let Some(deduplicate_copies) = options.deduplicate_copies {
+            config.deduplicate_copies = deduplicate_copies;
+            summary.updated += 1;
+        }
 
-                    module.original_path().to_path_buf()
-                }
-            };
+        if let Some(link_host_binaries) = options.link_host_binaries {
+            config.link_host_binaries = link_host_binaries;
+            summary.updated += 1;
+        }
 
-            if options.verbose {
-                println!(
-                    "  {}",
-                    plugin_path
-                        .strip_prefix(path)
-                        .unwrap_or(&plugin_path)
-                        .display()
-                );
-            }
+        if let Some(relative_symlinks) = options.relative_symlinks {
+            config.relative_symlinks = relative_symlinks;
+            summary.updated += 1;
         }
 
-        if options.verbose {
-            println!();
+        if let Some(no_verify) = options.no_verify {
+            config.no_verify = no_verify;
+            summary.updated += 1;
         }
-    }
 
-    // We'll print the skipped files all at once to prevetn clutter
-    let num_skipped_files = skipped_dll_files.len();
-    if options.verbose && !skipped_dll_files.is_empty() {
-        println!("Skipped files:");
-        for path in skipped_dll_files {
-            println!("- {}", path.display());
+        if let Some(verify_timeout) = options.verify_timeout {
+            config.verify_timeout = verify_timeout;
+            summary.updated += 1;
         }
-        println!();
-    }
 
-    // TODO: Move this elsewhere
-    // TODO: This can leave behind empty directories if we remove a subdirectory
-    orphan_files.extend(
-        WalkDir::new(yabridge_vst3_home())
-            .follow_links(true)
-            .same_file_system(true)
-            .into_iter()
-            .filter_entry(|entry| entry.file_type().is_dir())
-            .filter_map(|e| e.ok())
-            .filter(|entry| {
-                // Add all directories in `~/.vst3/yabridge` to `orphan_files` if they are not a
-                // VST3 module we just created. We'll ignore symlinks and regular files since those
-                // are always user created.
-                let extension = entry
-                    .path()
-                    .extension()
-                    .and_then(|extension| extension.to_str());
-
-                extension == Some("vst3") && !yabridge_vst3_bundles.contains_key(entry.path())
-            })
-            .map(|entry| NativeFile::Directory(entry.path().to_owned())),
-    );
+        Ok(summary)
+    })
+}
 
-    // Always warn about leftover files since those might cause warnings or errors when a VST host
-    // tries to load them
-    if !orphan_files.is_empty() {
-        let leftover_files_str = if orphan_files.len() == 1 {
-            format!("{} leftover file", orphan_files.len())
-        } else {
-            format!("{} leftover files", orphan_files.len())
-        };
-        if options.prune {
-            println!("Removing {}:", leftover_files_str);
-        } else {
-            println!(
-                "Found {}, rerun with the '--prune' option to remove them:",
-                leftover_files_str
+/// Change the settings for a single plugin directory. These take precedence over the global
+/// settings.
+fn set_directory_settings(
+    config: &mut Config,
+    directory: &Path,
+    options: &SetOptions,
+) -> Result<Summary> {
+    config.update(|config| {
+        let mut summary = Summary::default();
+        let overrides = config
+            .directory_overrides
+            .entry(directory.to_owned())
+            .or_default();
+
+        if let Some(target) = &options.target {
+            // Targets inside of the home directory are stored relative to the home directory so the
+            // config file can be shared between machines
+            let home = home_directory()?;
+            overrides.target = Some(
+                target
+                    .strip_prefix(&home)
+                    .map(|target| target.to_owned())
+                    .unwrap_or_else(|_| target.clone()),
             );
+            summary.updated += 1;
         }
 
-        for file in orphan_files {
-            println!("- {}", file.path().display());
-            if options.prune {
-                match file {
-                    NativeFile::Regular(path) | NativeFile::Symlink(path) => {
-                        utils::remove_file(path)?;
-                    }
-                    NativeFile::Directory(path) => {
-                        utils::remove_dir_all(path)?;
-                    }
-                }
-            }
+        if options.target_auto {
+            overrides.target = None;
+            summary.updated += 1;
         }
 
-        println!();
-    }
+        if let Some(extra_targets) = &options.extra_targets {
+            // These are stored the same way as the main target
+            let home = home_directory()?;
+            overrides.extra_targets = extra_targets
+                .iter()
+                .map(|target| {
+                    target
+                        .strip_prefix(&home)
+                        .map(|target| target.to_owned())
+                        .unwrap_or_else(|_| target.clone())
+                })
+                .collect();
+            summary.updated += 1;
+        }
 
-    println!(
-        "Finished setting up {} plugins using {} ({} new), skipped {} non-plugin .dll files",
-        num_installed,
-        config.method.plural_name(),
-        num_new,
-        num_skipped_files
-    );
+        match options.method {
+            Some("copy") => overrides.method = Some(InstallationMethod::Copy),
+            Some("symlink") => overrides.method = Some(InstallationMethod::Symlink),
+            Some(s) => return Err(anyhow!("Unexpected installation method '{}'", s)),
+            None => (),
+        }
+        if options.method.is_some() {
+            summary.updated += 1;
+        }
 
-    // Skipping the post-installation seting checks can be done only for this invocation of
-    // `yabridgectl sync`, or it can be skipped permanently through a config file option
-    if options.no_verify || config.no_verify {
-        return Ok(());
-    }
+        if options.method_auto {
+            overrides.method = None;
+            summary.updated += 1;
+        }
 
-    // The path setup is to make sure that the `libyabridge-{vst2,vst3}.so` copies can find
-    // `yabridge-host.exe`
-    if config.method == InstallationMethod::Copy {
-        verify_path_setup(config)?;
-    }
+        if let Some(wine) = &options.wine {
+            overrides.wine = Some(wine.clone());
+            summary.updated += 1;
+        }
 
-    // This check is only performed once per combination of Wine and yabridge versions
-    verify_wine_setup(config)?;
+        if options.wine_auto {
+            overrides.wine = None;
+            summary.updated += 1;
+        }
 
-    Ok(())
+        if options.all_subdirectories {
+            overrides.subdirectories.clear();
+            summary.updated += 1;
+        }
+
+        if overrides.is_empty() {
+            config.directory_overrides.remove(directory);
+        }
+
+        Ok(summary)
+    })
 }
 
-/// Create a copy or symlink of `from` to `to`. Depending on `force`, we might not actually create a
-/// new copy or symlink if `to` matches `from_hash`.
-fn install_file(
-    force: bool,
-    method: InstallationMethod,
-    from: &Path,
-    from_hash: Option<i64>,
-    to: &Path,
-) -> Result<bool> {
-    // We'll only recreate existing files when updating yabridge, when switching between the symlink
-    // and copy installation methods, or when the `force` option is set. If the target file already
-    // exists and does not require updating, we'll just skip the file since some DAWs will otherwise
-    // unnecessarily reindex the file. We check `std::fs::symlink_metadata` instead of
-    // `Path::exists()` because the latter reports false for broken symlinks.
-    if let Ok(metadata) = fs::symlink_metadata(&to) {
-        match (force, &method) {
-            (false, InstallationMethod::Copy) => {
-                // If the target file is already a real file (not a symlink) and its hash is the
-                // same as that of the `from` file we're trying to copy there, then we don't have to
-                // do anything
-                if let Some(hash) = from_hash {
-                    if metadata.file_type().is_file() && utils::hash_file(to)? == hash {
-                        return Ok(false);
-                    }
+/// Check whether `yabridge-host.exe` can run in every Wine prefix used by the plugin directories,
+/// and print the results as a table. Like the check performed during `yabridgectl sync`, prefixes
+/// that have already been verified for the current versions of Wine and yabridge are skipped.
+pub fn verify_prefixes(config: &mut Config) -> Result<Summary> {
+    let files = config.files()?;
+    let current_config = KnownConfig::current(
+        &files,
+        utils::wine_version(Duration::from_secs(config.verify_timeout))?,
+        false,
+    )?;
+
+    // Multiple plugin directories can share the same Wine prefix
+    let mut prefixes: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for directory in &config.plugin_dirs {
+        prefixes
+            .entry(utils::wine_prefix_for(directory))
+            .or_default()
+            .push(directory.clone());
+    }
+
+    let prefix_column_width = prefixes
+        .keys()
+        .map(|prefix| prefix.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("Wine prefix".len());
+    println!(
+        "{:width$}  Status",
+        "Wine prefix",
+        width = prefix_column_width
+    );
+
+    // Prefixes that were checked before are counted as skipped, and newly verified prefixes are
+    // counted as updated
+    let mut summary = Summary::default();
+    for (prefix, directories) in &prefixes {
+        let status = if config.known_prefix_configs.get(prefix) == Some(&current_config) {
+            summary.skipped += 1;
+            "ok (cached)".green()
+        } else if !prefix.join("drive_c").exists() {
+            summary.failed += 1;
+            "failed: this Wine prefix does not exist".red()
+        } else {
+            match utils::run_yabridge_host(&files, Some(prefix), &config.ignored_wine_errors)? {
+                HostStatus::Running => {
+                    config
+                        .known_prefix_configs
+                        .insert(prefix.clone(), current_config.clone());
+                    summary.updated += 1;
+                    "ok".green()
                 }
-            }
-            (false, InstallationMethod::Symlink) => {
-                // If the target file is already a symlink to `from`, then we can skip this file
-                if metadata.file_type().is_symlink() && to.read_link()? == from {
-                    return Ok(false);
+                HostStatus::Failed { last_error } => {
+                    summary.failed += 1;
+                    format!("failed: {}", last_error.as_deref().unwrap_or("<no_output>")).red()
                 }
             }
-            // With the force option we always want to recreate existing .so files
-            (true, _) => (),
+        };
+
+        println!(
+            "{:width$}  {}",
+            prefix.display(),
+            status,
+            width = prefix_column_width
+        );
+        for directory in directories {
+            println!("  {}", directory.display());
         }
+    }
 
-        utils::remove_file(&to)?;
-    };
+    config.write()?;
 
-    match method {
-        InstallationMethod::Copy => {
-            utils::copy(from, to)?;
-        }
-        InstallationMethod::Symlink => {
-            utils::symlink(from, to)?;
-        }
+    if summary.failed > 0 {
+        eprintln!(
+            "\n'yabridge-host.exe' could not be run in {} out of {} Wine prefixes",
+            summary.failed,
+            prefixes.len()
+        );
     }
 
-    Ok(true)
+    Ok(summary)
 }
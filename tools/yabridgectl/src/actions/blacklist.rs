@@ -19,20 +19,29 @@
 use anyhow::Result;
 use std::path::{Path, PathBuf};
 
+use crate::actions::Summary;
 use crate::config::Config;
 
 /// Add a path to the blacklist. Duplicates get ignord because we're using ordered sets.
-pub fn add_path(config: &mut Config, path: PathBuf) -> Result<()> {
-    config.blacklist.insert(path);
-    config.write()
+pub fn add_path(config: &mut Config, path: PathBuf) -> Result<Summary> {
+    let summary = if config.blacklist.insert(path) {
+        Summary::added(1)
+    } else {
+        Summary::skipped(1)
+    };
+    config.write()?;
+
+    Ok(summary)
 }
 
 /// Remove a path from the blacklist. The path is assumed to be part of `config.blacklist`,
 /// otherwise this is silently ignored.
-pub fn remove_path(config: &mut Config, path: &Path) -> Result<()> {
+pub fn remove_path(config: &mut Config, path: &Path) -> Result<Summary> {
     // We've already verified that this path is in `config.blacklist`
     config.blacklist.remove(path);
-    config.write()
+    config.write()?;
+
+    Ok(Summary::removed(1))
 }
 
 /// List the paths in the blacklist.
@@ -45,7 +54,10 @@ pub fn list_paths(config: &Config) -> Result<()> {
 }
 
 /// Clear the entire blacklist.
-pub fn clear(config: &mut Config) -> Result<()> {
+pub fn clear(config: &mut Config) -> Result<Summary> {
+    let summary = Summary::removed(config.blacklist.len());
     config.blacklist.clear();
-    config.write()
+    config.write()?;
+
+    Ok(summary)
 }
@@ -0,0 +1,268 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Handlers for the config subcommands. `yabridgectl config validate` checks the config file for
+//! problems without changing anything, so it reads the config file on its own instead of using the
+//! config `main()` has already loaded.
+
+use anyhow::{anyhow, Context, Result};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config};
+use crate::json::{self, JsonFormat};
+use crate::utils;
+
+/// The results of `yabridgectl config validate`, printed with `--json`.
+#[derive(Serialize, Debug)]
+struct Validation {
+    valid: bool,
+    /// The config file that was checked, if there is one.
+    path: Option<PathBuf>,
+    problems: Vec<String>,
+}
+
+/// Check the config file for problems. This returns an error if any problems were found, so
+/// yabridgectl will exit with a non-zero exit code.
+pub fn validate(json: Option<JsonFormat>) -> Result<()> {
+    let path = config::find_config_file()?;
+    let problems = match &path {
+        Some(path) => {
+            let toml_str = fs::read_to_string(path)
+                .with_context(|| format!("Could not read config file at '{}'", path.display()))?;
+            validate_str(&toml_str)
+        }
+        // The defaults will be used, and those are always valid
+        None => Vec::new(),
+    };
+
+    let validation = Validation {
+        valid: problems.is_empty(),
+        path,
+        problems,
+    };
+
+    match json {
+        Some(format) => json::print(&validation, format)?,
+        None => match &validation.path {
+            Some(path) if validation.valid => println!("'{}' is valid", path.display()),
+            Some(path) => {
+                println!("Found problems in '{}':", path.display());
+                for problem in &validation.problems {
+                    println!("- {}", problem);
+                }
+            }
+            None => println!("There is no config file yet, the default settings will be used"),
+        },
+    }
+
+    if validation.valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Found {} problem(s) in the config file",
+            validation.problems.len()
+        ))
+    }
+}
+
+/// Check the contents of a config file for problems, returning a description for every problem.
+fn validate_str(toml_str: &str) -> Vec<String> {
+    let raw_config: toml::Value = match toml::from_str(toml_str) {
+        Ok(value) => value,
+        Err(err) => return vec![format!("Could not parse the file: {}", err)],
+    };
+    let config: Config = match toml::from_str(toml_str) {
+        Ok(config) => config,
+        Err(err) => return vec![format!("Invalid setting: {}", err)],
+    };
+
+    let mut problems = Vec::new();
+
+    // Serde silently ignores options it doesn't know about. Serializing the parsed config again
+    // gives us all of the known options the file contained.
+    if let Ok(known_config) = toml::Value::try_from(&config) {
+        find_unknown_options(&raw_config, &known_config, "", &mut problems);
+    }
+
+    // These checks use the expanded and normalized paths, just like the rest of yabridgectl does
+    let mut normalized_config = config.clone();
+    if let Err(err) = normalized_config.expand_paths() {
+        problems.push(format!("{:#}", err));
+        return problems;
+    }
+    normalized_config.normalize();
+    if normalized_config.plugin_dirs.len() != config.plugin_dirs.len() {
+        problems.push(String::from(
+            "Some plugin directories are listed more than once using different spellings",
+        ));
+    }
+    let config = normalized_config;
+
+    for directory in &config.plugin_dirs {
+        if !directory.is_dir() {
+            problems.push(format!(
+                "Plugin directory '{}' does not exist",
+                directory.display()
+            ));
+        }
+    }
+
+    // Plugins in nested directories would be set up twice
+    for (directory, parent) in config::nested_plugin_dirs(&config.plugin_dirs) {
+        problems.push(format!(
+            "Plugin directory '{}' is inside of plugin directory '{}'",
+            directory.display(),
+            parent.display()
+        ));
+    }
+
+    let mut targets: BTreeMap<PathBuf, &Path> = BTreeMap::new();
+    for directory in config.directory_overrides.keys() {
+        if !config.plugin_dirs.contains(directory) {
+            problems.push(format!(
+                "There are settings for '{}', but it's not a plugin directory",
+                directory.display()
+            ));
+            continue;
+        }
+
+        let target = match config.target_dir(directory) {
+            Ok(target) => target,
+            Err(err) => {
+                problems.push(format!("{:#}", err));
+                continue;
+            }
+        };
+        if let Some(target) = target {
+            let target = utils::normalize_path(&target);
+            if let Some(other_directory) = targets.insert(target.clone(), directory) {
+                problems.push(format!(
+                    "Plugin directories '{}' and '{}' have the same target directory '{}'",
+                    other_directory.display(),
+                    directory.display(),
+                    target.display()
+                ));
+            }
+            if let Some(plugin_directory) = config.plugin_dirs.iter().find(|&plugin_directory| {
+                plugin_directory != directory && target.starts_with(plugin_directory)
+            }) {
+                // The `.so` files in there would be treated as leftovers for that directory
+                problems.push(format!(
+                    "The target directory for '{}' is inside of plugin directory '{}'",
+                    directory.display(),
+                    plugin_directory.display()
+                ));
+            }
+        }
+    }
+
+    for (name, profile) in &config.profiles {
+        for directory in &profile.plugin_dirs {
+            if !config.plugin_dirs.contains(directory) {
+                problems.push(format!(
+                    "Profile '{}' contains '{}', but it's not a plugin directory",
+                    name,
+                    directory.display()
+                ));
+            }
+        }
+    }
+
+    for plugin in &config.always_copy {
+        if !config
+            .plugin_dirs
+            .iter()
+            .any(|directory| plugin.starts_with(directory))
+        {
+            problems.push(format!(
+                "'{}' should always be copied, but it's not inside of a plugin directory",
+                plugin.display()
+            ));
+        }
+    }
+
+    for (plugin, directory) in &config.plugin_hosts {
+        if !config
+            .plugin_dirs
+            .iter()
+            .any(|plugin_directory| plugin.starts_with(plugin_directory))
+        {
+            problems.push(format!(
+                "'{}' uses a different build of yabridge, but it's not inside of a plugin directory",
+                plugin.display()
+            ));
+        }
+        if let Err(err) = config.host_files(directory) {
+            problems.push(format!(
+                "Could not use the build of yabridge in '{}': {:#}",
+                directory.display(),
+                err
+            ));
+        }
+    }
+
+    if let Some(yabridge_home) = &config.yabridge_home {
+        if !yabridge_home.is_dir() {
+            problems.push(format!(
+                "yabridge path '{}' does not exist",
+                yabridge_home.display()
+            ));
+        }
+    }
+
+    for pattern in &config.ignored_wine_errors {
+        match utils::ignored_wine_errors([pattern]) {
+            // Unanchored patterns that match an empty string match every line
+            Ok(regex) if regex.is_match("") && regex.is_match("0024:err:module:import_dll") => {
+                problems.push(format!(
+                    "Ignored Wine error pattern '{}' would ignore every error",
+                    pattern
+                ))
+            }
+            Ok(_) => (),
+            Err(err) => problems.push(format!("{:#}", err)),
+        }
+    }
+
+    problems
+}
+
+/// Report every option in `raw` that's not present in `known`, recursing into tables. `prefix` is
+/// the dotted path to the current table.
+fn find_unknown_options(
+    raw: &toml::Value,
+    known: &toml::Value,
+    prefix: &str,
+    problems: &mut Vec<String>,
+) {
+    if let (toml::Value::Table(raw), toml::Value::Table(known)) = (raw, known) {
+        for (key, value) in raw {
+            let name = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+
+            match known.get(key) {
+                Some(known_value) => find_unknown_options(value, known_value, &name, problems),
+                None => problems.push(format!("Unknown option '{}'", name)),
+            }
+        }
+    }
+}
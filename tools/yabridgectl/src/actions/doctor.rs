@@ -0,0 +1,239 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `yabridgectl doctor`, which runs every check that's otherwise spread out over `yabridgectl sync`
+//! and `yabridgectl healthcheck` and lists the results in one place. Unlike the health check this
+//! always starts Wine and the login shell, so it's meant for troubleshooting and not for
+//! monitoring.
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::actions::healthcheck::check_verification;
+use crate::config::{self, Config, InstallationMethod, YabridgeFiles, YABRIDGE_HOST_EXE_NAME};
+use crate::utils;
+
+/// The outcome of a single check.
+enum CheckStatus {
+    Ok,
+    /// Something looks off, but yabridge should still work.
+    Warning(String),
+    /// A critical problem that will prevent yabridge from working.
+    Failed(String),
+    /// The check could not be performed because of an earlier problem or because it doesn't apply.
+    Skipped(String),
+}
+
+/// Run all checks and print their results. This returns an error if any of the critical checks
+/// failed, so yabridgectl will exit with a non-zero exit code.
+pub fn doctor(config: &mut Config) -> Result<()> {
+    let mut checks: Vec<(&str, CheckStatus)> = Vec::new();
+
+    let files = config.files();
+    checks.push((
+        "yabridge's files",
+        match &files {
+            Ok(_) => CheckStatus::Ok,
+            Err(err) => CheckStatus::Failed(format!("{:#}", err)),
+        },
+    ));
+    match &files {
+        Ok(files) => {
+            checks.extend(check_libraries(files));
+            checks.push((
+                "same build",
+                if utils::verify_same_build(files) {
+                    CheckStatus::Ok
+                } else {
+                    CheckStatus::Failed(String::from(
+                        "yabridge's files come from different versions of yabridge",
+                    ))
+                },
+            ));
+            checks.push(("host libraries", check_host_libraries(files)));
+        }
+        Err(_) => checks.push((
+            "libraries",
+            CheckStatus::Skipped(String::from("yabridge's files are missing")),
+        )),
+    }
+    checks.push(("data directory", check_data_directory()));
+
+    // The PATH setup only matters for copies, see `verify_path_setup()`
+    let uses_copies = config.method == InstallationMethod::Copy
+        || config
+            .directory_overrides
+            .values()
+            .any(|overrides| overrides.method == Some(InstallationMethod::Copy));
+    checks.push((
+        "PATH setup",
+        if utils::is_skipping_checks() {
+            CheckStatus::Skipped(String::from("'--skip-checks' was passed"))
+        } else if uses_copies {
+            match utils::verify_path_setup(config) {
+                Ok(true) => CheckStatus::Ok,
+                Ok(false) => CheckStatus::Failed(format!(
+                    "'{}' can't be found through your login shell's PATH",
+                    YABRIDGE_HOST_EXE_NAME
+                )),
+                Err(err) => CheckStatus::Failed(format!("{:#}", err)),
+            }
+        } else {
+            CheckStatus::Skipped(String::from("plugins are only set up using symlinks"))
+        },
+    ));
+
+    // This has to be checked before running Wine, since a successful Wine check updates the last
+    // known configuration
+    match &files {
+        Ok(_) if utils::is_skipping_checks() => {
+            for name in ["last verification", "Wine setup"] {
+                checks.push((
+                    name,
+                    CheckStatus::Skipped(String::from("'--skip-checks' was passed")),
+                ));
+            }
+        }
+        Ok(files) => {
+            checks.push((
+                "last verification",
+                match check_verification(config, files) {
+                    Ok(()) => CheckStatus::Ok,
+                    Err(err) => CheckStatus::Warning(format!("{:#}", err)),
+                },
+            ));
+            checks.push((
+                "Wine setup",
+                match utils::verify_wine_setup(config, true, false) {
+                    Ok(()) => CheckStatus::Ok,
+                    Err(err) => CheckStatus::Failed(format!("{:#}", err)),
+                },
+            ));
+        }
+        Err(_) => {
+            for name in ["last verification", "Wine setup"] {
+                checks.push((
+                    name,
+                    CheckStatus::Skipped(String::from("yabridge's files are missing")),
+                ));
+            }
+        }
+    }
+
+    println!();
+    let mut num_failed = 0;
+    for (name, status) in &checks {
+        match status {
+            CheckStatus::Ok => println!("{}: {}", name, "ok".green()),
+            CheckStatus::Warning(message) => {
+                println!("{}: {} ({})", name, "warning".yellow(), message)
+            }
+            CheckStatus::Failed(message) => {
+                num_failed += 1;
+                println!("{}: {} ({})", name, "failed".red(), message)
+            }
+            CheckStatus::Skipped(message) => println!("{}: skipped ({})", name, message),
+        }
+    }
+
+    if num_failed == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("{} critical check(s) failed", num_failed))
+    }
+}
+
+/// Check whether yabridge's libraries and `yabridge-host.exe.so` can be read. VST3 and CLAP support
+/// are optional, so a missing library for those formats only results in a warning.
+fn check_libraries(files: &YabridgeFiles) -> Vec<(&'static str, CheckStatus)> {
+    let optional_library = |library: &Option<PathBuf>, format: &str| match library {
+        Some(path) => check_readable(path),
+        None => CheckStatus::Warning(format!(
+            "yabridge has not been built with {} support",
+            format
+        )),
+    };
+
+    vec![
+        (
+            "libyabridge-vst2.so",
+            check_readable(&files.libyabridge_vst2),
+        ),
+        (
+            "libyabridge-vst3.so",
+            optional_library(&files.libyabridge_vst3, "VST3"),
+        ),
+        (
+            "libyabridge-clap.so",
+            optional_library(&files.libyabridge_clap, "CLAP"),
+        ),
+        (
+            "yabridge-host.exe.so",
+            check_readable(&files.yabridge_host_exe_so),
+        ),
+    ]
+}
+
+/// Check whether all shared libraries `yabridge-host.exe.so` depends on can be found, see
+/// [`utils::missing_libraries()`]. Unlike the Wine setup check, this is never skipped based on the
+/// last known configuration. We can't replicate the dynamic linker's search perfectly, so missing
+/// libraries only result in a warning.
+fn check_host_libraries(files: &YabridgeFiles) -> CheckStatus {
+    let missing_libraries = utils::missing_libraries(&files.yabridge_host_exe_so);
+    if missing_libraries.is_empty() {
+        CheckStatus::Ok
+    } else {
+        CheckStatus::Warning(format!(
+            "'{}' needs these libraries that could not be found: {}",
+            YABRIDGE_HOST_EXE_NAME,
+            missing_libraries.join(", ")
+        ))
+    }
+}
+
+/// Check whether a file exists and whether it can be read by the current user.
+fn check_readable(path: &Path) -> CheckStatus {
+    match fs::File::open(path) {
+        Ok(_) => CheckStatus::Ok,
+        Err(err) => CheckStatus::Failed(format!("could not read '{}': {}", path.display(), err)),
+    }
+}
+
+/// Check `~/.local/share/yabridge`. Yabridge always searches this directory for
+/// `yabridge-host.exe`, but it's not needed when yabridge has been installed elsewhere.
+fn check_data_directory() -> CheckStatus {
+    let data_home = match config::yabridge_directories() {
+        Ok(dirs) => dirs.get_data_home(),
+        Err(err) => return CheckStatus::Failed(format!("{:#}", err)),
+    };
+
+    if data_home.join(YABRIDGE_HOST_EXE_NAME).exists() {
+        CheckStatus::Ok
+    } else if data_home.is_dir() {
+        CheckStatus::Warning(format!(
+            "'{}' does not contain '{}'",
+            data_home.display(),
+            YABRIDGE_HOST_EXE_NAME
+        ))
+    } else {
+        CheckStatus::Skipped(format!(
+            "'{}' does not exist, so yabridge must have been installed elsewhere",
+            data_home.display()
+        ))
+    }
+}
@@ -0,0 +1,258 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `yabridgectl healthcheck`, a quick check meant to be run periodically by monitoring systems.
+//! Unlike `yabridgectl sync` this never changes anything, and it only starts Wine when explicitly
+//! asked to since that can take several seconds.
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use serde_derive::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+use crate::cache::InstalledPlugins;
+use crate::config::{Config, KnownConfig, YabridgeFiles};
+use crate::json::{self, JsonFormat};
+use crate::utils::{self, HostStatus};
+
+/// Options passed to `yabridgectl healthcheck`, see `main()` for the definitions of these options.
+pub struct HealthcheckOptions {
+    pub json: Option<JsonFormat>,
+    pub status_file: Option<PathBuf>,
+    /// Also try to run `yabridge-host.exe`, which is much slower than the other checks.
+    pub wine: bool,
+}
+
+/// The results of a health check. This is printed with `--json` and written to the status file.
+#[derive(Serialize, Debug)]
+struct Health {
+    healthy: bool,
+    /// When the check was performed, in seconds since the Unix epoch.
+    timestamp: u64,
+    checks: Vec<Check>,
+}
+
+/// The outcome of a single check.
+#[derive(Serialize, Debug)]
+struct Check {
+    name: &'static str,
+    status: CheckStatus,
+    /// What went wrong, if the check failed.
+    message: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Ok,
+    Failed,
+    /// The check could not be performed, for instance because post-installation checks have been
+    /// disabled. Skipped checks don't make the setup unhealthy.
+    Skipped,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<()>) -> Check {
+        match result {
+            Ok(()) => Check {
+                name,
+                status: CheckStatus::Ok,
+                message: None,
+            },
+            Err(err) => Check {
+                name,
+                status: CheckStatus::Failed,
+                message: Some(format!("{:#}", err)),
+            },
+        }
+    }
+
+    fn skipped(name: &'static str, reason: &str) -> Check {
+        Check {
+            name,
+            status: CheckStatus::Skipped,
+            message: Some(reason.to_owned()),
+        }
+    }
+}
+
+/// Perform the health check. This returns an error if any of the checks failed, so yabridgectl
+/// will exit with a non-zero exit code.
+pub fn healthcheck(config: &Config, options: &HealthcheckOptions) -> Result<()> {
+    let mut checks = Vec::new();
+
+    let files = config.files();
+    checks.push(Check::new(
+        "files",
+        files
+            .as_ref()
+            .map(|_| ())
+            .map_err(|err| anyhow!("{:#}", err)),
+    ));
+    checks.push(Check::new("symlinks", check_symlinks()));
+    match &files {
+        Ok(_) if config.no_verify || utils::is_skipping_checks() => checks.push(Check::skipped(
+            "verification",
+            "post-installation setup checks have been disabled",
+        )),
+        Ok(files) => checks.push(Check::new(
+            "verification",
+            check_verification(config, files),
+        )),
+        Err(_) => checks.push(Check::skipped(
+            "verification",
+            "yabridge's files are missing",
+        )),
+    }
+    match &files {
+        Ok(_) if options.wine && utils::is_skipping_checks() => checks.push(Check::skipped(
+            "wine",
+            "post-installation setup checks have been disabled",
+        )),
+        Ok(files) if options.wine => checks.push(Check::new("wine", check_wine(config, files))),
+        Ok(_) => checks.push(Check::skipped("wine", "use '--wine' to enable this check")),
+        Err(_) => checks.push(Check::skipped("wine", "yabridge's files are missing")),
+    }
+
+    let health = Health {
+        healthy: checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Failed),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+        checks,
+    };
+
+    if let Some(path) = &options.status_file {
+        utils::write_atomically(
+            path,
+            &(json::to_string(&health, JsonFormat::Compact)? + "\n"),
+        )?;
+    }
+
+    match options.json {
+        Some(format) => json::print(&health, format)?,
+        None => {
+            for check in &health.checks {
+                let status = match check.status {
+                    CheckStatus::Ok => "ok".green(),
+                    CheckStatus::Failed => "failed".red(),
+                    CheckStatus::Skipped => "skipped".into(),
+                };
+                match &check.message {
+                    Some(message) => println!("{}: {} ({})", check.name, status, message),
+                    None => println!("{}: {}", check.name, status),
+                }
+            }
+        }
+    }
+
+    if health.healthy {
+        Ok(())
+    } else {
+        Err(anyhow!("The health check failed"))
+    }
+}
+
+/// Check whether any of the files set up during the last sync contain symlinks that point to files
+/// that no longer exist.
+fn check_symlinks() -> Result<()> {
+    let broken_symlinks: Vec<PathBuf> = InstalledPlugins::read()
+        .plugins
+        .values()
+        .flat_map(|installed| {
+            // For VST2 plugins with a target override we'll also have created a `.dll` symlink
+            let vst2_dll_symlink = installed.target.with_extension("dll");
+            let mut paths = vec![installed.target.clone()];
+            if installed.target.extension().and_then(|ext| ext.to_str()) == Some("so") {
+                paths.push(vst2_dll_symlink);
+            }
+
+            paths
+        })
+        // `WalkDir` can't tell us about a broken symlink at the root, so we'll check that one
+        // separately
+        .flat_map(|path| {
+            WalkDir::new(&path)
+                .min_depth(1)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.into_path())
+                .chain(std::iter::once(path))
+                .collect::<Vec<_>>()
+        })
+        .filter(|path| {
+            fs::symlink_metadata(path)
+                .map(|metadata| metadata.file_type().is_symlink())
+                .unwrap_or(false)
+                && fs::metadata(path).is_err()
+        })
+        .collect();
+
+    if broken_symlinks.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} broken symlinks, rerun 'yabridgectl sync': {}",
+            broken_symlinks.len(),
+            broken_symlinks
+                .iter()
+                .map(|path| format!("'{}'", path.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+/// Check whether the current versions of Wine and yabridge have been verified to work together
+/// during a previous sync, without actually starting Wine.
+pub fn check_verification(config: &Config, files: &YabridgeFiles) -> Result<()> {
+    let current_config = KnownConfig::current(
+        files,
+        utils::wine_version(Duration::from_secs(config.verify_timeout))?,
+        false,
+    )?;
+
+    if config
+        .last_known_config
+        .as_ref()
+        .is_some_and(|known_config| known_config.covers(&current_config))
+    {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Wine or yabridge has been updated since the last verification, rerun 'yabridgectl \
+             sync'"
+        ))
+    }
+}
+
+/// Actually try to run `yabridge-host.exe`. This is only done with `--wine` since starting Wine can
+/// take several seconds.
+fn check_wine(config: &Config, files: &YabridgeFiles) -> Result<()> {
+    match utils::run_yabridge_host(files, None, &config.ignored_wine_errors)? {
+        HostStatus::Running => Ok(()),
+        HostStatus::Failed { last_error } => Err(anyhow!(
+            "Could not run 'yabridge-host.exe': {}",
+            last_error.as_deref().unwrap_or("<no_output>")
+        )),
+    }
+}
@@ -0,0 +1,67 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Handlers for the host subcommands, just to keep `main.rs` clean.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::actions::Summary;
+use crate::config::Config;
+
+/// Use the build of yabridge in `directory` for a plugin. This will fail if `directory` doesn't
+/// contain yabridge's libraries and `yabridge-host.exe`.
+pub fn set(config: &mut Config, plugin_path: PathBuf, directory: PathBuf) -> Result<Summary> {
+    config.host_files(&directory).with_context(|| {
+        format!(
+            "'{}' does not contain a build of yabridge",
+            directory.display()
+        )
+    })?;
+
+    config.update(|config| {
+        let summary = match config.plugin_hosts.insert(plugin_path, directory.clone()) {
+            Some(previous) if previous == directory => Summary::skipped(1),
+            Some(_) => Summary {
+                updated: 1,
+                ..Summary::default()
+            },
+            None => Summary::added(1),
+        };
+
+        Ok(summary)
+    })
+}
+
+/// Use the regular build of yabridge for a plugin again. The plugin is assumed to have a host
+/// assigned to it, otherwise this is silently ignored.
+pub fn remove(config: &mut Config, plugin_path: &Path) -> Result<Summary> {
+    config.update(|config| {
+        config.plugin_hosts.remove(plugin_path);
+
+        Ok(Summary::removed(1))
+    })
+}
+
+/// List the plugins using another build of yabridge, along with the directories containing those
+/// builds.
+pub fn list(config: &Config) -> Result<()> {
+    for (plugin_path, directory) in &config.plugin_hosts {
+        println!("{}\t{}", plugin_path.display(), directory.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Handlers for the profile subcommands, just to keep `main.rs` clean.
+
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::actions::Summary;
+use crate::config::Config;
+
+/// Add plugin directories and plugins to a profile, creating the profile if it does not yet exist.
+/// Duplicate entries are ignored.
+pub fn add(
+    config: &mut Config,
+    name: &str,
+    directories: Vec<PathBuf>,
+    plugins: Vec<String>,
+) -> Result<Summary> {
+    config.update(|config| {
+        let mut summary = Summary::default();
+        if !config.profiles.contains_key(name) {
+            summary.added += 1;
+        }
+
+        let profile = config.profiles.entry(name.to_owned()).or_default();
+        for directory in directories {
+            if profile.plugin_dirs.insert(directory) {
+                summary.added += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+        for plugin in plugins {
+            if profile.plugins.contains(&plugin) {
+                summary.skipped += 1;
+            } else {
+                profile.plugins.push(plugin);
+                summary.added += 1;
+            }
+        }
+
+        Ok(summary)
+    })
+}
+
+/// Remove a profile. The profile is assumed to exist, otherwise this is silently ignored.
+pub fn remove(config: &mut Config, name: &str) -> Result<Summary> {
+    config.update(|config| {
+        config.profiles.remove(name);
+
+        Ok(Summary::removed(1))
+    })
+}
+
+/// List the profiles along with their plugin directories and plugins.
+pub fn list(config: &Config) -> Result<()> {
+    for (name, profile) in &config.profiles {
+        println!("{}", name);
+        if profile.plugin_dirs.is_empty() {
+            println!("  directories: all");
+        } else {
+            for directory in &profile.plugin_dirs {
+                println!("  directory: {}", directory.display());
+            }
+        }
+        if profile.plugins.is_empty() {
+            println!("  plugins: all");
+        } else {
+            for plugin in &profile.plugins {
+                println!("  plugin: {}", plugin);
+            }
+        }
+    }
+
+    Ok(())
+}
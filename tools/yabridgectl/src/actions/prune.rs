@@ -0,0 +1,334 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `yabridgectl prune`, which removes the files yabridgectl set up for plugins that no longer
+//! exist. Unlike `yabridgectl sync --prune` this doesn't search for plugins, so it doesn't need
+//! `winedump`. It only removes files it can prove belong to yabridge.
+
+use anyhow::{Context, Result};
+use serde_derive::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::actions::sync::{
+    find_clap_orphans, find_target_vst2_orphans, find_vst3_orphans, HostLibraries,
+};
+use crate::actions::Summary;
+use crate::config::{yabridge_clap_home, yabridge_vst3_home, Config};
+use crate::files::{LibArchitecture, NativeFile};
+use crate::json::{self, JsonFormat};
+use crate::utils::{self, get_file_type};
+
+/// An orphaned file found by [`find_orphans()`], for `yabridgectl list --orphans --json`.
+#[derive(Serialize, Debug)]
+struct OrphanedFile<'a> {
+    path: &'a Path,
+    reason: &'a str,
+}
+
+/// Remove the orphaned files yabridgectl set up for plugins that have since been uninstalled or
+/// moved, see [`find_orphans()`]. With `dry_run` the files are only listed.
+pub fn prune(config: &Config, dry_run: bool) -> Result<Summary> {
+    let orphans = find_orphans(config)?;
+    if orphans.is_empty() {
+        println!("Found no orphaned files");
+        return Ok(Summary::default());
+    }
+
+    println!(
+        "{} {} orphaned files:",
+        if dry_run { "Found" } else { "Removing" },
+        orphans.len()
+    );
+    for (file, reason) in &orphans {
+        println!("- {} ({})", file.path().display(), reason);
+    }
+
+    if dry_run {
+        println!("\nRerun this command without '--dry-run' to remove these files");
+        return Ok(Summary::default());
+    }
+
+    for (file, _) in &orphans {
+        match file {
+            NativeFile::Directory(path) => utils::remove_dir_all(path)?,
+            NativeFile::Regular(path) | NativeFile::Symlink(path) => utils::remove_file(path)?,
+        }
+    }
+
+    Ok(Summary::removed(orphans.len()))
+}
+
+/// List the orphaned files [`prune()`] would remove without changing anything, for `yabridgectl
+/// list --orphans`.
+pub fn list_orphans(config: &Config, json: Option<JsonFormat>) -> Result<()> {
+    let orphans = find_orphans(config)?;
+    if let Some(format) = json {
+        return json::print(
+            &orphans
+                .iter()
+                .map(|(file, reason)| OrphanedFile {
+                    path: file.path(),
+                    reason,
+                })
+                .collect::<Vec<_>>(),
+            format,
+        );
+    }
+
+    for (file, reason) in &orphans {
+        println!("{} ({})", file.path().display(), reason);
+    }
+
+    Ok(())
+}
+
+/// Find the orphaned files yabridgectl set up for plugins that have since been uninstalled or
+/// moved, along with the reason why they're considered to be orphaned. The results are sorted by
+/// path.
+///
+/// - `.so` files without a `.dll` file next to them are removed if they are a copy of or a symlink
+///   to one of yabridge's libraries, or if they are a symlink that no longer resolves. Files that
+///   only share the name but not the contents are left alone, since those may be native plugins.
+/// - Merged VST3 bundles are removed if the Windows VST3 modules they link to no longer exist.
+/// - CLAP plugins are removed if the Windows `.clap` file their `.clap-win` symlink points to no
+///   longer exists.
+pub fn find_orphans(config: &Config) -> Result<Vec<(NativeFile, &'static str)>> {
+    let known_hashes = yabridge_library_hashes(config)?;
+    let blacklist: HashSet<&Path> = config.blacklist.iter().map(|p| p.as_path()).collect();
+
+    // VST2 plugins are set up in the plugin directories or in their target directories, and they
+    // can also be symlinked to extra target directories
+    let mut directories: BTreeSet<PathBuf> = BTreeSet::new();
+    for directory in &config.plugin_dirs {
+        directories.insert(directory.clone());
+        directories.extend(config.target_dir(directory)?);
+        directories.extend(config.extra_target_dirs(directory)?);
+    }
+
+    let mut orphans: Vec<(NativeFile, &str)> = Vec::new();
+    for directory in &directories {
+        for file in find_so_files(directory, &blacklist) {
+            // The `libyabridge-vst3.so` files inside of merged bundles are handled below
+            if file
+                .path()
+                .ancestors()
+                .any(|path| path.extension().and_then(|ext| ext.to_str()) == Some("vst3"))
+            {
+                continue;
+            }
+
+            // `Path::exists()` follows symlinks, so this also catches `.dll` symlinks in target
+            // directories that point to a plugin that's been removed
+            let dll_path = file.path().with_extension("dll");
+            if dll_path.exists() {
+                continue;
+            }
+
+            let is_yabridge_library =
+                |path: &Path| utils::hash_file(path).is_ok_and(|hash| known_hashes.contains(&hash));
+            let reason = match &file {
+                NativeFile::Symlink(path) if !path.exists() => "broken symlink",
+                NativeFile::Regular(path) | NativeFile::Symlink(path)
+                    if is_yabridge_library(path) =>
+                {
+                    "plugin no longer exists"
+                }
+                _ => continue,
+            };
+            orphans.push((file, reason));
+            if let Some(dll_symlink @ NativeFile::Symlink(_)) = get_file_type(dll_path) {
+                orphans.push((dll_symlink, "broken symlink"));
+            }
+        }
+    }
+
+    // VST3 and CLAP plugins are set up in `~/.vst3/yabridge` and `~/.clap/yabridge`, or in the
+    // target directories
+    let mut target_dirs: Vec<PathBuf> = Vec::new();
+    for directory in &config.plugin_dirs {
+        target_dirs.extend(config.target_dir(directory)?);
+        target_dirs.extend(config.extra_target_dirs(directory)?);
+    }
+    for directory in std::iter::once(&yabridge_vst3_home()).chain(&target_dirs) {
+        for bundle in find_vst3_orphans(directory, true, &|_| false) {
+            if !has_windows_module(bundle.path()) {
+                orphans.push((bundle, "plugin no longer exists"));
+            }
+        }
+    }
+
+    // This finds both the `.clap` files and the `.clap-win` symlinks next to them
+    for directory in std::iter::once(&yabridge_clap_home()).chain(&target_dirs) {
+        for file in find_clap_orphans(directory, true, &|_| false) {
+            if !file.path().with_extension("clap-win").exists() {
+                orphans.push((file, "plugin no longer exists"));
+            }
+        }
+    }
+
+    orphans.sort_by(|(a, _), (b, _)| a.path().cmp(b.path()));
+    orphans.dedup_by(|(a, _), (b, _)| a.path() == b.path());
+
+    Ok(orphans)
+}
+
+/// Find all files yabridgectl set up for plugins in a plugin directory, used by `yabridgectl rm
+/// --prune`. Copies in the directory itself are matched by their hash, and the files in
+/// `~/.vst3/yabridge`, `~/.clap/yabridge`, and the directory's target and extra target directories
+/// are matched by the symlink to the Windows plugin they contain. The directory does not need to
+/// exist anymore, in which case only the latter files are found.
+pub fn find_directory_files(config: &Config, directory: &Path) -> Result<Vec<NativeFile>> {
+    let known_hashes = yabridge_library_hashes(config)?;
+    let mut files: Vec<NativeFile> = Vec::new();
+
+    // VST2 plugins set up in the plugin directory itself have a `.dll` file right next to them
+    for file in find_so_files(directory, &HashSet::new()) {
+        let is_yabridge_copy =
+            matches!(utils::hash_file(file.path()), Ok(hash) if known_hashes.contains(&hash));
+        if is_yabridge_copy && file.path().with_extension("dll").exists() {
+            files.push(file);
+        }
+    }
+
+    // VST2 plugins in the target directories have a `.dll` symlink to the original plugin
+    let target_dirs: Vec<PathBuf> = config
+        .target_dir(directory)?
+        .into_iter()
+        .chain(config.extra_target_dirs(directory)?)
+        .collect();
+    for target_dir in &target_dirs {
+        for file in find_target_vst2_orphans(target_dir, &HashSet::new()) {
+            let dll_path = file.path().with_extension("dll");
+            if links_into(&dll_path, directory) {
+                files.push(file);
+                files.extend(get_file_type(dll_path));
+            }
+        }
+    }
+
+    for home in std::iter::once(yabridge_vst3_home()).chain(target_dirs.clone()) {
+        for bundle in find_vst3_orphans(&home, true, &|_| false) {
+            let module_name = match bundle.path().file_name() {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let belongs_to_directory =
+                [LibArchitecture::Dll32, LibArchitecture::Dll64]
+                    .iter()
+                    .any(|architecture| {
+                        links_into(
+                            &bundle
+                                .path()
+                                .join("Contents")
+                                .join(architecture.vst_arch())
+                                .join(&module_name),
+                            directory,
+                        )
+                    });
+            if belongs_to_directory {
+                files.push(bundle);
+            }
+        }
+    }
+
+    for home in std::iter::once(yabridge_clap_home()).chain(target_dirs) {
+        for file in find_clap_orphans(&home, true, &|_| false) {
+            if links_into(&file.path().with_extension("clap-win"), directory) {
+                files.push(file);
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.path().cmp(b.path()));
+    files.dedup_by(|a, b| a.path() == b.path());
+
+    Ok(files)
+}
+
+/// Whether `path` is a symlink pointing to a file inside of `directory`. Relative symlinks are
+/// resolved relative to the symlink's parent directory. This doesn't require the target to exist.
+fn links_into(path: &Path, directory: &Path) -> bool {
+    utils::read_symlink(path)
+        .map(|target| target.starts_with(directory))
+        .unwrap_or(false)
+}
+
+/// Find all `.so` files under a directory. This is similar to `files::index()`, but it also includes
+/// broken symlinks. Those would otherwise be skipped because we follow symlinks while walking the
+/// directory.
+fn find_so_files(directory: &Path, blacklist: &HashSet<&Path>) -> Vec<NativeFile> {
+    WalkDir::new(directory)
+        .follow_links(true)
+        .into_iter()
+        .filter_entry(|entry| !blacklist.contains(entry.path()))
+        .filter_map(|entry| match entry {
+            Ok(entry) if !entry.file_type().is_dir() => Some(entry.into_path()),
+            Ok(_) => None,
+            // Following a broken symlink results in an error
+            Err(err) => err.path().map(Path::to_path_buf),
+        })
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("so"))
+        .filter_map(get_file_type)
+        .collect()
+}
+
+/// The hashes of all of yabridge's libraries, including the chainloaders and the libraries from the
+/// alternative builds in `config.plugin_hosts`. Copies of these files can safely be removed.
+fn yabridge_library_hashes(config: &Config) -> Result<HashSet<i64>> {
+    let files = config.files()?;
+    let mut libraries: Vec<&Path> = vec![&files.libyabridge_vst2];
+    libraries.extend(files.libyabridge_vst3.as_deref());
+    libraries.extend(files.libyabridge_chainloader_vst2.as_deref());
+    libraries.extend(files.libyabridge_chainloader_vst3.as_deref());
+    libraries.extend(files.libyabridge_clap.as_deref());
+    libraries.extend(files.libyabridge_chainloader_clap.as_deref());
+
+    let mut hashes = libraries
+        .into_iter()
+        .map(utils::hash_file)
+        .collect::<Result<HashSet<i64>>>()
+        .context("Could not hash yabridge's libraries")?;
+    for directory in config.plugin_hosts.values().collect::<BTreeSet<_>>() {
+        // A build that has since been removed can't have any copies we'd recognize anyway
+        if let Ok(host) = HostLibraries::new(config, directory) {
+            hashes.insert(host.vst2_hash);
+            hashes.extend(host.vst3.map(|(_, hash)| hash));
+            hashes.extend(host.clap.map(|(_, hash)| hash));
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Whether a merged VST3 bundle still contains a symlink to a Windows VST3 module that exists.
+fn has_windows_module(bundle: &Path) -> bool {
+    let module_name = match bundle.file_name() {
+        Some(name) => name,
+        None => return true,
+    };
+
+    [LibArchitecture::Dll32, LibArchitecture::Dll64]
+        .iter()
+        .any(|architecture| {
+            bundle
+                .join("Contents")
+                .join(architecture.vst_arch())
+                .join(module_name)
+                .exists()
+        })
+}
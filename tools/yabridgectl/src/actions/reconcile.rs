@@ -0,0 +1,407 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `yabridgectl verify-config-against-disk`, which compares the plugins the config says should be
+//! set up against the files that are actually on disk. Unlike `yabridgectl sync --export-plan`
+//! this doesn't describe what a sync would change, it only reports what's wrong right now. Nothing
+//! is ever changed.
+
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::actions::sync::{
+    find_clap_orphans, find_target_vst2_orphans, find_vst3_orphans, HostLibraries, PluginFilters,
+    SkipReason,
+};
+use crate::cache::InstalledPlugins;
+use crate::config::{yabridge_clap_home, yabridge_vst3_home, Config, InstallationMethod};
+use crate::files::{NativeFile, Plugin, Vst2Plugin};
+use crate::json::{self, JsonFormat};
+use crate::utils::{self, get_file_type};
+
+/// The results of `yabridgectl verify-config-against-disk`, printed with `--json`.
+#[derive(Serialize, Debug)]
+struct Reconciliation {
+    /// Whether every plugin has been set up correctly and there are no stray files.
+    consistent: bool,
+    plugins: Vec<PluginEntry>,
+    /// Files set up by yabridgectl that don't belong to any of the plugins in the plugin
+    /// directories.
+    strays: Vec<PathBuf>,
+}
+
+/// The installation status for a single plugin.
+#[derive(Serialize, Debug)]
+struct PluginEntry {
+    format: &'static str,
+    /// The Windows plugin's `.dll` file or `.vst3` module.
+    source: PathBuf,
+    /// The copy of or symlink to yabridge's library for this plugin.
+    target: PathBuf,
+    method: InstallationMethod,
+    status: EntryStatus,
+    /// Everything that's wrong with this plugin's installation.
+    problems: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum EntryStatus {
+    Ok,
+    /// The plugin has not been set up yet.
+    Missing,
+    /// The plugin has been set up, but the installation doesn't match the config.
+    Diverged,
+    /// The plugin is larger than the maximum plugin size and has not been set up, just like
+    /// `yabridgectl sync` would do.
+    Skipped,
+}
+
+/// Compare the config, the plugins in the plugin directories, and the files set up for them. Only
+/// the plugins in `profile` should be set up if it's set, just like with `yabridgectl sync
+/// --profile`. This returns an error if anything doesn't match, so yabridgectl will exit with a
+/// non-zero exit code.
+pub fn verify_config_against_disk(
+    config: &Config,
+    profile: Option<&str>,
+    json: Option<JsonFormat>,
+) -> Result<()> {
+    let filters = PluginFilters::from_config(config, profile)?;
+    let files = config.files()?;
+    let vst2_library = files.vst2_library();
+    let vst3_library = files.vst3_library();
+    let libyabridge_vst2_hash = utils::hash_file(vst2_library)?;
+    let libyabridge_vst3_hash = match vst3_library {
+        Some(path) => Some(utils::hash_file(path)?),
+        None => None,
+    };
+    let clap_library = files.clap_library().ok();
+    let libyabridge_clap_hash = match clap_library {
+        Some(path) => Some(utils::hash_file(path)?),
+        None => None,
+    };
+    let mut host_libraries: BTreeMap<&Path, HostLibraries> = BTreeMap::new();
+    for directory in config.plugin_hosts.values() {
+        if !host_libraries.contains_key(directory.as_path()) {
+            host_libraries.insert(directory, HostLibraries::new(config, directory)?);
+        }
+    }
+
+    let results = config
+        .search_directories_indexed()
+        .context("Failure while searching for plugins")?;
+
+    let mut plugins: Vec<PluginEntry> = Vec::new();
+    let mut strays: Vec<NativeFile> = Vec::new();
+    // These are used to find stray files in the target directories, see `yabridgectl sync`
+    let mut found_plugins: HashSet<PathBuf> = HashSet::new();
+    let mut vst2_target_paths: HashSet<PathBuf> = HashSet::new();
+    let mut vst3_bundles: HashSet<PathBuf> = HashSet::new();
+    let mut clap_target_paths: HashSet<PathBuf> = HashSet::new();
+    let mut target_dirs: BTreeSet<PathBuf> = BTreeSet::new();
+    for (path, search_results) in &results {
+        let extra_targets = config.extra_target_dirs(path)?;
+        target_dirs.extend(config.target_dir(path)?);
+        target_dirs.extend(extra_targets.iter().cloned());
+        strays.extend(search_results.vst2_orphans().into_iter().cloned());
+
+        for plugin in &search_results.plugins {
+            // The symlinks in the extra target directories are not strays either
+            let extra_target_paths = extra_targets
+                .iter()
+                .flat_map(|target| plugin.extra_target_links(path, target))
+                .map(|(_, to)| to);
+            match plugin {
+                Plugin::Vst2(_) => vst2_target_paths.extend(extra_target_paths),
+                Plugin::Vst3(_) => vst3_bundles.extend(extra_target_paths),
+                Plugin::Clap(_) => clap_target_paths.extend(extra_target_paths),
+            }
+
+            let (source, target, source_link, library, library_hash) = match plugin {
+                Plugin::Vst2(Vst2Plugin {
+                    path: plugin_path,
+                    target_path,
+                    ..
+                }) => {
+                    vst2_target_paths.insert(target_path.clone());
+
+                    // With a target directory, the `.dll` file is symlinked next to the `.so` file
+                    let dll_symlink_path = target_path.with_extension("dll");
+                    (
+                        plugin_path.clone(),
+                        target_path.clone(),
+                        Some((dll_symlink_path, plugin_path.clone()))
+                            .filter(|(dll_symlink_path, _)| dll_symlink_path != plugin_path),
+                        vst2_library,
+                        Some(libyabridge_vst2_hash),
+                    )
+                }
+                Plugin::Vst3(module) => {
+                    vst3_bundles.insert(module.target_bundle_home());
+
+                    // VST3 plugins are only set up when yabridge has been compiled with VST3
+                    // support
+                    let vst3_library = match vst3_library {
+                        Some(library) => library,
+                        None => continue,
+                    };
+                    (
+                        module.original_path().to_owned(),
+                        module.target_native_module_path(),
+                        Some((
+                            module.target_windows_module_path(),
+                            module.original_module_path(),
+                        )),
+                        vst3_library,
+                        libyabridge_vst3_hash,
+                    )
+                }
+                Plugin::Clap(clap_plugin) => {
+                    clap_target_paths.insert(clap_plugin.target_path());
+
+                    // `yabridgectl sync` refuses to run when yabridge doesn't support CLAP
+                    let clap_library = match clap_library {
+                        Some(library) => library,
+                        None => continue,
+                    };
+                    (
+                        clap_plugin.path.clone(),
+                        clap_plugin.target_path(),
+                        Some((clap_plugin.target_windows_path(), clap_plugin.path.clone())),
+                        clap_library,
+                        libyabridge_clap_hash,
+                    )
+                }
+            };
+            // Plugins using another build of yabridge should point to that build's libraries
+            let (library, library_hash) = match config
+                .plugin_hosts
+                .get(&source)
+                .map(|directory| (plugin, &host_libraries[directory.as_path()]))
+            {
+                Some((Plugin::Vst2(_), host)) => (host.vst2.as_path(), Some(host.vst2_hash)),
+                Some((Plugin::Vst3(_), host)) => match &host.vst3 {
+                    Some((library, hash)) => (library.as_path(), Some(*hash)),
+                    None => (library, library_hash),
+                },
+                Some((Plugin::Clap(_), host)) => match &host.clap {
+                    Some((library, hash)) => (library.as_path(), Some(*hash)),
+                    None => (library, library_hash),
+                },
+                None => (library, library_hash),
+            };
+            found_plugins.insert(source.clone());
+
+            // Existing installations for skipped plugins will be removed during the next sync,
+            // except for oversized plugins which are left alone
+            let skip_reason = filters.skip_reason(path, plugin);
+            if skip_reason.is_some() && skip_reason != Some(SkipReason::Oversized) {
+                match plugin {
+                    Plugin::Vst2(_) | Plugin::Clap(_) => {
+                        strays.extend(get_file_type(target));
+                        if let Some((dll_symlink_path, _)) = source_link {
+                            strays.extend(get_file_type(dll_symlink_path));
+                        }
+                    }
+                    Plugin::Vst3(module) => {
+                        strays.extend(get_file_type(module.target_windows_module_path()))
+                    }
+                }
+                continue;
+            }
+
+            let method = config.method_for(&source);
+            let mut entry = PluginEntry {
+                format: match plugin {
+                    Plugin::Vst2(_) => "vst2",
+                    Plugin::Vst3(_) => "vst3",
+                    Plugin::Clap(_) => "clap",
+                },
+                source,
+                target,
+                method,
+                status: EntryStatus::Ok,
+                problems: Vec::new(),
+            };
+
+            match get_file_type(entry.target.clone()) {
+                None if skip_reason == Some(SkipReason::Oversized) => {
+                    entry.status = EntryStatus::Skipped
+                }
+                None => entry.status = EntryStatus::Missing,
+                Some(NativeFile::Directory(_)) => entry
+                    .problems
+                    .push(String::from("The target is a directory")),
+                Some(NativeFile::Symlink(_)) if method == InstallationMethod::Copy => {
+                    entry.problems.push(String::from(
+                        "The target is a symlink, but it should be a copy",
+                    ))
+                }
+                Some(NativeFile::Regular(_)) if method == InstallationMethod::Symlink => {
+                    entry.problems.push(String::from(
+                        "The target is a copy, but it should be a symlink",
+                    ))
+                }
+                Some(NativeFile::Symlink(_)) => match utils::read_symlink(&entry.target) {
+                    Ok(link_target) if link_target == library => (),
+                    Ok(link_target) => entry.problems.push(format!(
+                        "The target points to '{}' instead of '{}'",
+                        link_target.display(),
+                        library.display()
+                    )),
+                    Err(err) => entry
+                        .problems
+                        .push(format!("Could not read the target: {}", err)),
+                },
+                Some(NativeFile::Regular(_)) => {
+                    if utils::hash_file(&entry.target).ok() != library_hash {
+                        entry.problems.push(format!(
+                            "The target is outdated, it does not match '{}'",
+                            library.display()
+                        ));
+                    }
+                }
+            }
+
+            // The Windows plugin should be symlinked to the right location
+            if let (Some((link_path, original_path)), EntryStatus::Ok) = (source_link, entry.status)
+            {
+                match utils::read_symlink(&link_path) {
+                    Ok(link_target) if link_target == original_path => (),
+                    Ok(link_target) => entry.problems.push(format!(
+                        "'{}' points to '{}' instead of '{}'",
+                        link_path.display(),
+                        link_target.display(),
+                        original_path.display()
+                    )),
+                    Err(_) => entry.problems.push(format!(
+                        "'{}' is not a symlink to '{}'",
+                        link_path.display(),
+                        original_path.display()
+                    )),
+                }
+            }
+
+            if !entry.problems.is_empty() {
+                entry.status = EntryStatus::Diverged;
+            }
+            plugins.push(entry);
+        }
+    }
+
+    // These are the same checks `yabridgectl sync` uses to find leftover files
+    let is_known_bundle = |path: &Path| vst3_bundles.contains(path);
+    let is_known_clap_plugin = |path: &Path| clap_target_paths.contains(path);
+    strays.extend(find_vst3_orphans(
+        &yabridge_vst3_home(),
+        false,
+        &is_known_bundle,
+    ));
+    strays.extend(find_clap_orphans(
+        &yabridge_clap_home(),
+        false,
+        &is_known_clap_plugin,
+    ));
+    for target_dir in &target_dirs {
+        strays.extend(find_target_vst2_orphans(target_dir, &vst2_target_paths));
+        strays.extend(find_vst3_orphans(target_dir, true, &is_known_bundle));
+        strays.extend(find_clap_orphans(target_dir, true, &is_known_clap_plugin));
+    }
+
+    // Installations recorded during a previous sync for plugins that no longer exist
+    strays.extend(
+        InstalledPlugins::read()
+            .plugins
+            .iter()
+            .filter(|(plugin_path, _)| !found_plugins.contains(*plugin_path))
+            .filter_map(|(_, installed)| get_file_type(installed.target.clone())),
+    );
+
+    let mut strays: Vec<PathBuf> = strays
+        .into_iter()
+        .map(|file| file.path().to_owned())
+        .collect();
+    strays.sort();
+    strays.dedup();
+    plugins.sort_by(|a, b| a.source.cmp(&b.source));
+
+    let reconciliation = Reconciliation {
+        consistent: strays.is_empty()
+            && plugins.iter().all(|entry| {
+                entry.status == EntryStatus::Ok || entry.status == EntryStatus::Skipped
+            }),
+        plugins,
+        strays,
+    };
+
+    match json {
+        Some(format) => json::print(&reconciliation, format)?,
+        None => print_reconciliation(&reconciliation),
+    }
+
+    if reconciliation.consistent {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "The installed files do not match the config, rerun 'yabridgectl sync' to fix this"
+        ))
+    }
+}
+
+/// Print the report in a human readable format. Plugins without any problems are only counted.
+fn print_reconciliation(reconciliation: &Reconciliation) {
+    let mut num_ok = 0;
+    for entry in &reconciliation.plugins {
+        let status = match entry.status {
+            EntryStatus::Ok => {
+                num_ok += 1;
+                continue;
+            }
+            EntryStatus::Missing => "missing".red(),
+            EntryStatus::Diverged => "diverged".red(),
+            EntryStatus::Skipped => "skipped".into(),
+        };
+
+        println!("{} :: {}, {}", entry.source.display(), entry.format, status);
+        println!("  target: {}", entry.target.display());
+        for problem in &entry.problems {
+            println!("  - {}", problem);
+        }
+    }
+
+    if !reconciliation.strays.is_empty() {
+        if num_ok < reconciliation.plugins.len() {
+            println!();
+        }
+        println!("Stray files not belonging to any plugin:");
+        for path in &reconciliation.strays {
+            println!("- {}", path.display());
+        }
+    }
+
+    if num_ok < reconciliation.plugins.len() || !reconciliation.strays.is_empty() {
+        println!();
+    }
+    println!(
+        "{} of {} plugins have been set up correctly, found {} stray files",
+        num_ok,
+        reconciliation.plugins.len(),
+        reconciliation.strays.len()
+    );
+}
@@ -0,0 +1,3956 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The handler for `yabridgectl sync`. Syncing happens in two steps. First we search for plugins
+//! and build a [`SyncPlan`] containing all of the files that should be created, updated or removed,
+//! and then we apply that plan. The plan can also be exported to a file so it can be reviewed and
+//! applied at a later moment.
+
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+use crate::actions::Summary;
+use crate::archives;
+use crate::cache::{
+    self, CopyHashes, ExtractedArchives, HashManifest, InstalledPlugin, InstalledPlugins,
+    PluginIndex, ScanCache,
+};
+use crate::config::{
+    self, yabridge_clap_home, yabridge_vst3_home, Config, InstallationMethod, Profile,
+    YabridgeFiles, YABRIDGE_HOST_32_EXE_NAME,
+};
+use crate::files::{
+    self, ClapPlugin, LibArchitecture, NativeFile, Plugin, SearchResults, Vst2Plugin, Vst3Module,
+};
+use crate::log::{self, logln};
+use crate::utils;
+use crate::utils::{
+    get_file_type, link_host_binaries, verify_path_setup, verify_vst2_search_paths,
+    verify_wine_setup,
+};
+
+/// Options passed to `yabridgectl sync`, see `main()` for the definitions of these options.
+pub struct SyncOptions {
+    pub apply_plan: Option<PathBuf>,
+    /// Ask for confirmation before creating new copies of yabridge's libraries.
+    pub confirm: bool,
+    /// Only check whether the changes can be made with the current permissions, without changing
+    /// anything.
+    pub check_permissions: bool,
+    pub continue_on_error: bool,
+    /// Only list the changes this sync would make, without changing anything. See
+    /// [`SyncPlan::print_changes()`].
+    pub dry_run: bool,
+    pub exclude_arch: BTreeSet<LibArchitecture>,
+    pub export_plan: Option<PathBuf>,
+    /// Only explain why the plugin at this path would or would not be set up, without changing
+    /// anything. See [`explain_file()`].
+    pub explain: Option<PathBuf>,
+    pub extract: bool,
+    /// Don't read directories that have not changed since the last scan. See
+    /// [`files::index_fast()`].
+    pub fast: bool,
+    pub force: bool,
+    /// Match the entries from `--plugins-from` and the active profile against plugin names without
+    /// regard for capitalization.
+    pub ignore_case: bool,
+    /// The number of threads used to hash and set up plugins. Defaults to the number of logical
+    /// CPUs.
+    pub jobs: Option<usize>,
+    /// Write a timestamped log of every step of the sync to this file, see [`crate::log`].
+    pub log_file: Option<PathBuf>,
+    /// Overrides `config.max_plugin_size` for this sync.
+    pub max_size: Option<u64>,
+    pub no_verify: bool,
+    /// Only set up the plugins matching one of these wildcard patterns, see
+    /// [`files::plugin_matches_pattern()`].
+    pub only: Vec<String>,
+    pub plugins_from: Option<PathBuf>,
+    /// Only set up plugins from these vendors, see [`files::plugin_matches_vendor()`].
+    pub vendors: Vec<String>,
+    /// When `vendors` is used, remove the existing installations for plugins from other vendors
+    /// instead of leaving them alone.
+    pub remove_other_vendors: bool,
+    /// When the same plugin is installed in multiple locations, only set up the most recently
+    /// modified version. See [`find_superseded_plugins()`].
+    pub prefer_newer: bool,
+    /// Only set up the plugins in this profile, and remove the existing installations for all other
+    /// plugins. See `Config::profiles`.
+    pub profile: Option<String>,
+    pub prune: bool,
+    /// Treat every problem found while verifying the Wine setup as an error instead of a warning.
+    pub strict_wine: bool,
+    pub target_prefix: Option<PathBuf>,
+    pub verbose: bool,
+    /// Only check whether every plugin has been set up correctly, without changing anything. See
+    /// [`verify_installations()`].
+    pub verify: bool,
+    /// Don't ask for confirmation before removing a large number of plugins, see
+    /// [`confirm_removals()`].
+    pub yes: bool,
+}
+
+/// A list of plugins read from the file passed to `yabridgectl sync --plugins-from <file>`. When
+/// this is used, only the plugins matching one of the entries in this list will be set up, and
+/// everything else is left untouched.
+struct PluginList {
+    /// The file the list was read from, used for reporting.
+    path: PathBuf,
+    /// The entries in the list. An entry can be an absolute path to a plugin, a path relative to a
+    /// plugin directory, a file name, or a plugin name without the file extension.
+    entries: Vec<String>,
+    /// Indices into `entries` of the entries that matched at least one plugin.
+    matched_entries: BTreeSet<usize>,
+    ignore_case: bool,
+}
+
+impl PluginList {
+    /// Read a plugin list. The file should contain one entry per line. Empty lines and lines
+    /// starting with a `#` are ignored.
+    fn read(path: &Path, ignore_case: bool) -> Result<PluginList> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Could not read plugin list at '{}'", path.display()))?;
+
+        Ok(PluginList {
+            path: path.to_owned(),
+            entries: contents
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect(),
+            matched_entries: BTreeSet::new(),
+            ignore_case,
+        })
+    }
+
+    /// Check whether a plugin is in this list, and keep track of the entries that matched.
+    fn matches(&mut self, plugin: &Plugin) -> bool {
+        let plugin_path = plugin.original_path();
+
+        let mut is_match = false;
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if files::plugin_matches_entry(plugin_path, entry, self.ignore_case) {
+                self.matched_entries.insert(idx);
+                is_match = true;
+            }
+        }
+
+        is_match
+    }
+
+    /// Print the number of matched entries, and list the entries that did not match any plugins.
+    fn report(&self) {
+        println!(
+            "Matched {} of {} entries from '{}'",
+            self.matched_entries.len(),
+            self.entries.len(),
+            self.path.display()
+        );
+
+        let unmatched_entries: Vec<&String> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| !self.matched_entries.contains(idx))
+            .map(|(_, entry)| entry)
+            .collect();
+        if !unmatched_entries.is_empty() {
+            utils::warnln!(
+                "\n{}: The following entries did not match any plugins:",
+                "WARNING".red()
+            );
+            for entry in unmatched_entries {
+                utils::warnln!("- {}", entry);
+            }
+        }
+
+        println!();
+    }
+}
+
+/// Why a plugin, or a file that looks like a plugin, was not set up. `yabridgectl sync` reports how
+/// many plugins were skipped for every reason, and `yabridgectl status` shows the reason next to
+/// every plugin that won't be set up.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The `.dll` file turned out to not be a VST2 plugin.
+    NotAPlugin,
+    /// A Windows shortcut pointing to a plugin.
+    Shortcut,
+    /// An archive containing plugins that has not been extracted with `yabridgectl sync --extract`.
+    Archive,
+    /// The plugin's architecture has been excluded.
+    ExcludedArchitecture,
+    /// The plugin's format has been disabled with `yabridgectl set --formats`.
+    DisabledFormat,
+    /// The plugin matches one of the wildcard patterns in the blacklist.
+    Blacklisted,
+    /// The plugin is not part of the active profile.
+    NotInProfile,
+    /// The plugin does not match any of the entries from `yabridgectl sync --plugins-from`.
+    NotInPluginList,
+    /// The plugin is not from one of the vendors passed to `yabridgectl sync --vendor`.
+    OtherVendor,
+    /// The plugin does not match any of the patterns passed to `yabridgectl sync --only`.
+    NotMatchingPattern,
+    /// A more recent version of the plugin is installed elsewhere, see `--prefer-newer`.
+    Superseded,
+    /// The plugin would be copied, but it is larger than the maximum plugin size.
+    Oversized,
+    /// A VST2 plugin in a read-only directory without a target directory.
+    ReadOnlyDirectory,
+    /// The same architecture of the VST3 plugin was already provided by another Wine prefix.
+    DuplicateVst3,
+    /// A VST3 plugin while yabridge has been compiled without VST3 support.
+    NoVst3Support,
+}
+
+impl SkipReason {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SkipReason::NotAPlugin => "not a plugin",
+            SkipReason::Shortcut => "Windows shortcut",
+            SkipReason::Archive => "not extracted",
+            SkipReason::ExcludedArchitecture => "excluded architecture",
+            SkipReason::DisabledFormat => "format disabled",
+            SkipReason::Blacklisted => "blacklisted",
+            SkipReason::NotInProfile => "not in profile",
+            SkipReason::NotInPluginList => "not in plugin list",
+            SkipReason::OtherVendor => "other vendor",
+            SkipReason::NotMatchingPattern => "not matching --only",
+            SkipReason::Superseded => "newer version elsewhere",
+            SkipReason::Oversized => "too large to copy",
+            SkipReason::ReadOnlyDirectory => "read-only directory",
+            SkipReason::DuplicateVst3 => "duplicate VST3 architecture",
+            SkipReason::NoVst3Support => "no VST3 support",
+        }
+    }
+}
+
+/// Format the number of skipped plugins for every reason as a single line, e.g. `excluded
+/// architecture: 2, too large to copy: 1`.
+pub fn format_skip_reasons(counts: &BTreeMap<SkipReason, usize>) -> String {
+    counts
+        .iter()
+        .map(|(reason, count)| format!("{}: {}", reason.description(), count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The size of the plugin's file if it would be set up using a copy and it's larger than
+/// `max_plugin_size`. These plugins are skipped. This only needs to stat the plugin file, so it
+/// doesn't slow down syncing.
+pub fn oversized_plugin_size(
+    config: &Config,
+    plugin: &Plugin,
+    max_plugin_size: Option<u64>,
+) -> Option<u64> {
+    let max_plugin_size = max_plugin_size?;
+    let (plugin_path, plugin_file) = match plugin {
+        Plugin::Vst2(Vst2Plugin { path, .. }) | Plugin::Clap(ClapPlugin { path, .. }) => {
+            (path.clone(), path.clone())
+        }
+        Plugin::Vst3(module) => (
+            module.original_path().to_owned(),
+            module.original_module_path(),
+        ),
+    };
+    if config.method_for(&plugin_path) != InstallationMethod::Copy {
+        return None;
+    }
+
+    fs::metadata(&plugin_file)
+        .ok()
+        .map(|metadata| metadata.len())
+        .filter(|&size| size > max_plugin_size)
+}
+
+/// The filters that decide whether a plugin found in a plugin directory will be set up. `yabridgectl
+/// sync`, `yabridgectl sync --explain`, and `yabridgectl verify-config-against-disk` all use these,
+/// so they always agree on why a plugin is skipped.
+pub struct PluginFilters<'a> {
+    config: &'a Config,
+    /// Plugins with these architectures are skipped, and their existing installations are removed.
+    excluded_architectures: BTreeSet<LibArchitecture>,
+    /// Plugins that are not part of the active profile are treated the same way as plugins with an
+    /// excluded architecture.
+    profile: Option<&'a Profile>,
+    /// The vendors passed to `--vendor`. Plugins from other vendors are skipped.
+    vendors: &'a [String],
+    ignore_case: bool,
+    max_plugin_size: Option<u64>,
+}
+
+impl<'a> PluginFilters<'a> {
+    /// The filters set in the config, optionally limited to the plugins in a profile. These are the
+    /// filters used by a sync without any other command line options.
+    pub fn from_config(config: &'a Config, profile: Option<&str>) -> Result<Self> {
+        let profile = match profile {
+            Some(name) => Some(
+                config
+                    .profiles
+                    .get(name)
+                    .with_context(|| format!("Unknown profile '{}'", name))?,
+            ),
+            None => None,
+        };
+
+        Ok(PluginFilters {
+            config,
+            excluded_architectures: config.excluded_architectures.clone(),
+            profile,
+            vendors: &[],
+            ignore_case: false,
+            max_plugin_size: config.max_plugin_size,
+        })
+    }
+
+    /// The filters for a sync with these options.
+    fn new(config: &'a Config, options: &'a SyncOptions) -> Result<Self> {
+        let mut filters = PluginFilters::from_config(config, options.profile.as_deref())?;
+        filters
+            .excluded_architectures
+            .extend(options.exclude_arch.iter().cloned());
+        filters.vendors = &options.vendors;
+        filters.ignore_case = options.ignore_case;
+        filters.max_plugin_size = options.max_size.or(config.max_plugin_size);
+
+        Ok(filters)
+    }
+
+    /// Whether the plugin is from a vendor other than the ones selected with `--vendor`.
+    fn is_other_vendor(&self, directory: &Path, plugin: &Plugin) -> bool {
+        !self.vendors.is_empty()
+            && !files::plugin_matches_vendor(directory, plugin.original_path(), self.vendors)
+    }
+
+    /// The reason why `plugin` will not be set up, if any. Being superseded by a newer version with
+    /// `--prefer-newer` depends on all other plugins, so that's not checked here.
+    pub fn skip_reason(&self, directory: &Path, plugin: &Plugin) -> Option<SkipReason> {
+        let plugin_path = plugin.original_path();
+        if !self.config.is_format_enabled(plugin.format()) {
+            Some(SkipReason::DisabledFormat)
+        } else if self.config.is_blacklisted(directory, plugin_path) {
+            Some(SkipReason::Blacklisted)
+        } else if self
+            .profile
+            .is_some_and(|profile| !profile.includes(directory, plugin_path, self.ignore_case))
+        {
+            Some(SkipReason::NotInProfile)
+        } else if self.is_other_vendor(directory, plugin) {
+            Some(SkipReason::OtherVendor)
+        } else if self.excluded_architectures.contains(&plugin.architecture()) {
+            Some(SkipReason::ExcludedArchitecture)
+        } else if oversized_plugin_size(self.config, plugin, self.max_plugin_size).is_some() {
+            Some(SkipReason::Oversized)
+        } else {
+            None
+        }
+    }
+}
+
+/// All changes `yabridgectl sync` is going to make to the file system. This can be exported with
+/// `yabridgectl sync --export-plan <file>` and applied later using `--apply-plan <file>`. Every
+/// operation stores the state of the file it's going to modify at the time the plan was made, so we
+/// can refuse to apply a plan when the file system has changed in the meantime.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct SyncPlan {
+    /// The operations to perform, in order.
+    pub operations: Vec<Operation>,
+    /// When set, all files will be installed relative to this directory instead of relative to the
+    /// root of the file system. Set through `yabridgectl sync --target-prefix`. The operations in
+    /// the plan already contain the prefixed paths, so this is not stored in exported plans.
+    #[serde(skip)]
+    pub target_prefix: Option<PathBuf>,
+    /// Hard link copies with the same contents to each other instead of storing every copy
+    /// separately. Set through `config.deduplicate_copies`.
+    #[serde(skip)]
+    pub deduplicate: bool,
+    /// Create symlinks containing a path relative to the symlink's location instead of an absolute
+    /// path. Set through `config.relative_symlinks`.
+    #[serde(skip)]
+    pub relative_symlinks: bool,
+    /// The first copy we came across for every hash, used for deduplicating copies.
+    #[serde(skip)]
+    copies: HashMap<i64, PathBuf>,
+    /// Print the number of completed operations while executing the plan. Only used when writing
+    /// to a terminal.
+    #[serde(skip)]
+    pub show_progress: bool,
+    /// The hashes of existing copies from previous syncs, used to check whether a copy is up to date
+    /// without reading it again. This is empty with `yabridgectl sync --force`.
+    #[serde(skip)]
+    pub copy_hashes: CopyHashes,
+}
+
+/// A single change to the file system made during `yabridgectl sync`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Operation {
+    /// Create a copy of or a symlink to `from` at `to`, replacing whatever was at `to` before.
+    Install {
+        method: InstallationMethod,
+        from: PathBuf,
+        /// The hash of `from` when the plan was made. This is only set for yabridge's own
+        /// libraries, and we use this to count the number of plugins that were set up.
+        from_hash: Option<i64>,
+        to: PathBuf,
+        /// What `to` looked like when the plan was made.
+        existing: FileState,
+        /// When deduplicating copies, a previous copy with the same contents. `to` will be a hard
+        /// link to this file instead of a new copy, unless the two files are on different file
+        /// systems.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        link: Option<PathBuf>,
+        /// When creating relative symlinks, the path relative to `to`'s parent directory the
+        /// symlink should contain instead of `from`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        symlink_target: Option<PathBuf>,
+    },
+    /// Move an existing installation for a plugin that has been moved to a new location. Any
+    /// operations after this one that touch `to` will see the moved files.
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+        /// What `from` looked like when the plan was made.
+        existing: FileState,
+    },
+    /// Remove a file, a symlink, or an entire directory.
+    Remove {
+        path: PathBuf,
+        /// What `path` looked like when the plan was made.
+        existing: FileState,
+    },
+}
+
+/// How an operation in a [`SyncPlan`] changes which plugins are set up, see
+/// [`SyncPlan::changes()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlannedChange {
+    Added,
+    Updated,
+    Removed,
+}
+
+/// The state of a file at the time a plan was made, used to detect whether anything changed between
+/// creating and applying a plan.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileState {
+    Missing,
+    /// A regular file, along with the hash of its contents.
+    File {
+        hash: i64,
+    },
+    Symlink {
+        target: PathBuf,
+    },
+    Directory,
+}
+
+impl FileState {
+    /// Inspect the current state of a file, without following symlinks.
+    pub fn of(path: &Path) -> Result<FileState> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => Ok(FileState::Symlink {
+                target: path
+                    .read_link()
+                    .with_context(|| format!("Could not read symlink '{}'", path.display()))?,
+            }),
+            Ok(metadata) if metadata.file_type().is_dir() => Ok(FileState::Directory),
+            Ok(_) => Ok(FileState::File {
+                hash: utils::hash_file(path)?,
+            }),
+            Err(_) => Ok(FileState::Missing),
+        }
+    }
+
+    /// The same as [`of()`](Self::of()), but regular files are hashed through `copy_hashes` so
+    /// unchanged copies don't need to be read again.
+    pub fn of_cached(path: &Path, copy_hashes: &mut CopyHashes) -> Result<FileState> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_file() => Ok(FileState::File {
+                hash: copy_hashes.hash(path)?,
+            }),
+            _ => FileState::of(path),
+        }
+    }
+}
+
+impl SyncPlan {
+    /// Read a previously exported plan.
+    pub fn read(path: &Path) -> Result<SyncPlan> {
+        let toml_str = fs::read_to_string(path)
+            .with_context(|| format!("Could not read sync plan at '{}'", path.display()))?;
+
+        toml::from_str(&toml_str).with_context(|| format!("Failed to parse '{}'", path.display()))
+    }
+
+    /// Export this plan so it can be applied later with `yabridgectl sync --apply-plan`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
+
+        fs::write(path, toml_str)
+            .with_context(|| format!("Failed to write sync plan to '{}'", path.display()))
+    }
+
+    /// Plan creating a copy of or a symlink to `from` at `to`. Depending on `force`, we might not
+    /// actually create a new copy or symlink if `to` already matches `from_hash`. Returns whether
+    /// the file will be (re)created.
+    pub fn install(
+        &mut self,
+        force: bool,
+        method: InstallationMethod,
+        from: &Path,
+        from_hash: Option<i64>,
+        to: &Path,
+    ) -> Result<bool> {
+        // Symlinks should still point to the file's location on the final system, so only `to` is
+        // prefixed. For the same reason relative symlinks are relative to the unprefixed path.
+        let symlink_target = match (&method, to.parent()) {
+            (InstallationMethod::Symlink, Some(parent)) if self.relative_symlinks => {
+                Some(utils::relative_path(from, parent))
+            }
+            _ => None,
+        };
+        let to = &self.prefixed(to);
+
+        // We'll only recreate existing files when updating yabridge, when switching between the
+        // symlink and copy installation methods, or when the `force` option is set. If the target
+        // file already exists and does not require updating, we'll just skip the file since some
+        // DAWs will otherwise unnecessarily reindex the file. `FileState::of()` doesn't follow
+        // symlinks, so this also works for broken symlinks. If we're going to move an existing
+        // installation to `to`, then we'll compare against that installation instead.
+        let existing =
+            FileState::of_cached(&resolve_moves(&self.operations, to), &mut self.copy_hashes)?;
+
+        // When deduplicating, every copy with the same contents should be a hard link to the first
+        // copy. Existing separate copies will be replaced by hard links.
+        let link = match (&method, from_hash) {
+            (InstallationMethod::Copy, Some(hash)) if self.deduplicate => {
+                match self.copies.get(&hash) {
+                    Some(first_copy) => {
+                        if !force
+                            && existing == (FileState::File { hash })
+                            && is_same_file(first_copy, to)
+                        {
+                            return Ok(false);
+                        }
+
+                        Some(first_copy.clone())
+                    }
+                    None => {
+                        self.copies.insert(hash, to.to_owned());
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        if !force && link.is_none() {
+            match (&method, &existing) {
+                // If the target file is already a real file (not a symlink) and its hash is the same
+                // as that of the `from` file we're trying to copy there, then we don't have to do
+                // anything
+                (InstallationMethod::Copy, FileState::File { hash })
+                    if Some(*hash) == from_hash =>
+                {
+                    return Ok(false);
+                }
+                // If the target file is already a symlink to `from`, then we can skip this file.
+                // Switching between absolute and relative symlinks recreates the symlink.
+                (InstallationMethod::Symlink, FileState::Symlink { target })
+                    if target == symlink_target.as_deref().unwrap_or(from) =>
+                {
+                    return Ok(false);
+                }
+                _ => (),
+            }
+        }
+
+        self.operations.push(Operation::Install {
+            method,
+            from: from.to_owned(),
+            from_hash,
+            to: to.to_owned(),
+            existing,
+            link,
+            symlink_target,
+        });
+
+        Ok(true)
+    }
+
+    /// Get the path `path` would have when using a target prefix.
+    fn prefixed(&self, path: &Path) -> PathBuf {
+        match &self.target_prefix {
+            Some(prefix) => prefix.join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.to_owned(),
+        }
+    }
+
+    /// Plan moving an existing installation from `from` to `to`.
+    pub fn move_file(&mut self, from: &Path, to: &Path) -> Result<()> {
+        self.operations.push(Operation::Move {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            existing: FileState::of(from)?,
+        });
+
+        Ok(())
+    }
+
+    /// Plan removing a file or directory we found during the search.
+    pub fn remove(&mut self, file: &NativeFile) -> Result<()> {
+        self.operations.push(Operation::Remove {
+            path: file.path().to_owned(),
+            existing: FileState::of(file.path())?,
+        });
+
+        Ok(())
+    }
+
+    /// The number of copies or symlinks of yabridge's own libraries this plan will create.
+    pub fn num_new_plugins(&self) -> usize {
+        self.operations
+            .iter()
+            .filter(|operation| {
+                matches!(
+                    operation,
+                    Operation::Install {
+                        from_hash: Some(_),
+                        ..
+                    }
+                )
+            })
+            .count()
+    }
+
+    /// The number of copies this plan will replace with hard links to other copies.
+    pub fn num_hard_links(&self) -> usize {
+        self.operations
+            .iter()
+            .filter(|operation| matches!(operation, Operation::Install { link: Some(_), .. }))
+            .count()
+    }
+
+    /// The number of new copies of yabridge's libraries this plan will create, and the additional
+    /// disk space those copies will take up. Hard links to deduplicated copies and copies replacing
+    /// an existing copy don't take up any additional space, so they are not counted.
+    pub fn new_copies_size(&self) -> (usize, u64) {
+        self.operations
+            .iter()
+            .filter_map(|operation| match operation {
+                Operation::Install {
+                    method: InstallationMethod::Copy,
+                    from,
+                    existing: FileState::Missing | FileState::Symlink { .. },
+                    link: None,
+                    ..
+                } => Some(
+                    fs::metadata(from)
+                        .map(|metadata| metadata.len())
+                        .unwrap_or(0),
+                ),
+                _ => None,
+            })
+            .fold((0, 0), |(num_copies, size), copy_size| {
+                (num_copies + 1, size + copy_size)
+            })
+    }
+
+    /// The existing symlinks to yabridge's libraries this plan will replace because the files they
+    /// point to no longer exist, for instance because yabridge has been moved or uninstalled in
+    /// the meantime.
+    pub fn broken_symlinks(&self) -> Vec<&Path> {
+        self.operations
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, operation)| match operation {
+                Operation::Install {
+                    from_hash: Some(_),
+                    to,
+                    existing,
+                    ..
+                } if is_broken_symlink(&resolve_moves(&self.operations[..idx], to), existing) => {
+                    Some(to.as_path())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The number of existing plugin installations this plan will remove. See
+    /// [`changes()`](Self::changes()).
+    pub fn num_removed_plugins(&self) -> usize {
+        self.changes()
+            .into_iter()
+            .filter(|(change, _)| *change == PlannedChange::Removed)
+            .count()
+    }
+
+    /// The number of existing installations this plan will move to a new location.
+    pub fn num_moved_plugins(&self) -> usize {
+        self.operations
+            .iter()
+            .filter(|operation| matches!(operation, Operation::Move { .. }))
+            .count()
+    }
+
+    /// The operations in this plan that change which plugins are set up. Newly set up plugins count
+    /// as added, while replaced and moved installations count as updated. Operations for the other
+    /// files that are part of a plugin's installation, like the symlinks in merged VST3 bundles, are
+    /// not included.
+    pub fn changes(&self) -> Vec<(PlannedChange, &Operation)> {
+        self.indexed_changes()
+            .into_iter()
+            .map(|(idx, change)| (change, &self.operations[idx]))
+            .collect()
+    }
+
+    /// The same as [`changes()`](Self::changes()), but with the index of the operation in the plan
+    /// instead of a reference to it.
+    fn indexed_changes(&self) -> Vec<(usize, PlannedChange)> {
+        self.operations
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, operation)| match operation {
+                // Files inside of a moved installation have already been counted by the move
+                Operation::Install {
+                    from_hash: Some(_),
+                    to,
+                    ..
+                } if resolve_moves(&self.operations[..idx], to) != *to => None,
+                Operation::Install {
+                    from_hash: Some(_),
+                    existing: FileState::Missing,
+                    ..
+                } => Some((idx, PlannedChange::Added)),
+                Operation::Install {
+                    from_hash: Some(_), ..
+                }
+                | Operation::Move { .. } => Some((idx, PlannedChange::Updated)),
+                Operation::Install { .. } => None,
+                Operation::Remove { .. } => Some((idx, PlannedChange::Removed)),
+            })
+            .collect()
+    }
+
+    /// Summarize the changes in this plan for the summary printed at the end of `yabridgectl
+    /// sync`, see [`changes()`](Self::changes()). `plugin_operations` contains the range of
+    /// operations in the plan for every plugin that was set up, and the plugins without any
+    /// changes in their range are counted as skipped since they were already up to date.
+    pub fn summary(
+        &self,
+        plugin_operations: &[(PathBuf, Range<usize>)],
+        num_failed: usize,
+    ) -> Summary {
+        let mut summary = Summary {
+            failed: num_failed,
+            ..Summary::default()
+        };
+        let changes = self.indexed_changes();
+        for (_, change) in &changes {
+            match change {
+                PlannedChange::Added => summary.added += 1,
+                PlannedChange::Updated => summary.updated += 1,
+                PlannedChange::Removed => summary.removed += 1,
+            }
+        }
+        summary.skipped = plugin_operations
+            .iter()
+            .filter(|(_, operations)| !changes.iter().any(|(idx, _)| operations.contains(idx)))
+            .count();
+
+        summary
+    }
+
+    /// List the changes in this plan grouped by the kind of change, for `yabridgectl sync
+    /// --dry-run`. See [`changes()`](Self::changes()).
+    pub fn print_changes(&self) {
+        let changes = self.changes();
+        if changes.is_empty() {
+            println!("Everything is up to date");
+            return;
+        }
+
+        for (kind, description) in [
+            (PlannedChange::Added, "Would set up"),
+            (PlannedChange::Updated, "Would update"),
+            (PlannedChange::Removed, "Would remove"),
+        ] {
+            let operations: Vec<&Operation> = changes
+                .iter()
+                .filter(|(change, _)| *change == kind)
+                .map(|(_, operation)| *operation)
+                .collect();
+            if operations.is_empty() {
+                continue;
+            }
+
+            println!("{} {} file(s):", description, operations.len());
+            for operation in operations {
+                match operation {
+                    Operation::Install { to, .. } => println!("- {}", to.display()),
+                    Operation::Move { from, to, .. } => {
+                        println!("- {} (moved from {})", to.display(), from.display())
+                    }
+                    Operation::Remove { path, .. } => println!("- {}", path.display()),
+                }
+            }
+            println!();
+        }
+    }
+
+    /// Check whether the file system still looks the same as it did when this plan was made. This
+    /// will return an error listing all of the changed files if it doesn't, in which case the plan
+    /// should not be applied.
+    pub fn verify(&self) -> Result<()> {
+        let mut changed_files: Vec<String> = Vec::new();
+        for (idx, operation) in self.operations.iter().enumerate() {
+            // Files inside of moved installations are checked at their current location
+            let resolve = |path: &Path| resolve_moves(&self.operations[..idx], path);
+            match operation {
+                Operation::Install {
+                    from,
+                    from_hash,
+                    to,
+                    existing,
+                    ..
+                } => {
+                    // The files in the extra target directories link to files created earlier in
+                    // the plan
+                    let is_planned = self.operations[..idx].iter().any(|operation| {
+                        matches!(operation, Operation::Install { to, .. } if to == from)
+                    });
+                    match (from_hash, from.exists() || is_planned) {
+                        (_, false) => {
+                            changed_files.push(format!("'{}' no longer exists", from.display()))
+                        }
+                        (Some(hash), true) if utils::hash_file(from)? != *hash => {
+                            changed_files.push(format!("'{}' has been modified", from.display()))
+                        }
+                        _ => (),
+                    }
+
+                    if FileState::of(&resolve(to))? != *existing {
+                        changed_files.push(format!("'{}' has been modified", to.display()));
+                    }
+                }
+                Operation::Move { from, to, existing } => {
+                    if FileState::of(&resolve(from))? != *existing {
+                        changed_files.push(format!("'{}' has been modified", from.display()));
+                    }
+                    if FileState::of(&resolve(to))? != FileState::Missing {
+                        changed_files.push(format!("'{}' already exists", to.display()));
+                    }
+                }
+                Operation::Remove { path, existing } => {
+                    if FileState::of(&resolve(path))? != *existing {
+                        changed_files.push(format!("'{}' has been modified", path.display()));
+                    }
+                }
+            }
+        }
+
+        for changed_file in &changed_files {
+            logln!("Plan verification failed: {}", changed_file);
+        }
+        if changed_files.is_empty() {
+            logln!("Verified {} planned change(s)", self.operations.len());
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "The file system has changed since this plan was created, refusing to apply it:\n{}",
+                changed_files
+                    .iter()
+                    .map(|change| format!("- {}", change))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ))
+        }
+    }
+
+    /// Apply all operations in this plan. This stops at the first operation that fails. See
+    /// [`SyncPlan::phases()`] for the order operations are applied in.
+    pub fn execute(&self) -> Result<()> {
+        let progress = Progress::new(self.show_progress, self.operations.len());
+        let [moves, operations, links] = self.phases();
+        for operation in moves {
+            progress.run(operation)?;
+        }
+        operations
+            .into_par_iter()
+            .try_for_each(|operation| progress.run(operation))?;
+        links
+            .into_par_iter()
+            .try_for_each(|operation| progress.run(operation))?;
+        progress.finish();
+
+        Ok(())
+    }
+
+    /// Split the operations into the three phases they're applied in. Moves are applied first and
+    /// in order, since other operations may touch the moved files. All other operations touch
+    /// different files, so they can be applied in parallel. Hard links to deduplicated copies are
+    /// created last since they need the first copy to exist.
+    fn phases(&self) -> [Vec<&Operation>; 3] {
+        let mut phases = [Vec::new(), Vec::new(), Vec::new()];
+        for operation in &self.operations {
+            let phase = match operation {
+                Operation::Move { .. } => 0,
+                Operation::Install { link: Some(_), .. } => 2,
+                _ => 1,
+            };
+            phases[phase].push(operation);
+        }
+
+        phases
+    }
+
+    /// Check whether every operation in this plan can be performed with the current user's
+    /// permissions, without changing anything. This returns a description for every problem, so
+    /// they can all be reported at once instead of failing halfway through a sync.
+    pub fn check_permissions(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        // Many operations happen in the same directories
+        let mut checked_dirs: HashMap<PathBuf, bool> = HashMap::new();
+        let mut is_writable = |directory: &Path| {
+            *checked_dirs
+                .entry(directory.to_owned())
+                .or_insert_with(|| utils::is_writable_dir(directory))
+        };
+
+        for operation in &self.operations {
+            // The existing file that will be removed or replaced, and the new file that will be
+            // created
+            let (replaced, created): (Option<(&str, &Path)>, Option<&Path>) = match operation {
+                Operation::Install {
+                    to,
+                    existing: FileState::Missing,
+                    ..
+                } => (None, Some(to)),
+                Operation::Install { to, .. } => (Some(("replace", to)), None),
+                Operation::Move { from, to, .. } => (Some(("move", from)), Some(to)),
+                Operation::Remove {
+                    existing: FileState::Missing,
+                    ..
+                } => (None, None),
+                Operation::Remove { path, .. } => (Some(("remove", path)), None),
+            };
+
+            if let Some((action, path)) = replaced {
+                let directory = path.parent().unwrap();
+                if !is_writable(directory) {
+                    problems.push(format!(
+                        "Cannot {} '{}', '{}' is not writable",
+                        action,
+                        path.display(),
+                        directory.display()
+                    ));
+                } else if !utils::can_replace(path) {
+                    problems.push(format!(
+                        "Cannot {} '{}', it is owned by another user",
+                        action,
+                        path.display()
+                    ));
+                }
+            }
+            if let Some(path) = created {
+                // Missing parent directories will be created, so the first existing ancestor needs
+                // to be writable
+                let directory = existing_ancestor(path);
+                if !is_writable(&directory) {
+                    problems.push(format!(
+                        "Cannot create '{}', '{}' is not writable",
+                        path.display(),
+                        directory.display()
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Apply all operations in this plan, in order, without stopping when an operation fails.
+    /// Returns the errors for all failed operations. Used for `yabridgectl sync
+    /// --continue-on-error`.
+    pub fn execute_all(&self) -> Vec<anyhow::Error> {
+        let progress = Progress::new(self.show_progress, self.operations.len());
+        let [moves, operations, links] = self.phases();
+        let mut failures: Vec<anyhow::Error> = moves
+            .into_iter()
+            .filter_map(|operation| progress.run(operation).err())
+            .collect();
+        for phase in [operations, links] {
+            // Rayon keeps the original order when collecting, so the errors are always reported in
+            // the same order
+            failures.extend(
+                phase
+                    .into_par_iter()
+                    .filter_map(|operation| progress.run(operation).err())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        progress.finish();
+
+        failures
+    }
+}
+
+/// Counts the operations applied while executing a [`SyncPlan`], printing `completed/total` to
+/// STDERR as it goes when enabled.
+struct Progress {
+    enabled: bool,
+    completed: AtomicUsize,
+    total: usize,
+}
+
+impl Progress {
+    fn new(enabled: bool, total: usize) -> Progress {
+        Progress {
+            enabled,
+            completed: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    /// Apply an operation and update the progress indicator.
+    fn run(&self, operation: &Operation) -> Result<()> {
+        let result = operation.execute();
+        let completed = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.enabled {
+            eprint!("\rApplying changes: {}/{}", completed, self.total);
+        }
+
+        result
+    }
+
+    /// Move past the progress indicator once all operations have been applied.
+    fn finish(&self) {
+        if self.enabled && self.completed.load(Ordering::Relaxed) > 0 {
+            eprintln!();
+        }
+    }
+}
+
+impl Operation {
+    /// Perform this operation.
+    pub fn execute(&self) -> Result<()> {
+        match self {
+            Operation::Install {
+                method,
+                from,
+                to,
+                existing,
+                link,
+                symlink_target,
+                ..
+            } => {
+                logln!(
+                    "Installing '{}' to '{}' using {} (existing: {:?})",
+                    from.display(),
+                    to.display(),
+                    method,
+                    existing
+                );
+
+                // Copies are moved over the existing file instead, so an interrupted sync can never
+                // leave behind a partially written library
+                let replaces_atomically = *method == InstallationMethod::Copy && link.is_none();
+                match existing {
+                    FileState::Missing => utils::create_dir_all(to.parent().unwrap())?,
+                    FileState::Directory => utils::remove_dir_all(to)?,
+                    FileState::File { .. } | FileState::Symlink { .. } if replaces_atomically => (),
+                    FileState::File { .. } | FileState::Symlink { .. } => utils::remove_file(to)?,
+                }
+
+                match (method, link) {
+                    // Hard links can't cross file system boundaries, so we'll fall back to a
+                    // regular copy if this doesn't work
+                    (InstallationMethod::Copy, Some(link)) => {
+                        if let Err(err) = utils::hard_link(link, to) {
+                            logln!(
+                                "Could not hard link '{}' to '{}', copying instead: {:#}",
+                                link.display(),
+                                to.display(),
+                                err
+                            );
+                            utils::copy_atomically(from, to)?;
+                        }
+                    }
+                    (InstallationMethod::Copy, None) => {
+                        utils::copy_atomically(from, to)?;
+                    }
+                    (InstallationMethod::Symlink, _) => {
+                        utils::symlink(symlink_target.as_ref().unwrap_or(from), to)?;
+                    }
+                }
+            }
+            Operation::Move { from, to, .. } => {
+                logln!("Moving '{}' to '{}'", from.display(), to.display());
+                utils::create_dir_all(to.parent().unwrap())?;
+                utils::move_path(from, to)?;
+            }
+            Operation::Remove { path, existing } => {
+                logln!("Removing '{}' (existing: {:?})", path.display(), existing);
+                match existing {
+                    FileState::Missing => (),
+                    FileState::Directory => utils::remove_dir_all(path)?,
+                    FileState::File { .. } | FileState::Symlink { .. } => utils::remove_file(path)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Describe what's wrong with the file this operation would change, or `None` if the operation
+    /// would not actually change anything. Used for `yabridgectl sync --verify`.
+    fn problem(&self) -> Option<&'static str> {
+        match self {
+            Operation::Install {
+                method: InstallationMethod::Symlink,
+                from,
+                existing: FileState::Symlink { target },
+                symlink_target,
+                ..
+            } if target == symlink_target.as_ref().unwrap_or(from) => None,
+            Operation::Install {
+                method: InstallationMethod::Copy,
+                from_hash: Some(hash),
+                existing:
+                    FileState::File {
+                        hash: existing_hash,
+                    },
+                ..
+            } if existing_hash == hash => None,
+            Operation::Install {
+                existing: FileState::Missing,
+                ..
+            } => Some("missing"),
+            Operation::Install { to, existing, .. } if is_broken_symlink(to, existing) => {
+                Some("broken symlink")
+            }
+            Operation::Install {
+                method: InstallationMethod::Symlink,
+                existing: FileState::Symlink { .. },
+                ..
+            }
+            | Operation::Move { .. } => Some("wrong target"),
+            Operation::Install { .. } => Some("stale"),
+            Operation::Remove { .. } => Some("leftover"),
+        }
+    }
+}
+
+/// Whether `existing`, the state of the file at `path`, is a symlink pointing to a file that does
+/// not exist. Relative symlinks are resolved relative to `path`'s parent directory.
+fn is_broken_symlink(path: &Path, existing: &FileState) -> bool {
+    match (existing, path.parent()) {
+        (FileState::Symlink { target }, Some(parent)) => !parent.join(target).exists(),
+        _ => false,
+    }
+}
+
+/// The closest ancestor of `path` that exists, which is where `utils::create_dir_all()` will create
+/// the first new directory for `path`.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_dir())
+        .unwrap_or_else(|| Path::new("/"))
+        .to_owned()
+}
+
+/// Check whether `path` and `other_path` are hard links to the same file. Returns false if either
+/// file doesn't exist.
+fn is_same_file(path: &Path, other_path: &Path) -> bool {
+    match (fs::symlink_metadata(path), fs::symlink_metadata(other_path)) {
+        (Ok(metadata), Ok(other_metadata)) => {
+            metadata.dev() == other_metadata.dev() && metadata.ino() == other_metadata.ino()
+        }
+        _ => false,
+    }
+}
+
+/// Find where `path` currently is, taking into account the `Move` operations in `operations` that
+/// would move `path` or one of its parent directories.
+fn resolve_moves(operations: &[Operation], path: &Path) -> PathBuf {
+    operations
+        .iter()
+        .rev()
+        .find_map(|operation| match operation {
+            Operation::Move { from, to, .. } if path == to => Some(from.clone()),
+            Operation::Move { from, to, .. } => path
+                .strip_prefix(to)
+                .ok()
+                .map(|relative_path| from.join(relative_path)),
+            _ => None,
+        })
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Set up yabridge for all Windows VST2 plugins in the plugin directories. Will also remove orphan
+/// `.so` files if the prune option is set.
+pub fn do_sync(config: &mut Config, options: &SyncOptions) -> Result<Summary> {
+    if let Some(path) = &options.log_file {
+        log::open(path)?;
+    }
+
+    // Searching, hashing, and setting up plugins all happen on rayon's thread pool, so `--jobs`
+    // limits all of those
+    match options.jobs {
+        Some(jobs) => rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .context("Could not create the thread pool")?
+            .install(|| sync_plugins(config, options)),
+        None => sync_plugins(config, options),
+    }
+}
+
+/// The implementation for [`do_sync()`], running on the configured thread pool.
+fn sync_plugins(config: &mut Config, options: &SyncOptions) -> Result<Summary> {
+    if let Some(plan_path) = &options.apply_plan {
+        return apply_plan(config, plan_path, options);
+    }
+    if let Some(path) = &options.explain {
+        return explain_file(config, options, path);
+    }
+
+    for (directory, parent) in config::nested_plugin_dirs(&config.plugin_dirs) {
+        utils::warnln!(
+            "{}\n",
+            utils::wrap(&format!(
+                "Warning: Plugin directory '{}' is inside of plugin directory '{}', so its \
+                 plugins may be set up twice. Use 'yabridgectl rm {}' to remove the nested \
+                 directory.",
+                directory.display(),
+                parent.display(),
+                directory.display()
+            ))
+        );
+    }
+
+    let files: YabridgeFiles = config.files()?;
+    // If this version of yabridge uses chainloaders, then these will be the chainloaders instead of
+    // the actual libraries
+    let vst2_library = files.vst2_library();
+    let vst3_library = files.vst3_library();
+    let libyabridge_vst2_hash = utils::hash_file(vst2_library)?;
+    let libyabridge_vst3_hash = match vst3_library {
+        Some(path) => Some(utils::hash_file(path)?),
+        None => None,
+    };
+    // The error for a missing CLAP library is only returned once we come across a CLAP plugin
+    let clap_library = files.clap_library().ok();
+    let libyabridge_clap_hash = match clap_library {
+        Some(path) => Some(utils::hash_file(path)?),
+        None => None,
+    };
+
+    let libraries: Vec<(&str, &Path)> = [
+        ("VST2", Some(vst2_library)),
+        ("VST3", vst3_library),
+        ("CLAP", clap_library),
+    ]
+    .iter()
+    .filter_map(|(format, library)| library.map(|library| (*format, library)))
+    .collect();
+    let (last_format, other_formats) = libraries.split_last().unwrap();
+    if other_formats.is_empty() {
+        println!("Setting up {} plugins using:", last_format.0);
+    } else {
+        println!(
+            "Setting up {} and {} plugins using:",
+            other_formats
+                .iter()
+                .map(|(format, _)| *format)
+                .collect::<Vec<_>>()
+                .join(", "),
+            last_format.0
+        );
+    }
+    for (_, library) in &libraries {
+        println!("- {}", library.display());
+    }
+    println!();
+
+    // Plugins using another build of yabridge are symlinked to that build's libraries instead,
+    // keyed by the directory containing the build. See `config.plugin_hosts`.
+    let mut host_libraries: BTreeMap<PathBuf, HostLibraries> = BTreeMap::new();
+    for directory in config.plugin_hosts.values() {
+        if !host_libraries.contains_key(directory) {
+            host_libraries.insert(directory.clone(), HostLibraries::new(config, directory)?);
+        }
+    }
+
+    // Plugins distributed as archives are extracted first, so the regular search will pick them up
+    if options.extract {
+        let num_extracted = archives::extract_archives(config, options.prune)?;
+        if num_extracted > 0 {
+            println!("Extracted {} archives\n", num_extracted);
+        }
+    }
+
+    // None of the modes that only report on what a sync would do should create or modify any files,
+    // and that includes yabridgectl's own caches
+    let update_caches = !(options.dry_run || options.verify || options.check_permissions);
+
+    // Files that haven't changed since the last sync don't need to be classified again, unless
+    // we're forcing everything to be set up from scratch
+    let plugin_index = if options.force {
+        PluginIndex::default()
+    } else {
+        PluginIndex::read()
+    };
+
+    // Once `--fast` has been used, regular syncs will also update the scan cache so any changes
+    // missed by a fast scan are picked up again
+    let results = if options.fast || ScanCache::exists() {
+        let previous = if options.fast {
+            ScanCache::read()
+        } else {
+            ScanCache::default()
+        };
+        let (results, scan_cache) = config
+            .search_directories_fast(&previous, &plugin_index)
+            .context("Failure while searching for plugins")?;
+        if update_caches {
+            scan_cache.write()?;
+        }
+
+        results
+    } else {
+        config
+            .search_directories(&plugin_index)
+            .context("Failure while searching for plugins")?
+    };
+    if log::is_enabled() {
+        log_search_results(&results);
+    }
+    if update_caches {
+        PluginIndex {
+            files: results
+                .values()
+                .flat_map(|search_results| search_results.indexed_files.clone())
+                .collect(),
+        }
+        .write()?;
+    }
+
+    // When installing to a target prefix we won't touch anything on the live system, so we'll only
+    // create new files and we won't move or remove any existing files
+    let is_live_install = options.target_prefix.is_none();
+
+    // Plugins we've set up during previous syncs. If a plugin has been moved to another location,
+    // then we'll move its existing installation instead of creating a new one.
+    let mut installed_plugins = InstalledPlugins::read();
+    let mut new_installed_plugins: BTreeMap<PathBuf, InstalledPlugin> = BTreeMap::new();
+    // Existing installations can only be moved if their plugin no longer exists and if the
+    // installation is not going to be used for any other plugin
+    let planned_targets: HashSet<PathBuf> = results
+        .values()
+        .flat_map(|search_results| &search_results.plugins)
+        .map(|plugin| match plugin {
+            Plugin::Vst2(Vst2Plugin { target_path, .. }) => target_path.clone(),
+            Plugin::Vst3(module) => module.target_bundle_home(),
+            Plugin::Clap(clap_plugin) => clap_plugin.target_path(),
+        })
+        .collect();
+    let mut move_candidates: Vec<InstalledPlugin> = installed_plugins
+        .plugins
+        .iter()
+        .filter(|(plugin_path, installed)| {
+            is_live_install
+                && !plugin_path.exists()
+                && get_file_type(installed.target.clone()).is_some()
+                && !planned_targets.contains(&installed.target)
+        })
+        .map(|(_, installed)| installed.clone())
+        .collect();
+    // The existing installations we're going to move, as `(from, to)` pairs
+    let mut moved_installations: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    // When syncing only a subset of plugins we'll leave all other plugins and files alone
+    let mut plugin_list = match &options.plugins_from {
+        Some(path) => Some(PluginList::read(path, options.ignore_case)?),
+        None => None,
+    };
+    let filter_vendors = !options.vendors.is_empty() && !options.remove_other_vendors;
+    let is_partial_sync = plugin_list.is_some() || filter_vendors || !options.only.is_empty();
+    // The number of plugins matching `--vendor`
+    let mut num_vendor_matches = 0;
+    // The number of 32-bit plugins we set up. These can't be loaded if yabridge has been built
+    // without `yabridge-host-32.exe`.
+    let mut num_32bit_plugins = 0;
+
+    // Plugins with an excluded architecture, a disabled format, and so on are skipped, and their
+    // existing installations are removed
+    let filters = PluginFilters::new(config, options)?;
+
+    // All changes we're going to make will be added to this plan first
+    let mut plan = SyncPlan {
+        target_prefix: options.target_prefix.clone(),
+        deduplicate: config.deduplicate_copies,
+        relative_symlinks: config.relative_symlinks,
+        show_progress: !utils::is_quiet() && unsafe { libc::isatty(libc::STDERR_FILENO) } == 1,
+        // With `--force` every copy is hashed again, which also refreshes the cache
+        copy_hashes: if options.force {
+            CopyHashes::default()
+        } else {
+            CopyHashes::read()
+        },
+        ..SyncPlan::default()
+    };
+
+    // Keep track of some global statistics
+    // The number of plugins we set up yabridge for
+    let mut num_installed = 0;
+    // The number of plugins we skipped because of `filters`, other than oversized plugins. The
+    // number of plugins per reason is added to `skip_reasons`.
+    let mut num_filtered = 0;
+    // The number of plugins skipped for every reason. Some of these are only added at the end.
+    let mut skip_reasons: BTreeMap<SkipReason, usize> = BTreeMap::new();
+    // Plugins that would be set up using copies but that are larger than the maximum plugin size,
+    // along with their sizes. Existing installations for these plugins are left alone.
+    let max_plugin_size = options.max_size.or(config.max_plugin_size);
+    let mut oversized_plugins: Vec<(PathBuf, u64)> = Vec::new();
+    let mut oversized_vst3_bundles: BTreeSet<PathBuf> = BTreeSet::new();
+    // Whether a plugin will not be set up because of one of the filters
+    let is_filtered =
+        |directory: &Path, plugin: &Plugin| filters.skip_reason(directory, plugin).is_some();
+    // With `--prefer-newer`, plugins that have a more recent version elsewhere are skipped. These
+    // map the skipped plugin's path to the path of the version we'll set up instead.
+    let superseded_plugins = if options.prefer_newer {
+        find_superseded_plugins(&results, is_filtered)
+    } else {
+        BTreeMap::new()
+    };
+    let mut num_superseded = 0;
+    let is_skipped = |directory: &Path, plugin: &Plugin| {
+        is_filtered(directory, plugin) || superseded_plugins.contains_key(plugin.original_path())
+    };
+
+    // Two plugins would end up at the same location when plugin directories share a target
+    // directory, and the second plugin would overwrite the first one. The same thing happens when
+    // two plugins only differ in case and their target directory is on a case insensitive file
+    // system, like a FAT32 or exFAT partition. This is reported before making any changes. With
+    // `--continue-on-error` only the first plugin will be set up, and this maps the paths of the
+    // other plugins to their target paths.
+    // The plugins are also set up in their directories' extra target directories, so those are
+    // checked for collisions as well.
+    let mut mirrored_plugins: Vec<Plugin> = Vec::new();
+    for (path, search_results) in &results {
+        for target in config.extra_target_dirs(path)? {
+            for plugin in &search_results.plugins {
+                if !is_skipped(path, plugin) {
+                    let mut mirror = plugin.clone();
+                    mirror.set_target(path, &target);
+                    mirrored_plugins.push(mirror);
+                }
+            }
+        }
+    }
+    let mut case_insensitive_dirs: HashMap<PathBuf, bool> = HashMap::new();
+    let target_collisions = find_target_collisions(
+        results
+            .iter()
+            .flat_map(|(path, search_results)| {
+                search_results
+                    .plugins
+                    .iter()
+                    .filter(move |plugin| !is_skipped(path, plugin))
+            })
+            .chain(&mirrored_plugins),
+        |directory| {
+            *case_insensitive_dirs
+                .entry(directory.to_owned())
+                .or_insert_with(|| utils::is_case_insensitive(directory))
+        },
+    );
+    let mut colliding_plugins: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for (target_path, plugin_paths) in &target_collisions {
+        let is_case_insensitive = target_path
+            .parent()
+            .and_then(|directory| case_insensitive_dirs.get(directory))
+            .copied()
+            .unwrap_or(false);
+        utils::warnln!(
+            "{}",
+            utils::wrap(&format!(
+                "{}: These plugins would all be set up at '{}'{}:",
+                "WARNING".red(),
+                target_path.display(),
+                if is_case_insensitive {
+                    ", since that directory is on a case insensitive file system"
+                } else {
+                    ""
+                }
+            ))
+        );
+        for plugin_path in plugin_paths {
+            utils::warnln!("- {}", plugin_path.display());
+        }
+        utils::warnln!();
+
+        colliding_plugins.extend(
+            plugin_paths
+                .iter()
+                .skip(1)
+                .map(|plugin_path| (plugin_path.clone(), target_path.clone())),
+        );
+    }
+    if !target_collisions.is_empty() && !options.continue_on_error {
+        return Err(anyhow!(
+            "Found {} plugins that would be set up at the same location as another plugin. Use \
+             'yabridgectl set <path> --target <path>' to set up one of these plugin directories \
+             in another location, or rerun with '--continue-on-error' to only set up the first \
+             plugin.",
+            colliding_plugins.len()
+        ));
+    }
+
+    // Hashing plugins can fail for individual plugins. With `--continue-on-error` those plugins are
+    // skipped and the errors are reported together with the other failures at the end.
+    let mut planning_failures: Vec<anyhow::Error> = Vec::new();
+    // Existing installations for plugins with an excluded architecture. These will always be
+    // removed.
+    let mut excluded_files: Vec<NativeFile> = Vec::new();
+    // The files we skipped during the scan because they turned out to not be plugins
+    let mut skipped_dll_files: Vec<PathBuf> = Vec::new();
+    // Windows shortcuts we found that point to plugins, along with their targets. These can't be
+    // set up, but the user may have added the shortcuts' directory by mistake.
+    let mut plugin_shortcuts: Vec<(PathBuf, String)> = Vec::new();
+    // Archives containing plugins. These are only extracted when using the `--extract` option.
+    let mut extracted_archives = ExtractedArchives::read();
+    let mut plugin_archives: Vec<PathBuf> = Vec::new();
+    // `.so` files and unused VST3 modules we found during scanning that didn't have a corresponding
+    // copy or symlink of `libyabridge-vst2.so`
+    let mut orphan_files: Vec<NativeFile> = Vec::new();
+    // All the VST3 modules we have set up yabridge for. We need this to detect leftover VST3
+    // modules in `~/.vst3/yabridge`.
+    let mut yabridge_vst3_bundles: BTreeMap<PathBuf, BTreeSet<LibArchitecture>> = BTreeMap::new();
+    // VST3 modules with an excluded architecture. If we did not set up any other architecture for
+    // the module's merged bundle, then the entire bundle will be removed.
+    // These are stored along with the module's symlinks in the extra target directories.
+    let mut excluded_vst3_modules: Vec<(Vst3Module, Vec<PathBuf>)> = Vec::new();
+    // The operations in the plan for every plugin we set up, used for `--verify`
+    let mut plugin_operations: Vec<(PathBuf, Range<usize>)> = Vec::new();
+    // The merged VST3 bundles we symlinked a Windows VST3 bundle's `Resources` directory to
+    let mut vst3_resources_bundles: BTreeSet<PathBuf> = BTreeSet::new();
+    // The paths of all `.so` files for VST2 plugins, including those that have been excluded. Used
+    // to detect leftover files in target directories set with `yabridgectl set --target`.
+    let mut vst2_target_paths: HashSet<PathBuf> = HashSet::new();
+    // The same for the `.clap` files for CLAP plugins, used to detect leftover files in
+    // `~/.clap/yabridge` and in the target directories
+    let mut clap_target_paths: HashSet<PathBuf> = HashSet::new();
+    // The merged VST3 bundles symlinked to the extra target directories, used to detect leftover
+    // bundles in those directories
+    let mut extra_target_bundles: BTreeSet<PathBuf> = BTreeSet::new();
+    // The symlinks in the extra target directories we already planned. A merged VST3 bundle can
+    // contain multiple modules, but it only needs to be symlinked once.
+    let mut planned_extra_links: HashSet<PathBuf> = HashSet::new();
+    // The target directory overrides and the extra target directories for the plugin directories
+    // we searched through
+    let mut target_dirs: BTreeSet<PathBuf> = BTreeSet::new();
+    // The plugin directories we set up VST2 plugins in, or their target directories if they have
+    // one. Used to check whether those directories will be picked up by the host.
+    let mut vst2_directories: Vec<PathBuf> = Vec::new();
+    for (path, mut search_results) in results {
+        if let Some(plugin_list) = &mut plugin_list {
+            let num_plugins = search_results.plugins.len();
+            search_results
+                .plugins
+                .retain(|plugin| plugin_list.matches(plugin));
+            *skip_reasons.entry(SkipReason::NotInPluginList).or_default() +=
+                num_plugins - search_results.plugins.len();
+        }
+        if filter_vendors {
+            let num_plugins = search_results.plugins.len();
+            search_results.plugins.retain(|plugin| {
+                files::plugin_matches_vendor(path, plugin.original_path(), &options.vendors)
+            });
+            *skip_reasons.entry(SkipReason::OtherVendor).or_default() +=
+                num_plugins - search_results.plugins.len();
+        }
+        if !options.only.is_empty() {
+            let num_plugins = search_results.plugins.len();
+            search_results.plugins.retain(|plugin| {
+                options.only.iter().any(|pattern| {
+                    files::plugin_matches_pattern(path, plugin.original_path(), pattern)
+                })
+            });
+            *skip_reasons
+                .entry(SkipReason::NotMatchingPattern)
+                .or_default() += num_plugins - search_results.plugins.len();
+        }
+
+        let target_dir = config.target_dir(path)?;
+        let extra_targets = config.extra_target_dirs(path)?;
+        let hash_manifest = HashManifest::read(path);
+
+        // VST2 plugins are set up next to their `.dll` files, which isn't possible on a read-only
+        // Windows partition. `yabridgectl add` sets a target directory for those, but the partition
+        // may also have been remounted read-only later.
+        let has_vst2_plugins = search_results
+            .plugins
+            .iter()
+            .any(|plugin| matches!(plugin, Plugin::Vst2(_)));
+        if has_vst2_plugins
+            && target_dir.is_none()
+            && is_live_install
+            && matches!(
+                utils::mount_info(path),
+                Some(utils::MountInfo {
+                    read_only: true,
+                    ..
+                })
+            )
+        {
+            utils::warnln!(
+                "{}\n",
+                utils::wrap(&format!(
+                    "{}: '{}' is on a read-only file system, skipping its VST2 plugins. Use \
+                     'yabridgectl set {} --target <path>' to set them up in another directory.",
+                    "WARNING".red(),
+                    path.display(),
+                    path.display()
+                ))
+            );
+            let num_plugins = search_results.plugins.len();
+            search_results
+                .plugins
+                .retain(|plugin| !matches!(plugin, Plugin::Vst2(_)));
+            *skip_reasons
+                .entry(SkipReason::ReadOnlyDirectory)
+                .or_default() += num_plugins - search_results.plugins.len();
+        }
+
+        // Hashing the plugins is by far the slowest part of planning a sync, so this is done for all
+        // plugins in the directory at once before we go through them one by one. Plugins that are
+        // going to be skipped are not hashed. The hashes are only used to find the existing
+        // installations of moved plugins, so plugins that have already been set up aren't hashed
+        // either.
+        let mut installed_entries: HashMap<PathBuf, Result<InstalledPlugin>> = search_results
+            .plugins
+            .par_iter()
+            .filter(|plugin| !is_skipped(path, plugin))
+            .map(|plugin| {
+                let (hash_path, target) = match plugin {
+                    Plugin::Vst2(Vst2Plugin {
+                        path, target_path, ..
+                    }) => (path.clone(), target_path.clone()),
+                    Plugin::Vst3(module) => {
+                        (module.original_module_path(), module.target_bundle_home())
+                    }
+                    Plugin::Clap(clap_plugin) => {
+                        (clap_plugin.path.clone(), clap_plugin.target_path())
+                    }
+                };
+                let previous = installed_plugins.plugins.get(plugin.original_path());
+                let installed = if get_file_type(target.clone()).is_some() {
+                    InstalledPlugin::without_hash(&hash_path, target, previous)
+                } else {
+                    InstalledPlugin::new(
+                        &hash_path,
+                        target,
+                        previous,
+                        hash_manifest.as_ref().map(|manifest| (path, manifest)),
+                    )
+                };
+
+                (plugin.original_path().to_owned(), installed)
+            })
+            .collect();
+
+        num_installed += search_results.plugins.len();
+        if search_results
+            .plugins
+            .iter()
+            .any(|plugin| matches!(plugin, Plugin::Vst2(_)))
+        {
+            vst2_directories.push(target_dir.clone().unwrap_or_else(|| path.to_owned()));
+            vst2_directories.extend(extra_targets.iter().cloned());
+        }
+        target_dirs.extend(target_dir);
+        target_dirs.extend(extra_targets.iter().cloned());
+        if !is_partial_sync && is_live_install {
+            orphan_files.extend(search_results.vst2_orphans().into_iter().cloned());
+        }
+        skipped_dll_files.extend(search_results.skipped_files);
+        plugin_shortcuts.extend(
+            search_results
+                .shortcut_files
+                .into_iter()
+                .filter_map(|path| {
+                    let target = files::read_plugin_shortcut_target(&path)?;
+                    Some((path, target))
+                }),
+        );
+        if !options.extract {
+            plugin_archives.extend(search_results.archive_files.into_iter().filter(|archive| {
+                !extracted_archives.archives.contains_key(archive)
+                    && archives::contains_plugins(archive, &mut extracted_archives).unwrap_or(false)
+            }));
+        }
+
+        if options.verbose {
+            // Always print these paths with trailing slashes for consistency's sake because paths
+            // can be added both with and without a trailing slash
+            println!("{}", path.join("").display());
+        }
+
+        for plugin in search_results.plugins {
+            let extra_target_links: Vec<(PathBuf, PathBuf)> = extra_targets
+                .iter()
+                .flat_map(|target| plugin.extra_target_links(path, target))
+                .collect();
+            let extra_target_paths = extra_target_links.iter().map(|(_, to)| to.clone());
+            match &plugin {
+                Plugin::Vst2(Vst2Plugin { target_path, .. }) => {
+                    vst2_target_paths.insert(target_path.clone());
+                    vst2_target_paths.extend(extra_target_paths);
+                }
+                Plugin::Clap(clap_plugin) => {
+                    clap_target_paths.insert(clap_plugin.target_path());
+                    clap_target_paths.extend(extra_target_paths);
+                }
+                Plugin::Vst3(_) => extra_target_bundles.extend(extra_target_paths),
+            }
+
+            if !filters.is_other_vendor(path, &plugin) {
+                num_vendor_matches += 1;
+            }
+            // Existing installations of oversized plugins are left alone, so those are handled
+            // separately below
+            if let Some(reason) = filters
+                .skip_reason(path, &plugin)
+                .filter(|&reason| reason != SkipReason::Oversized)
+            {
+                *skip_reasons.entry(reason).or_default() += 1;
+                num_filtered += 1;
+                match &plugin {
+                    Plugin::Vst2(Vst2Plugin {
+                        path: plugin_path,
+                        target_path,
+                        ..
+                    }) => {
+                        excluded_files.extend(get_file_type(target_path.clone()));
+                        // With a target override we'll also have created a symlink to the `.dll`
+                        let dll_symlink_path = target_path.with_extension("dll");
+                        if &dll_symlink_path != plugin_path {
+                            excluded_files.extend(get_file_type(dll_symlink_path));
+                        }
+                    }
+                    // The merged bundle may still contain a version of the plugin with a different
+                    // architecture, so we can only decide what to remove after we've set up all
+                    // other plugins
+                    Plugin::Vst3(module) => {
+                        excluded_vst3_modules.push((
+                            module.clone(),
+                            extra_target_links.into_iter().map(|(_, to)| to).collect(),
+                        ));
+                        continue;
+                    }
+                    Plugin::Clap(clap_plugin) => {
+                        excluded_files.extend(get_file_type(clap_plugin.target_path()));
+                        excluded_files.extend(get_file_type(clap_plugin.target_windows_path()));
+                    }
+                }
+                excluded_files.extend(
+                    extra_target_links
+                        .into_iter()
+                        .filter_map(|(_, to)| get_file_type(to)),
+                );
+
+                continue;
+            }
+
+            // Plugins using another build of yabridge use that build's `yabridge-host-32.exe`
+            if plugin.architecture() == LibArchitecture::Dll32
+                && !config.plugin_hosts.contains_key(plugin.original_path())
+            {
+                num_32bit_plugins += 1;
+            }
+
+            let original_path = plugin.original_path();
+            if superseded_plugins.contains_key(original_path) {
+                num_superseded += 1;
+                // The newer version of a VST3 plugin will be set up in the same bundle, but VST2
+                // plugins are set up right next to their `.dll` file. Those older copies are
+                // treated as leftovers so they get removed with `--prune`.
+                if let Plugin::Vst2(Vst2Plugin { target_path, .. }) = &plugin {
+                    if is_live_install && !is_partial_sync {
+                        orphan_files.extend(get_file_type(target_path.clone()));
+                        orphan_files.extend(
+                            extra_target_links
+                                .into_iter()
+                                .filter_map(|(_, to)| get_file_type(to)),
+                        );
+                    }
+                }
+
+                continue;
+            }
+
+            if let Some(size) = oversized_plugin_size(config, &plugin, max_plugin_size) {
+                if let Plugin::Vst3(module) = &plugin {
+                    oversized_vst3_bundles.insert(module.target_bundle_home());
+                }
+                oversized_plugins.push((original_path.to_owned(), size));
+
+                continue;
+            }
+
+            // These collisions have already been reported above. Only the first plugin is set up.
+            if let Some(target_path) = colliding_plugins.get(original_path) {
+                planning_failures.push(anyhow!(
+                    "'{}' was not set up because another plugin would be set up at '{}'",
+                    original_path.display(),
+                    target_path.display()
+                ));
+                continue;
+            }
+
+            // If verbose mode is enabled we'll print the path to the plugin after setting it up
+            let first_operation = plan.operations.len();
+            let plugin_path: PathBuf = match plugin {
+                // We'll set up the copies or symlinks for VST2 plugins
+                Plugin::Vst2(Vst2Plugin {
+                    path: plugin_path,
+                    target_path,
+                    ..
+                }) => {
+                    let installed = match installed_entries.remove(&plugin_path) {
+                        Some(Ok(installed)) => installed,
+                        Some(Err(err)) if options.continue_on_error => {
+                            planning_failures.push(err);
+                            continue;
+                        }
+                        Some(Err(err)) => return Err(err),
+                        None => unreachable!("'{}' was not hashed", plugin_path.display()),
+                    };
+                    if get_file_type(target_path.clone()).is_none() {
+                        if let Some(from) = take_move_candidate(&mut move_candidates, &installed) {
+                            plan.move_file(&from, &target_path)?;
+                            moved_installations.push((from, target_path.clone()));
+                        }
+                    }
+                    new_installed_plugins.insert(plugin_path.clone(), installed);
+
+                    let (library, library_hash) = match config.plugin_hosts.get(&plugin_path) {
+                        Some(directory) => {
+                            let host = &host_libraries[directory];
+                            (host.vst2.as_path(), host.vst2_hash)
+                        }
+                        None => (vst2_library, libyabridge_vst2_hash),
+                    };
+                    plan.install(
+                        options.force,
+                        config.method_for(&plugin_path),
+                        library,
+                        Some(library_hash),
+                        &target_path,
+                    )?;
+
+                    // Yabridge looks for a `.dll` file next to the `.so` file, so when the plugin
+                    // directory has a target override we'll also need to symlink the original
+                    // `.dll` file to there
+                    let dll_symlink_path = target_path.with_extension("dll");
+                    if dll_symlink_path != plugin_path {
+                        plan.install(
+                            false,
+                            InstallationMethod::Symlink,
+                            &plugin_path,
+                            None,
+                            &dll_symlink_path,
+                        )?;
+                    }
+
+                    plugin_path.clone()
+                }
+                // And then create merged bundles for the VST3 plugins:
+                // https://developer.steinberg.help/display/VST/Plug-in+Format+Structure#PluginFormatStructure-MergedBundle
+                Plugin::Vst3(module) => {
+                    // Only set up VST3 plugins when yabridge has been compiled with VST3 support
+                    if libyabridge_vst3_hash.is_none() {
+                        *skip_reasons.entry(SkipReason::NoVst3Support).or_default() += 1;
+                        continue;
+                    }
+
+                    // 32-bit and 64-bit versions of the plugin cna live inside of the same
+                    // bundle), and show a warning if we come across any duplicates.
+                    let already_installed_architectures = yabridge_vst3_bundles
+                        .entry(module.target_bundle_home())
+                        .or_default();
+                    if !already_installed_architectures.insert(module.architecture) {
+                        utils::warnln!(
+                            "{}",
+                            utils::wrap(&format!(
+                            "{}: The {} version of '{}' has already been provided by another Wine \
+                             prefix, skipping '{}'\n",
+                            "WARNING".red(),
+                            module.architecture,
+                            module.target_bundle_home().display(),
+                            module.original_module_path().display(),
+                        ))
+                        );
+                        *skip_reasons.entry(SkipReason::DuplicateVst3).or_default() += 1;
+
+                        continue;
+                    }
+
+                    // If this module has been moved, we'll move the old merged bundle to its new
+                    // location. The bundle's name is based on the module's name, so we can only do
+                    // this if the module has not been renamed.
+                    let bundle_home = module.target_bundle_home();
+                    let installed = match installed_entries.remove(module.original_path()) {
+                        Some(Ok(installed)) => installed,
+                        Some(Err(err)) if options.continue_on_error => {
+                            planning_failures.push(err);
+                            continue;
+                        }
+                        Some(Err(err)) => return Err(err),
+                        None => {
+                            unreachable!("'{}' was not hashed", module.original_path().display())
+                        }
+                    };
+                    if get_file_type(bundle_home.clone()).is_none() {
+                        if let Some(from) = take_move_candidate(&mut move_candidates, &installed) {
+                            plan.move_file(&from, &bundle_home)?;
+                            moved_installations.push((from, bundle_home.clone()));
+                        }
+                    }
+                    new_installed_plugins.insert(module.original_path().to_owned(), installed);
+
+                    // We're building a merged VST3 bundle containing both a copy or symlink to
+                    // `libyabridge-vst3.so` and the Windows VST3 plugin
+                    let (library, library_hash) =
+                        match config.plugin_hosts.get(module.original_path()) {
+                            Some(directory) => match &host_libraries[directory].vst3 {
+                                Some((library, hash)) => (library.as_path(), Some(*hash)),
+                                None => {
+                                    return Err(anyhow!(
+                                        "The build of yabridge in '{}' used for '{}' does not \
+                                         support VST3 plugins",
+                                        directory.display(),
+                                        module.original_path().display()
+                                    ))
+                                }
+                            },
+                            None => (vst3_library.unwrap(), libyabridge_vst3_hash),
+                        };
+                    plan.install(
+                        options.force,
+                        config.method_for(module.original_path()),
+                        library,
+                        library_hash,
+                        &module.target_native_module_path(),
+                    )?;
+
+                    // We'll then symlink the Windows VST3 module to that bundle to create a merged
+                    // bundle: https://developer.steinberg.help/display/VST/Plug-in+Format+Structure#PluginFormatStructure-MergedBundle
+                    plan.install(
+                        true,
+                        InstallationMethod::Symlink,
+                        &module.original_module_path(),
+                        None,
+                        &module.target_windows_module_path(),
+                    )?;
+
+                    // If `module` is a bundle, then it may contain a `Resources` directory with
+                    // screenshots and documentation
+                    // TODO: Also symlink presets, but this is a bit more involved. See
+                    //       https://developer.steinberg.help/display/VST/Preset+Locations
+                    if let Some(original_resources_dir) = module.original_resources_dir() {
+                        plan.install(
+                            false,
+                            InstallationMethod::Symlink,
+                            &original_resources_dir,
+                            None,
+                            &module.target_resources_dir(),
+                        )?;
+                        vst3_resources_bundles.insert(bundle_home);
+                    }
+
+                    module.original_path().to_path_buf()
+                }
+                // CLAP plugins are set up in `~/.clap/yabridge` using a copy of or a symlink to
+                // `libyabridge-clap.so`, with a symlink to the Windows `.clap` file next to it
+                Plugin::Clap(clap_plugin) => {
+                    let target_path = clap_plugin.target_path();
+                    let installed = match installed_entries.remove(&clap_plugin.path) {
+                        Some(Ok(installed)) => installed,
+                        Some(Err(err)) if options.continue_on_error => {
+                            planning_failures.push(err);
+                            continue;
+                        }
+                        Some(Err(err)) => return Err(err),
+                        None => unreachable!("'{}' was not hashed", clap_plugin.path.display()),
+                    };
+                    if get_file_type(target_path.clone()).is_none() {
+                        if let Some(from) = take_move_candidate(&mut move_candidates, &installed) {
+                            plan.move_file(&from, &target_path)?;
+                            moved_installations.push((from, target_path.clone()));
+                        }
+                    }
+                    new_installed_plugins.insert(clap_plugin.path.clone(), installed);
+
+                    let (library, library_hash) = match config.plugin_hosts.get(&clap_plugin.path) {
+                        Some(directory) => match &host_libraries[directory].clap {
+                            Some((library, hash)) => (library.as_path(), *hash),
+                            None => {
+                                return Err(anyhow!(
+                                    "The build of yabridge in '{}' used for '{}' does not \
+                                     support CLAP plugins",
+                                    directory.display(),
+                                    clap_plugin.path.display()
+                                ))
+                            }
+                        },
+                        None => (files.clap_library()?, libyabridge_clap_hash.unwrap()),
+                    };
+                    plan.install(
+                        options.force,
+                        config.method_for(&clap_plugin.path),
+                        library,
+                        Some(library_hash),
+                        &target_path,
+                    )?;
+                    plan.install(
+                        false,
+                        InstallationMethod::Symlink,
+                        &clap_plugin.path,
+                        None,
+                        &clap_plugin.target_windows_path(),
+                    )?;
+
+                    clap_plugin.path
+                }
+            };
+
+            // The installation we just planned is then symlinked to the extra target directories
+            for (from, to) in extra_target_links {
+                if planned_extra_links.insert(to.clone()) {
+                    plan.install(options.force, InstallationMethod::Symlink, &from, None, &to)?;
+                }
+            }
+
+            plugin_operations.push((plugin_path.clone(), first_operation..plan.operations.len()));
+
+            if options.verbose {
+                println!(
+                    "  {}",
+                    plugin_path
+                        .strip_prefix(path)
+                        .unwrap_or(&plugin_path)
+                        .display()
+                );
+            }
+        }
+
+        if options.verbose {
+            println!();
+        }
+    }
+
+    if let Some(plugin_list) = &plugin_list {
+        plugin_list.report();
+    }
+    if !options.vendors.is_empty() {
+        println!(
+            "Matched {} plugins from {}\n",
+            num_vendor_matches,
+            options
+                .vendors
+                .iter()
+                .map(|vendor| format!("'{}'", vendor))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    // We'll print the skipped files all at once to prevetn clutter
+    let num_skipped_files = skipped_dll_files.len();
+    if options.verbose && !skipped_dll_files.is_empty() {
+        println!("Skipped files:");
+        for path in skipped_dll_files {
+            println!("- {}", path.display());
+        }
+        println!();
+    }
+
+    if num_32bit_plugins > 0 && files.yabridge_host_32_exe.is_none() {
+        utils::warnln!(
+            "{}\n",
+            utils::wrap(&format!(
+                "{}: Found {} 32-bit plugins, but '{}' could not be found next to '{}'. These \
+                 plugins will fail to load. Make sure your version of yabridge has been built \
+                 with support for 32-bit plugins.",
+                "WARNING".red(),
+                num_32bit_plugins,
+                YABRIDGE_HOST_32_EXE_NAME,
+                files.yabridge_host_exe.display()
+            ))
+        );
+    }
+
+    if !plugin_shortcuts.is_empty() {
+        utils::warnln!(
+            "{}",
+            utils::wrap(&format!(
+                "{}: Found {} Windows shortcuts to plugins. Shortcuts are not actual plugins and \
+                 they can't be set up, so they have been skipped. Add the directories containing \
+                 the plugins they point to instead:",
+                "WARNING".red(),
+                plugin_shortcuts.len()
+            ))
+        );
+        for (path, target) in &plugin_shortcuts {
+            match utils::windows_path_in_prefix(&utils::wine_prefix_for(path), target) {
+                Some(target_path) => {
+                    utils::warnln!("- {} -> {}", path.display(), target_path.display())
+                }
+                None => utils::warnln!("- {} -> {}", path.display(), target),
+            }
+        }
+        utils::warnln!();
+    }
+
+    if !plugin_archives.is_empty() {
+        println!(
+            "Found {} archives containing plugins, rerun with the '--extract' option to extract \
+             and set up these plugins:",
+            plugin_archives.len()
+        );
+        for archive in &plugin_archives {
+            println!("- {}", archive.display());
+        }
+        println!();
+    }
+    // This contains the archives we've checked for plugins, so they aren't listed again next time
+    if update_caches {
+        extracted_archives.write()?;
+    }
+
+    // The old locations of moved installations should not be treated as leftover files. With a
+    // target override the moved VST2 plugin's old `.dll` symlink now points to a file that no
+    // longer exists, so that one can be removed.
+    let mut moved_from: HashSet<PathBuf> = HashSet::new();
+    if !moved_installations.is_empty() {
+        println!(
+            "Moving {} existing installation(s) for plugins that have been moved:",
+            moved_installations.len()
+        );
+        for (from, to) in &moved_installations {
+            println!("- {} -> {}", from.display(), to.display());
+            moved_from.insert(from.clone());
+
+            // The same applies to the `.clap-win` symlink next to a moved CLAP plugin
+            for extension in ["dll", "clap-win"] {
+                let symlink_path = from.with_extension(extension);
+                if let Some(symlink @ NativeFile::Symlink(_)) = get_file_type(symlink_path.clone())
+                {
+                    if !symlink_path.exists() {
+                        plan.remove(&symlink)?;
+                        moved_from.insert(symlink_path);
+                    }
+                }
+            }
+        }
+        println!();
+    }
+
+    // A merged bundle's `Resources` symlink stays behind when the Windows VST3 plugin is no longer a
+    // bundle, or when its bundle no longer contains a `Resources` directory
+    if is_live_install {
+        for symlink in
+            find_stale_resources_symlinks(yabridge_vst3_bundles.keys(), &vst3_resources_bundles)
+        {
+            plan.remove(&symlink)?;
+        }
+    }
+
+    // We'll always remove existing installations for plugins with excluded architectures, since
+    // those plugins would otherwise still show up in the host
+    let mut excluded_vst3_bundles: BTreeSet<PathBuf> = BTreeSet::new();
+    for (module, extra_target_links) in excluded_vst3_modules {
+        let bundle_home = module.target_bundle_home();
+        if yabridge_vst3_bundles.contains_key(&bundle_home) {
+            excluded_files.extend(get_file_type(module.target_windows_module_path()));
+        } else if excluded_vst3_bundles.insert(bundle_home.clone()) {
+            excluded_files.extend(get_file_type(bundle_home));
+            excluded_files.extend(extra_target_links.into_iter().filter_map(get_file_type));
+        }
+    }
+    // With `--dry-run` and `--export-plan` nothing gets removed during this sync
+    let is_deferred = options.dry_run || options.export_plan.is_some();
+    if !excluded_files.is_empty() && is_live_install {
+        println!(
+            "{} {} file(s) for {}:",
+            if is_deferred {
+                "Would remove"
+            } else {
+                "Removing"
+            },
+            excluded_files.len(),
+            if options.profile.is_some() {
+                "excluded plugins and plugins that are not part of the profile"
+            } else {
+                "plugins with excluded architectures"
+            }
+        );
+        for file in excluded_files {
+            println!("- {}", file.path().display());
+            plan.remove(&file)?;
+        }
+        println!();
+    }
+
+    // TODO: Move this elsewhere
+    // TODO: This can leave behind empty directories if we remove a subdirectory
+    // When only syncing some of the plugins, all other bundles would be considered leftovers
+    if !is_partial_sync && is_live_install {
+        let is_known_bundle = |path: &Path| {
+            yabridge_vst3_bundles.contains_key(path)
+                || excluded_vst3_bundles.contains(path)
+                || oversized_vst3_bundles.contains(path)
+                || extra_target_bundles.contains(path)
+        };
+
+        let is_known_clap_plugin = |path: &Path| clap_target_paths.contains(path);
+
+        orphan_files.extend(find_vst3_orphans(
+            &yabridge_vst3_home(),
+            false,
+            &is_known_bundle,
+        ));
+        orphan_files.extend(find_clap_orphans(
+            &yabridge_clap_home(),
+            false,
+            &is_known_clap_plugin,
+        ));
+        for target_dir in &target_dirs {
+            orphan_files.extend(find_target_vst2_orphans(target_dir, &vst2_target_paths));
+            orphan_files.extend(find_vst3_orphans(target_dir, true, &is_known_bundle));
+            orphan_files.extend(find_clap_orphans(target_dir, true, &is_known_clap_plugin));
+        }
+
+        // A target directory can contain `~/.vst3/yabridge`, so we may have found some bundles
+        // twice
+        let mut seen_paths: HashSet<PathBuf> = HashSet::new();
+        orphan_files.retain(|file| seen_paths.insert(file.path().to_owned()));
+    }
+    orphan_files.retain(|file| !moved_from.contains(file.path()));
+
+    // Always warn about leftover files since those might cause warnings or errors when a VST host
+    // tries to load them
+    if !orphan_files.is_empty() {
+        let leftover_files_str = if orphan_files.len() == 1 {
+            format!("{} leftover file", orphan_files.len())
+        } else {
+            format!("{} leftover files", orphan_files.len())
+        };
+        if options.prune && is_deferred {
+            println!("Would remove {}:", leftover_files_str);
+        } else if options.prune {
+            println!("Removing {}:", leftover_files_str);
+        } else {
+            println!(
+                "Found {}, rerun with the '--prune' option to remove them:",
+                leftover_files_str
+            );
+        }
+
+        for file in orphan_files {
+            println!("- {}", file.path().display());
+            if options.prune {
+                plan.remove(&file)?;
+            }
+        }
+
+        println!();
+    }
+
+    // When exporting the plan we'll stop here, since the plan will be applied at a later moment
+    if let Some(plan_path) = &options.export_plan {
+        plan.write(plan_path)?;
+        println!(
+            "Wrote {} change(s) to '{}', use 'yabridgectl sync --apply-plan {}' to apply them",
+            plan.operations.len(),
+            plan_path.display(),
+            plan_path.display()
+        );
+
+        return Ok(Summary::default());
+    }
+    if options.check_permissions {
+        return check_plan_permissions(&plan);
+    }
+    if options.verify {
+        return verify_installations(&plan, &plugin_operations, &planning_failures);
+    }
+
+    // These would otherwise be replaced silently as part of the other updated plugins
+    let broken_symlinks = plan.broken_symlinks();
+    if !broken_symlinks.is_empty() {
+        println!(
+            "Found {} broken symlinks to yabridge's libraries, these will be recreated to point \
+             to the current version of yabridge:",
+            broken_symlinks.len()
+        );
+        for path in broken_symlinks {
+            println!("- {}", path.display());
+        }
+        println!();
+    }
+
+    if options.dry_run {
+        plan.print_changes();
+        report_failures(&planning_failures);
+        println!("Nothing has been changed, rerun this command without '--dry-run' to apply these changes");
+
+        return Ok(Summary::default());
+    }
+
+    if !confirm_removals(
+        &plan,
+        installed_plugins.plugins.len(),
+        config.removal_limit,
+        options.yes,
+    )? {
+        println!("Nothing has been changed");
+        return Ok(Summary::default());
+    }
+
+    let (num_new_copies, new_copies_size) = plan.new_copies_size();
+    if num_new_copies > 0 {
+        println!(
+            "Creating {} new copies of yabridge's libraries, using {} of additional disk space",
+            num_new_copies,
+            utils::format_size(new_copies_size)
+        );
+        if options.confirm {
+            match promptly::prompt_opt::<String, &str>(
+                "Would you like to continue? Entering anything other than YES will leave \
+                 everything unchanged",
+            ) {
+                Ok(Some(answer)) if answer == "YES" => (),
+                _ => {
+                    println!("Nothing has been changed");
+                    return Ok(Summary::default());
+                }
+            }
+        }
+        println!();
+    }
+
+    let mut failures = planning_failures;
+    failures.extend(execute_plan(&plan, options.continue_on_error)?);
+
+    // These plugins will always be hashed, since their modification times can't be trusted
+    let future_dated_plugins: Vec<&PathBuf> = new_installed_plugins
+        .iter()
+        .filter(|(_, installed)| cache::is_in_future(installed.modified))
+        .map(|(plugin_path, _)| plugin_path)
+        .collect();
+    if !future_dated_plugins.is_empty() {
+        utils::warnln!(
+            "\n{}",
+            utils::wrap(&format!(
+                "Warning: {} plugins have modification times in the future. This usually means \
+                 that the system clock was set incorrectly at some point. These plugins will be \
+                 hashed during every sync until the system clock is correct or until their \
+                 modification times have been fixed, for instance by running 'touch' on them:",
+                future_dated_plugins.len()
+            ))
+        );
+        for plugin_path in future_dated_plugins {
+            utils::warnln!("- {}", plugin_path.display());
+        }
+        utils::warnln!();
+    }
+
+    // Entries for plugins whose installations have since been removed are no longer useful
+    installed_plugins.plugins.retain(|plugin_path, installed| {
+        !new_installed_plugins.contains_key(plugin_path)
+            && get_file_type(installed.target.clone()).is_some()
+    });
+    if is_live_install {
+        installed_plugins.plugins.extend(new_installed_plugins);
+        installed_plugins.write()?;
+        plan.copy_hashes.write()?;
+    }
+
+    for (reason, count) in [
+        (SkipReason::NotAPlugin, num_skipped_files),
+        (SkipReason::Shortcut, plugin_shortcuts.len()),
+        (SkipReason::Archive, plugin_archives.len()),
+        (SkipReason::Superseded, num_superseded),
+        (SkipReason::Oversized, oversized_plugins.len()),
+    ] {
+        *skip_reasons.entry(reason).or_default() += count;
+    }
+    skip_reasons.retain(|_, count| *count > 0);
+
+    println!(
+        "Finished setting up {} plugins using {} ({} new, {} moved), skipped {} non-plugin .dll \
+         files",
+        num_installed - num_filtered - oversized_plugins.len() - num_superseded,
+        config.method.plural_name(),
+        plan.num_new_plugins(),
+        plan.num_moved_plugins(),
+        num_skipped_files
+    );
+    if plan.num_hard_links() > 0 {
+        println!(
+            "Deduplicated {} copies using hard links",
+            plan.num_hard_links()
+        );
+    }
+    let num_skipped = |reason| skip_reasons.get(&reason).copied().unwrap_or_default();
+    let num_excluded = num_skipped(SkipReason::ExcludedArchitecture);
+    if num_excluded > 0 {
+        println!(
+            "Excluded {} plugins because of their architecture ({})",
+            num_excluded,
+            filters
+                .excluded_architectures
+                .iter()
+                .map(|architecture| architecture.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    let num_disabled_formats = num_skipped(SkipReason::DisabledFormat);
+    if num_disabled_formats > 0 {
+        println!(
+            "Skipped {} plugins because their format has been disabled (enabled: {})",
+            num_disabled_formats,
+            config
+                .formats
+                .iter()
+                .map(|format| format.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if let Some(name) = &options.profile {
+        println!(
+            "Skipped {} plugins that are not part of the '{}' profile",
+            num_skipped(SkipReason::NotInProfile),
+            name
+        );
+    }
+    if !superseded_plugins.is_empty() {
+        println!(
+            "Skipped {} plugins because a newer version is installed elsewhere:",
+            superseded_plugins.len()
+        );
+        for (path, newer_path) in &superseded_plugins {
+            println!("- {} (using {})", path.display(), newer_path.display());
+        }
+    }
+    if !oversized_plugins.is_empty() {
+        println!(
+            "Skipped {} plugins larger than {} that would have been set up using copies:",
+            oversized_plugins.len(),
+            utils::format_size(max_plugin_size.unwrap_or_default())
+        );
+        for (path, size) in &oversized_plugins {
+            println!("- {} ({})", path.display(), utils::format_size(*size));
+        }
+    }
+    if !skip_reasons.is_empty() {
+        println!("Skipped: {}", format_skip_reasons(&skip_reasons));
+    }
+    report_failures(&failures);
+
+    if failures.is_empty() && is_live_install {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .ok();
+        config.update(|config| {
+            config.last_sync = now;
+            Ok(())
+        })?;
+    }
+
+    if config.link_host_binaries && is_live_install {
+        link_host_binaries(&config.files()?)?;
+    }
+
+    // Skipping the post-installation seting checks can be done only for this invocation of
+    // `yabridgectl sync`, or it can be skipped permanently through a config file option. These
+    // checks only make sense for the live system, so they're also skipped with a target prefix.
+    // The global `--skip-checks` option skips these checks for every command.
+    if utils::is_skipping_checks() {
+        logln!("Skipping the post-installation setup checks");
+    } else if !(options.no_verify || config.no_verify) && is_live_install {
+        // VST2 plugins are set up in place, so the host will only pick them up if the directory is
+        // on its search path
+        verify_vst2_search_paths(
+            &vst2_directories
+                .iter()
+                .map(PathBuf::as_path)
+                .collect::<Vec<_>>(),
+        );
+
+        verify_setup(config, options.strict_wine, num_32bit_plugins > 0)?;
+    } else if options.strict_wine && is_live_install {
+        // The Wine setup is always verified with `--strict-wine`, even when the post-installation
+        // checks have been disabled in the config
+        verify_wine_setup(config, true, num_32bit_plugins > 0)?;
+    }
+
+    Ok(Summary {
+        skip_reasons,
+        ..plan.summary(&plugin_operations, failures.len())
+    })
+}
+
+/// The libraries from another build of yabridge used for some of the plugins, along with their
+/// hashes. See `config.plugin_hosts`.
+pub struct HostLibraries {
+    pub vst2: PathBuf,
+    pub vst2_hash: i64,
+    pub vst3: Option<(PathBuf, i64)>,
+    pub clap: Option<(PathBuf, i64)>,
+}
+
+impl HostLibraries {
+    /// Find the libraries in a directory containing another build of yabridge. Yabridge looks for
+    /// `yabridge-host.exe` next to the actual library, so we'll never use the chainloaders here.
+    pub fn new(config: &Config, directory: &Path) -> Result<HostLibraries> {
+        let files = config.host_files(directory).with_context(|| {
+            format!(
+                "Could not use the build of yabridge in '{}', use 'yabridgectl host rm' to stop \
+                 using it",
+                directory.display()
+            )
+        })?;
+
+        Ok(HostLibraries {
+            vst2_hash: utils::hash_file(&files.libyabridge_vst2)?,
+            vst3: match files.libyabridge_vst3 {
+                Some(library) => Some((library.clone(), utils::hash_file(&library)?)),
+                None => None,
+            },
+            clap: match files.libyabridge_clap {
+                Some(library) => Some((library.clone(), utils::hash_file(&library)?)),
+                None => None,
+            },
+            vst2: files.libyabridge_vst2,
+        })
+    }
+}
+
+/// Apply a plan previously exported with `yabridgectl sync --export-plan`. This will refuse to do
+/// anything if any of the files touched by the plan have changed since the plan was made.
+fn apply_plan(config: &mut Config, plan_path: &Path, options: &SyncOptions) -> Result<Summary> {
+    let plan = SyncPlan {
+        show_progress: !utils::is_quiet() && unsafe { libc::isatty(libc::STDERR_FILENO) } == 1,
+        ..SyncPlan::read(plan_path)?
+    };
+    plan.verify()?;
+    if options.check_permissions {
+        return check_plan_permissions(&plan);
+    }
+    if !confirm_removals(
+        &plan,
+        InstalledPlugins::read().plugins.len(),
+        config.removal_limit,
+        options.yes,
+    )? {
+        println!("Nothing has been changed");
+        return Ok(Summary::default());
+    }
+
+    let failures = execute_plan(&plan, options.continue_on_error)?;
+
+    println!(
+        "Applied {} change(s) from '{}' ({} new plugins)",
+        plan.operations.len() - failures.len(),
+        plan_path.display(),
+        plan.num_new_plugins()
+    );
+    report_failures(&failures);
+
+    if config.link_host_binaries {
+        link_host_binaries(&config.files()?)?;
+    }
+
+    // Plans don't record the plugins' architectures, so the 32-bit host is not checked here
+    if utils::is_skipping_checks() {
+        logln!("Skipping the post-installation setup checks");
+    } else if !(options.no_verify || config.no_verify) {
+        verify_setup(config, options.strict_wine, false)?;
+    } else if options.strict_wine {
+        verify_wine_setup(config, true, false)?;
+    }
+
+    // Plans only contain plugins that need to be changed, so none of these are skipped
+    Ok(plan.summary(&[], failures.len()))
+}
+
+/// What a sync would do with a plugin, for `yabridgectl sync --explain`. See [`explain_file()`].
+enum Explanation {
+    /// The plugin will be set up at `target_path`.
+    SetUp {
+        target_path: PathBuf,
+        method: InstallationMethod,
+    },
+    /// The plugin will not be set up for this reason.
+    Skipped(SkipReason),
+    /// Another plugin would be set up at the same location. Only the other plugin will be set up.
+    Collision {
+        target_path: PathBuf,
+        other_plugin: PathBuf,
+    },
+}
+
+/// Explain why a sync with the current options would or would not set up the file at `path`, for
+/// `yabridgectl sync --explain <path>`. This goes through the same checks as a regular sync in the
+/// same order, but nothing will be changed.
+fn explain_file(config: &Config, options: &SyncOptions, path: &Path) -> Result<Summary> {
+    let explain = |explanation: String| -> Result<Summary> {
+        println!(
+            "{}",
+            utils::wrap(&format!("'{}' {}", path.display(), explanation))
+        );
+        Ok(Summary::default())
+    };
+
+    // With nested plugin directories the innermost directory is used, just like for the
+    // installation method
+    let directory =
+        match config
+            .plugin_dirs
+            .iter()
+            .filter(|directory| path.starts_with(directory))
+            .max_by_key(|directory| directory.components().count())
+        {
+            Some(directory) => directory.as_path(),
+            None => return explain(String::from(
+                "is not inside of any plugin directory. Use 'yabridgectl add <path>' to add the \
+                 directory containing it.",
+            )),
+        };
+    if let Some(entry) = config
+        .blacklist
+        .iter()
+        .find(|entry| !config::is_blacklist_pattern(entry) && path.starts_with(entry))
+    {
+        return explain(format!(
+            "is skipped because '{}' is in the blacklist.",
+            entry.display()
+        ));
+    }
+    if let Some(subdirectory) = config
+        .index_blacklist(directory)
+        .into_iter()
+        .find(|subdirectory| {
+            subdirectory.parent() == Some(directory) && path.starts_with(subdirectory)
+        })
+    {
+        return explain(format!(
+            "is skipped because '{}' was not selected with 'yabridgectl add --interactive'. Use \
+             'yabridgectl set {} --all-subdirectories' to include it again.",
+            subdirectory.display(),
+            directory.display()
+        ));
+    }
+    if files::is_ignored_file(path, &config.ignored_suffixes) {
+        return explain(String::from(
+            "is skipped because it's a hidden file or because its name ends with one of the \
+             suffixes set with 'yabridgectl set --ignore-suffix'.",
+        ));
+    }
+
+    let results = config
+        .search_directories_indexed()
+        .context("Failure while searching for plugins")?;
+    let search_results = &results[directory];
+    let matching_plugins: Vec<&Plugin> = search_results
+        .plugins
+        .iter()
+        .filter(|plugin| match plugin {
+            Plugin::Vst3(module) => {
+                path == module.original_path() || path == module.original_module_path()
+            }
+            _ => path == plugin.original_path(),
+        })
+        .collect();
+    if matching_plugins.is_empty() {
+        return if search_results.skipped_files.iter().any(|file| file == path) {
+            explain(format!(
+                "is not a plugin because {}.",
+                files::explain_skipped_file(path)?
+            ))
+        } else if search_results
+            .shortcut_files
+            .iter()
+            .any(|file| file == path)
+        {
+            explain(match files::read_plugin_shortcut_target(path) {
+                Some(target) => format!(
+                    "is a Windows shortcut pointing to '{}'. Shortcuts can't be set up, add the \
+                     directory containing that plugin instead.",
+                    target
+                ),
+                None => String::from("is a Windows shortcut that does not point to a plugin."),
+            })
+        } else if search_results.archive_files.iter().any(|file| file == path) {
+            explain(String::from(
+                "is an archive. Use 'yabridgectl sync --extract' to extract and set up the \
+                 plugins inside of it.",
+            ))
+        } else {
+            explain(String::from(
+                "was not found while searching for plugins. Only '.dll', '.vst3', and '.clap' \
+                 files can be set up.",
+            ))
+        };
+    }
+
+    let files: YabridgeFiles = config.files()?;
+    let filters = PluginFilters::new(config, options)?;
+    let mut plugin_list = match &options.plugins_from {
+        Some(path) => Some(PluginList::read(path, options.ignore_case)?),
+        None => None,
+    };
+    let filter_vendors = !options.vendors.is_empty() && !options.remove_other_vendors;
+    let max_plugin_size = options.max_size.or(config.max_plugin_size);
+    let is_read_only = config.target_dir(directory)?.is_none()
+        && options.target_prefix.is_none()
+        && matches!(
+            utils::mount_info(directory),
+            Some(utils::MountInfo {
+                read_only: true,
+                ..
+            })
+        );
+    let superseded_plugins = if options.prefer_newer {
+        find_superseded_plugins(&results, |directory, plugin| {
+            filters.skip_reason(directory, plugin).is_some()
+        })
+    } else {
+        BTreeMap::new()
+    };
+    let skip_reason = |directory: &Path, plugin: &Plugin| {
+        filters.skip_reason(directory, plugin).or_else(|| {
+            if superseded_plugins.contains_key(plugin.original_path()) {
+                Some(SkipReason::Superseded)
+            } else {
+                None
+            }
+        })
+    };
+
+    let mut mirrored_plugins: Vec<Plugin> = Vec::new();
+    for (path, search_results) in &results {
+        for target in config.extra_target_dirs(path)? {
+            for plugin in &search_results.plugins {
+                if skip_reason(path, plugin).is_none() {
+                    let mut mirror = plugin.clone();
+                    mirror.set_target(path, &target);
+                    mirrored_plugins.push(mirror);
+                }
+            }
+        }
+    }
+    let target_collisions = find_target_collisions(
+        results
+            .iter()
+            .flat_map(|(path, search_results)| {
+                search_results
+                    .plugins
+                    .iter()
+                    .filter(move |plugin| skip_reason(path, plugin).is_none())
+            })
+            .chain(&mirrored_plugins),
+        utils::is_case_insensitive,
+    );
+
+    for plugin in matching_plugins {
+        let plugin_path = plugin.original_path();
+        let explanation = if plugin_list
+            .as_mut()
+            .is_some_and(|plugin_list| !plugin_list.matches(plugin))
+        {
+            Explanation::Skipped(SkipReason::NotInPluginList)
+        } else if filter_vendors
+            && !files::plugin_matches_vendor(directory, plugin_path, &options.vendors)
+        {
+            Explanation::Skipped(SkipReason::OtherVendor)
+        } else if !options.only.is_empty()
+            && !options
+                .only
+                .iter()
+                .any(|pattern| files::plugin_matches_pattern(directory, plugin_path, pattern))
+        {
+            Explanation::Skipped(SkipReason::NotMatchingPattern)
+        } else if matches!(plugin, Plugin::Vst2(_)) && is_read_only {
+            Explanation::Skipped(SkipReason::ReadOnlyDirectory)
+        } else if let Some(reason) = skip_reason(directory, plugin) {
+            Explanation::Skipped(reason)
+        } else if let Some((target_path, plugin_paths)) = target_collisions
+            .iter()
+            .find(|(_, plugin_paths)| plugin_paths[1..].iter().any(|path| path == plugin_path))
+        {
+            Explanation::Collision {
+                target_path: target_path.clone(),
+                other_plugin: plugin_paths[0].clone(),
+            }
+        } else if matches!(plugin, Plugin::Vst3(_)) && files.vst3_library().is_none() {
+            Explanation::Skipped(SkipReason::NoVst3Support)
+        } else {
+            Explanation::SetUp {
+                target_path: match plugin {
+                    Plugin::Vst2(Vst2Plugin { target_path, .. }) => target_path.clone(),
+                    Plugin::Vst3(module) => module.target_bundle_home(),
+                    Plugin::Clap(clap_plugin) => clap_plugin.target_path(),
+                },
+                method: config.method_for(plugin_path),
+            }
+        };
+
+        let description = match explanation {
+            Explanation::SetUp {
+                target_path,
+                method,
+            } => {
+                let mut description = format!(
+                    "will be set up at '{}' using a {}.",
+                    target_path.display(),
+                    method
+                );
+                let extra_targets = config.extra_target_dirs(directory)?;
+                if !extra_targets.is_empty() {
+                    description.push_str(&format!(
+                        " It will also be symlinked to {}.",
+                        extra_targets
+                            .iter()
+                            .map(|target| format!("'{}'", target.display()))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+                if plugin.architecture() == LibArchitecture::Dll32
+                    && files.yabridge_host_32_exe.is_none()
+                    && !config.plugin_hosts.contains_key(plugin_path)
+                {
+                    description.push_str(&format!(
+                        " This is a 32-bit plugin, but '{}' could not be found, so the plugin will \
+                         fail to load until yabridge is installed with 32-bit support.",
+                        YABRIDGE_HOST_32_EXE_NAME
+                    ));
+                }
+
+                description
+            }
+            Explanation::Skipped(reason) => format!(
+                "is skipped: {}.",
+                match reason {
+                    SkipReason::NotInPluginList => format!(
+                        "it does not match any of the entries in '{}'",
+                        options.plugins_from.as_ref().unwrap().display()
+                    ),
+                    SkipReason::OtherVendor => format!(
+                        "it's not from one of these vendors: {}",
+                        options.vendors.join(", ")
+                    ),
+                    SkipReason::NotMatchingPattern => format!(
+                        "it does not match any of these patterns: {}",
+                        options.only.join(", ")
+                    ),
+                    SkipReason::ReadOnlyDirectory => String::from(
+                        "VST2 plugins can't be set up in a directory on a read-only file system, \
+                         use 'yabridgectl set <path> --target <path>' to set them up elsewhere",
+                    ),
+                    SkipReason::DisabledFormat => format!(
+                        "{} plugins have been disabled with 'yabridgectl set --formats'",
+                        plugin.format()
+                    ),
+                    SkipReason::Blacklisted => {
+                        String::from("it matches one of the patterns in the blacklist")
+                    }
+                    SkipReason::NotInProfile => format!(
+                        "it's not part of the '{}' profile",
+                        options.profile.as_deref().unwrap_or_default()
+                    ),
+                    SkipReason::ExcludedArchitecture => {
+                        format!("{} plugins have been excluded", plugin.architecture())
+                    }
+                    SkipReason::Superseded => format!(
+                        "a more recent version is installed at '{}'",
+                        superseded_plugins[plugin_path].display()
+                    ),
+                    SkipReason::Oversized => format!(
+                        "it's larger than the maximum size for copied plugins ({})",
+                        utils::format_size(max_plugin_size.unwrap_or_default())
+                    ),
+                    SkipReason::NoVst3Support => {
+                        String::from("yabridge has been built without VST3 support")
+                    }
+                    reason => reason.description().to_owned(),
+                }
+            ),
+            Explanation::Collision {
+                target_path,
+                other_plugin,
+            } => format!(
+                "is skipped because '{}' would also be set up at '{}'. Use 'yabridgectl set \
+                 <path> --target <path>' to set up one of these plugin directories in another \
+                 location.",
+                other_plugin.display(),
+                target_path.display()
+            ),
+        };
+        println!(
+            "{}",
+            utils::wrap(&format!(
+                "'{}' ({}, {}) {}",
+                plugin_path.display(),
+                plugin.format(),
+                plugin.architecture(),
+                description
+            ))
+        );
+    }
+
+    Ok(Summary::default())
+}
+
+/// Syncs removing fewer plugins than this never count as a mass removal, even when they remove more
+/// than a quarter of the installed plugins. See [`is_mass_removal()`].
+const MIN_MASS_REMOVAL: usize = 5;
+
+/// Whether removing `num_removed` plugins out of the `num_installed` plugins set up during previous
+/// syncs should be confirmed first. This is the case when more than `removal_limit` plugins or more
+/// than a quarter of the installed plugins would be removed. A limit of 0 disables this check.
+pub fn is_mass_removal(num_removed: usize, num_installed: usize, removal_limit: usize) -> bool {
+    removal_limit > 0
+        && (num_removed > removal_limit
+            || (num_removed >= MIN_MASS_REMOVAL && num_removed * 4 > num_installed))
+}
+
+/// Ask for confirmation before applying a plan that removes a suspiciously large number of plugins,
+/// see [`is_mass_removal()`]. Returns whether the plan should be applied. With `yes` this never
+/// asks. When STDIN is not a terminal there's nobody to ask, so this returns an error instead.
+fn confirm_removals(
+    plan: &SyncPlan,
+    num_installed: usize,
+    removal_limit: usize,
+    yes: bool,
+) -> Result<bool> {
+    let num_removed = plan.num_removed_plugins();
+    if yes || !is_mass_removal(num_removed, num_installed, removal_limit) {
+        return Ok(true);
+    }
+
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return Err(anyhow!(
+            "Refusing to remove {} plugins without confirmation, rerun this command with '--yes' \
+             to remove them anyway",
+            num_removed
+        ));
+    }
+
+    utils::warnln!(
+        "{}",
+        utils::wrap(&format!(
+            "{}: This sync would remove {} of the {} plugins that have been set up. This can \
+             happen when a plugin directory has been moved or when the drive containing it has \
+             not been mounted. Use 'yabridgectl sync --dry-run' to see which plugins would be \
+             removed.",
+            "WARNING".red(),
+            num_removed,
+            num_installed.max(num_removed)
+        ))
+    );
+
+    Ok(matches!(
+        promptly::prompt_opt::<String, &str>(
+            "Would you like to continue? Entering anything other than YES will leave everything \
+             unchanged",
+        ),
+        Ok(Some(answer)) if answer == "YES"
+    ))
+}
+
+/// Apply a plan. With `continue_on_error` all operations will be attempted and the errors for the
+/// failed operations are returned, otherwise this stops at the first error.
+/// Permission problems are checked for before anything is changed, see
+/// [`SyncPlan::check_permissions()`].
+fn execute_plan(plan: &SyncPlan, continue_on_error: bool) -> Result<Vec<anyhow::Error>> {
+    let problems = plan.check_permissions();
+    if !problems.is_empty() {
+        report_permission_problems(&problems);
+        // The affected operations will be reported again when they fail
+        if !continue_on_error {
+            return Err(anyhow!(
+                "Found {} permission problem(s), nothing has been changed",
+                problems.len()
+            ));
+        }
+    }
+
+    if continue_on_error {
+        Ok(plan.execute_all())
+    } else {
+        plan.execute().map(|()| Vec::new())
+    }
+}
+
+/// Print the changes the permission check found to be impossible with the current user's
+/// permissions, see [`SyncPlan::check_permissions()`].
+fn report_permission_problems(problems: &[String]) {
+    utils::errorln!(
+        "\n{}: {} change(s) can't be made with the current permissions:",
+        "ERROR".red(),
+        problems.len()
+    );
+    for problem in problems {
+        eprintln!("- {}", problem);
+    }
+}
+
+/// Only check whether a plan can be applied with the current permissions, for `yabridgectl sync
+/// --check-permissions`. Nothing will be changed.
+fn check_plan_permissions(plan: &SyncPlan) -> Result<Summary> {
+    let problems = plan.check_permissions();
+    if problems.is_empty() {
+        println!(
+            "All {} change(s) can be made with the current permissions",
+            plan.operations.len()
+        );
+
+        Ok(Summary::default())
+    } else {
+        report_permission_problems(&problems);
+
+        Err(anyhow!("Found {} permission problem(s)", problems.len()))
+    }
+}
+
+/// Check whether every plugin has been set up correctly for `yabridgectl sync --verify`, based on the
+/// changes a sync would make. `plugin_operations` contains the range of operations in the plan for
+/// every plugin we would set up. All other operations remove leftover files or existing
+/// installations for skipped plugins. Returns an error if anything is out of date.
+fn verify_installations(
+    plan: &SyncPlan,
+    plugin_operations: &[(PathBuf, Range<usize>)],
+    planning_failures: &[anyhow::Error],
+) -> Result<Summary> {
+    let mut num_problems = 0;
+    for (plugin_path, operations) in plugin_operations {
+        let problem = plan.operations[operations.clone()]
+            .iter()
+            .find_map(Operation::problem);
+        logln!(
+            "Verified '{}': {}",
+            plugin_path.display(),
+            problem.unwrap_or("OK")
+        );
+        match problem {
+            Some(problem) => {
+                num_problems += 1;
+                println!("- {} ({})", plugin_path.display(), problem.red());
+            }
+            None => println!("- {} ({})", plugin_path.display(), "OK".green()),
+        }
+    }
+
+    let leftover_files: Vec<&Path> = plan
+        .operations
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| {
+            !plugin_operations
+                .iter()
+                .any(|(_, operations)| operations.contains(idx))
+        })
+        .filter_map(|(_, operation)| match operation {
+            Operation::Remove { path, .. } => Some(path.as_path()),
+            _ => None,
+        })
+        .collect();
+    if !leftover_files.is_empty() {
+        num_problems += leftover_files.len();
+        println!(
+            "\nFound {} file(s) that should be removed:",
+            leftover_files.len()
+        );
+        for path in leftover_files {
+            println!("- {}", path.display());
+        }
+    }
+
+    if !planning_failures.is_empty() {
+        num_problems += planning_failures.len();
+        utils::errorln!(
+            "\n{}: {} plugin(s) could not be checked:",
+            "ERROR".red(),
+            planning_failures.len()
+        );
+        for error in planning_failures {
+            eprintln!("- {:#}", error);
+        }
+    }
+
+    if num_problems == 0 {
+        println!(
+            "\nAll {} plugins have been set up correctly",
+            plugin_operations.len()
+        );
+
+        Ok(Summary::default())
+    } else {
+        Err(anyhow!(
+            "Found {} problem(s), run 'yabridgectl sync' to fix them",
+            num_problems
+        ))
+    }
+}
+
+fn report_failures(failures: &[anyhow::Error]) {
+    if failures.is_empty() {
+        return;
+    }
+
+    utils::errorln!(
+        "\n{}: {} change(s) could not be applied:",
+        "ERROR".red(),
+        failures.len()
+    );
+    for error in failures {
+        // The alternate formatting prints the entire chain of causes on a single line
+        eprintln!("- {:#}", error);
+        logln!("Failed to apply a change: {:#}", error);
+    }
+}
+
+/// Write everything we found while searching the plugin directories to the log file, see
+/// [`crate::log`].
+fn log_search_results(results: &BTreeMap<&Path, SearchResults>) {
+    for (directory, search_results) in results {
+        logln!(
+            "Searched '{}': found {} plugin(s), skipped {} file(s)",
+            directory.display(),
+            search_results.plugins.len(),
+            search_results.skipped_files.len()
+        );
+        for plugin in &search_results.plugins {
+            logln!("Found plugin '{}'", plugin.original_path().display());
+        }
+        for path in &search_results.skipped_files {
+            logln!("Skipped '{}', not a plugin", path.display());
+        }
+        for path in &search_results.shortcut_files {
+            logln!("Skipped '{}', Windows shortcut", path.display());
+        }
+        for path in &search_results.archive_files {
+            logln!("Skipped '{}', archive", path.display());
+        }
+        for file in &search_results.so_files {
+            logln!("Found native library '{}'", file.path().display());
+        }
+    }
+}
+
+/// Find an existing installation for a plugin that has been moved. This matches installations of
+/// plugins that no longer exist by the hash of the plugin's file. If either plugin was not hashed,
+/// see [`InstalledPlugin::without_hash()`], then the file's size and modification time are compared
+/// instead, since moving a file keeps those intact. Since the names of VST3 bundles and the files
+/// inside of them are based on the module's name, we'll only consider installations with the same
+/// file name. The matched installation is removed from `candidates`.
+fn take_move_candidate(
+    candidates: &mut Vec<InstalledPlugin>,
+    installed: &InstalledPlugin,
+) -> Option<PathBuf> {
+    let is_vst3 = installed.target.extension().and_then(|ext| ext.to_str()) == Some("vst3");
+    let idx = candidates.iter().position(|candidate| {
+        let same_contents = match (candidate.hash, installed.hash) {
+            (Some(candidate_hash), Some(hash)) => candidate_hash == hash,
+            _ => candidate.size == installed.size && candidate.modified == installed.modified,
+        };
+
+        same_contents && (!is_vst3 || candidate.target.file_name() == installed.target.file_name())
+    })?;
+
+    Some(candidates.remove(idx).target)
+}
+
+/// Different versions of the same plugin as `(modified, path, hashed_path)` tuples, used in
+/// [`find_superseded_plugins()`]. `hashed_path` is the file that is hashed to compare the versions'
+/// contents, which is the module inside of the bundle for VST3 bundles.
+type PluginVersions = Vec<(SystemTime, PathBuf, PathBuf)>;
+
+/// Find plugins that have the same file name and architecture as another plugin that was modified
+/// more recently, for `yabridgectl sync --prefer-newer`. Returns a map from the paths of the older
+/// plugins to the path of the newest version. Only plugins for which `is_skipped` returns `false`
+/// are considered, since a newer version that won't be set up for another reason should not cause
+/// the older version to be skipped as well. Versions with the same contents as the newest version
+/// are not different versions, so those are set up as usual.
+fn find_superseded_plugins(
+    results: &BTreeMap<&Path, SearchResults>,
+    is_skipped: impl Fn(&Path, &Plugin) -> bool,
+) -> BTreeMap<PathBuf, PathBuf> {
+    // The plugins grouped by their lowercase file name and architecture. The file name includes the
+    // extension, so different plugin formats are kept apart. The paths are paired with their
+    // modification times and the file that should be hashed to compare their contents.
+    let mut plugins_by_name: BTreeMap<(String, LibArchitecture), PluginVersions> = BTreeMap::new();
+    for (plugin, directory) in results.iter().flat_map(|(directory, search_results)| {
+        search_results
+            .plugins
+            .iter()
+            .map(move |plugin| (plugin, *directory))
+    }) {
+        if is_skipped(directory, plugin) {
+            continue;
+        }
+
+        let (path, module_path) = match plugin {
+            Plugin::Vst2(Vst2Plugin { path, .. }) | Plugin::Clap(ClapPlugin { path, .. }) => {
+                (path.to_owned(), path.to_owned())
+            }
+            Plugin::Vst3(module) => (
+                module.original_path().to_owned(),
+                module.original_module_path(),
+            ),
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let modified = fs::metadata(&module_path)
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(UNIX_EPOCH);
+
+        plugins_by_name
+            .entry((name, plugin.architecture()))
+            .or_default()
+            .push((modified, path, module_path));
+    }
+
+    let mut superseded_plugins = BTreeMap::new();
+    for mut versions in plugins_by_name.into_values() {
+        if versions.len() < 2 {
+            continue;
+        }
+
+        // Sort from newest to oldest, so the first entry is the version we'll set up
+        versions.sort_by(|(a, _, _), (b, _, _)| b.cmp(a));
+        let (_, newest_path, newest_module_path) = versions.remove(0);
+        let newest_hash = utils::hash_file(&newest_module_path).ok();
+        for (_, path, module_path) in versions {
+            let is_same_version =
+                newest_hash.is_some() && utils::hash_file(&module_path).ok() == newest_hash;
+            if !is_same_version {
+                superseded_plugins.insert(path, newest_path.clone());
+            }
+        }
+    }
+
+    superseded_plugins
+}
+
+/// Find the plugins that would be set up at the same location as another plugin. This can happen
+/// when multiple plugin directories share a target directory. Returns the target paths along with
+/// the paths of all plugins that would be set up there, in the order they were found.
+/// `is_case_insensitive` is called with a target directory, and it should return whether that
+/// directory is on a case insensitive file system. Target paths in those directories that only
+/// differ in case also collide. VST3 modules with the same bundle are merged into that bundle
+/// instead, so those only collide when their bundle names differ in case.
+fn find_target_collisions<'a>(
+    plugins: impl IntoIterator<Item = &'a Plugin>,
+    mut is_case_insensitive: impl FnMut(&Path) -> bool,
+) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    // For every (possibly lowercased) target path, the actual target paths and plugin paths
+    let mut plugins_by_target: BTreeMap<PathBuf, Vec<(PathBuf, &Plugin)>> = BTreeMap::new();
+    for plugin in plugins {
+        let target_path = match plugin {
+            Plugin::Vst2(Vst2Plugin { target_path, .. }) => target_path.clone(),
+            Plugin::Vst3(module) => module.target_bundle_home(),
+            Plugin::Clap(clap_plugin) => clap_plugin.target_path(),
+        };
+        let key = match target_path.parent() {
+            Some(directory) if is_case_insensitive(directory) => {
+                PathBuf::from(target_path.to_string_lossy().to_lowercase())
+            }
+            _ => target_path.clone(),
+        };
+
+        plugins_by_target
+            .entry(key)
+            .or_default()
+            .push((target_path, plugin));
+    }
+
+    plugins_by_target
+        .into_values()
+        .filter_map(|plugins| {
+            let (first_target_path, _) = &plugins[0];
+            let plugin_paths: Vec<PathBuf> = plugins
+                .iter()
+                .enumerate()
+                .filter(|(i, (target_path, plugin))| {
+                    *i == 0
+                        || !matches!(plugin, Plugin::Vst3(_))
+                        || target_path != first_target_path
+                })
+                .map(|(_, (_, plugin))| plugin.original_path().to_owned())
+                .collect();
+
+            if plugin_paths.len() > 1 {
+                Some((first_target_path.clone(), plugin_paths))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Find the `Contents/Resources` symlinks in the merged VST3 bundles in `bundle_homes` that should
+/// no longer be there. `resources_bundles` contains the bundles we just symlinked a Windows VST3
+/// bundle's `Resources` directory to, those are left alone. Actual directories are also left alone
+/// since yabridgectl never creates those.
+fn find_stale_resources_symlinks<'a>(
+    bundle_homes: impl IntoIterator<Item = &'a PathBuf>,
+    resources_bundles: &BTreeSet<PathBuf>,
+) -> Vec<NativeFile> {
+    bundle_homes
+        .into_iter()
+        .filter(|bundle_home| !resources_bundles.contains(*bundle_home))
+        .filter_map(|bundle_home| {
+            match get_file_type(bundle_home.join("Contents").join("Resources")) {
+                Some(symlink @ NativeFile::Symlink(_)) => Some(symlink),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Find the `.clap` files and the `.clap-win` symlinks next to them in `directory` that were not
+/// created or updated during this sync. Like with [`find_vst3_orphans()`], target directories may
+/// also contain native plugins, so with `only_yabridge_plugins` only `.clap` files with a
+/// `.clap-win` symlink next to them are considered to be ours.
+pub fn find_clap_orphans(
+    directory: &Path,
+    only_yabridge_plugins: bool,
+    is_known_plugin: &dyn Fn(&Path) -> bool,
+) -> Vec<NativeFile> {
+    WalkDir::new(directory)
+        .follow_links(true)
+        .same_file_system(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| {
+            !entry.file_type().is_dir()
+                && entry.path().extension().and_then(|ext| ext.to_str()) == Some("clap")
+                && !is_known_plugin(entry.path())
+        })
+        .flat_map(|entry| {
+            let windows_path = entry.path().with_extension("clap-win");
+            match get_file_type(windows_path) {
+                Some(windows_file) => vec![get_file_type(entry.into_path()), Some(windows_file)],
+                None if only_yabridge_plugins => Vec::new(),
+                None => vec![get_file_type(entry.into_path())],
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+/// Find merged VST3 bundles in `directory` that were not created or updated during this sync.
+/// Everything in `~/.vst3/yabridge` is managed by yabridgectl, but target directories set with
+/// `yabridgectl set --target` may also contain native plugins. With `only_yabridge_bundles`, only
+/// bundles containing a symlink to a Windows VST3 module are considered to be ours.
+pub fn find_vst3_orphans(
+    directory: &Path,
+    only_yabridge_bundles: bool,
+    is_known_bundle: &dyn Fn(&Path) -> bool,
+) -> Vec<NativeFile> {
+    WalkDir::new(directory)
+        .follow_links(true)
+        .same_file_system(true)
+        .into_iter()
+        .filter_entry(|entry| entry.file_type().is_dir())
+        .filter_map(|e| e.ok())
+        .filter(|entry| {
+            // Add all merged bundles to `orphan_files` if they are not a VST3 module we just
+            // created. We'll ignore symlinks and regular files since those are always user created.
+            let extension = entry
+                .path()
+                .extension()
+                .and_then(|extension| extension.to_str());
+
+            extension == Some("vst3")
+                && !is_known_bundle(entry.path())
+                && (!only_yabridge_bundles || is_yabridge_bundle(entry.path()))
+        })
+        .map(|entry| NativeFile::Directory(entry.path().to_owned()))
+        .collect()
+}
+
+/// Check whether a VST3 bundle is a merged bundle created by yabridgectl. These bundles contain a
+/// symlink to the original Windows VST3 module.
+fn is_yabridge_bundle(bundle: &Path) -> bool {
+    let module_name = match bundle.file_name() {
+        Some(name) => name,
+        None => return false,
+    };
+
+    [LibArchitecture::Dll32, LibArchitecture::Dll64]
+        .iter()
+        .any(|architecture| {
+            let module_path = bundle
+                .join("Contents")
+                .join(architecture.vst_arch())
+                .join(module_name);
+            matches!(get_file_type(module_path), Some(NativeFile::Symlink(_)))
+        })
+}
+
+/// Find VST2 plugins set up by yabridgectl in a target directory that no longer belong to any
+/// plugin. Since these target directories may also contain native VST2 plugins, we'll only
+/// consider `.so` files that have a symlink to a `.dll` file next to them, and we'll remove both.
+pub fn find_target_vst2_orphans(
+    directory: &Path,
+    known_paths: &HashSet<PathBuf>,
+) -> Vec<NativeFile> {
+    files::index(directory, &HashSet::new())
+        .so_files
+        .into_iter()
+        .filter(|file| {
+            !known_paths.contains(file.path())
+                // The `libyabridge-vst3.so` copies in merged VST3 bundles are not VST2 plugins
+                && !file
+                    .path()
+                    .ancestors()
+                    .any(|path| path.extension().and_then(|ext| ext.to_str()) == Some("vst3"))
+        })
+        .filter_map(
+            |file| match get_file_type(file.path().with_extension("dll")) {
+                Some(dll_symlink @ NativeFile::Symlink(_)) => Some(vec![file, dll_symlink]),
+                _ => None,
+            },
+        )
+        .flatten()
+        .collect()
+}
+
+/// The post-installation setup checks shared by a regular sync and by applying a sync plan.
+/// `verify_32bit_host` should be set when 32-bit plugins were found, see [`verify_wine_setup()`].
+fn verify_setup(config: &mut Config, strict_wine: bool, verify_32bit_host: bool) -> Result<()> {
+    // The path setup is to make sure that the `libyabridge-{vst2,vst3}.so` copies can find
+    // `yabridge-host.exe`
+    if config.method == InstallationMethod::Copy
+        || config
+            .directory_overrides
+            .values()
+            .any(|overrides| overrides.method == Some(InstallationMethod::Copy))
+    {
+        logln!("Verifying the path setup");
+        verify_path_setup(config)?;
+    }
+
+    // This check is only performed once per combination of Wine and yabridge versions
+    logln!("Verifying the Wine setup");
+    verify_wine_setup(config, strict_wine, verify_32bit_host)?;
+    logln!("Verified the Wine setup");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::{PluginFormat, Vst3ModuleType};
+    use std::fs::File;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    /// Create a VST2 plugin file with the given contents that was last modified `age` ago.
+    fn vst2_plugin(path: PathBuf, contents: &str, age: Duration) -> Plugin {
+        fs::write(&path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+
+        Plugin::Vst2(Vst2Plugin {
+            target_path: path.with_extension("so"),
+            path,
+            architecture: LibArchitecture::Dll64,
+        })
+    }
+
+    fn search_results(plugins: Vec<Plugin>) -> SearchResults {
+        SearchResults {
+            plugins,
+            ..SearchResults::default()
+        }
+    }
+
+    #[test]
+    fn superseded_by_newer_version() {
+        let (old_dir, new_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let old_path = old_dir.path().join("Plug.dll");
+        let new_path = new_dir.path().join("plug.DLL");
+        let results = BTreeMap::from([
+            (
+                old_dir.path(),
+                search_results(vec![vst2_plugin(
+                    old_path.clone(),
+                    "version 1",
+                    Duration::from_secs(3600),
+                )]),
+            ),
+            (
+                new_dir.path(),
+                search_results(vec![vst2_plugin(
+                    new_path.clone(),
+                    "version 2",
+                    Duration::from_secs(60),
+                )]),
+            ),
+        ]);
+
+        assert_eq!(
+            find_superseded_plugins(&results, |_, _| false),
+            BTreeMap::from([(old_path, new_path)])
+        );
+    }
+
+    #[test]
+    fn identical_copies_are_not_superseded() {
+        let (old_dir, new_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let results = BTreeMap::from([
+            (
+                old_dir.path(),
+                search_results(vec![vst2_plugin(
+                    old_dir.path().join("Plug.dll"),
+                    "version 1",
+                    Duration::from_secs(3600),
+                )]),
+            ),
+            (
+                new_dir.path(),
+                search_results(vec![vst2_plugin(
+                    new_dir.path().join("Plug.dll"),
+                    "version 1",
+                    Duration::from_secs(60),
+                )]),
+            ),
+        ]);
+
+        assert!(find_superseded_plugins(&results, |_, _| false).is_empty());
+    }
+
+    #[test]
+    fn skipped_plugins_do_not_supersede() {
+        let (old_dir, new_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let results = BTreeMap::from([
+            (
+                old_dir.path(),
+                search_results(vec![vst2_plugin(
+                    old_dir.path().join("Plug.dll"),
+                    "version 1",
+                    Duration::from_secs(3600),
+                )]),
+            ),
+            (
+                new_dir.path(),
+                search_results(vec![vst2_plugin(
+                    new_dir.path().join("Plug.dll"),
+                    "version 2",
+                    Duration::from_secs(60),
+                )]),
+            ),
+        ]);
+
+        // The newer version won't be set up, so the older version should be set up instead
+        let new_dir_path = new_dir.path().to_owned();
+        assert!(
+            find_superseded_plugins(&results, |directory, _| directory == new_dir_path).is_empty()
+        );
+        // And if both are skipped then neither is superseded, so they aren't counted twice
+        assert!(find_superseded_plugins(&results, |_, _| true).is_empty());
+    }
+
+    /// A legacy VST3 module at `path` that will be set up in `target_home`. These tests don't touch
+    /// the file system.
+    fn vst3_module(path: &str, architecture: LibArchitecture, target_home: &str) -> Plugin {
+        Plugin::Vst3(Vst3Module {
+            module: Vst3ModuleType::Legacy(PathBuf::from(path)),
+            architecture,
+            subdirectory: None,
+            target_home: PathBuf::from(target_home),
+        })
+    }
+
+    /// A VST2 plugin at `path` that will be set up at `target_path`.
+    fn vst2_target(path: &str, target_path: &str) -> Plugin {
+        Plugin::Vst2(Vst2Plugin {
+            path: PathBuf::from(path),
+            target_path: PathBuf::from(target_path),
+            architecture: LibArchitecture::Dll64,
+        })
+    }
+
+    #[test]
+    fn target_collisions_shared_target() {
+        let plugins = [
+            vst2_target("/a/Plug.dll", "/target/Plug.so"),
+            vst2_target("/b/Plug.dll", "/target/Plug.so"),
+            vst2_target("/b/Other.dll", "/target/Other.so"),
+            vst2_target("/c/Plug.dll", "/other-target/Plug.so"),
+        ];
+
+        assert_eq!(
+            find_target_collisions(&plugins, |_| false),
+            vec![(
+                PathBuf::from("/target/Plug.so"),
+                vec![PathBuf::from("/a/Plug.dll"), PathBuf::from("/b/Plug.dll")]
+            )]
+        );
+    }
+
+    #[test]
+    fn target_collisions_without_collisions() {
+        let plugins = [
+            vst2_target("/a/Plug.dll", "/target/Plug.so"),
+            vst2_target("/a/Other.dll", "/target/Other.so"),
+            vst3_module("/a/Plug.vst3", LibArchitecture::Dll64, "/target"),
+        ];
+
+        assert!(find_target_collisions(&plugins, |_| false).is_empty());
+    }
+
+    #[test]
+    fn target_collisions_merged_vst3_bundles() {
+        // The 32-bit and 64-bit versions of a VST3 module are merged into a single bundle
+        let plugins = [
+            vst3_module("/a/Plug.vst3", LibArchitecture::Dll64, "/target"),
+            vst3_module("/b/Plug.vst3", LibArchitecture::Dll32, "/target"),
+        ];
+        assert!(find_target_collisions(&plugins, |_| false).is_empty());
+
+        // But a VST2 plugin can't share its target with a VST3 bundle
+        let plugins = [
+            vst3_module("/a/Plug.vst3", LibArchitecture::Dll64, "/target"),
+            vst2_target("/b/Plug.dll", "/target/Plug.vst3"),
+        ];
+        assert_eq!(
+            find_target_collisions(&plugins, |_| false),
+            vec![(
+                PathBuf::from("/target/Plug.vst3"),
+                vec![PathBuf::from("/a/Plug.vst3"), PathBuf::from("/b/Plug.dll")]
+            )]
+        );
+    }
+
+    #[test]
+    fn target_collisions_case_insensitive() {
+        let plugins = [
+            vst2_target("/a/Plug.dll", "/fat32/Plug.so"),
+            vst2_target("/b/PLUG.dll", "/fat32/PLUG.so"),
+            vst2_target("/a/Plug.dll", "/ext4/Plug.so"),
+            vst2_target("/b/PLUG.dll", "/ext4/PLUG.so"),
+        ];
+        let is_case_insensitive = |directory: &Path| directory == Path::new("/fat32");
+
+        // Only the target directory on the case insensitive file system has a collision
+        assert_eq!(
+            find_target_collisions(&plugins, is_case_insensitive),
+            vec![(
+                PathBuf::from("/fat32/Plug.so"),
+                vec![PathBuf::from("/a/Plug.dll"), PathBuf::from("/b/PLUG.dll")]
+            )]
+        );
+        assert!(find_target_collisions(&plugins, |_| false).is_empty());
+    }
+
+    #[test]
+    fn target_collisions_case_insensitive_vst3_bundles() {
+        // Bundles with the exact same name are merged, but bundle names that only differ in case
+        // can't both exist on a case insensitive file system
+        let plugins = [
+            vst3_module("/a/Plug.vst3", LibArchitecture::Dll64, "/target"),
+            vst3_module("/b/Plug.vst3", LibArchitecture::Dll32, "/target"),
+            vst3_module("/c/plug.vst3", LibArchitecture::Dll64, "/target"),
+        ];
+
+        assert_eq!(
+            find_target_collisions(&plugins, |_| true),
+            vec![(
+                PathBuf::from("/target/Plug.vst3"),
+                vec![PathBuf::from("/a/Plug.vst3"), PathBuf::from("/c/plug.vst3")]
+            )]
+        );
+        assert!(find_target_collisions(&plugins, |_| false).is_empty());
+    }
+
+    #[test]
+    fn stale_resources_symlinks() {
+        let dir = tempdir().unwrap();
+        let windows_resources = dir.path().join("Plugin.vst3/Contents/Resources");
+        fs::create_dir_all(&windows_resources).unwrap();
+
+        let bundle = |name: &str| {
+            let bundle_home = dir.path().join("yabridge").join(name);
+            fs::create_dir_all(bundle_home.join("Contents")).unwrap();
+            bundle_home
+        };
+        let (current, stale, directory, missing) = (
+            bundle("A.vst3"),
+            bundle("B.vst3"),
+            bundle("C.vst3"),
+            bundle("D.vst3"),
+        );
+        for bundle_home in [&current, &stale] {
+            std::os::unix::fs::symlink(&windows_resources, bundle_home.join("Contents/Resources"))
+                .unwrap();
+        }
+        fs::create_dir(directory.join("Contents/Resources")).unwrap();
+
+        let bundle_homes = [current.clone(), stale.clone(), directory, missing];
+        assert_eq!(
+            find_stale_resources_symlinks(&bundle_homes, &BTreeSet::from([current])),
+            vec![NativeFile::Symlink(stale.join("Contents/Resources"))]
+        );
+    }
+
+    #[test]
+    fn broken_symlinks() {
+        let directory = tempdir().unwrap();
+        let plugin = directory.path().join("Plugin.dll");
+        fs::write(&plugin, "").unwrap();
+        let links = directory.path().join("links");
+        fs::create_dir(&links).unwrap();
+
+        // Both absolute and relative targets, relative to the directory containing the symlink
+        let valid = links.join("valid.dll");
+        std::os::unix::fs::symlink("../Plugin.dll", &valid).unwrap();
+        let dangling = links.join("dangling.dll");
+        std::os::unix::fs::symlink("../Removed.dll", &dangling).unwrap();
+        let dangling_absolute = links.join("dangling-absolute.dll");
+        std::os::unix::fs::symlink(directory.path().join("Removed.dll"), &dangling_absolute)
+            .unwrap();
+
+        assert!(!is_broken_symlink(&valid, &FileState::of(&valid).unwrap()));
+        assert!(is_broken_symlink(
+            &dangling,
+            &FileState::of(&dangling).unwrap()
+        ));
+        assert!(is_broken_symlink(
+            &dangling_absolute,
+            &FileState::of(&dangling_absolute).unwrap()
+        ));
+        // Only symlinks can be broken
+        assert!(!is_broken_symlink(
+            &plugin,
+            &FileState::of(&plugin).unwrap()
+        ));
+        let missing = links.join("missing.dll");
+        assert!(!is_broken_symlink(
+            &missing,
+            &FileState::of(&missing).unwrap()
+        ));
+    }
+
+    #[test]
+    fn skip_reason_order() {
+        let mut config = Config::default();
+        let directory = Path::new("/plugins");
+        let plugin = vst2_target("/plugins/Foo.dll", "/plugins/Foo.so");
+        fn filters(config: &Config) -> PluginFilters<'_> {
+            PluginFilters {
+                config,
+                excluded_architectures: BTreeSet::new(),
+                profile: None,
+                vendors: &[],
+                ignore_case: false,
+                max_plugin_size: None,
+            }
+        }
+        assert_eq!(filters(&config).skip_reason(directory, &plugin), None);
+
+        // When multiple filters apply, the same reason should be reported by both `yabridgectl
+        // sync` and `--explain`
+        let mut excluded = filters(&config);
+        excluded
+            .excluded_architectures
+            .insert(LibArchitecture::Dll64);
+        assert_eq!(
+            excluded.skip_reason(directory, &plugin),
+            Some(SkipReason::ExcludedArchitecture)
+        );
+        let profile = Profile {
+            plugin_dirs: BTreeSet::from([PathBuf::from("/other-plugins")]),
+            plugins: Vec::new(),
+        };
+        excluded.profile = Some(&profile);
+        assert_eq!(
+            excluded.skip_reason(directory, &plugin),
+            Some(SkipReason::NotInProfile)
+        );
+
+        config.blacklist.insert(PathBuf::from("*Foo*"));
+        assert_eq!(
+            filters(&config).skip_reason(directory, &plugin),
+            Some(SkipReason::Blacklisted)
+        );
+        config.formats.remove(&PluginFormat::Vst2);
+        assert_eq!(
+            filters(&config).skip_reason(directory, &plugin),
+            Some(SkipReason::DisabledFormat)
+        );
+    }
+
+    #[test]
+    fn plan_summary_counts_plugins() {
+        let install = |to: &str, from_hash: Option<i64>, existing: FileState| Operation::Install {
+            method: InstallationMethod::Copy,
+            from: PathBuf::from("/usr/lib/libyabridge-vst3.so"),
+            from_hash,
+            to: PathBuf::from(to),
+            existing,
+            link: None,
+            symlink_target: None,
+        };
+        let plan = SyncPlan {
+            operations: vec![
+                // A new plugin, along with a file that's not yabridge's library
+                install(
+                    "/vst3/New.vst3/x86_64-linux/New.so",
+                    Some(1),
+                    FileState::Missing,
+                ),
+                install(
+                    "/vst3/New.vst3/x86_64-win/New.vst3",
+                    None,
+                    FileState::Missing,
+                ),
+                // An outdated copy
+                install(
+                    "/vst3/Outdated.vst3/x86_64-linux/Outdated.so",
+                    Some(1),
+                    FileState::File { hash: 2 },
+                ),
+                // A plugin with multiple changes should still only count as a single plugin when
+                // counting the plugins that were up to date
+                install(
+                    "/vst3/Both.vst3/x86_64-linux/Both.so",
+                    Some(1),
+                    FileState::Missing,
+                ),
+                install(
+                    "/vst3/Both.vst3/i386-linux/Both.so",
+                    Some(1),
+                    FileState::Missing,
+                ),
+                // A leftover file that doesn't belong to any plugin
+                Operation::Remove {
+                    path: PathBuf::from("/vst3/Old.vst3"),
+                    existing: FileState::Directory,
+                },
+            ],
+            ..SyncPlan::default()
+        };
+        let plugin_operations = [
+            (PathBuf::from("/plugins/New.vst3"), 0..2),
+            (PathBuf::from("/plugins/UpToDate.vst3"), 2..2),
+            (PathBuf::from("/plugins/Outdated.vst3"), 2..3),
+            (PathBuf::from("/plugins/Both.vst3"), 3..5),
+        ];
+
+        assert_eq!(
+            plan.summary(&plugin_operations, 1),
+            Summary {
+                added: 3,
+                updated: 1,
+                removed: 1,
+                skipped: 1,
+                failed: 1,
+                ..Summary::default()
+            }
+        );
+        // Applied plans only contain plugins that need to be changed
+        assert_eq!(plan.summary(&[], 0).skipped, 0);
+    }
+
+    #[test]
+    fn broken_symlinks_are_repaired() {
+        let directory = tempdir().unwrap();
+        let library = directory.path().join("libyabridge-vst2.so");
+        fs::write(&library, "yabridge").unwrap();
+        // This points to the location of a previous yabridge installation that no longer exists
+        let target = directory.path().join("Plugin.so");
+        std::os::unix::fs::symlink(directory.path().join("old/libyabridge-vst2.so"), &target)
+            .unwrap();
+
+        let mut plan = SyncPlan::default();
+        let library_hash = utils::hash_file(&library).unwrap();
+        assert!(plan
+            .install(
+                false,
+                InstallationMethod::Symlink,
+                &library,
+                Some(library_hash),
+                &target
+            )
+            .unwrap());
+        assert_eq!(plan.broken_symlinks(), vec![target.as_path()]);
+
+        assert!(execute_plan(&plan, false).unwrap().is_empty());
+        assert_eq!(fs::read_link(&target).unwrap(), library);
+        assert!(target.exists());
+
+        // Once the symlink has been repaired, the next sync doesn't need to change anything
+        let mut plan = SyncPlan::default();
+        assert!(!plan
+            .install(
+                false,
+                InstallationMethod::Symlink,
+                &library,
+                Some(library_hash),
+                &target
+            )
+            .unwrap());
+        assert!(plan.broken_symlinks().is_empty());
+    }
+}
@@ -0,0 +1,611 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `yabridgectl watch`, which keeps running in the background and resyncs whenever plugins get
+//! added, removed or updated, or when the config file changes. The plugin directories are watched
+//! recursively using inotify through the `notify` crate. Installers can touch hundreds of files at
+//! once, so we only resync once the plugin directories have stopped changing for one interval.
+//! `yabridgectl reload` makes a running watch mode instance reread the config file and resync right
+//! away.
+
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use notify::event::{AccessKind, AccessMode, ModifyKind, RemoveKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::actions::prune;
+use crate::actions::sync::{self, SyncOptions};
+use crate::config::{self, Config, CONFIG_FILE_NAME};
+use crate::files::NativeFile;
+use crate::json::JsonFormat;
+use crate::utils;
+
+/// Options passed to `yabridgectl watch`, see `main()` for the definitions of these options.
+pub struct WatchOptions {
+    /// How long the plugin directories should stop changing for before we resync.
+    pub interval: Duration,
+    pub json: Option<JsonFormat>,
+    /// The options used for every sync. `prune` also controls whether leftover files in plugin
+    /// directories that have been removed from the config get removed.
+    pub sync: SyncOptions,
+}
+
+/// The maximum number of changed files listed before a resync. Installers can add hundreds of files
+/// at once, so the rest is only counted.
+const MAX_LISTED_CHANGES: usize = 10;
+
+/// How often we check whether Ctrl+C has been pressed or whether a reload has been requested while
+/// waiting for file system events.
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The name of the file in yabridgectl's cache directory containing the process ID of the running
+/// watch mode instance. Used by `yabridgectl reload`.
+const PID_FILE_NAME: &str = "watch.pid";
+
+/// Set by the `SIGINT` handler installed in [`watch()`]. Watch mode stops once the current sync has
+/// finished.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Set by the `SIGHUP` handler installed in [`watch()`], which is sent by `yabridgectl reload`.
+/// Watch mode rereads the config file and resyncs once the current sync has finished.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigint(_: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn handle_sighup(_: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Contains watch mode's process ID while it's running. The file is removed again when this gets
+/// dropped.
+struct PidFile(PathBuf);
+
+impl PidFile {
+    fn create() -> Result<PidFile> {
+        let path = config::yabridgectl_directories()?
+            .place_cache_file(PID_FILE_NAME)
+            .context("Could not create the PID file")?;
+        fs::write(&path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Could not write to '{}'", path.display()))?;
+
+        Ok(PidFile(path))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// The plugin files that changed since the last sync, along with a short description of how they
+/// changed.
+type Changes = BTreeMap<PathBuf, &'static str>;
+
+/// Watches the plugin directories and the directory containing the config file for changes. Events
+/// are sent to `events`.
+struct DirectoryWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// The plugin directories we're currently watching. These are updated when the config changes
+    /// through [`DirectoryWatcher::watch_plugin_dirs()`].
+    plugin_dirs: BTreeSet<PathBuf>,
+}
+
+impl DirectoryWatcher {
+    /// Start watching the config file. Editors often replace the config file instead of writing to
+    /// it, so we watch the directory containing the config file instead.
+    fn new(config_path: Option<&Path>) -> Result<DirectoryWatcher> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(sender).context("Could not set up the file watcher")?;
+        if let Some(config_directory) = config_path.and_then(Path::parent) {
+            watcher
+                .watch(config_directory, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Could not watch '{}'", config_directory.display()))?;
+        }
+
+        Ok(DirectoryWatcher {
+            watcher,
+            events,
+            plugin_dirs: BTreeSet::new(),
+        })
+    }
+
+    /// Watch all of the plugin directories in `config` and stop watching the plugin directories
+    /// that have been removed from it. Failing to watch a directory, for instance because it does
+    /// not exist, only results in a warning.
+    fn watch_plugin_dirs(&mut self, config: &Config) {
+        for directory in self.plugin_dirs.difference(&config.plugin_dirs) {
+            let _ = self.watcher.unwatch(directory);
+        }
+        for directory in config.plugin_dirs.difference(&self.plugin_dirs) {
+            if let Err(err) = self.watcher.watch(directory, RecursiveMode::Recursive) {
+                utils::warnln!(
+                    "{}: Could not watch '{}' for changes: {}",
+                    "WARNING".yellow(),
+                    directory.display(),
+                    err
+                );
+            }
+        }
+
+        self.plugin_dirs = config.plugin_dirs.clone();
+    }
+}
+
+/// Keep running and resync whenever the plugin directories or the config file change. Changes to
+/// the plugin directories are only acted upon once they have stopped changing for one interval, so
+/// we don't try to set up a plugin while its installer is still copying files. If the config file
+/// contains errors after it has been edited, then we'll keep using the last valid config until
+/// those errors have been fixed. Pressing Ctrl+C stops watch mode, but a sync that's in progress
+/// will be allowed to finish first. Running `yabridgectl reload` has the same effect as changing the
+/// config file, see [`reload()`].
+pub fn watch(config: &mut Config, options: &WatchOptions) -> Result<()> {
+    let config_path = config::find_config_file()?;
+    let _pid_file = PidFile::create()?;
+
+    // Interrupting a sync halfway through would leave the plugins half set up
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_sigint as *const () as libc::sighandler_t,
+        );
+        libc::signal(
+            libc::SIGHUP,
+            handle_sighup as *const () as libc::sighandler_t,
+        );
+    };
+
+    let mut watcher = DirectoryWatcher::new(config_path.as_deref())?;
+    watcher.watch_plugin_dirs(config);
+    println!(
+        "Watching {} plugin directories for changes, press Ctrl+C to stop",
+        config.plugin_dirs.len()
+    );
+
+    // Set when a sync is needed, but it has not been performed yet. Either because the plugin
+    // directories were still changing, or because another yabridgectl instance was running.
+    let mut pending = !sync_once(config, options);
+    let mut config_mtime = modified(config_path.as_deref());
+    // The plugin files that changed since the last sync, used to report what triggered the next
+    // sync, and the last time one of those files changed
+    let mut changes = Changes::new();
+    let mut last_change: Option<Instant> = if pending { Some(Instant::now()) } else { None };
+    loop {
+        let mut config_changed = false;
+        match watcher.events.recv_timeout(SIGNAL_CHECK_INTERVAL) {
+            Ok(Ok(event)) => {
+                config_changed = config_path
+                    .as_ref()
+                    .is_some_and(|config_path| event.paths.contains(config_path));
+
+                if collect_changes(&event, &mut changes) {
+                    // We'll wait for the directories to settle down before syncing
+                    pending = true;
+                    last_change = Some(Instant::now());
+                }
+            }
+            Ok(Err(err)) => utils::warnln!("{}: {}", "WARNING".yellow(), err),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow!("The file watcher stopped unexpectedly"))
+            }
+        }
+
+        if STOP_REQUESTED.load(Ordering::Relaxed) {
+            println!("\nStopping watch mode");
+            return Ok(());
+        }
+
+        let reload_requested = RELOAD_REQUESTED.swap(false, Ordering::Relaxed);
+        let new_config_mtime = modified(config_path.as_deref());
+        if reload_requested || (config_changed && new_config_mtime != config_mtime) {
+            config_mtime = new_config_mtime;
+
+            match Config::read() {
+                Ok(new_config) => {
+                    if reload_requested {
+                        println!("\nReloading the config file");
+                    } else {
+                        println!("\nThe config file has changed, reloading");
+                    }
+                    report_removed_directories(config, &new_config, options.sync.prune);
+                    *config = new_config;
+                    watcher.watch_plugin_dirs(config);
+                    pending = true;
+                }
+                Err(err) => utils::errorln!(
+                    "\n{}: {:#}\nKeeping the previous config until this has been fixed",
+                    "WARNING".yellow(),
+                    err
+                ),
+            }
+        }
+
+        let is_settled = match last_change {
+            Some(last_change) => last_change.elapsed() >= options.interval,
+            None => true,
+        };
+        if pending && is_settled {
+            report_changes(&changes);
+            pending = !sync_once(config, options);
+            if pending {
+                // Another yabridgectl instance is running, so we'll try again after one interval
+                last_change = Some(Instant::now());
+            } else {
+                changes.clear();
+                last_change = None;
+            }
+
+            // Syncing writes to the config file and it may add files to the plugin directories, so
+            // we shouldn't treat those changes as new changes
+            config_mtime = modified(config_path.as_deref());
+            while watcher.events.try_recv().is_ok() {}
+        }
+    }
+}
+
+/// Record the changes to plugin files described by `event` in `changes`. Other files and access
+/// events are ignored. Removing a directory counts as a change since it may have contained plugins.
+/// Returns whether anything was recorded.
+fn collect_changes(event: &Event, changes: &mut Changes) -> bool {
+    let is_removed_directory = event.kind == EventKind::Remove(RemoveKind::Folder);
+    let mut has_changes = false;
+    let mut record = |path: &Path, change: &'static str| {
+        if is_plugin_file(path) || is_removed_directory {
+            // New files also generate modification events while they're being written
+            if change == "modified" {
+                changes.entry(path.to_owned()).or_insert(change);
+            } else {
+                changes.insert(path.to_owned(), change);
+            }
+            has_changes = true;
+        }
+    };
+
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            for path in &event.paths {
+                record(path, "added");
+            }
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            for path in &event.paths {
+                record(path, "removed");
+            }
+        }
+        // Renames within the watched directories contain both the old and the new path
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            if let [from, to] = event.paths.as_slice() {
+                record(from, "removed");
+                record(to, "added");
+            }
+        }
+        EventKind::Modify(_) | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+            for path in &event.paths {
+                record(path, "modified");
+            }
+        }
+        _ => (),
+    }
+
+    has_changes
+}
+
+/// Whether `path` looks like a Windows VST2, VST3, or CLAP plugin file.
+fn is_plugin_file(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => {
+            extension.eq_ignore_ascii_case("dll")
+                || extension.eq_ignore_ascii_case("vst3")
+                || extension.eq_ignore_ascii_case("clap")
+        }
+        None => false,
+    }
+}
+
+/// Print the plugin files that changed since the last sync, see [`collect_changes()`].
+fn report_changes(changes: &Changes) {
+    if changes.is_empty() {
+        return;
+    }
+
+    println!("\nDetected {} changed plugin files:", changes.len());
+    for (path, change) in changes.iter().take(MAX_LISTED_CHANGES) {
+        println!("- {} '{}'", change, path.display());
+    }
+    if changes.len() > MAX_LISTED_CHANGES {
+        println!("- and {} more", changes.len() - MAX_LISTED_CHANGES);
+    }
+}
+
+/// Perform a single sync while holding the lock. Errors are printed instead of returned so watch
+/// mode keeps running. Returns `false` if we could not sync because another instance of yabridgectl
+/// was running, in which case we should try again later.
+fn sync_once(config: &mut Config, options: &WatchOptions) -> bool {
+    let _lock = match config::acquire_lock() {
+        Ok(lock) => lock,
+        Err(_) => return false,
+    };
+
+    match sync::do_sync(config, &options.sync) {
+        Ok(summary) => {
+            if let Err(err) = summary.print(options.json) {
+                utils::errorln!("{}: {:#}", "Error".red(), err);
+            }
+        }
+        Err(err) => utils::errorln!("{}: {:#}", "Error".red(), err),
+    }
+
+    true
+}
+
+/// When a plugin directory gets removed from the config, the files yabridgectl set up for the
+/// plugins in there will be left behind. `yabridgectl rm` would ask the user what to do with them,
+/// but since we can't ask questions here we'll either remove them or list them depending on whether
+/// the `--prune` option was passed. This only considers files that can be proven to belong to
+/// yabridge, see [`prune::find_directory_files()`], so native plugins in those directories are left
+/// alone.
+fn report_removed_directories(old_config: &Config, new_config: &Config, prune: bool) {
+    let removed_directories: BTreeSet<&PathBuf> = old_config
+        .plugin_dirs
+        .difference(&new_config.plugin_dirs)
+        .collect();
+    for directory in removed_directories {
+        // The old config contains the removed directory's target directory overrides
+        let bridged_files = match prune::find_directory_files(old_config, directory) {
+            Ok(files) => files,
+            Err(err) => {
+                utils::errorln!("{}: {:#}", "Error".red(), err);
+                continue;
+            }
+        };
+        if bridged_files.is_empty() {
+            continue;
+        }
+
+        if prune {
+            println!(
+                "Removing {} files set up for plugins in '{}'",
+                bridged_files.len(),
+                directory.display()
+            );
+            for file in &bridged_files {
+                let result = match file {
+                    NativeFile::Directory(path) => utils::remove_dir_all(path),
+                    NativeFile::Regular(path) | NativeFile::Symlink(path) => {
+                        utils::remove_file(path)
+                    }
+                };
+                if let Err(err) = result {
+                    utils::errorln!("{}: {:#}", "Error".red(), err);
+                }
+            }
+        } else {
+            utils::warnln!(
+                "{}",
+                utils::wrap(&format!(
+                    "{}: '{}' is no longer a plugin directory, but {} files set up for the \
+                     plugins in there have been left behind. Run 'yabridgectl watch' with \
+                     '--prune' to remove them automatically:",
+                    "WARNING".yellow(),
+                    directory.display(),
+                    bridged_files.len()
+                ))
+            );
+            for file in &bridged_files {
+                utils::warnln!("- {}", file.path().display());
+            }
+        }
+    }
+}
+
+/// Make the running `yabridgectl watch` instance reread the config file and resync, for
+/// `yabridgectl reload`. This sends `SIGHUP` to the process ID stored in the PID file. Returns an
+/// error if watch mode is not running.
+pub fn reload() -> Result<()> {
+    let pid_path = config::yabridgectl_directories()?
+        .get_cache_home()
+        .join(PID_FILE_NAME);
+    let pid: libc::pid_t = match fs::read_to_string(&pid_path) {
+        Ok(contents) => contents
+            .trim()
+            .parse()
+            .with_context(|| format!("'{}' does not contain a process ID", pid_path.display()))?,
+        Err(_) => return Err(anyhow!("'yabridgectl watch' is not running")),
+    };
+
+    // The PID file may have been left behind if watch mode got killed, and the process ID may have
+    // since been reused by another process
+    let is_yabridgectl = fs::read_to_string(format!("/proc/{}/comm", pid))
+        .is_ok_and(|name| name.trim() == "yabridgectl");
+    if !is_yabridgectl || unsafe { libc::kill(pid, libc::SIGHUP) } != 0 {
+        let _ = fs::remove_file(&pid_path);
+        return Err(anyhow!("'yabridgectl watch' is not running"));
+    }
+
+    println!("Asked 'yabridgectl watch' to reload the config file");
+
+    Ok(())
+}
+
+/// Print a systemd user unit that runs `yabridgectl watch` in the background. The unit uses the
+/// current yabridgectl binary, and it copies over the environment variables that affect which config
+/// file and which Wine installation will be used, since systemd services don't inherit the user's
+/// shell environment.
+pub fn generate_service() -> Result<()> {
+    let yabridgectl = env::current_exe().context("Could not find the yabridgectl binary")?;
+    let config_path_override = config::config_path_override();
+    let config_path = match (config::find_config_file()?, &config_path_override) {
+        (Some(path), _) => path,
+        (None, Some(path)) => path.clone(),
+        (None, None) => config::yabridgectl_directories()?
+            .get_config_home()
+            .join(CONFIG_FILE_NAME),
+    };
+    // `main()` adds yabridge's data directory to the search path, and it will do that again when
+    // the service starts
+    let yabridge_data_home = format!(
+        ":{}",
+        config::yabridge_directories()?.get_data_home().display()
+    );
+
+    println!("[Unit]");
+    println!("Description=Set up yabridge for new and updated plugins");
+    println!("Documentation=https://github.com/robbert-vdh/yabridge/tree/master/tools/yabridgectl");
+    println!();
+    println!("[Service]");
+    println!("Type=simple");
+    println!("# Uses the config file at '{}'", config_path.display());
+    match &config_path_override {
+        Some(path) => println!(
+            "ExecStart={} --config {} watch",
+            systemd_quote(&yabridgectl.to_string_lossy()),
+            systemd_quote(&path.to_string_lossy())
+        ),
+        None => println!(
+            "ExecStart={} watch",
+            systemd_quote(&yabridgectl.to_string_lossy())
+        ),
+    }
+    // Syncing again after a failure is harmless, but we shouldn't keep restarting in a loop
+    println!("Restart=on-failure");
+    println!("RestartSec=30");
+    for variable in &[
+        "PATH",
+        "XDG_CONFIG_HOME",
+        "XDG_DATA_HOME",
+        "XDG_CACHE_HOME",
+        "WINELOADER",
+    ] {
+        if let Ok(value) = env::var(variable) {
+            let value = value.strip_suffix(&yabridge_data_home).unwrap_or(&value);
+            println!(
+                "Environment={}",
+                systemd_quote(&format!("{}={}", variable, value))
+            );
+        }
+    }
+    println!();
+    println!("[Install]");
+    println!("WantedBy=default.target");
+
+    Ok(())
+}
+
+/// Quote a value for use in a systemd unit file. Quotes and backslashes are escaped, and percent
+/// signs are escaped so systemd won't treat them as specifiers.
+fn systemd_quote(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+    )
+}
+
+/// The modification time for the config file, if it exists.
+fn modified(path: Option<&Path>) -> Option<SystemTime> {
+    path.and_then(|path| fs::metadata(path).ok())
+        .and_then(|metadata| metadata.modified().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange};
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        Event {
+            paths: paths.iter().map(PathBuf::from).collect(),
+            ..Event::new(kind)
+        }
+    }
+
+    #[test]
+    fn collect_plugin_file_changes() {
+        let mut changes = Changes::new();
+        assert!(collect_changes(
+            &event(EventKind::Create(CreateKind::File), &["/plugins/New.dll"]),
+            &mut changes
+        ));
+        assert!(collect_changes(
+            &event(
+                EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+                &["/plugins/New.dll", "/plugins/Updated.VST3"]
+            ),
+            &mut changes
+        ));
+        assert!(collect_changes(
+            &event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["/plugins/Old.clap", "/plugins/Renamed.clap"]
+            ),
+            &mut changes
+        ));
+        assert!(collect_changes(
+            &event(EventKind::Remove(RemoveKind::Folder), &["/plugins/Vendor"]),
+            &mut changes
+        ));
+
+        assert_eq!(
+            changes,
+            Changes::from([
+                (PathBuf::from("/plugins/New.dll"), "added"),
+                (PathBuf::from("/plugins/Old.clap"), "removed"),
+                (PathBuf::from("/plugins/Renamed.clap"), "added"),
+                (PathBuf::from("/plugins/Updated.VST3"), "modified"),
+                (PathBuf::from("/plugins/Vendor"), "removed"),
+            ])
+        );
+    }
+
+    #[test]
+    fn ignore_other_changes() {
+        let mut changes = Changes::new();
+        // Syncing reads the plugin files, and installers create all kinds of other files
+        assert!(!collect_changes(
+            &event(
+                EventKind::Access(AccessKind::Close(AccessMode::Read)),
+                &["/plugins/Plugin.dll"]
+            ),
+            &mut changes
+        ));
+        assert!(!collect_changes(
+            &event(
+                EventKind::Create(CreateKind::File),
+                &["/plugins/readme.txt"]
+            ),
+            &mut changes
+        ));
+        assert!(!collect_changes(
+            &event(EventKind::Remove(RemoveKind::File), &["/plugins/Plugin.so"]),
+            &mut changes
+        ));
+        assert!(changes.is_empty());
+    }
+}
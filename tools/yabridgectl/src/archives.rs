@@ -0,0 +1,276 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Extracting plugins that are distributed as `.zip` archives for `yabridgectl sync --extract`.
+//! Archives are extracted to `$XDG_DATA_HOME/yabridgectl/archives` so we never write files to the
+//! plugin directories other than the ones yabridge needs. A symlink with the archive's name without
+//! the `.zip` extension is created next to the archive that points to the extracted files, so the
+//! extracted plugins are picked up by the regular search and they run in the same Wine prefix as
+//! the other plugins in that directory. We use the `unzip` program for this.
+
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cache::{self, ExtractedArchive, ExtractedArchives, ScannedArchive};
+use crate::config::{yabridgectl_directories, Config};
+use crate::files::{self, NativeFile};
+use crate::utils::{self, get_file_type};
+
+/// The name of the directory archives are extracted to, relative to `$XDG_DATA_HOME/yabridgectl`.
+const EXTRACTION_DIRECTORY_NAME: &str = "archives";
+
+/// Check whether a `.zip` archive contains any VST2 or VST3 plugins. We can't know for sure without
+/// extracting the archive, so this checks for `.dll` and `.vst3` files. Listing the contents of a
+/// large archive takes a while, so the result is cached in `extracted_archives` until the archive's
+/// size or modification time changes. Returns an error if `unzip` could not be run or if the file is
+/// not a valid archive.
+pub fn contains_plugins(
+    archive: &Path,
+    extracted_archives: &mut ExtractedArchives,
+) -> Result<bool> {
+    let (size, modified) = cache::file_size_and_mtime(archive)?;
+    match extracted_archives.scanned.get(archive) {
+        Some(scanned) if scanned.size == size && scanned.modified == modified => {
+            return Ok(scanned.contains_plugins)
+        }
+        _ => (),
+    }
+
+    let output = Command::new("unzip")
+        .arg("-Z1")
+        .arg(archive)
+        .output()
+        .context("Could not run 'unzip', make sure it is installed")?;
+    if !output.status.success() {
+        return Err(anyhow!("'{}' is not a valid archive", archive.display()));
+    }
+
+    let contains_plugins = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|entry| {
+            let entry = entry.to_lowercase();
+            entry.ends_with(".dll") || entry.ends_with(".vst3") || entry.contains(".vst3/")
+        });
+    extracted_archives.scanned.insert(
+        archive.to_owned(),
+        ScannedArchive {
+            size,
+            modified,
+            contains_plugins,
+        },
+    );
+
+    Ok(contains_plugins)
+}
+
+/// The directory an archive will be extracted to. This is a directory in
+/// `$XDG_DATA_HOME/yabridgectl/archives` named after the archive, with a hash of the archive's path
+/// to keep archives with the same name apart.
+pub fn extraction_directory(archive: &Path) -> Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    archive.hash(&mut hasher);
+    let name = format!(
+        "{}-{:016x}",
+        archive
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default(),
+        hasher.finish()
+    );
+
+    Ok(yabridgectl_directories()?
+        .get_data_home()
+        .join(EXTRACTION_DIRECTORY_NAME)
+        .join(name))
+}
+
+/// The symlink next to an archive that points to the extracted files. This is the archive's path
+/// without the `.zip` extension.
+pub fn link_path(archive: &Path) -> PathBuf {
+    archive.with_extension("")
+}
+
+/// Extract all archives containing plugins in the plugin directories. Archives that have already
+/// been extracted are only extracted again when they have changed. If an archive has been removed,
+/// then the files extracted from it will be removed when `prune` is set. Otherwise we'll print a
+/// warning. Returns the number of archives that were extracted.
+pub fn extract_archives(config: &Config, prune: bool) -> Result<usize> {
+    let mut extracted_archives = ExtractedArchives::read();
+    let mut num_extracted = 0;
+    for directory in &config.plugin_dirs {
+        for archive in files::index(directory, &config.index_blacklist(directory)).zip_files {
+            let (size, modified) = cache::file_size_and_mtime(&archive)?;
+            match extracted_archives.archives.get(&archive) {
+                Some(previous)
+                    if previous.size == size
+                        && previous.modified == modified
+                        && previous.directory.exists() =>
+                {
+                    continue
+                }
+                // We'll extract the updated archive from scratch
+                Some(previous) => remove_extracted_files(&archive, previous)?,
+                None => {
+                    match contains_plugins(&archive, &mut extracted_archives) {
+                        Ok(true) => (),
+                        Ok(false) => continue,
+                        Err(err) => {
+                            utils::warnln!("{}: {:#}", "WARNING".red(), err);
+                            continue;
+                        }
+                    }
+
+                    // We should never overwrite files that we did not create ourselves
+                    let link = link_path(&archive);
+                    if get_file_type(link.clone()).is_some() {
+                        utils::warnln!(
+                            "{}: Not extracting '{}' because '{}' already exists",
+                            "WARNING".red(),
+                            archive.display(),
+                            link.display()
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            println!("Extracting '{}'", archive.display());
+            let target = extraction_directory(&archive)?;
+            if target.exists() {
+                utils::remove_dir_all(&target)?;
+            }
+            extract(&archive, &target)?;
+            utils::symlink(&target, link_path(&archive))?;
+            extracted_archives.archives.insert(
+                archive.clone(),
+                ExtractedArchive {
+                    size,
+                    modified,
+                    directory: target,
+                },
+            );
+            num_extracted += 1;
+        }
+    }
+
+    // Archives that have been removed, or that are no longer part of a plugin directory
+    let leftover_archives: Vec<PathBuf> = extracted_archives
+        .archives
+        .keys()
+        .filter(|archive| {
+            !archive.exists()
+                || !config
+                    .plugin_dirs
+                    .iter()
+                    .any(|directory| archive.starts_with(directory))
+        })
+        .cloned()
+        .collect();
+    if !leftover_archives.is_empty() {
+        if prune {
+            println!("Removing files extracted from archives that no longer exist:");
+        } else {
+            println!(
+                "Found files extracted from archives that no longer exist, rerun with the \
+                 '--prune' option to remove them:"
+            );
+        }
+
+        for archive in leftover_archives {
+            let extracted = &extracted_archives.archives[&archive];
+            println!("- {}", extracted.directory.display());
+            if prune {
+                remove_extracted_files(&archive, extracted)?;
+                extracted_archives.archives.remove(&archive);
+            }
+        }
+        println!();
+    }
+
+    extracted_archives
+        .scanned
+        .retain(|archive, _| archive.exists());
+    extracted_archives.write()?;
+
+    Ok(num_extracted)
+}
+
+/// Remove the files extracted from an archive along with the symlink next to the archive. Archives
+/// extracted by older versions of yabridgectl were extracted right next to the archive, in which
+/// case `extracted.directory` is that directory.
+fn remove_extracted_files(archive: &Path, extracted: &ExtractedArchive) -> Result<()> {
+    if extracted.directory.exists() {
+        utils::remove_dir_all(&extracted.directory)?;
+    }
+
+    let link = link_path(archive);
+    if let Some(NativeFile::Symlink(_)) = get_file_type(link.clone()) {
+        utils::remove_file(&link)?;
+    }
+
+    Ok(())
+}
+
+/// Extract an archive to `directory`, keeping the archive's directory structure intact.
+fn extract(archive: &Path, directory: &Path) -> Result<()> {
+    let status = Command::new("unzip")
+        .arg("-q")
+        .arg("-o")
+        .arg(archive)
+        .arg("-d")
+        .arg(directory)
+        .status()
+        .context("Could not run 'unzip', make sure it is installed")?;
+    if !status.success() {
+        return Err(anyhow!("Could not extract '{}'", archive.display()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn contains_plugins_uses_cached_result() {
+        let directory = tempdir().unwrap();
+        let archive = directory.path().join("Plugins.zip");
+        fs::write(&archive, "not actually an archive").unwrap();
+        let (size, modified) = cache::file_size_and_mtime(&archive).unwrap();
+
+        let mut extracted_archives = ExtractedArchives::default();
+        extracted_archives.scanned.insert(
+            archive.clone(),
+            ScannedArchive {
+                size,
+                modified,
+                contains_plugins: true,
+            },
+        );
+        assert!(contains_plugins(&archive, &mut extracted_archives).unwrap());
+
+        // After the archive changes it has to be listed again, which fails for this file
+        fs::write(&archive, "still not an archive").unwrap();
+        assert!(contains_plugins(&archive, &mut extracted_archives).is_err());
+    }
+}
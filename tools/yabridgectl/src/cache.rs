@@ -0,0 +1,702 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! State yabridgectl keeps between runs in `$XDG_CACHE_HOME/yabridgectl`. Unlike the config file
+//! this is never edited by the user, and deleting it is always safe.
+
+use anyhow::{Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::yabridgectl_directories;
+use crate::files::LibArchitecture;
+use crate::utils;
+
+/// The name of the file containing the installed plugins, relative to
+/// `$XDG_CACHE_HOME/yabridgectl`.
+const INSTALLED_PLUGINS_FILE_NAME: &str = "installed.toml";
+/// The name of the file containing the archives extracted with `yabridgectl sync --extract`,
+/// relative to `$XDG_CACHE_HOME/yabridgectl`.
+const EXTRACTED_ARCHIVES_FILE_NAME: &str = "extracted.toml";
+/// The name of the file containing the hashes of the copies of yabridge's libraries, relative to
+/// `$XDG_CACHE_HOME/yabridgectl`. See [`CopyHashes`].
+const COPY_HASHES_FILE_NAME: &str = "copies.toml";
+/// The name of the file containing the directories indexed during the last sync, relative to
+/// `$XDG_CACHE_HOME/yabridgectl`. See [`ScanCache`].
+const SCAN_CACHE_FILE_NAME: &str = "scan.toml";
+/// The name of the file containing the classified `.dll` and `.vst3` files, relative to
+/// `$XDG_CACHE_HOME/yabridgectl`. See [`PluginIndex`].
+const PLUGIN_INDEX_FILE_NAME: &str = "index.toml";
+/// The name of the hash manifest for a plugin directory, relative to that plugin directory. See
+/// [`HashManifest`].
+pub const HASH_MANIFEST_FILE_NAME: &str = ".yabridgectl-hashes.toml";
+/// Modification times more than this many seconds in the future are considered to be the result of
+/// a wrong system clock. See [`is_in_future()`].
+const MAX_CLOCK_SKEW: u64 = 60 * 60 * 24;
+
+/// The plugins set up during previous syncs. We use this to detect plugins that have been moved to
+/// another location, so we can move their existing installation instead of creating a new one.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct InstalledPlugins {
+    /// Information about the installed plugins, keyed by the path to the Windows plugin. For VST3
+    /// modules this is the path to the `.vst3` file or bundle.
+    pub plugins: BTreeMap<PathBuf, InstalledPlugin>,
+}
+
+/// A plugin yabridgectl set up during a previous sync.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstalledPlugin {
+    /// The hash of the plugin's `.dll` or `.vst3` file, see [`utils::hash_file()`]. This is only
+    /// used to detect moved plugins, so plugins are only hashed when they don't have an
+    /// installation yet. This is `None` if the plugin has changed since it was hashed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<i64>,
+    /// The plugin file's size in bytes. Used together with `modified` to avoid rehashing unchanged
+    /// plugins.
+    pub size: u64,
+    /// The plugin file's modification time in seconds since the Unix epoch.
+    pub modified: u64,
+    /// The `.so` file for VST2 plugins, or the merged bundle for VST3 modules.
+    pub target: PathBuf,
+}
+
+impl InstalledPlugins {
+    /// Read the installed plugins from the cache. If the file doesn't exist or if it cannot be
+    /// parsed, then we'll just start over with an empty list.
+    pub fn read() -> InstalledPlugins {
+        yabridgectl_directories()
+            .ok()
+            .and_then(|dirs| dirs.find_cache_file(INSTALLED_PLUGINS_FILE_NAME))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|toml_str| toml::from_str(&toml_str).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the installed plugins to the cache.
+    pub fn write(&self) -> Result<()> {
+        let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
+        let cache_path = yabridgectl_directories()?
+            .place_cache_file(INSTALLED_PLUGINS_FILE_NAME)
+            .context("Could not create cache file")?;
+
+        fs::write(&cache_path, toml_str)
+            .with_context(|| format!("Failed to write cache file to '{}'", cache_path.display()))
+    }
+}
+
+/// The hashes of the copies of yabridge's libraries found during previous syncs. Checking whether a
+/// copy is up to date would otherwise require reading every copy during every sync. A hash is only
+/// reused when the copy's size and modification time haven't changed since it was hashed. After
+/// updating yabridge these hashes won't match the new libraries anymore, so the copies will still
+/// be replaced as usual.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct CopyHashes {
+    /// The hashed copies, keyed by their path.
+    pub files: BTreeMap<PathBuf, HashedCopy>,
+}
+
+/// A single copy in [`CopyHashes`].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HashedCopy {
+    /// The hash of the file, see [`utils::hash_file()`].
+    pub hash: i64,
+    /// The file's size in bytes when it was hashed.
+    pub size: u64,
+    /// The file's modification time in seconds since the Unix epoch when it was hashed.
+    pub modified: u64,
+}
+
+impl CopyHashes {
+    /// Read the hashes from the cache. If the file doesn't exist or if it cannot be parsed, then
+    /// every copy will simply be hashed again.
+    pub fn read() -> CopyHashes {
+        yabridgectl_directories()
+            .ok()
+            .and_then(|dirs| dirs.find_cache_file(COPY_HASHES_FILE_NAME))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|toml_str| toml::from_str(&toml_str).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the hashes to the cache. Entries for files that no longer exist are dropped.
+    pub fn write(&mut self) -> Result<()> {
+        self.files.retain(|path, _| path.is_file());
+
+        let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
+        let cache_path = yabridgectl_directories()?
+            .place_cache_file(COPY_HASHES_FILE_NAME)
+            .context("Could not create cache file")?;
+
+        fs::write(&cache_path, toml_str)
+            .with_context(|| format!("Failed to write cache file to '{}'", cache_path.display()))
+    }
+
+    /// Hash a file, reusing the previous hash if the file hasn't changed since it was last hashed.
+    pub fn hash(&mut self, path: &Path) -> Result<i64> {
+        let (size, modified) = file_size_and_mtime(path)?;
+        match self.files.get(path) {
+            Some(entry)
+                if entry.size == size && entry.modified == modified && !is_in_future(modified) =>
+            {
+                return Ok(entry.hash);
+            }
+            _ => (),
+        }
+
+        let hash = utils::hash_file(path)?;
+        // A file modified during the current second could still be modified again without changing
+        // its modification time, so the hash for those files can't be trusted later
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        if modified < now {
+            self.files.insert(
+                path.to_owned(),
+                HashedCopy {
+                    hash,
+                    size,
+                    modified,
+                },
+            );
+        }
+
+        Ok(hash)
+    }
+}
+
+/// The archives extracted during previous syncs with `yabridgectl sync --extract`. We need to keep
+/// track of these so we can extract an archive again when it changes, and so we can clean up the
+/// extracted files after the archive has been removed.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct ExtractedArchives {
+    /// The extracted archives, keyed by the path to the archive.
+    pub archives: BTreeMap<PathBuf, ExtractedArchive>,
+    /// Whether the archives found in the plugin directories contain any plugins, keyed by the path
+    /// to the archive. Listing the contents of a large archive is slow, so this is only done again
+    /// when the archive has changed. See [`crate::archives::contains_plugins()`].
+    pub scanned: BTreeMap<PathBuf, ScannedArchive>,
+}
+
+/// An archive extracted during a previous sync.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedArchive {
+    /// The archive's size in bytes when it was extracted.
+    pub size: u64,
+    /// The archive's modification time in seconds since the Unix epoch when it was extracted.
+    pub modified: u64,
+    /// The directory the archive was extracted to. See [`crate::archives::extraction_directory()`].
+    pub directory: PathBuf,
+}
+
+/// The result of checking whether an archive contains any plugins.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScannedArchive {
+    /// The archive's size in bytes when it was checked.
+    pub size: u64,
+    /// The archive's modification time in seconds since the Unix epoch when it was checked.
+    pub modified: u64,
+    pub contains_plugins: bool,
+}
+
+impl ExtractedArchives {
+    /// Read the extracted archives from the cache. If the file doesn't exist or if it cannot be
+    /// parsed, then we'll start over with an empty list.
+    pub fn read() -> ExtractedArchives {
+        yabridgectl_directories()
+            .ok()
+            .and_then(|dirs| dirs.find_cache_file(EXTRACTED_ARCHIVES_FILE_NAME))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|toml_str| toml::from_str(&toml_str).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the extracted archives to the cache.
+    pub fn write(&self) -> Result<()> {
+        let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
+        let cache_path = yabridgectl_directories()?
+            .place_cache_file(EXTRACTED_ARCHIVES_FILE_NAME)
+            .context("Could not create cache file")?;
+
+        fs::write(&cache_path, toml_str)
+            .with_context(|| format!("Failed to write cache file to '{}'", cache_path.display()))
+    }
+}
+
+/// The contents of every directory indexed during the last sync, used by `yabridgectl sync --fast`.
+/// A directory's modification time only changes when files are added to, removed from, or renamed
+/// within that directory, so if a directory has not been modified since the last scan started, then
+/// we can reuse its contents from this cache instead of reading it again. This file is only written
+/// once `--fast` has been used.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct ScanCache {
+    /// When the last scan started, in seconds since the Unix epoch. Directories with an older
+    /// modification time have not changed since then.
+    pub watermark: u64,
+    /// The indexed directories, keyed by their path.
+    pub directories: BTreeMap<PathBuf, ScannedDirectory>,
+}
+
+/// The relevant contents of a single directory indexed during the last sync.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ScannedDirectory {
+    /// The `.dll`, `.vst3`, `.lnk`, `.zip`, and `.so` files directly inside of this directory.
+    pub files: Vec<PathBuf>,
+    /// The `.so` files from `files` that are symlinks.
+    pub symlinks: Vec<PathBuf>,
+    /// The directories directly inside of this directory, including symlinks to directories.
+    pub subdirectories: Vec<PathBuf>,
+}
+
+impl ScanCache {
+    /// Read the scan cache from the last sync. If the file doesn't exist or if it cannot be parsed,
+    /// then we'll just index everything again.
+    pub fn read() -> ScanCache {
+        yabridgectl_directories()
+            .ok()
+            .and_then(|dirs| dirs.find_cache_file(SCAN_CACHE_FILE_NAME))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|toml_str| toml::from_str(&toml_str).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether `--fast` has been used before. Regular syncs only update the cache when it exists.
+    pub fn exists() -> bool {
+        yabridgectl_directories()
+            .ok()
+            .and_then(|dirs| dirs.find_cache_file(SCAN_CACHE_FILE_NAME))
+            .is_some()
+    }
+
+    /// Write the scan cache.
+    pub fn write(&self) -> Result<()> {
+        let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
+        let cache_path = yabridgectl_directories()?
+            .place_cache_file(SCAN_CACHE_FILE_NAME)
+            .context("Could not create cache file")?;
+
+        fs::write(&cache_path, toml_str)
+            .with_context(|| format!("Failed to write cache file to '{}'", cache_path.display()))
+    }
+}
+
+/// Whether the `.dll` and `.vst3` files found during the last sync are plugins, and what their
+/// architecture is. Finding this out requires running `winedump` for every file, which is by far
+/// the slowest part of searching for plugins. This index is refreshed during every sync, and
+/// read-only commands like `yabridgectl status` only use it without updating it.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct PluginIndex {
+    /// The classified files, keyed by their path.
+    pub files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+/// A single `.dll` or `.vst3` file in the [`PluginIndex`].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexedFile {
+    /// The file's size in bytes.
+    pub size: u64,
+    /// The file's modification time in seconds since the Unix epoch.
+    pub modified: u64,
+    /// The file's hash, see [`utils::hash_file()`]. This is only computed when the file's size or
+    /// modification time changed, so files that have only been touched don't need to be classified
+    /// again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<i64>,
+    pub architecture: LibArchitecture,
+    /// Whether the file exports one of the entry points for its plugin format.
+    pub is_plugin: bool,
+}
+
+impl PluginIndex {
+    /// Read the plugin index from the last sync. If the file doesn't exist or if it cannot be
+    /// parsed, then every file will be classified again.
+    pub fn read() -> PluginIndex {
+        yabridgectl_directories()
+            .ok()
+            .and_then(|dirs| dirs.find_cache_file(PLUGIN_INDEX_FILE_NAME))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|toml_str| toml::from_str(&toml_str).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the plugin index.
+    pub fn write(&self) -> Result<()> {
+        let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
+        let cache_path = yabridgectl_directories()?
+            .place_cache_file(PLUGIN_INDEX_FILE_NAME)
+            .context("Could not create cache file")?;
+
+        fs::write(&cache_path, toml_str)
+            .with_context(|| format!("Failed to write cache file to '{}'", cache_path.display()))
+    }
+
+    /// Look up the entry for `path` with the given size and modification time. If only the file's
+    /// modification time changed, then the file is hashed to check whether its contents changed as
+    /// well. Returns an up to date entry if the file did not change. Otherwise the file needs to be
+    /// classified again, and the hash is returned if it had to be computed so it can be stored in
+    /// the new entry.
+    pub fn lookup(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: u64,
+    ) -> std::result::Result<IndexedFile, Option<i64>> {
+        let entry = match self.files.get(path) {
+            Some(entry) if entry.size == size => entry,
+            _ => return Err(None),
+        };
+        if entry.modified == modified && !is_in_future(modified) {
+            return Ok(entry.clone());
+        }
+
+        let hash = utils::hash_file(path).ok();
+        match hash {
+            Some(hash) if entry.hash == Some(hash) => Ok(IndexedFile {
+                modified,
+                hash: Some(hash),
+                ..entry.clone()
+            }),
+            _ => Err(hash),
+        }
+    }
+}
+
+/// Precomputed hashes for the plugins in a plugin directory, generated with `yabridgectl
+/// hash-manifest`. This is meant for system-wide plugin directories shared by multiple users, where
+/// the directory is read-only for everyone except for the administrator. Instead of every user
+/// hashing every plugin during their first sync, the administrator can generate this manifest once
+/// and yabridgectl will use those hashes for files that have not changed since. The manifest is
+/// stored in the plugin directory itself as [`HASH_MANIFEST_FILE_NAME`].
+///
+/// Whether a file has changed is decided using its size, modification time, inode number, and
+/// status change time. Modification times are easily preserved when files get replaced, but
+/// replacing or modifying a file always results in a new inode or status change time. On file
+/// systems that don't have stable inode numbers, like some FUSE and network file systems, the
+/// manifest will rarely match and plugins are hashed as usual.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct HashManifest {
+    /// The version of yabridgectl that generated the manifest. Hashes are not guaranteed to be
+    /// stable between versions, so manifests generated by other versions are ignored.
+    pub version: String,
+    /// The hashed files, keyed by their path relative to the plugin directory.
+    pub files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+/// A file in a [`HashManifest`].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// The file's hash, see [`utils::hash_file()`].
+    pub hash: i64,
+    /// The file's size in bytes.
+    pub size: u64,
+    /// The file's modification time in seconds since the Unix epoch.
+    pub modified: u64,
+    /// The file's inode number.
+    pub inode: u64,
+    /// The file's status change time (`ctime`) in seconds since the Unix epoch. Unlike the
+    /// modification time, this can't be changed by tools that preserve timestamps.
+    pub changed: i64,
+}
+
+impl ManifestEntry {
+    /// Hash a file and record its metadata.
+    fn new(path: &Path) -> Result<ManifestEntry> {
+        let (size, modified) = file_size_and_mtime(path)?;
+        let (inode, changed) = inode_and_ctime(path)?;
+
+        Ok(ManifestEntry {
+            hash: utils::hash_file(path)?,
+            size,
+            modified,
+            inode,
+            changed,
+        })
+    }
+}
+
+impl HashManifest {
+    /// Hash `files` and create a manifest for them. All files should be inside of `directory`.
+    pub fn generate(directory: &Path, files: &[PathBuf]) -> Result<HashManifest> {
+        let mut manifest = HashManifest {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            files: BTreeMap::new(),
+        };
+        for file in files {
+            let relative_path = file.strip_prefix(directory).with_context(|| {
+                format!(
+                    "'{}' is not inside of '{}'",
+                    file.display(),
+                    directory.display()
+                )
+            })?;
+            manifest
+                .files
+                .insert(relative_path.to_owned(), ManifestEntry::new(file)?);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Read the manifest for a plugin directory. Returns `None` if the directory doesn't have a
+    /// manifest, or if it cannot be used.
+    pub fn read(directory: &Path) -> Option<HashManifest> {
+        fs::read_to_string(directory.join(HASH_MANIFEST_FILE_NAME))
+            .ok()
+            .and_then(|toml_str| toml::from_str::<HashManifest>(&toml_str).ok())
+            .filter(|manifest| manifest.version == env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Write the manifest to `directory`.
+    pub fn write(&self, directory: &Path) -> Result<()> {
+        let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
+        utils::write_atomically(directory.join(HASH_MANIFEST_FILE_NAME), &toml_str)
+    }
+
+    /// Look up the hash for a file in `directory`. This only returns the hash if the file's size,
+    /// modification time, inode number, and status change time still match those in the manifest.
+    fn hash_for(&self, directory: &Path, path: &Path, size: u64, modified: u64) -> Option<i64> {
+        let entry = self.files.get(path.strip_prefix(directory).ok()?)?;
+        let (inode, changed) = inode_and_ctime(path).ok()?;
+        if entry.size == size
+            && entry.modified == modified
+            && entry.inode == inode
+            && entry.changed == changed
+        {
+            Some(entry.hash)
+        } else {
+            None
+        }
+    }
+}
+
+impl InstalledPlugin {
+    /// Create a new entry for a plugin. `hash_path` is the file that should be hashed, which is not
+    /// necessarily the same as the plugin's path for VST3 bundles. If `previous` refers to the same
+    /// file and the file's size and modification time haven't changed, then we'll reuse the hash
+    /// from that entry instead of hashing the file again. Otherwise we'll try the plugin
+    /// directory's hash manifest, if it has one, before falling back to hashing the file.
+    pub fn new(
+        hash_path: &Path,
+        target: PathBuf,
+        previous: Option<&InstalledPlugin>,
+        manifest: Option<(&Path, &HashManifest)>,
+    ) -> Result<InstalledPlugin> {
+        let (size, modified) = file_size_and_mtime(hash_path)?;
+        // If the system clock was wrong when the file was written, then the modification time can't
+        // be used to tell whether the file has changed
+        if is_in_future(modified) {
+            return Ok(InstalledPlugin {
+                hash: Some(utils::hash_file(hash_path)?),
+                size,
+                modified,
+                target,
+            });
+        }
+
+        let manifest_hash = || {
+            manifest.and_then(|(directory, manifest)| {
+                manifest.hash_for(directory, hash_path, size, modified)
+            })
+        };
+        let hash = match unchanged_hash(previous, size, modified).or_else(manifest_hash) {
+            Some(hash) => hash,
+            None => utils::hash_file(hash_path)?,
+        };
+
+        Ok(InstalledPlugin {
+            hash: Some(hash),
+            size,
+            modified,
+            target,
+        })
+    }
+
+    /// The same as [`new()`](Self::new()), but without hashing the file. The hash from `previous`
+    /// is kept if the file has not changed since. This is used for plugins that have already been
+    /// set up, since the hash is only needed when looking for the installation of a moved plugin.
+    pub fn without_hash(
+        hash_path: &Path,
+        target: PathBuf,
+        previous: Option<&InstalledPlugin>,
+    ) -> Result<InstalledPlugin> {
+        let (size, modified) = file_size_and_mtime(hash_path)?;
+        let hash = if is_in_future(modified) {
+            None
+        } else {
+            unchanged_hash(previous, size, modified)
+        };
+
+        Ok(InstalledPlugin {
+            hash,
+            size,
+            modified,
+            target,
+        })
+    }
+}
+
+/// The hash from a previous entry for a file, if the file's size and modification time are still
+/// the same as they were when it was hashed.
+fn unchanged_hash(previous: Option<&InstalledPlugin>, size: u64, modified: u64) -> Option<i64> {
+    previous
+        .filter(|previous| previous.size == size && previous.modified == modified)
+        .and_then(|previous| previous.hash)
+}
+
+/// Get a file's inode number and status change time, used by [`HashManifest`] to detect files that
+/// have been replaced while keeping their size and modification time.
+fn inode_and_ctime(path: &Path) -> Result<(u64, i64)> {
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("Could not read metadata for '{}'", path.display()))?;
+
+    Ok((metadata.ino(), metadata.ctime()))
+}
+
+/// Check whether a modification time from [`file_size_and_mtime()`] lies implausibly far in the
+/// future. This happens when files were written while the system clock was set incorrectly, for
+/// instance on machines without a real-time clock before the time has been synchronized. We can't
+/// rely on these modification times to detect changes.
+pub fn is_in_future(modified: u64) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    modified > now.saturating_add(MAX_CLOCK_SKEW)
+}
+
+/// Get a file's size and modification time, used to detect whether a file has changed without
+/// having to hash it.
+pub fn file_size_and_mtime(path: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("Could not read metadata for '{}'", path.display()))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    Ok((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    /// Create a file that was modified a year from now.
+    fn future_file(path: &Path) {
+        fs::write(path, "plugin").unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(365 * 24 * 60 * 60))
+            .unwrap();
+    }
+
+    #[test]
+    fn future_modification_times() {
+        let directory = tempdir().unwrap();
+        let current = directory.path().join("Current.dll");
+        fs::write(&current, "plugin").unwrap();
+        let future = directory.path().join("Future.dll");
+        future_file(&future);
+
+        assert!(!is_in_future(file_size_and_mtime(&current).unwrap().1));
+        assert!(is_in_future(file_size_and_mtime(&future).unwrap().1));
+    }
+
+    #[test]
+    fn future_files_are_always_hashed() {
+        let directory = tempdir().unwrap();
+        let plugin = directory.path().join("Plugin.dll");
+        future_file(&plugin);
+
+        // The size and modification time match, but the modification time can't be trusted
+        let (size, modified) = file_size_and_mtime(&plugin).unwrap();
+        let previous = InstalledPlugin {
+            hash: Some(1234),
+            size,
+            modified,
+            target: PathBuf::new(),
+        };
+        let installed =
+            InstalledPlugin::new(&plugin, PathBuf::new(), Some(&previous), None).unwrap();
+        assert_eq!(installed.hash, Some(utils::hash_file(&plugin).unwrap()));
+
+        let installed =
+            InstalledPlugin::without_hash(&plugin, PathBuf::new(), Some(&previous)).unwrap();
+        assert_eq!(installed.hash, None);
+    }
+
+    #[test]
+    fn manifest_matches_unchanged_files() {
+        let directory = tempdir().unwrap();
+        let plugin = directory.path().join("Plugin.dll");
+        fs::write(&plugin, "plugin").unwrap();
+
+        let manifest =
+            HashManifest::generate(directory.path(), std::slice::from_ref(&plugin)).unwrap();
+        let (size, modified) = file_size_and_mtime(&plugin).unwrap();
+        assert_eq!(
+            manifest.hash_for(directory.path(), &plugin, size, modified),
+            Some(utils::hash_file(&plugin).unwrap())
+        );
+    }
+
+    #[test]
+    fn manifest_ignores_replaced_files() {
+        let directory = tempdir().unwrap();
+        let plugin = directory.path().join("Plugin.dll");
+        fs::write(&plugin, "plugin").unwrap();
+        let manifest =
+            HashManifest::generate(directory.path(), std::slice::from_ref(&plugin)).unwrap();
+        let original_modified = fs::metadata(&plugin).unwrap().modified().unwrap();
+
+        // A file with the same size and modification time replaces the original plugin
+        let replacement = directory.path().join("Plugin.dll.new");
+        fs::write(&replacement, "update").unwrap();
+        File::options()
+            .write(true)
+            .open(&replacement)
+            .unwrap()
+            .set_modified(original_modified)
+            .unwrap();
+        fs::rename(&replacement, &plugin).unwrap();
+
+        let (size, modified) = file_size_and_mtime(&plugin).unwrap();
+        assert_eq!(size, manifest.files[Path::new("Plugin.dll")].size);
+        assert_eq!(modified, manifest.files[Path::new("Plugin.dll")].modified);
+        assert_eq!(
+            manifest.hash_for(directory.path(), &plugin, size, modified),
+            None
+        );
+    }
+}
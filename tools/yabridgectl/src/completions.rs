@@ -0,0 +1,259 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Shell completion scripts for `yabridgectl completions <shell>`. These are generated from the
+//! clap [app](clap::App) defined in `main()`, so they always contain the same subcommands and
+//! options as the actual command line interface. The bash, elvish, fish, and zsh scripts are
+//! generated by `clap_generate`. That doesn't support nushell, so the nushell module is generated
+//! here. Positional arguments are always completed as file names there, since the possible values
+//! for those are mostly taken from the config file and they would go out of date as soon as the
+//! config changes.
+
+use anyhow::{anyhow, Result};
+use clap::{App, AppSettings, ArgSettings};
+use clap_generate::generators::{Bash, Elvish, Fish, Zsh};
+use clap_generate::Generator;
+use std::fmt::Write;
+use std::io;
+
+/// The shells we can generate completions for. These match the names used in `$SHELL`.
+pub const SHELLS: &[&str] = &["bash", "elvish", "fish", "nu", "zsh"];
+
+/// A command or subcommand along with the options and arguments that can follow it on the command
+/// line.
+struct Command {
+    /// The names of the subcommands leading up to this command, starting with `yabridgectl`.
+    path: Vec<String>,
+    options: Vec<CommandOption>,
+    positionals: Vec<Positional>,
+}
+
+/// An option or flag. Flags don't take any values.
+struct CommandOption {
+    short: Option<char>,
+    long: Option<String>,
+    about: String,
+    takes_value: bool,
+    possible_values: Vec<String>,
+}
+
+/// A positional argument. These are all completed as file names.
+struct Positional {
+    name: String,
+    about: String,
+    required: bool,
+    multiple: bool,
+}
+
+/// Print the completion script for `shell` to STDOUT. Returns an error if we don't support the
+/// shell, although clap already verifies this using [`SHELLS`].
+pub fn print(app: &App, shell: &str) -> Result<()> {
+    match shell {
+        "bash" => generate::<Bash>(app),
+        "elvish" => generate::<Elvish>(app),
+        "fish" => generate::<Fish>(app),
+        "nu" => {
+            let mut commands = Vec::new();
+            collect_commands(app, vec![app.get_name().to_owned()], &mut commands);
+            print!("{}", nushell(&commands));
+        }
+        "zsh" => generate::<Zsh>(app),
+        shell => {
+            return Err(anyhow!(
+                "Unsupported shell '{}', the supported shells are: {}",
+                shell,
+                SHELLS.join(", ")
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+/// Print a completion script generated by one of `clap_generate`'s generators to STDOUT.
+fn generate<G: Generator>(app: &App) {
+    let mut app = app.clone();
+    let name = app.get_name().to_owned();
+    clap_generate::generate::<G, _>(&mut app, name, &mut io::stdout());
+}
+
+/// Recursively gather all of the (non-hidden) subcommands and their arguments in `commands`.
+fn collect_commands(app: &App, path: Vec<String>, commands: &mut Vec<Command>) {
+    // Clap only adds the help flags and propagates global options to the subcommands when parsing
+    // the command line. `_build()` does the same thing without parsing anything, and it's what
+    // clap's own completion generators use.
+    let mut app = app.clone();
+    app._build();
+
+    let first_line = |about: Option<&str>| {
+        about
+            .and_then(|about| about.lines().next())
+            .unwrap_or_default()
+            .to_owned()
+    };
+
+    let subcommands: Vec<&App> = app
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_set(AppSettings::Hidden))
+        .collect();
+
+    let mut command = Command {
+        path: path.clone(),
+        options: Vec::new(),
+        positionals: Vec::new(),
+    };
+    for arg in app
+        .get_arguments()
+        .filter(|arg| !arg.is_set(ArgSettings::Hidden))
+    {
+        if arg.get_short().is_none() && arg.get_long().is_none() {
+            command.positionals.push(Positional {
+                name: arg.get_name().to_owned(),
+                about: first_line(arg.get_about()),
+                required: arg.is_set(ArgSettings::Required),
+                multiple: arg.is_set(ArgSettings::MultipleValues)
+                    || arg.is_set(ArgSettings::MultipleOccurrences),
+            });
+        } else {
+            command.options.push(CommandOption {
+                short: arg.get_short(),
+                long: arg.get_long().map(String::from),
+                about: first_line(arg.get_about()),
+                takes_value: arg.is_set(ArgSettings::TakesValue),
+                possible_values: arg
+                    .get_possible_values()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect(),
+            });
+        }
+    }
+    command
+        .positionals
+        .sort_by_key(|positional| !positional.required);
+    commands.push(command);
+
+    for subcommand in subcommands {
+        let mut path = path.clone();
+        path.push(subcommand.get_name().to_owned());
+        collect_commands(subcommand, path, commands);
+    }
+}
+
+fn nushell(commands: &[Command]) -> String {
+    // Nushell uses custom completion commands for the possible values
+    let mut script = String::from("module completions {\n");
+    for command in commands {
+        for option in &command.options {
+            if let (Some(long), false) = (&option.long, option.possible_values.is_empty()) {
+                writeln!(
+                    script,
+                    "\n  def \"nu-complete {} {}\" [] {{\n    [ {} ]\n  }}",
+                    command.path.join(" "),
+                    long,
+                    option
+                        .possible_values
+                        .iter()
+                        .map(|value| format!("{:?}", value))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    for command in commands {
+        writeln!(script, "\n  export extern \"{}\" [", command.path.join(" ")).unwrap();
+        for positional in &command.positionals {
+            let name = positional.name.replace('-', "_");
+            let parameter = match (positional.multiple, positional.required) {
+                (true, _) => format!("...{}: path", name),
+                (false, true) => format!("{}: path", name),
+                (false, false) => format!("{}?: path", name),
+            };
+            writeln!(script, "    {:<31} # {}", parameter, positional.about).unwrap();
+        }
+        for option in &command.options {
+            let mut flag = match (&option.long, option.short) {
+                (Some(long), Some(short)) => format!("--{}(-{})", long, short),
+                (Some(long), None) => format!("--{}", long),
+                (None, Some(short)) => format!("-{}", short),
+                (None, None) => continue,
+            };
+            if option.takes_value {
+                flag.push_str(": string");
+                if let (Some(long), false) = (&option.long, option.possible_values.is_empty()) {
+                    write!(flag, "@\"nu-complete {} {}\"", command.path.join(" "), long).unwrap();
+                }
+            }
+            writeln!(script, "    {:<31} # {}", flag, option.about).unwrap();
+        }
+        script.push_str("  ]\n");
+    }
+    script.push_str("}\n\nexport use completions *\n");
+
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    #[test]
+    fn nushell_module() {
+        let app = App::new("yabridgectl")
+            .arg(
+                Arg::new("color")
+                    .long("color")
+                    .about("When to use colors")
+                    .takes_value(true)
+                    .possible_values(&["auto", "never"]),
+            )
+            .subcommand(
+                App::new("add")
+                    .about("Add a plugin install location\nMore details")
+                    .arg(Arg::new("path").about("Path to a directory").required(true))
+                    .arg(Arg::new("quiet").short('q').long("quiet").about("Be quiet")),
+            )
+            .subcommand(App::new("secret").setting(AppSettings::Hidden));
+
+        let mut commands = Vec::new();
+        collect_commands(&app, vec![String::from("yabridgectl")], &mut commands);
+        let script = nushell(&commands);
+
+        assert!(script.starts_with("module completions {\n"));
+        assert!(script.ends_with("export use completions *\n"));
+        assert!(script.contains(
+            "  def \"nu-complete yabridgectl color\" [] {\n    [ \"auto\" \"never\" ]\n  }"
+        ));
+        assert!(script.contains("  export extern \"yabridgectl\" [\n"));
+        assert!(script
+            .contains("--color: string@\"nu-complete yabridgectl color\" # When to use colors\n"));
+        assert!(script.contains("  export extern \"yabridgectl add\" [\n"));
+        assert!(script.contains(&format!("    {:<31} # Path to a directory\n", "path: path")));
+        assert!(script.contains(&format!("    {:<31} # Be quiet\n", "--quiet(-q)")));
+        // Clap's help flags are added to every command, and hidden subcommands are left out
+        assert!(script.contains(&format!(
+            "    {:<31} # Prints help information\n",
+            "--help(-h)"
+        )));
+        assert!(!script.contains("secret"));
+        assert!(!script.contains("More details"));
+    }
+}
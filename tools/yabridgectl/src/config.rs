@@ -17,20 +17,30 @@
 //! Utilities for managing yabrigectl's configuration.
 
 use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
 use rayon::prelude::*;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::env;
 use std::fmt::Display;
-use std::fs;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 use which::which;
 use xdg::BaseDirectories;
 
-use crate::files::{self, SearchResults};
+use crate::cache::{PluginIndex, ScanCache, ScannedDirectory};
+use crate::files::{self, LibArchitecture, NativeFile, PluginFormat, SearchResults};
+use crate::utils::{self, get_file_type};
 
 /// The name of the config file, relative to `$XDG_CONFIG_HOME/YABRIDGECTL_PREFIX`.
 pub const CONFIG_FILE_NAME: &str = "config.toml";
+/// The name of the lock file used to prevent multiple yabridgectl instances from modifying the
+/// config or the installed plugins at the same time, relative to `$XDG_CACHE_HOME/YABRIDGECTL_PREFIX`.
+const LOCK_FILE_NAME: &str = "yabridgectl.lock";
 /// The name of the XDG base directory prefix for yabridgectl, relative to `$XDG_CONFIG_HOME` and
 /// `$XDG_DATA_HOME`.
 const YABRIDGECTL_PREFIX: &str = "yabridgectl";
@@ -39,20 +49,43 @@ const YABRIDGECTL_PREFIX: &str = "yabridgectl";
 pub const LIBYABRIDGE_VST2_NAME: &str = "libyabridge-vst2.so";
 /// The name of yabridge's VST3 library.
 pub const LIBYABRIDGE_VST3_NAME: &str = "libyabridge-vst3.so";
+/// The name of the chainloader library for VST2 plugins. Newer versions of yabridge ship with these
+/// small chainloader libraries that load the actual `libyabridge-vst2.so` from yabridge's
+/// installation directory, so that copies never have to be updated.
+pub const LIBYABRIDGE_CHAINLOADER_VST2_NAME: &str = "libyabridge-chainloader-vst2.so";
+/// The name of the chainloader library for VST3 plugins.
+pub const LIBYABRIDGE_CHAINLOADER_VST3_NAME: &str = "libyabridge-chainloader-vst3.so";
+/// The name of yabridge's CLAP library.
+pub const LIBYABRIDGE_CLAP_NAME: &str = "libyabridge-clap.so";
+/// The name of the chainloader library for CLAP plugins.
+pub const LIBYABRIDGE_CHAINLOADER_CLAP_NAME: &str = "libyabridge-chainloader-clap.so";
 /// The name of the script we're going to run to verify that everything's working correctly.
 pub const YABRIDGE_HOST_EXE_NAME: &str = "yabridge-host.exe";
+/// The name of the host used for 32-bit plugins. This is optional, since yabridge can be built
+/// without support for 32-bit plugins.
+pub const YABRIDGE_HOST_32_EXE_NAME: &str = "yabridge-host-32.exe";
 /// The name of the XDG base directory prefix for yabridge's own files, relative to
 /// `$XDG_CONFIG_HOME` and `$XDG_DATA_HOME`.
 const YABRIDGE_PREFIX: &str = "yabridge";
+/// The default value for `Config::ignored_suffixes`.
+const DEFAULT_IGNORED_SUFFIXES: &[&str] = &[".bak", ".old", ".orig", ".backup"];
 
 /// The path relative to `$HOME` that VST3 modules bridged by yabridgectl life in. By putting this
 /// in a subdirectory we can easily clean up any orphan files without interfering with other native
 /// plugins.
 const YABRIDGE_VST3_HOME: &str = ".vst3/yabridge";
+/// The same as [`YABRIDGE_VST3_HOME`], but for CLAP plugins.
+const YABRIDGE_CLAP_HOME: &str = ".clap/yabridge";
+
+lazy_static! {
+    /// The config file passed with the global `--config` option. When this is not set, the config
+    /// file at `$XDG_CONFIG_HOME/yabridgectl/config.toml` is used. See [`set_config_path()`].
+    static ref CONFIG_PATH_OVERRIDE: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
 
 /// The configuration used for yabridgectl. This will be serialized to and deserialized from
 /// `$XDG_CONFIG_HOME/yabridge/config.toml`.
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(default)]
 pub struct Config {
     /// The installation method to use. We will default to creating copies since that works
@@ -70,15 +103,154 @@ pub struct Config {
     /// Always skip post-installation setup checks. This can be set temporarily by passing the
     /// `--no-verify` option to `yabridgectl sync`.
     pub no_verify: bool,
+    /// The number of seconds the post-installation setup checks wait for the login shell and for
+    /// Wine before giving up on them. Slow shell profiles or a wineserver that's still starting can
+    /// otherwise stall these checks. See [`crate::utils::output_with_timeout()`].
+    pub verify_timeout: u64,
+    /// Plugins whose `.dll` or `.vst3` file is larger than this many bytes are skipped when they
+    /// would be set up using copies. This can be set temporarily with the `--max-size` option.
+    pub max_plugin_size: Option<u64>,
+    /// `yabridgectl sync` asks for confirmation before removing more than this many plugins at
+    /// once, since that usually means that a plugin directory has been mistyped or that a drive has
+    /// not been mounted. Setting this to 0 disables this check. See
+    /// [`crate::actions::sync::is_mass_removal()`].
+    pub removal_limit: usize,
+    /// When set, copies of yabridge's libraries with the same contents are hard linked to each
+    /// other instead of being stored separately. This only affects the copy installation method.
+    pub deduplicate_copies: bool,
+    /// When set, `yabridgectl sync` symlinks `yabridge-host.exe` and the other host binaries to
+    /// `~/.local/share/yabridge`. Yabridge always searches that directory, so copies of yabridge's
+    /// libraries can find the host binaries without having to add them to the login shell's search
+    /// path. See [`utils::link_host_binaries()`].
+    pub link_host_binaries: bool,
+    /// When set, symlinks created during `yabridgectl sync` contain a path relative to the
+    /// symlink's location instead of an absolute path. This keeps symlinks working when yabridge
+    /// and the plugins are on the same drive, and that drive gets mounted somewhere else.
+    pub relative_symlinks: bool,
+    /// When `yabridgectl sync` last set up the plugins without any errors, as a Unix timestamp in
+    /// seconds. This is shown in `yabridgectl status`. Syncs with `--target-prefix` are not
+    /// recorded here.
+    pub last_sync: Option<u64>,
     /// Files and directories that should be skipped during the indexing process. If this contains a
-    /// directory, then everything under that directory will also be skipped. Like with
+    /// directory, then everything under that directory will also be skipped. Entries containing
+    /// `*` or `?` wildcards are patterns instead. Those are matched against the plugins found
+    /// during indexing, see [`is_blacklisted()`](Self::is_blacklisted()). Like with
     /// `plugin_dirs`, we're using a `BTreeSet` here because it looks nicer in the config file, even
     /// though a hash set would make much more sense.
     pub blacklist: BTreeSet<PathBuf>,
+    /// Files whose name ends with one of these suffixes, with or without their extension, are never
+    /// considered to be plugins. This is meant for backups and other leftover files, like
+    /// `Plugin.bak.dll`. Hidden files are always skipped. Set with `yabridgectl set
+    /// --ignore-suffix`. See [`files::is_ignored_file()`].
+    pub ignored_suffixes: Vec<String>,
+    /// Plugins with these architectures will never be set up, and any existing installations for
+    /// those plugins will be removed during `yabridgectl sync`. This is useful on systems without
+    /// any 32-bit Wine support. Additional architectures can be excluded temporarily with the
+    /// `--exclude-arch` option.
+    pub excluded_architectures: BTreeSet<LibArchitecture>,
+    /// The plugin formats yabridgectl should set up. Plugins in any other format are skipped the
+    /// same way as plugins with an excluded architecture, and any existing installations for those
+    /// plugins will be removed during `yabridgectl sync`. Defaults to all supported formats. Set
+    /// with `yabridgectl set --formats`.
+    pub formats: BTreeSet<PluginFormat>,
+    /// Regular expressions for messages Wine prints to STDERR that should not be treated as errors
+    /// when checking whether `yabridge-host.exe` can run. This can be used to ignore benign `err:`
+    /// messages from unrelated Wine components that would otherwise cause the check to fail. See
+    /// [`crate::utils::ignored_wine_errors()`].
+    pub ignored_wine_errors: Vec<String>,
+    /// Plugins that should always be set up using copies, even when using the symlink installation
+    /// method. Some plugins look for their resources relative to the location of the `.so` file,
+    /// which doesn't work when that file is a symlink. These are paths to VST2 plugin `.dll` files
+    /// or to VST3 modules. See [`Config::method_for()`].
+    pub always_copy: BTreeSet<PathBuf>,
+    /// Plugins that should use another build of yabridge, keyed by the path to the plugin's `.dll`
+    /// file or VST3 module. The values are directories containing that build's libraries and
+    /// `yabridge-host.exe`. Yabridge looks for `yabridge-host.exe` next to the library a plugin's
+    /// `.so` file points to, so these plugins are always set up using symlinks. Set with
+    /// `yabridgectl host set`. See [`Config::host_files()`].
+    pub plugin_hosts: BTreeMap<PathBuf, PathBuf>,
+    /// Settings that only apply to a single plugin directory, keyed by the directory's path. These
+    /// are set with `yabridgectl set <directory>`.
+    pub directory_overrides: BTreeMap<PathBuf, DirectoryOverrides>,
+    /// Named subsets of the plugin directories and plugins. When syncing with `yabridgectl
+    /// --profile <name> sync`, only the plugins in that profile are set up, and existing
+    /// installations for all other plugins are removed.
+    pub profiles: BTreeMap<String, Profile>,
     /// The last known combination of Wine and yabridge versions that would work together properly.
     /// This is mostly to diagnose issues with older Wine versions (such as those in Ubuntu's repos)
     /// early on.
     pub last_known_config: Option<KnownConfig>,
+    /// The same as `last_known_config`, but for the individual Wine prefixes checked with
+    /// `yabridgectl verify-prefixes`. This is keyed by the path to the Wine prefix.
+    pub known_prefix_configs: BTreeMap<PathBuf, KnownConfig>,
+    /// The same as `last_known_config`, but for the Wine binaries set for individual plugin
+    /// directories with `yabridgectl set <directory> --wine`. This is keyed by the path to the Wine
+    /// binary.
+    pub known_loader_configs: BTreeMap<PathBuf, KnownConfig>,
+}
+
+/// Settings for a single plugin directory that take precedence over the global settings.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DirectoryOverrides {
+    /// The directory yabridge should be set up in for the plugins in this directory. VST2 plugins
+    /// are normally set up right next to their `.dll` files and VST3 plugins are normally set up in
+    /// `~/.vst3/yabridge`. Relative paths are relative to the home directory. See
+    /// [`Config::target_dir()`].
+    pub target: Option<PathBuf>,
+    /// Additional directories the plugins in this directory are made available in, for hosts that
+    /// search other directories. These contain symlinks to the files set up in `target` or in the
+    /// default locations, so the plugins are only set up once. Relative paths are relative to the
+    /// home directory. See [`Config::extra_target_dirs()`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_targets: Vec<PathBuf>,
+    /// The installation method for the plugins in this directory, if it should be different from
+    /// the global installation method. See [`Config::method_for()`].
+    pub method: Option<InstallationMethod>,
+    /// The Wine binary the plugins in this directory are run with, if this is not the same as the
+    /// `$WINELOADER` or `wine` binary yabridgectl uses. `yabridge-host.exe` is verified separately
+    /// for every Wine binary in use. See [`Config::wine_loaders()`].
+    pub wine: Option<PathBuf>,
+    /// The subdirectories of this plugin directory that should be searched for plugins, relative to
+    /// the plugin directory. These are selected with `yabridgectl add --interactive`. If this is
+    /// empty, then all subdirectories are included. Plugins directly inside of the plugin directory
+    /// are always included. See [`Config::index_blacklist()`].
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub subdirectories: BTreeSet<PathBuf>,
+}
+
+impl DirectoryOverrides {
+    /// Whether none of the settings have been overridden. Empty overrides are removed from the
+    /// config file.
+    pub fn is_empty(&self) -> bool {
+        self == &DirectoryOverrides::default()
+    }
+}
+
+/// A subset of the plugin directories and the plugins in them, see `Config::profiles`.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Profile {
+    /// The plugin directories that are part of this profile. If this is empty, then all plugin
+    /// directories are included.
+    pub plugin_dirs: BTreeSet<PathBuf>,
+    /// The plugins in those directories that are part of this profile. These entries work the same
+    /// way as the entries in the file passed to `yabridgectl sync --plugins-from`, see
+    /// [`files::plugin_matches_entry()`]. If this is empty, then all plugins are included.
+    pub plugins: Vec<String>,
+}
+
+impl Profile {
+    /// Whether the plugin at `plugin_path`, found in plugin directory `directory`, is part of this
+    /// profile. See [`files::plugin_matches_entry()`] for `ignore_case`.
+    pub fn includes(&self, directory: &Path, plugin_path: &Path, ignore_case: bool) -> bool {
+        (self.plugin_dirs.is_empty() || self.plugin_dirs.contains(directory))
+            && (self.plugins.is_empty()
+                || self
+                    .plugins
+                    .iter()
+                    .any(|entry| files::plugin_matches_entry(plugin_path, entry, ignore_case)))
+    }
 }
 
 /// Specifies how yabridge will be set up for the found plugins.
@@ -120,7 +292,7 @@ impl Display for InstallationMethod {
 /// winelibs, and Ubuntu ships with old versions of Wine. To prevent repeating unnecessarily
 /// repeating this check we'll keep track of the last combination of Wine and yabridge that would
 /// work together properly.
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct KnownConfig {
     /// The output of `wine --version`, minus the trailing newline.
     pub wine_version: String,
@@ -134,6 +306,73 @@ pub struct KnownConfig {
     ///
     /// https://github.com/alexcrichton/toml-rs/issues/256
     pub yabridge_host_hash: i64,
+    /// The hash of the CLAP library, if this version of yabridge has been built with CLAP support.
+    /// CLAP support is distributed separately on some distros, so installing it should also cause
+    /// the Wine setup to be checked again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub libyabridge_clap_hash: Option<i64>,
+    /// The hash of `yabridge-host-32.exe.so`. This is only set when the 32-bit host has been
+    /// verified as well, which only happens when there are 32-bit plugins to set up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yabridge_host_32_hash: Option<i64>,
+}
+
+impl KnownConfig {
+    /// The combination of Wine and yabridge that's currently installed, to compare against
+    /// `config.last_known_config`. With `include_32bit_host`, this also includes the 32-bit host if
+    /// yabridge has been built with one.
+    pub fn current(
+        files: &YabridgeFiles,
+        wine_version: String,
+        include_32bit_host: bool,
+    ) -> Result<KnownConfig> {
+        Ok(KnownConfig {
+            wine_version,
+            // Hash the contents of `yabridge-host.exe.so` since `yabridge-host.exe` is only a Wine
+            // generated shell script
+            yabridge_host_hash: utils::hash_file(&files.yabridge_host_exe_so)?,
+            libyabridge_clap_hash: match &files.libyabridge_clap {
+                Some(path) => Some(utils::hash_file(path)?),
+                None => None,
+            },
+            yabridge_host_32_hash: match &files.yabridge_host_32_exe_so {
+                Some(path) if include_32bit_host => Some(utils::hash_file(path)?),
+                _ => None,
+            },
+        })
+    }
+
+    /// Whether `current` has already been verified if this combination was known to work. A
+    /// combination where the 32-bit host has been verified also covers the same combination without
+    /// the 32-bit host.
+    pub fn covers(&self, current: &KnownConfig) -> bool {
+        self == current
+            || (current.yabridge_host_32_hash.is_none()
+                && KnownConfig {
+                    yabridge_host_32_hash: None,
+                    ..self.clone()
+                } == *current)
+    }
+}
+
+/// Whether a blacklist entry is a wildcard pattern rather than a path, see `Config::blacklist`.
+pub fn is_blacklist_pattern(entry: &Path) -> bool {
+    entry.to_string_lossy().contains(&['*', '?'][..])
+}
+
+/// Find the plugin directories that are nested inside of another plugin directory, as `(directory,
+/// parent)` pairs. Plugins in those directories would otherwise be found and set up twice. This
+/// only compares path components, so the paths should already be normalized.
+pub fn nested_plugin_dirs(plugin_dirs: &BTreeSet<PathBuf>) -> Vec<(&Path, &Path)> {
+    plugin_dirs
+        .iter()
+        .flat_map(|directory| {
+            plugin_dirs
+                .iter()
+                .filter(move |&parent| parent != directory && directory.starts_with(parent))
+                .map(move |parent| (directory.as_path(), parent.as_path()))
+        })
+        .collect()
 }
 
 /// Paths to all of yabridge's files based on the `yabridge_home` setting. Created by
@@ -145,12 +384,84 @@ pub struct YabridgeFiles {
     /// The path to `libyabridge-vst3.so` we should use, if yabridge has been compiled with VST3
     /// support.
     pub libyabridge_vst3: Option<PathBuf>,
+    /// The path to `libyabridge-chainloader-vst2.so`, if this version of yabridge uses
+    /// chainloaders. In that case this library should be set up for every plugin instead of
+    /// `libyabridge-vst2.so`.
+    pub libyabridge_chainloader_vst2: Option<PathBuf>,
+    /// The same as `libyabridge_chainloader_vst2`, but for `libyabridge-chainloader-vst3.so`.
+    pub libyabridge_chainloader_vst3: Option<PathBuf>,
+    /// The path to `libyabridge-clap.so` we should use, if yabridge has been compiled with CLAP
+    /// support.
+    pub libyabridge_clap: Option<PathBuf>,
+    /// The same as `libyabridge_chainloader_vst2`, but for `libyabridge-chainloader-clap.so`.
+    pub libyabridge_chainloader_clap: Option<PathBuf>,
     /// The path to `yabridge-host.exe`. This is the path yabridge will actually use, and it does
     /// not have to be relative to `yabridge_home`.
     pub yabridge_host_exe: PathBuf,
     /// The actual Winelib binary for `yabridge-host.exe`. Will be hashed to check whether the user
     /// has updated yabridge.
     pub yabridge_host_exe_so: PathBuf,
+    /// The path to `yabridge-host-32.exe`, if yabridge has been built with support for 32-bit
+    /// plugins. This should be next to `yabridge-host.exe`.
+    pub yabridge_host_32_exe: Option<PathBuf>,
+    /// The Winelib binary for `yabridge-host-32.exe`, if yabridge has been built with support for
+    /// 32-bit plugins.
+    pub yabridge_host_32_exe_so: Option<PathBuf>,
+}
+
+impl YabridgeFiles {
+    /// Whether this version of yabridge uses chainloader libraries.
+    pub fn uses_chainloader(&self) -> bool {
+        self.libyabridge_chainloader_vst2.is_some()
+    }
+
+    /// All of yabridge's libraries and host binaries that were found. These should all come from the
+    /// same build, see [`utils::verify_same_build()`].
+    pub fn components(&self) -> Vec<PathBuf> {
+        let mut components = vec![self.libyabridge_vst2.clone()];
+        components.extend(self.libyabridge_vst3.clone());
+        components.extend(self.libyabridge_chainloader_vst2.clone());
+        components.extend(self.libyabridge_chainloader_vst3.clone());
+        components.extend(self.libyabridge_clap.clone());
+        components.extend(self.libyabridge_chainloader_clap.clone());
+        components.push(self.yabridge_host_exe_so.clone());
+        components.extend(self.yabridge_host_32_exe_so.clone());
+
+        components
+    }
+
+    /// The library that should be copied or symlinked for every VST2 plugin. This is the
+    /// chainloader if yabridge ships with one.
+    pub fn vst2_library(&self) -> &Path {
+        self.libyabridge_chainloader_vst2
+            .as_deref()
+            .unwrap_or(&self.libyabridge_vst2)
+    }
+
+    /// The library that should be copied or symlinked for every VST3 module, if yabridge has been
+    /// compiled with VST3 support. This is the chainloader if yabridge ships with one.
+    pub fn vst3_library(&self) -> Option<&Path> {
+        self.libyabridge_chainloader_vst3
+            .as_deref()
+            .or(self.libyabridge_vst3.as_deref())
+    }
+
+    /// The library that should be copied or symlinked for every CLAP plugin. Unlike VST3 support,
+    /// CLAP support is required once the user has CLAP plugins, so this returns an error explaining
+    /// what's missing if yabridge has been compiled without CLAP support.
+    pub fn clap_library(&self) -> Result<&Path> {
+        self.libyabridge_chainloader_clap
+            .as_deref()
+            .or(self.libyabridge_clap.as_deref())
+            .with_context(|| {
+                format!(
+                    "Found CLAP plugins, but '{}' could not be found next to '{}'. Make sure \
+                     you're using a version of yabridge that has been compiled with CLAP support.",
+                    LIBYABRIDGE_CLAP_NAME,
+                    self.libyabridge_vst2.display()
+                )
+            })
+    }
 }
 
 impl Default for Config {
@@ -160,8 +471,28 @@ impl Default for Config {
             yabridge_home: None,
             plugin_dirs: BTreeSet::new(),
             no_verify: false,
+            verify_timeout: 10,
+            max_plugin_size: None,
+            removal_limit: 50,
+            deduplicate_copies: false,
+            link_host_binaries: false,
+            relative_symlinks: false,
+            last_sync: None,
             blacklist: BTreeSet::new(),
+            ignored_suffixes: DEFAULT_IGNORED_SUFFIXES
+                .iter()
+                .map(|suffix| suffix.to_string())
+                .collect(),
+            excluded_architectures: BTreeSet::new(),
+            formats: PluginFormat::ALL.iter().cloned().collect(),
+            ignored_wine_errors: Vec::new(),
+            always_copy: BTreeSet::new(),
+            plugin_hosts: BTreeMap::new(),
+            directory_overrides: BTreeMap::new(),
+            profiles: BTreeMap::new(),
             last_known_config: None,
+            known_prefix_configs: BTreeMap::new(),
+            known_loader_configs: BTreeMap::new(),
         }
     }
 }
@@ -170,14 +501,20 @@ impl Config {
     /// Try to read the config file, creating a new default file if necessary. This will fail if the
     /// file could not be created or if it could not be parsed.
     pub fn read() -> Result<Config> {
-        match yabridgectl_directories()?.find_config_file(CONFIG_FILE_NAME) {
+        match find_config_file()? {
             Some(path) => {
                 let toml_str = fs::read_to_string(&path).with_context(|| {
                     format!("Could not read config file at '{}'", path.display())
                 })?;
 
-                toml::from_str(&toml_str)
-                    .with_context(|| format!("Failed to parse '{}'", path.display()))
+                let mut config: Config = toml::from_str(&toml_str)
+                    .with_context(|| format!("Failed to parse '{}'", path.display()))?;
+                config
+                    .expand_paths()
+                    .with_context(|| format!("Failed to parse '{}'", path.display()))?;
+                config.normalize();
+
+                Ok(config)
             }
             None => {
                 let defaults = Config::default();
@@ -191,17 +528,150 @@ impl Config {
         }
     }
 
-    /// Write the config to disk, creating the file if it does not yet exist.
+    /// Expand `~` and environment variables in all paths in the config, for when the config file
+    /// has been edited by hand. See [`utils::expand_path()`]. References to environment variables
+    /// that are not set are kept as is. This returns an error if a path references a user that does
+    /// not exist.
+    pub fn expand_paths(&mut self) -> Result<()> {
+        let expand_set = |paths: &BTreeSet<PathBuf>| -> Result<BTreeSet<PathBuf>> {
+            paths.iter().map(|path| utils::expand_path(path)).collect()
+        };
+
+        self.plugin_dirs = expand_set(&self.plugin_dirs)?;
+        self.blacklist = expand_set(&self.blacklist)?;
+        self.always_copy = expand_set(&self.always_copy)?;
+        self.plugin_hosts = self
+            .plugin_hosts
+            .iter()
+            .map(|(path, host)| -> Result<(PathBuf, PathBuf)> {
+                Ok((utils::expand_path(path)?, utils::expand_path(host)?))
+            })
+            .collect::<Result<_>>()?;
+        let mut directory_overrides: BTreeMap<PathBuf, DirectoryOverrides> = BTreeMap::new();
+        for (path, overrides) in &self.directory_overrides {
+            let mut overrides = overrides.clone();
+            overrides.target = overrides
+                .target
+                .map(|target| utils::expand_path(&target))
+                .transpose()?;
+            overrides.extra_targets = overrides
+                .extra_targets
+                .iter()
+                .map(|target| utils::expand_path(target))
+                .collect::<Result<_>>()?;
+            overrides.wine = overrides
+                .wine
+                .map(|wine| utils::expand_path(&wine))
+                .transpose()?;
+            directory_overrides.insert(utils::expand_path(path)?, overrides);
+        }
+        self.directory_overrides = directory_overrides;
+        for profile in self.profiles.values_mut() {
+            profile.plugin_dirs = expand_set(&profile.plugin_dirs)?;
+        }
+        self.yabridge_home = self
+            .yabridge_home
+            .as_ref()
+            .map(|path| utils::expand_path(path))
+            .transpose()?;
+
+        Ok(())
+    }
+
+    /// Normalize all paths in the config and remove any duplicate entries this results in. This way
+    /// the same directory can't be searched twice when it has been added using two different
+    /// spellings. See [`utils::normalize_path()`]. The normalized config is only written back to
+    /// disk when the config is modified or when yabridgectl is run with `--clean`.
+    pub fn normalize(&mut self) {
+        let plugin_dirs: BTreeSet<PathBuf> = self
+            .plugin_dirs
+            .iter()
+            .map(|path| utils::normalize_path(path))
+            .collect();
+        let blacklist: BTreeSet<PathBuf> = self
+            .blacklist
+            .iter()
+            .map(|path| utils::normalize_path(path))
+            .collect();
+        let always_copy: BTreeSet<PathBuf> = self
+            .always_copy
+            .iter()
+            .map(|path| utils::normalize_path(path))
+            .collect();
+        let plugin_hosts: BTreeMap<PathBuf, PathBuf> = self
+            .plugin_hosts
+            .iter()
+            .map(|(path, host)| (utils::normalize_path(path), utils::normalize_path(host)))
+            .collect();
+        let mut directory_overrides: BTreeMap<PathBuf, DirectoryOverrides> = BTreeMap::new();
+        for (path, overrides) in &self.directory_overrides {
+            directory_overrides
+                .entry(utils::normalize_path(path))
+                .or_insert_with(|| overrides.clone());
+        }
+        let mut profiles = self.profiles.clone();
+        for profile in profiles.values_mut() {
+            profile.plugin_dirs = profile
+                .plugin_dirs
+                .iter()
+                .map(|path| utils::normalize_path(path))
+                .collect();
+        }
+        let yabridge_home = self
+            .yabridge_home
+            .as_ref()
+            .map(|path| utils::normalize_path(path));
+
+        self.plugin_dirs = plugin_dirs;
+        self.blacklist = blacklist;
+        self.always_copy = always_copy;
+        self.plugin_hosts = plugin_hosts;
+        self.directory_overrides = directory_overrides;
+        self.profiles = profiles;
+        self.yabridge_home = yabridge_home;
+    }
+
+    /// Write the config to disk, creating the file if it does not yet exist. The file is replaced
+    /// atomically, so it will never end up partially written. If the config file is a symlink, then
+    /// the file it points to will be replaced instead.
     pub fn write(&self) -> Result<()> {
+        self.write_to(place_config_file()?)
+    }
+
+    /// The same as [`write()`](Self::write()), but for a config file at a specific location.
+    fn write_to(&self, config_path: PathBuf) -> Result<()> {
         let toml_str = toml::to_string_pretty(&self).context("Could not format TOML")?;
-        let config_path = yabridgectl_directories()?
-            .place_config_file(CONFIG_FILE_NAME)
-            .context("Could not create config file")?;
+        let config_path = config_path.canonicalize().unwrap_or(config_path);
 
-        fs::write(&config_path, toml_str)
+        utils::write_atomically(&config_path, &toml_str)
             .with_context(|| format!("Failed to write config file to '{}'", config_path.display()))
     }
 
+    /// Make several changes to the config at once. The changes are made to a copy of the config,
+    /// which is only written to disk and stored in `self` if `f` succeeds. This way either all of
+    /// the changes are saved or none of them are, even if `f` fails halfway through.
+    pub fn update<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Config) -> Result<T>,
+    {
+        self.update_with(f, Config::write)
+    }
+
+    /// The implementation of [`update()`](Self::update()), with the function used to write the
+    /// updated config to disk.
+    fn update_with<T, F, W>(&mut self, f: F, write: W) -> Result<T>
+    where
+        F: FnOnce(&mut Config) -> Result<T>,
+        W: FnOnce(&Config) -> Result<()>,
+    {
+        let mut new_config = self.clone();
+        let result = f(&mut new_config)?;
+        write(&new_config)?;
+        *self = new_config;
+
+        Ok(result)
+    }
+
     /// Find all of yabridge's files based on `yabridge_home`. For the binaries we'll search for
     /// them the exact same way as yabridge itself will.
     pub fn files(&self) -> Result<YabridgeFiles> {
@@ -214,6 +684,7 @@ impl Config {
                 if candidate.exists() {
                     candidate
                 } else {
+                    verify_not_dangling(&candidate)?;
                     return Err(anyhow!(
                         "Could not find '{}' in '{}'",
                         LIBYABRIDGE_VST2_NAME,
@@ -264,6 +735,34 @@ impl Config {
             _ => None,
         };
 
+        // Newer versions of yabridge come with chainloader libraries that should be set up instead
+        let libyabridge_chainloader_vst2 =
+            match libyabridge_vst2.with_file_name(LIBYABRIDGE_CHAINLOADER_VST2_NAME) {
+                path if path.exists() => Some(path),
+                _ => None,
+            };
+        let libyabridge_chainloader_vst3 = match (
+            &libyabridge_vst3,
+            libyabridge_vst2.with_file_name(LIBYABRIDGE_CHAINLOADER_VST3_NAME),
+        ) {
+            (Some(_), path) if path.exists() => Some(path),
+            _ => None,
+        };
+
+        // CLAP support may also be missing, either because yabridge was compiled without it or
+        // because this is an older version of yabridge
+        let libyabridge_clap = match libyabridge_vst2.with_file_name(LIBYABRIDGE_CLAP_NAME) {
+            path if path.exists() => Some(path),
+            _ => None,
+        };
+        let libyabridge_chainloader_clap = match (
+            &libyabridge_clap,
+            libyabridge_vst2.with_file_name(LIBYABRIDGE_CHAINLOADER_CLAP_NAME),
+        ) {
+            (Some(_), path) if path.exists() => Some(path),
+            _ => None,
+        };
+
         // `yabridge-host.exe` should either be in the search path, or it should be in
         // `~/.local/share/yabridge`
         let yabridge_host_exe = match which(YABRIDGE_HOST_EXE_NAME)
@@ -272,33 +771,427 @@ impl Config {
         {
             Some(path) => path,
             _ => {
+                // Both of the above skip over dangling symlinks, so we'll check for those
+                // separately since that situation would otherwise be very confusing
+                let search_path = env::var_os("PATH").unwrap_or_default();
+                for directory in
+                    env::split_paths(&search_path).chain(std::iter::once(xdg_dirs.get_data_home()))
+                {
+                    verify_not_dangling(&directory.join(YABRIDGE_HOST_EXE_NAME))?;
+                }
+
                 return Err(anyhow!("Could not locate '{}'.", YABRIDGE_HOST_EXE_NAME));
             }
         };
         let yabridge_host_exe_so = yabridge_host_exe.with_extension("exe.so");
+        verify_not_dangling(&yabridge_host_exe_so)?;
+        let yabridge_host_32_exe = match yabridge_host_exe.with_file_name(YABRIDGE_HOST_32_EXE_NAME)
+        {
+            path if path.exists() => Some(path),
+            _ => None,
+        };
+        let yabridge_host_32_exe_so = yabridge_host_32_exe
+            .as_ref()
+            .map(|path| path.with_extension("exe.so"));
+        if let Some(path) = &yabridge_host_32_exe_so {
+            verify_not_dangling(path)?;
+        }
+
+        // A partially installed yabridge package may contain empty files or placeholders instead of
+        // the actual libraries, and plugins set up using those would silently fail to load
+        for library in [
+            Some(&libyabridge_vst2),
+            libyabridge_vst3.as_ref(),
+            libyabridge_chainloader_vst2.as_ref(),
+            libyabridge_chainloader_vst3.as_ref(),
+            libyabridge_clap.as_ref(),
+            libyabridge_chainloader_clap.as_ref(),
+            Some(&yabridge_host_exe_so),
+            yabridge_host_32_exe_so.as_ref(),
+        ]
+        .iter()
+        .flatten()
+        {
+            verify_elf_library(library)?;
+        }
 
         Ok(YabridgeFiles {
             libyabridge_vst2,
             libyabridge_vst3,
+            libyabridge_chainloader_vst2,
+            libyabridge_chainloader_vst3,
+            libyabridge_clap,
+            libyabridge_chainloader_clap,
             yabridge_host_exe,
             yabridge_host_exe_so,
+            yabridge_host_32_exe,
+            yabridge_host_32_exe_so,
         })
     }
 
-    /// Search for VST2 and VST3 plugins in all of the registered plugins directories. This will
-    /// return an error if `winedump` could not be called.
-    pub fn search_directories(&self) -> Result<BTreeMap<&Path, SearchResults>> {
-        let blacklist: HashSet<&Path> = self.blacklist.iter().map(|p| p.as_path()).collect();
+    /// Find yabridge's files in a directory containing another build of yabridge, for
+    /// `config.plugin_hosts`. Unlike [`files()`](Self::files()), `yabridge-host.exe` also has to be
+    /// in that directory since that's where yabridge will look for it.
+    pub fn host_files(&self, directory: &Path) -> Result<YabridgeFiles> {
+        let files = Config {
+            yabridge_home: Some(directory.to_owned()),
+            ..self.clone()
+        }
+        .files()?;
+
+        let yabridge_host_exe = directory.join(YABRIDGE_HOST_EXE_NAME);
+        if !yabridge_host_exe.exists() {
+            return Err(anyhow!(
+                "Could not find '{}' in '{}'",
+                YABRIDGE_HOST_EXE_NAME,
+                directory.display()
+            ));
+        }
+
+        let yabridge_host_32_exe = match directory.join(YABRIDGE_HOST_32_EXE_NAME) {
+            path if path.exists() => Some(path),
+            _ => None,
+        };
+
+        Ok(YabridgeFiles {
+            yabridge_host_exe_so: yabridge_host_exe.with_extension("exe.so"),
+            yabridge_host_exe,
+            yabridge_host_32_exe_so: yabridge_host_32_exe
+                .as_ref()
+                .map(|path| path.with_extension("exe.so")),
+            yabridge_host_32_exe,
+            ..files
+        })
+    }
+
+    /// Get the installation method that should be used to set up the plugin at `plugin_path`. This
+    /// is the configured installation method for the plugin's directory, unless the plugin should
+    /// always be copied. Plugins using another build of yabridge are always symlinked, see
+    /// `config.plugin_hosts`.
+    pub fn method_for(&self, plugin_path: &Path) -> InstallationMethod {
+        if self.plugin_hosts.contains_key(plugin_path) {
+            InstallationMethod::Symlink
+        } else if self.always_copy.contains(plugin_path) {
+            InstallationMethod::Copy
+        } else {
+            // Plugin directories can be nested, in which case the innermost directory's override
+            // takes precedence
+            self.directory_overrides
+                .iter()
+                .filter(|(directory, overrides)| {
+                    overrides.method.is_some() && plugin_path.starts_with(directory)
+                })
+                .max_by_key(|(directory, _)| directory.components().count())
+                .and_then(|(_, overrides)| overrides.method)
+                .unwrap_or(self.method)
+        }
+    }
+
+    /// Get the installation method for the plugins in a plugin directory. This is the global
+    /// installation method, unless it has been overridden with `yabridgectl set <directory>
+    /// --method`.
+    pub fn directory_method(&self, directory: &Path) -> InstallationMethod {
+        self.directory_overrides
+            .get(directory)
+            .and_then(|overrides| overrides.method)
+            .unwrap_or(self.method)
+    }
+
+    /// The Wine binaries set for plugin directories with `yabridgectl set <directory> --wine`, along
+    /// with the directories using them.
+    pub fn wine_loaders(&self) -> BTreeMap<&Path, Vec<&Path>> {
+        let mut loaders: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+        for directory in &self.plugin_dirs {
+            if let Some(wine) = self
+                .directory_overrides
+                .get(directory)
+                .and_then(|overrides| overrides.wine.as_deref())
+            {
+                loaders.entry(wine).or_default().push(directory);
+            }
+        }
+
+        loaders
+    }
+
+    /// Whether a plugin in `directory` matches one of the wildcard patterns in the blacklist. Plain
+    /// paths in the blacklist are already skipped while indexing, so those are not checked here.
+    /// Patterns are matched against the plugin's full path. Relative patterns are also matched
+    /// against the path relative to the plugin directory.
+    pub fn is_blacklisted(&self, directory: &Path, plugin_path: &Path) -> bool {
+        self.blacklist
+            .iter()
+            .filter(|entry| is_blacklist_pattern(entry))
+            .any(|pattern| {
+                files::plugin_matches_pattern(directory, plugin_path, &pattern.to_string_lossy())
+            })
+    }
+
+    /// The paths that should be skipped while indexing `directory`. These are the plain paths in the
+    /// blacklist, along with the subdirectories of `directory` that are not part of its
+    /// subdirectory selection. See [`DirectoryOverrides::subdirectories`].
+    pub fn index_blacklist(&self, directory: &Path) -> HashSet<PathBuf> {
+        let mut blacklist: HashSet<PathBuf> = self.blacklist.iter().cloned().collect();
+        let selected_subdirectories = match self.directory_overrides.get(directory) {
+            Some(overrides) if !overrides.subdirectories.is_empty() => &overrides.subdirectories,
+            _ => return blacklist,
+        };
+
+        // VST3 bundles directly inside of the plugin directory are plugins, not subdirectories
+        if let Ok(entries) = fs::read_dir(directory) {
+            blacklist.extend(
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| {
+                        let path = entry.path();
+                        path.is_dir()
+                            && path.extension().and_then(|ext| ext.to_str()) != Some("vst3")
+                    })
+                    .filter(|entry| {
+                        !selected_subdirectories.contains(Path::new(&entry.file_name()))
+                    })
+                    .map(|entry| entry.path()),
+            );
+        }
 
+        blacklist
+    }
+
+    /// Whether plugins in this format should be set up, see `config.formats`.
+    pub fn is_format_enabled(&self, format: PluginFormat) -> bool {
+        self.formats.contains(&format)
+    }
+
+    /// Get the target directory override for a plugin directory, if it has one. Relative targets
+    /// are resolved relative to the home directory, so this returns an error if `$HOME` is not set.
+    pub fn target_dir(&self, directory: &Path) -> Result<Option<PathBuf>> {
+        match self
+            .directory_overrides
+            .get(directory)
+            .and_then(|overrides| overrides.target.as_ref())
+        {
+            Some(target) => Ok(Some(home_directory()?.join(target))),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the extra target directories for a plugin directory, resolved the same way as
+    /// [`target_dir()`](Self::target_dir()). Extra targets that are the same as the directory's
+    /// main target are skipped.
+    pub fn extra_target_dirs(&self, directory: &Path) -> Result<Vec<PathBuf>> {
+        let extra_targets = match self.directory_overrides.get(directory) {
+            Some(overrides) if !overrides.extra_targets.is_empty() => &overrides.extra_targets,
+            _ => return Ok(Vec::new()),
+        };
+
+        let home = home_directory()?;
+        let target_dir = self.target_dir(directory)?;
+
+        Ok(extra_targets
+            .iter()
+            .map(|target| home.join(target))
+            .filter(|target| Some(target) != target_dir.as_ref())
+            .collect())
+    }
+
+    /// Search for VST2 and VST3 plugins in all of the registered plugins directories. This will
+    /// return an error if `winedump` could not be called. The results will already have the
+    /// directory's target override applied. Files that have not changed since they were added to
+    /// `plugin_index` are not classified again, see [`SearchIndex::search()`].
+    pub fn search_directories(
+        &self,
+        plugin_index: &PluginIndex,
+    ) -> Result<BTreeMap<&Path, SearchResults>> {
         self.plugin_dirs
             .par_iter()
             .map(|path| {
-                files::index(path, &blacklist)
-                    .search()
-                    .map(|search_results| (path.as_path(), search_results))
+                let mut search_results = files::index(path, &self.index_blacklist(path))
+                    .without_ignored_files(&self.ignored_suffixes)
+                    .search(plugin_index)?;
+                if let Some(target) = self.target_dir(path)? {
+                    search_results.set_target(path, &target);
+                }
+
+                Ok((path.as_path(), search_results))
             })
             .collect()
     }
+
+    /// The same as [`search_directories()`](Self::search_directories()), but directories that have
+    /// not changed since the last scan are not read again. See [`files::index_fast()`]. The scan
+    /// cache for the next scan is returned alongside the results.
+    pub fn search_directories_fast(
+        &self,
+        previous: &ScanCache,
+        plugin_index: &PluginIndex,
+    ) -> Result<(BTreeMap<&Path, SearchResults>, ScanCache)> {
+        let watermark = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        let results: Vec<(&Path, SearchResults, BTreeMap<PathBuf, ScannedDirectory>)> = self
+            .plugin_dirs
+            .par_iter()
+            .map(|path| {
+                let mut scanned = BTreeMap::new();
+                let mut search_results =
+                    files::index_fast(path, &self.index_blacklist(path), previous, &mut scanned)
+                        .without_ignored_files(&self.ignored_suffixes)
+                        .search(plugin_index)?;
+                if let Some(target) = self.target_dir(path)? {
+                    search_results.set_target(path, &target);
+                }
+
+                Ok((path.as_path(), search_results, scanned))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut scan_cache = ScanCache {
+            watermark,
+            directories: BTreeMap::new(),
+        };
+        let results = results
+            .into_iter()
+            .map(|(path, search_results, scanned)| {
+                scan_cache.directories.extend(scanned);
+                (path, search_results)
+            })
+            .collect();
+
+        Ok((results, scan_cache))
+    }
+
+    /// Search for plugins using the scan cache and the plugin index from the last sync, without
+    /// updating either of them. This is used by commands that only report on the current state, so
+    /// they don't need to read unchanged directories or run `winedump` for unchanged files again.
+    /// Only `yabridgectl sync` refreshes these caches.
+    pub fn search_directories_indexed(&self) -> Result<BTreeMap<&Path, SearchResults>> {
+        let plugin_index = PluginIndex::read();
+        if ScanCache::exists() {
+            self.search_directories_fast(&ScanCache::read(), &plugin_index)
+                .map(|(results, _)| results)
+        } else {
+            self.search_directories(&plugin_index)
+        }
+    }
+}
+
+/// An exclusive lock held while yabridgectl modifies the config or the installed plugins. The lock
+/// is released when this object is dropped, and the operating system will also release it if
+/// yabridgectl exits in any other way.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// Acquire an exclusive lock before running a command that modifies the config or the installed
+/// plugins, so two yabridgectl instances can never modify the same files at the same time. This
+/// will return an error if another yabridgectl instance is currently holding the lock. Read-only
+/// commands don't need to acquire this lock.
+pub fn acquire_lock() -> Result<Lock> {
+    let lock_path = yabridgectl_directories()?
+        .place_cache_file(LOCK_FILE_NAME)
+        .context("Could not create lock file")?;
+    let file = File::create(&lock_path)
+        .with_context(|| format!("Could not open lock file '{}'", lock_path.display()))?;
+
+    // Locks created with `flock()` are tied to the file descriptor, so they're released
+    // automatically when the file gets closed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(anyhow!(
+            "Another instance of yabridgectl is currently running. Wait for it to finish, and \
+             then try again."
+        ));
+    }
+
+    Ok(Lock { _file: file })
+}
+
+/// Return an error if `path` is a symlink pointing to a file that does not exist. When a symlink to
+/// one of yabridge's files is left behind after uninstalling or moving yabridge, that would
+/// otherwise result in confusing errors about missing files or about being unable to run
+/// `yabridge-host.exe`.
+fn verify_not_dangling(path: &Path) -> Result<()> {
+    match get_file_type(path.to_owned()) {
+        Some(NativeFile::Symlink(_)) if !path.exists() => Err(anyhow!(
+            "'{}' is a dangling symlink pointing at '{}'. Reinstalling yabridge or removing the \
+             symlink should fix this.",
+            path.display(),
+            path.read_link().unwrap_or_default().display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Return an error if `path` is not an ELF shared library. This catches empty files and text
+/// placeholders left behind by a partially installed yabridge package before we set up any plugins
+/// using them. Files that don't exist are skipped, those are reported elsewhere.
+fn verify_elf_library(path: &Path) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) if !path.exists() => return Ok(()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Could not open '{}'", path.display()))
+        }
+    };
+    let mut header = Vec::new();
+    file.take(4)
+        .read_to_end(&mut header)
+        .with_context(|| format!("Could not read '{}'", path.display()))?;
+
+    if header.is_empty() {
+        Err(anyhow!(
+            "'{}' is an empty file. Your yabridge installation may be incomplete, reinstalling \
+             yabridge should fix this.",
+            path.display()
+        ))
+    } else if !files::has_elf_magic(&header) {
+        Err(anyhow!(
+            "'{}' is not a shared library. Your yabridge installation may be incomplete, \
+             reinstalling yabridge should fix this.",
+            path.display()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Read the config from and write the config to `path` instead of the default location. Used for the
+/// global `--config` option. This has to be called before the config is read.
+pub fn set_config_path(path: PathBuf) {
+    *CONFIG_PATH_OVERRIDE.write().unwrap() = Some(path);
+}
+
+/// The config file passed with the global `--config` option, if any.
+pub fn config_path_override() -> Option<PathBuf> {
+    CONFIG_PATH_OVERRIDE.read().unwrap().clone()
+}
+
+/// Find the config file, either the one passed with `--config` or the one in yabridgectl's XDG
+/// config directory. Returns `None` if the config file does not exist yet.
+pub fn find_config_file() -> Result<Option<PathBuf>> {
+    match config_path_override() {
+        Some(path) => Ok(Some(path).filter(|path| path.exists())),
+        None => Ok(yabridgectl_directories()?.find_config_file(CONFIG_FILE_NAME)),
+    }
+}
+
+/// Get the path the config file should be written to, creating its parent directories if needed.
+/// See [`find_config_file()`].
+fn place_config_file() -> Result<PathBuf> {
+    match config_path_override() {
+        Some(path) => {
+            if let Some(parent) = path.parent() {
+                utils::create_dir_all(parent)?;
+            }
+
+            Ok(path)
+        }
+        None => yabridgectl_directories()?
+            .place_config_file(CONFIG_FILE_NAME)
+            .context("Could not create config file"),
+    }
 }
 
 /// Fetch the XDG base directories for yabridge's own files, converting any error messages if this
@@ -314,9 +1207,284 @@ pub fn yabridgectl_directories() -> Result<BaseDirectories> {
     BaseDirectories::with_prefix(YABRIDGECTL_PREFIX).context("Error while parsing base directories")
 }
 
+/// Get the user's home directory from `$HOME`. Target directory overrides are stored relative to
+/// the home directory, so those can't be resolved without it.
+pub fn home_directory() -> Result<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("$HOME is not set, so the target directories can't be resolved"))
+}
+
 /// Get the path where VST3 modules bridged by yabridgectl should be placed in. This is a
 /// subdirectory of `~/.vst3` so we can easily clean up leftover files without interfering with
 /// other native plugins.
 pub fn yabridge_vst3_home() -> PathBuf {
     Path::new(&env::var("HOME").expect("$HOME is not set")).join(YABRIDGE_VST3_HOME)
 }
+
+/// Get the path where CLAP plugins bridged by yabridgectl should be placed in. Just like with
+/// [`yabridge_vst3_home()`], this is a subdirectory of `~/.clap`.
+pub fn yabridge_clap_home() -> PathBuf {
+    Path::new(&env::var("HOME").expect("$HOME is not set")).join(YABRIDGE_CLAP_HOME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn normalize_removes_duplicates() {
+        let mut config = Config::default();
+        config.plugin_dirs.insert(PathBuf::from("/plugins/vst"));
+        config.plugin_dirs.insert(PathBuf::from("/plugins/vst/"));
+        config
+            .plugin_dirs
+            .insert(PathBuf::from("/plugins/./other/../vst"));
+        config
+            .blacklist
+            .insert(PathBuf::from("/plugins/vst//Foo.dll"));
+        config.yabridge_home = Some(PathBuf::from("/opt/yabridge/../yabridge/"));
+        config.directory_overrides.insert(
+            PathBuf::from("/plugins/vst/"),
+            DirectoryOverrides {
+                method: Some(InstallationMethod::Symlink),
+                ..DirectoryOverrides::default()
+            },
+        );
+
+        config.normalize();
+
+        assert_eq!(
+            config.plugin_dirs.into_iter().collect::<Vec<_>>(),
+            vec![PathBuf::from("/plugins/vst")]
+        );
+        assert_eq!(
+            config.blacklist.into_iter().collect::<Vec<_>>(),
+            vec![PathBuf::from("/plugins/vst/Foo.dll")]
+        );
+        assert_eq!(config.yabridge_home, Some(PathBuf::from("/opt/yabridge")));
+        assert_eq!(
+            config.directory_overrides.keys().collect::<Vec<_>>(),
+            vec![Path::new("/plugins/vst")]
+        );
+    }
+
+    #[test]
+    fn normalize_keeps_normalized_config() {
+        let mut config = Config::default();
+        config.plugin_dirs.insert(PathBuf::from("/plugins/a"));
+        config.plugin_dirs.insert(PathBuf::from("/plugins/b"));
+        let expected = config.plugin_dirs.clone();
+
+        config.normalize();
+
+        assert_eq!(config.plugin_dirs, expected);
+    }
+
+    #[test]
+    fn failed_update_leaves_config_unchanged() {
+        let directory = tempdir().unwrap();
+        let config_path = directory.path().join(CONFIG_FILE_NAME);
+        // This doesn't use `set_config_path()`, since that would affect all other tests
+        let write = |config: &Config| config.write_to(config_path.clone());
+
+        let mut config = Config::default();
+        config
+            .update_with(
+                |config| {
+                    config.plugin_dirs.insert(PathBuf::from("/plugins/a"));
+                    Ok(())
+                },
+                write,
+            )
+            .unwrap();
+        let written = fs::read_to_string(&config_path).unwrap();
+        assert!(written.contains("/plugins/a"));
+
+        // The first change has already been made to the copy when the second one fails
+        let result: Result<()> = config.update_with(
+            |config| {
+                config.plugin_dirs.insert(PathBuf::from("/plugins/b"));
+                Err(anyhow!("Something went wrong"))
+            },
+            write,
+        );
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), written);
+        assert_eq!(
+            config.plugin_dirs,
+            BTreeSet::from([PathBuf::from("/plugins/a")])
+        );
+    }
+
+    #[test]
+    fn dangling_symlink_is_an_error() {
+        let directory = tempdir().unwrap();
+        let target = directory.path().join("missing.exe");
+        let path = directory.path().join(YABRIDGE_HOST_EXE_NAME);
+        symlink(&target, &path).unwrap();
+
+        let err = verify_not_dangling(&path).unwrap_err().to_string();
+        assert!(err.contains("dangling symlink"));
+        assert!(err.contains(&target.display().to_string()));
+    }
+
+    #[test]
+    fn existing_files_are_not_dangling() {
+        let directory = tempdir().unwrap();
+        let target = directory.path().join("yabridge-host.exe.so");
+        let path = directory.path().join(YABRIDGE_HOST_EXE_NAME);
+        fs::write(&target, b"").unwrap();
+        symlink(&target, &path).unwrap();
+
+        assert!(verify_not_dangling(&target).is_ok());
+        assert!(verify_not_dangling(&path).is_ok());
+        assert!(verify_not_dangling(&directory.path().join("missing")).is_ok());
+    }
+
+    #[test]
+    fn files_reports_dangling_library() {
+        let directory = tempdir().unwrap();
+        symlink(
+            directory.path().join("missing.so"),
+            directory.path().join(LIBYABRIDGE_VST2_NAME),
+        )
+        .unwrap();
+
+        let config = Config {
+            yabridge_home: Some(directory.path().to_owned()),
+            ..Config::default()
+        };
+        let err = config.files().unwrap_err().to_string();
+        assert!(err.contains("dangling symlink"));
+    }
+
+    #[test]
+    fn elf_libraries_are_accepted() {
+        let directory = tempdir().unwrap();
+        let library = directory.path().join("libyabridge-vst2.so");
+        fs::write(&library, b"\x7fELF\x02\x01\x01\0rest of the library").unwrap();
+        assert!(verify_elf_library(&library).is_ok());
+
+        // Missing files are reported elsewhere
+        assert!(verify_elf_library(&directory.path().join("missing.so")).is_ok());
+    }
+
+    #[test]
+    fn corrupt_libraries_are_rejected() {
+        let directory = tempdir().unwrap();
+        for (name, contents, expected_error) in [
+            ("empty.so", &b""[..], "is an empty file"),
+            (
+                "placeholder.so",
+                &b"version https://git-lfs.github.com/spec/v1\n"[..],
+                "is not a shared library",
+            ),
+            // Only part of the magic bytes, like from an interrupted download
+            ("truncated.so", &b"\x7fEL"[..], "is not a shared library"),
+            (
+                "windows.so",
+                &b"MZ\x90\0\x03\0\0\0"[..],
+                "is not a shared library",
+            ),
+        ] {
+            let library = directory.path().join(name);
+            fs::write(&library, contents).unwrap();
+
+            let err = verify_elf_library(&library).unwrap_err().to_string();
+            assert!(err.contains(expected_error), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn index_blacklist_skips_unselected_subdirectories() {
+        let directory = tempdir().unwrap();
+        let plugin_dir = directory.path().to_owned();
+        for subdirectory in ["Selected", "Unselected", "Plugin.vst3"] {
+            fs::create_dir(plugin_dir.join(subdirectory)).unwrap();
+        }
+        fs::write(plugin_dir.join("Plugin.dll"), "").unwrap();
+
+        let mut config = Config {
+            blacklist: BTreeSet::from([PathBuf::from("/blacklisted")]),
+            ..Config::default()
+        };
+        // Without a selection only the blacklist is used
+        assert_eq!(
+            config.index_blacklist(&plugin_dir),
+            HashSet::from([PathBuf::from("/blacklisted")])
+        );
+
+        config.directory_overrides.insert(
+            plugin_dir.clone(),
+            DirectoryOverrides {
+                subdirectories: BTreeSet::from([PathBuf::from("Selected")]),
+                ..DirectoryOverrides::default()
+            },
+        );
+        // Files and VST3 bundles directly inside of the plugin directory are always included
+        assert_eq!(
+            config.index_blacklist(&plugin_dir),
+            HashSet::from([PathBuf::from("/blacklisted"), plugin_dir.join("Unselected")])
+        );
+        // The selection only applies to that plugin directory
+        assert_eq!(
+            config.index_blacklist(&plugin_dir.join("Unselected")),
+            HashSet::from([PathBuf::from("/blacklisted")])
+        );
+    }
+
+    #[test]
+    fn nested_plugin_dirs_are_found() {
+        let plugin_dirs: BTreeSet<PathBuf> = [
+            "/prefix/drive_c/Program Files",
+            "/prefix/drive_c/Program Files/Steinberg/VSTPlugins",
+            "/prefix/drive_c/Program Files/Common Files/VST3",
+            "/other/plugins",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        assert_eq!(
+            nested_plugin_dirs(&plugin_dirs),
+            vec![
+                (
+                    Path::new("/prefix/drive_c/Program Files/Common Files/VST3"),
+                    Path::new("/prefix/drive_c/Program Files")
+                ),
+                (
+                    Path::new("/prefix/drive_c/Program Files/Steinberg/VSTPlugins"),
+                    Path::new("/prefix/drive_c/Program Files")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_plugin_dirs_compares_components() {
+        // `/plugins-old` starts with the string `/plugins`, but it's not inside of that directory
+        let plugin_dirs: BTreeSet<PathBuf> = ["/plugins", "/plugins-old", "/plugins2/vst"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert!(nested_plugin_dirs(&plugin_dirs).is_empty());
+        assert!(nested_plugin_dirs(&BTreeSet::new()).is_empty());
+    }
+
+    #[test]
+    fn expand_paths_keeps_unset_variables() {
+        env::remove_var("YABRIDGECTL_TEST_RECYCLE");
+        let directory = PathBuf::from("/mnt/c/$YABRIDGECTL_TEST_RECYCLE.Bin/VstPlugins");
+        let mut config = Config {
+            plugin_dirs: BTreeSet::from([directory.clone()]),
+            ..Config::default()
+        };
+
+        config.expand_paths().unwrap();
+        assert_eq!(config.plugin_dirs, BTreeSet::from([directory]));
+    }
+}
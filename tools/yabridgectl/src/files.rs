@@ -18,29 +18,48 @@
 
 use aho_corasick::AhoCorasick;
 use anyhow::{Context, Result};
+use colored::Colorize;
 use lazy_static::lazy_static;
 use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Display;
-use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
+use std::sync::Mutex;
 use walkdir::WalkDir;
 
-use crate::config::yabridge_vst3_home;
-use crate::utils::get_file_type;
+use crate::cache::{self, IndexedFile, PluginIndex, ScanCache, ScannedDirectory};
+use crate::config::{yabridge_clap_home, yabridge_vst3_home};
+use crate::utils::{self, get_file_type};
 
 /// Stores the results from searching through a directory. We'll search for Windows VST2 plugin
 /// `.dll` files, Windows VST3 plugin modules, and native Linux `.so` files inside of a directory.
 /// These `.so` files are kept track of so we can report the current installation status of VST2
 /// plugins and to be able to prune orphan files. Since VST3 plugins have to be installed in
 /// `~/.vst3`, these orphan files are only relevant for VST2 plugins.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SearchResults {
-    /// The plugins found during the search. This contains both VST2 plugins and VST3 modules.
+    /// The plugins found during the search. This contains VST2 plugins, VST3 modules, and CLAP
+    /// plugins.
     pub plugins: Vec<Plugin>,
     /// `.dll` files skipped over during the search. Used for printing statistics and shown when
     /// running `yabridgectl sync --verbose`.
     pub skipped_files: Vec<PathBuf>,
+    /// Windows shortcuts (`.lnk` files) found during the search. Plugin installers sometimes create
+    /// these, and they can't be set up since they're not actual plugins. See
+    /// [`read_shortcut_target()`].
+    pub shortcut_files: Vec<PathBuf>,
+    /// `.zip` archives found during the search. These may contain plugins, which can be extracted
+    /// with `yabridgectl sync --extract`.
+    pub archive_files: Vec<PathBuf>,
+    /// The classification for every `.dll`, `.vst3`, and `.clap` file found during the search, used to
+    /// update the plugin index. See [`PluginIndex`].
+    pub indexed_files: BTreeMap<PathBuf, IndexedFile>,
 
     /// Absolute paths to any `.so` files inside of the directory, and whether they're a symlink or
     /// a regular file.
@@ -56,6 +75,13 @@ pub struct SearchIndex {
     /// Any `.vst3` file or directory. This can be either a legacy `.vst3` DLL module or a VST
     /// 3.6.10 module (or some kind of random other file, of course).
     pub vst3_files: Vec<PathBuf>,
+    /// Any `.clap` file.
+    pub clap_files: Vec<PathBuf>,
+    /// Any `.lnk` file. These are Windows shortcuts, which we can't set up but which we should warn
+    /// about.
+    pub lnk_files: Vec<PathBuf>,
+    /// Any `.zip` file. These may contain plugins, see [`crate::archives`].
+    pub zip_files: Vec<PathBuf>,
     /// Absolute paths to any `.so` files inside of the directory, and whether they're a symlink or
     /// a regular file.
     pub so_files: Vec<NativeFile>,
@@ -80,11 +106,83 @@ impl NativeFile {
     }
 }
 
-/// A plugin as found during the search. This can be either a VST2 plugin or a VST3 module.
+/// A plugin as found during the search. This can be a VST2 plugin, a VST3 module, or a CLAP plugin.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Plugin {
     Vst2(Vst2Plugin),
     Vst3(Vst3Module),
+    Clap(ClapPlugin),
+}
+
+impl Plugin {
+    /// The architecture of the plugin's `.dll` or `.vst3` file.
+    pub fn architecture(&self) -> LibArchitecture {
+        match self {
+            Plugin::Vst2(Vst2Plugin { architecture, .. })
+            | Plugin::Vst3(Vst3Module { architecture, .. })
+            | Plugin::Clap(ClapPlugin { architecture, .. }) => *architecture,
+        }
+    }
+
+    /// The plugin's format.
+    pub fn format(&self) -> PluginFormat {
+        match self {
+            Plugin::Vst2(_) => PluginFormat::Vst2,
+            Plugin::Vst3(_) => PluginFormat::Vst3,
+            Plugin::Clap(_) => PluginFormat::Clap,
+        }
+    }
+
+    /// The path to a VST2 plugin's `.dll` file, to a VST3 module, or to a CLAP plugin's `.clap`
+    /// file.
+    pub fn original_path(&self) -> &Path {
+        match self {
+            Plugin::Vst2(Vst2Plugin { path, .. }) | Plugin::Clap(ClapPlugin { path, .. }) => path,
+            Plugin::Vst3(module) => module.original_path(),
+        }
+    }
+
+    /// Set up this plugin, found in `directory`, in `target` instead of in its default location.
+    /// See [`SearchResults::set_target()`].
+    pub fn set_target(&mut self, directory: &Path, target: &Path) {
+        match self {
+            Plugin::Vst2(plugin) => {
+                if let Ok(relative_path) = plugin.path.strip_prefix(directory) {
+                    plugin.target_path = target.join(relative_path).with_extension("so");
+                }
+            }
+            Plugin::Vst3(module) => module.target_home = target.to_owned(),
+            Plugin::Clap(clap_plugin) => clap_plugin.target_home = target.to_owned(),
+        }
+    }
+
+    /// The symlinks that make this plugin, found in `directory`, available in one of that
+    /// directory's extra target directories, as `(from, to)` pairs. The files yabridge was set up
+    /// with point to the plugin's main installation, so hosts that resolve symlinks still end up at
+    /// a working plugin. VST2 and CLAP plugins also get a symlink to the Windows plugin, and merged
+    /// VST3 bundles are symlinked as a whole.
+    pub fn extra_target_links(&self, directory: &Path, target: &Path) -> Vec<(PathBuf, PathBuf)> {
+        let mut mirror = self.clone();
+        mirror.set_target(directory, target);
+
+        match (self, &mirror) {
+            (Plugin::Vst2(plugin), Plugin::Vst2(mirror)) => vec![
+                (plugin.target_path.clone(), mirror.target_path.clone()),
+                (
+                    plugin.path.clone(),
+                    mirror.target_path.with_extension("dll"),
+                ),
+            ],
+            (Plugin::Vst3(module), Plugin::Vst3(mirror)) => {
+                vec![(module.target_bundle_home(), mirror.target_bundle_home())]
+            }
+            (Plugin::Clap(plugin), Plugin::Clap(mirror)) => vec![
+                (plugin.target_path(), mirror.target_path()),
+                (plugin.path.clone(), mirror.target_windows_path()),
+            ],
+            _ => unreachable!(),
+        }
+    }
 }
 
 /// VST2 plugins we found during a search along with their architecture.
@@ -94,6 +192,10 @@ pub struct Vst2Plugin {
     pub path: PathBuf,
     /// The architecture of the VST2 plugin.
     pub architecture: LibArchitecture,
+    /// The path to the `.so` file yabridge should be set up at. This is right next to the plugin's
+    /// `.dll` file, unless the plugin directory has a target override. See
+    /// [`SearchResults::set_target()`].
+    pub target_path: PathBuf,
 }
 
 /// VST3 modules we found during a search.
@@ -107,6 +209,9 @@ pub struct Vst3Module {
     /// plugisn by the same manufacturer together. We detect this by looking for a parent `VST3`
     /// directory. If we can't find that, this will be `None`.
     pub subdirectory: Option<PathBuf>,
+    /// The directory the merged bundle will be created in. This is `~/.vst3/yabridge`, unless the
+    /// plugin directory has a target override. See [`SearchResults::set_target()`].
+    pub target_home: PathBuf,
 }
 
 /// The type of the VST3 module. VST 3.6.10 style bundles require slightly different handling
@@ -173,7 +278,8 @@ impl Vst3Module {
         }
     }
 
-    /// Get the path to the bundle in `~/.vst3` corresponding to the bridged version of this module.
+    /// Get the path to the bundle in `~/.vst3/yabridge` (or in the plugin directory's target
+    /// directory) corresponding to the bridged version of this module.
     /// We will try to recreate the original subdirectory structure so plugins are still grouped by
     /// manufacturer.
     ///
@@ -181,10 +287,11 @@ impl Vst3Module {
     ///        Wine prefixes?
     pub fn target_bundle_home(&self) -> PathBuf {
         match &self.subdirectory {
-            Some(directory) => yabridge_vst3_home()
+            Some(directory) => self
+                .target_home
                 .join(directory)
                 .join(self.original_module_name()),
-            None => yabridge_vst3_home().join(self.original_module_name()),
+            None => self.target_home.join(self.original_module_name()),
         }
     }
 
@@ -237,10 +344,46 @@ impl Vst3Module {
     }
 }
 
+/// CLAP plugins we found during a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClapPlugin {
+    /// The absolute path to the Windows `.clap` file.
+    pub path: PathBuf,
+    /// The architecture of the CLAP plugin.
+    pub architecture: LibArchitecture,
+    /// The `CLAP` subdirectory the plugin was in, if any. Just like with VST3 modules, this is used
+    /// to keep plugins grouped by manufacturer.
+    pub subdirectory: Option<PathBuf>,
+    /// The directory the plugin will be set up in. This is `~/.clap/yabridge`, unless the plugin
+    /// directory has a target override. See [`SearchResults::set_target()`].
+    pub target_home: PathBuf,
+}
+
+impl ClapPlugin {
+    /// Get the path to the `.clap` file in `~/.clap/yabridge` (or in the plugin directory's target
+    /// directory) that will be a copy of or a symlink to `libyabridge-clap.so`.
+    pub fn target_path(&self) -> PathBuf {
+        let file_name = self.path.file_name().unwrap();
+        match &self.subdirectory {
+            Some(directory) => self.target_home.join(directory).join(file_name),
+            None => self.target_home.join(file_name),
+        }
+    }
+
+    /// Get the path to where we'll symlink the Windows `.clap` file. Yabridge looks for a
+    /// `.clap-win` file next to the native `.clap` file to find the plugin it should load.
+    pub fn target_windows_path(&self) -> PathBuf {
+        self.target_path().with_extension("clap-win")
+    }
+}
+
 /// The architecture of a `.dll` file. Needed so we can create a merged bundle for VST3 plugins.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy)]
+/// These are serialized as `x86` and `x86_64` in the config file and on the command line.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy)]
 pub enum LibArchitecture {
+    #[serde(rename = "x86")]
     Dll32,
+    #[serde(rename = "x86_64")]
     Dll64,
 }
 
@@ -253,6 +396,120 @@ impl Display for LibArchitecture {
     }
 }
 
+impl FromStr for LibArchitecture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86" => Ok(LibArchitecture::Dll32),
+            "x86_64" => Ok(LibArchitecture::Dll64),
+            s => Err(format!("Unknown architecture '{}'", s)),
+        }
+    }
+}
+
+/// The plugin formats yabridgectl can set up. These are serialized as `vst2`, `vst3`, and `clap` in
+/// the config file and on the command line.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginFormat {
+    Vst2,
+    Vst3,
+    Clap,
+}
+
+impl PluginFormat {
+    /// All supported plugin formats.
+    pub const ALL: [PluginFormat; 3] = [PluginFormat::Vst2, PluginFormat::Vst3, PluginFormat::Clap];
+
+    /// The functions a Windows library should export to be recognized as a plugin in this format.
+    pub fn entry_points(&self) -> &'static [&'static str] {
+        match &self {
+            PluginFormat::Vst2 => &["VSTPluginMain", "main", "main_plugin"],
+            PluginFormat::Vst3 => &["GetPluginFactory"],
+            PluginFormat::Clap => &["clap_entry"],
+        }
+    }
+
+    /// The plugin format files with this extension are searched for, if any. VST2 plugins are
+    /// `.dll` files, and VST3 modules and CLAP plugins use their format's name as their extension.
+    pub fn from_extension(path: &Path) -> Option<PluginFormat> {
+        match path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .as_deref()
+        {
+            Some("dll") => Some(PluginFormat::Vst2),
+            Some("vst3") => Some(PluginFormat::Vst3),
+            Some("clap") => Some(PluginFormat::Clap),
+            _ => None,
+        }
+    }
+}
+
+/// Why a `.dll`, `.vst3`, or `.clap` file found while searching for plugins was not recognized as a
+/// plugin. These files end up in [`SearchResults::skipped_files`]. See
+/// [`explain_skipped_file()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotAPluginReason {
+    /// The file is not a 32-bit or 64-bit x86 Windows library.
+    NotAWindowsLibrary,
+    /// The file is a Windows library, but it does not export any of the entry points for the
+    /// plugin format that matches its file extension.
+    MissingEntryPoint(PluginFormat),
+}
+
+impl Display for NotAPluginReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            NotAPluginReason::NotAWindowsLibrary => {
+                write!(f, "it is not a 32-bit or 64-bit Windows library")
+            }
+            NotAPluginReason::MissingEntryPoint(format) => write!(
+                f,
+                "it does not export any of the {} entry points ({})",
+                format,
+                format.entry_points().join(", ")
+            ),
+        }
+    }
+}
+
+/// Figure out why a file in [`SearchResults::skipped_files`] was not recognized as a plugin. The
+/// plugin index only stores whether a file is a plugin, so this reads the file's PE header again.
+pub fn explain_skipped_file(path: &Path) -> Result<NotAPluginReason> {
+    Ok(
+        match (pe_architecture(path)?, PluginFormat::from_extension(path)) {
+            (Some(_), Some(format)) => NotAPluginReason::MissingEntryPoint(format),
+            _ => NotAPluginReason::NotAWindowsLibrary,
+        },
+    )
+}
+
+impl Display for PluginFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            PluginFormat::Vst2 => write!(f, "VST2"),
+            PluginFormat::Vst3 => write!(f, "VST3"),
+            PluginFormat::Clap => write!(f, "CLAP"),
+        }
+    }
+}
+
+impl FromStr for PluginFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vst2" => Ok(PluginFormat::Vst2),
+            "vst3" => Ok(PluginFormat::Vst3),
+            "clap" => Ok(PluginFormat::Clap),
+            s => Err(format!("Unknown plugin format '{}'", s)),
+        }
+    }
+}
+
 impl LibArchitecture {
     /// Get the corresponding VST3 architecture directory name. See
     /// https://developer.steinberg.help/display/VST/Plug-in+Format+Structure#PluginFormatStructure-FortheWindowsplatform.
@@ -265,6 +522,16 @@ impl LibArchitecture {
 }
 
 impl SearchResults {
+    /// Set up the plugins found in `directory` in `target` instead of in their default locations.
+    /// VST2 plugins will be set up in the same relative location within `target` as the `.dll`
+    /// file had within `directory`, and VST3 bundles and CLAP plugins will be created in `target`
+    /// instead of in `~/.vst3/yabridge` and `~/.clap/yabridge`.
+    pub fn set_target(&mut self, directory: &Path, target: &Path) {
+        for plugin in &mut self.plugins {
+            plugin.set_target(directory, target);
+        }
+    }
+
     /// Create a map out of all found plugins based on their file path that contains both a
     /// reference to the plugin (so we can print information about it) and the current installation
     /// status. The installation status will be `None` if the plugin has not yet been set up.
@@ -278,12 +545,15 @@ impl SearchResults {
         self.plugins
             .iter()
             .map(|plugin| match plugin {
-                Plugin::Vst2(Vst2Plugin { path, .. }) => {
+                Plugin::Vst2(Vst2Plugin {
+                    path, target_path, ..
+                }) => {
                     // For VST2 plugins we'll just look at the similarly named `.so` file right next
-                    // to the plugin `.dll` file.
-                    match so_files.get(path.with_extension("so").as_path()) {
+                    // to the plugin `.dll` file. If the plugin directory has a target override then
+                    // that file won't be part of the search results.
+                    match so_files.get(target_path.as_path()) {
                         Some(&file_type) => (path.clone(), (plugin, Some(file_type.clone()))),
-                        None => (path.clone(), (plugin, None)),
+                        None => (path.clone(), (plugin, get_file_type(target_path.clone()))),
                     }
                 }
                 // We have not stored the paths to the corresponding `.so` files yet for VST3
@@ -295,6 +565,10 @@ impl SearchResults {
                         get_file_type(vst3_module.target_native_module_path()),
                     ),
                 ),
+                Plugin::Clap(clap_plugin) => (
+                    clap_plugin.path.clone(),
+                    (plugin, get_file_type(clap_plugin.target_path())),
+                ),
             })
             .collect()
     }
@@ -311,8 +585,8 @@ impl SearchResults {
             .collect();
 
         for plugin in &self.plugins {
-            if let Plugin::Vst2(Vst2Plugin { path, .. }) = plugin {
-                orphans.remove(path.with_extension("so").as_path());
+            if let Plugin::Vst2(Vst2Plugin { target_path, .. }) = plugin {
+                orphans.remove(target_path.as_path());
             }
         }
 
@@ -320,13 +594,222 @@ impl SearchResults {
     }
 }
 
+/// Determine the architecture of a Windows library by reading the machine type from its PE header.
+/// Returns `None` if the file is not a PE32 or PE32+ binary, or if it has been compiled for some
+/// other architecture. This is much faster than asking `winedump`.
+pub fn pe_architecture(path: &Path) -> Result<Option<LibArchitecture>> {
+    /// The machine types from the COFF file header for i386 and x86-64 binaries.
+    const IMAGE_FILE_MACHINE_I386: u16 = 0x014c;
+    const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+
+    let mut file =
+        File::open(path).with_context(|| format!("Could not open '{}'", path.display()))?;
+
+    // The DOS header starts with `MZ` and contains the offset to the PE header at `0x3c`
+    let mut dos_header = [0u8; 64];
+    if file.read_exact(&mut dos_header).is_err() || &dos_header[..2] != b"MZ" {
+        return Ok(None);
+    }
+    let pe_header_offset = u32::from_le_bytes([
+        dos_header[0x3c],
+        dos_header[0x3d],
+        dos_header[0x3e],
+        dos_header[0x3f],
+    ]);
+
+    // The PE header starts with a `PE\0\0` signature followed by the machine type
+    let mut pe_header = [0u8; 6];
+    if file.seek(SeekFrom::Start(pe_header_offset as u64)).is_err()
+        || file.read_exact(&mut pe_header).is_err()
+        || &pe_header[..4] != b"PE\0\0"
+    {
+        return Ok(None);
+    }
+
+    Ok(match u16::from_le_bytes([pe_header[4], pe_header[5]]) {
+        IMAGE_FILE_MACHINE_I386 => Some(LibArchitecture::Dll32),
+        IMAGE_FILE_MACHINE_AMD64 => Some(LibArchitecture::Dll64),
+        _ => None,
+    })
+}
+
+/// Whether a Windows library exports one of `format`'s entry points, based on the export table in
+/// its PE header. Only whole function names are compared, so a library exporting `GetDomainName`
+/// is not a VST2 plugin. Returns `None` if the export table could not be read, see
+/// [`pe_exported_functions()`].
+pub fn pe_exports_entry_point(path: &Path, format: PluginFormat) -> Result<Option<bool>> {
+    Ok(pe_exported_functions(path)?.map(|functions| {
+        functions
+            .iter()
+            .any(|function| format.entry_points().contains(&function.as_str()))
+    }))
+}
+
+/// Read the names of the functions exported by a Windows library from the export table in its PE
+/// header. Returns `None` if the file is not a PE32 or PE32+ binary or if the export table could not
+/// be read, in which case `winedump` should be used instead. Libraries without an export table
+/// return an empty list. This only reads the headers and the export table, so this is much faster
+/// than asking `winedump` for large plugins.
+pub fn pe_exported_functions(path: &Path) -> Result<Option<Vec<String>>> {
+    /// The magic numbers at the start of the optional header for PE32 and PE32+ binaries.
+    const PE32_MAGIC: u16 = 0x10b;
+    const PE32_PLUS_MAGIC: u16 = 0x20b;
+    /// Export tables with more names than this are almost certainly not valid.
+    const MAX_EXPORTED_NAMES: u32 = 65_536;
+
+    let mut file =
+        File::open(path).with_context(|| format!("Could not open '{}'", path.display()))?;
+    let mut read_at = |offset: u64, buffer: &mut [u8]| -> bool {
+        file.seek(SeekFrom::Start(offset)).is_ok() && file.read_exact(buffer).is_ok()
+    };
+    let u16_at =
+        |buffer: &[u8], offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+    let u32_at = |buffer: &[u8], offset: usize| {
+        u32::from_le_bytes([
+            buffer[offset],
+            buffer[offset + 1],
+            buffer[offset + 2],
+            buffer[offset + 3],
+        ])
+    };
+
+    // See `pe_architecture()` for the DOS header and the PE signature. The signature is followed by
+    // the 20 byte COFF file header and the optional header.
+    let mut dos_header = [0u8; 64];
+    if !read_at(0, &mut dos_header) || &dos_header[..2] != b"MZ" {
+        return Ok(None);
+    }
+    let pe_header_offset = u32_at(&dos_header, 0x3c) as u64;
+    let mut pe_header = [0u8; 24];
+    if !read_at(pe_header_offset, &mut pe_header) || &pe_header[..4] != b"PE\0\0" {
+        return Ok(None);
+    }
+    let num_sections = u16_at(&pe_header, 6) as usize;
+    let optional_header_size = u16_at(&pe_header, 20) as usize;
+
+    // The export table is the first data directory. The data directories start at a different
+    // offset for PE32 and PE32+ binaries.
+    let mut optional_header = vec![0u8; optional_header_size];
+    if optional_header_size < 2 || !read_at(pe_header_offset + 24, &mut optional_header) {
+        return Ok(None);
+    }
+    let data_directories_offset = match u16_at(&optional_header, 0) {
+        PE32_MAGIC => 96,
+        PE32_PLUS_MAGIC => 112,
+        _ => return Ok(None),
+    };
+    if optional_header_size < data_directories_offset + 8
+        || u32_at(&optional_header, data_directories_offset - 4) == 0
+    {
+        return Ok(Some(Vec::new()));
+    }
+    let export_table_rva = u32_at(&optional_header, data_directories_offset);
+    if export_table_rva == 0 {
+        return Ok(Some(Vec::new()));
+    }
+
+    // Every section header contains the section's virtual address, its size, and where its data is
+    // stored in the file. We need these to translate the RVAs in the export table to file offsets.
+    let mut section_headers = vec![0u8; num_sections * 40];
+    if !read_at(
+        pe_header_offset + 24 + optional_header_size as u64,
+        &mut section_headers,
+    ) {
+        return Ok(None);
+    }
+    let to_offset = |rva: u32| -> Option<u64> {
+        section_headers.chunks_exact(40).find_map(|section| {
+            let virtual_size = u32_at(section, 8);
+            let virtual_address = u32_at(section, 12);
+            let raw_size = u32_at(section, 16);
+            let raw_offset = u32_at(section, 20);
+            let size = virtual_size.max(raw_size);
+
+            if rva >= virtual_address && rva - virtual_address < size {
+                Some(raw_offset as u64 + (rva - virtual_address) as u64)
+            } else {
+                None
+            }
+        })
+    };
+
+    // The export directory contains the number of exported names and the RVA of an array
+    // containing the RVAs of those names
+    let mut export_directory = [0u8; 40];
+    let export_directory_read =
+        to_offset(export_table_rva).is_some_and(|offset| read_at(offset, &mut export_directory));
+    if !export_directory_read {
+        return Ok(None);
+    }
+    let num_names = u32_at(&export_directory, 24);
+    let names_rva = u32_at(&export_directory, 32);
+    if num_names > MAX_EXPORTED_NAMES {
+        return Ok(None);
+    }
+
+    let mut name_rvas = vec![0u8; num_names as usize * 4];
+    let name_rvas_read = to_offset(names_rva).is_some_and(|offset| read_at(offset, &mut name_rvas));
+    if !name_rvas_read {
+        return Ok(None);
+    }
+
+    let mut names = Vec::with_capacity(num_names as usize);
+    for name_rva in name_rvas.chunks_exact(4).map(|rva| u32_at(rva, 0)) {
+        // Names are null terminated, and function names longer than this don't exist in practice
+        let mut name = [0u8; 256];
+        let name_offset = match to_offset(name_rva) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        // The name may be right at the end of the file, so we'll read as much as we can
+        let num_read = match file.seek(SeekFrom::Start(name_offset)) {
+            Ok(_) => file.read(&mut name).unwrap_or(0),
+            Err(_) => 0,
+        };
+        match name[..num_read].iter().position(|&c| c == 0) {
+            Some(length) => names.push(String::from_utf8_lossy(&name[..length]).into_owned()),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(names))
+}
+
+/// Whether a file we came across while indexing should not be considered as a plugin. Hidden files
+/// are always ignored, since these are usually left behind by other tools. Files whose name ends
+/// with one of `ignored_suffixes`, either with or without the extension, are also ignored. This
+/// covers backups like `Plugin.bak.dll`. The suffixes are matched case insensitively. See
+/// `config.ignored_suffixes`.
+pub fn is_ignored_file(path: &Path, ignored_suffixes: &[String]) -> bool {
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().to_lowercase(),
+        None => return false,
+    };
+    if file_name.starts_with('.') {
+        return true;
+    }
+
+    let file_stem = path
+        .file_stem()
+        .map(|file_stem| file_stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    ignored_suffixes
+        .iter()
+        .filter(|suffix| !suffix.is_empty())
+        .map(|suffix| suffix.to_lowercase())
+        .any(|suffix| file_name.ends_with(&suffix) || file_stem.ends_with(&suffix))
+}
+
 /// Find all `.dll`, `.vst3` and `.so` files under a directory. These results can be filtered down
 /// to actual VST2 plugins and VST3 modules using `search()`. Any path found in the blacklist will
 /// be pruned immediately, so this can be used to both not index individual files and to skip an
 /// entire directory.
-pub fn index(directory: &Path, blacklist: &HashSet<&Path>) -> SearchIndex {
+pub fn index(directory: &Path, blacklist: &HashSet<PathBuf>) -> SearchIndex {
     let mut dll_files: Vec<PathBuf> = Vec::new();
     let mut vst3_files: Vec<PathBuf> = Vec::new();
+    let mut clap_files: Vec<PathBuf> = Vec::new();
+    let mut lnk_files: Vec<PathBuf> = Vec::new();
+    let mut zip_files: Vec<PathBuf> = Vec::new();
     let mut so_files: Vec<NativeFile> = Vec::new();
     // XXX: We're silently skipping directories and files we don't have permission to read. This
     //      sounds like the expected behavior, but I"m not entirely sure.
@@ -334,7 +817,17 @@ pub fn index(directory: &Path, blacklist: &HashSet<&Path>) -> SearchIndex {
         .follow_links(true)
         .into_iter()
         .filter_entry(|e| !blacklist.contains(e.path()))
-        .filter_map(|e| e.ok())
+        .filter_map(|e| match e {
+            Ok(entry) => Some(entry),
+            // WalkDir already refuses to descend into a symlink pointing to one of its own parent
+            // directories, but the user should know why those plugins are missing
+            Err(err) => {
+                if let (Some(path), Some(ancestor)) = (err.path(), err.loop_ancestor()) {
+                    warn_symlink_loop(path, ancestor);
+                }
+                None
+            }
+        })
         .filter(|e| !e.file_type().is_dir())
         .enumerate()
     {
@@ -343,7 +836,7 @@ pub fn index(directory: &Path, blacklist: &HashSet<&Path>) -> SearchIndex {
         // scan for leftover `.so` files, which would of course take an enternity. This warning will
         // at least tell the user what's happening and that they can safely cancel the scan.
         if file_idx == 100_000 {
-            eprintln!(
+            utils::warnln!(
                 "Indexed over 100.000 files, press Ctrl+C to cancel this operation if this was not \
                  intentional."
             )
@@ -352,6 +845,9 @@ pub fn index(directory: &Path, blacklist: &HashSet<&Path>) -> SearchIndex {
         match entry.path().extension().and_then(|os| os.to_str()) {
             Some("dll") => dll_files.push(entry.into_path()),
             Some("vst3") => vst3_files.push(entry.into_path()),
+            Some("clap") => clap_files.push(entry.into_path()),
+            Some("lnk") => lnk_files.push(entry.into_path()),
+            Some("zip") => zip_files.push(entry.into_path()),
             Some("so") => {
                 if entry.path_is_symlink() {
                     so_files.push(NativeFile::Symlink(entry.into_path()));
@@ -366,22 +862,199 @@ pub fn index(directory: &Path, blacklist: &HashSet<&Path>) -> SearchIndex {
     SearchIndex {
         dll_files,
         vst3_files,
+        clap_files,
+        lnk_files,
+        zip_files,
         so_files,
     }
 }
 
+/// The directories above a directory visited by [`index_fast()`], identified by their device and
+/// inode numbers so symlinks pointing back to one of them can be detected.
+type DirectoryAncestors = Vec<((u64, u64), PathBuf)>;
+
+/// The same as [`index()`], but directories that have not been modified since `previous` was
+/// created are not read again. Their contents are taken from `previous` instead. Every directory we
+/// come across is added to `scanned`, so it can be stored for the next scan. Using an empty
+/// `previous` cache will read every directory. This is used for `yabridgectl sync --fast`.
+///
+/// A directory's modification time does not change when the contents of a subdirectory or a file
+/// change, so we still need to check every subdirectory. Changes can be missed when a tool sets a
+/// directory's modification time to a time in the past, like `rsync --times` does.
+pub fn index_fast(
+    directory: &Path,
+    blacklist: &HashSet<PathBuf>,
+    previous: &ScanCache,
+    scanned: &mut BTreeMap<PathBuf, ScannedDirectory>,
+) -> SearchIndex {
+    let mut index = SearchIndex {
+        dll_files: Vec::new(),
+        vst3_files: Vec::new(),
+        clap_files: Vec::new(),
+        lnk_files: Vec::new(),
+        zip_files: Vec::new(),
+        so_files: Vec::new(),
+    };
+
+    // Symlinks are followed just like in `index()`, so we need to keep track of the directories
+    // above the current directory to avoid symlink loops
+    let mut directories: Vec<(PathBuf, DirectoryAncestors)> =
+        vec![(directory.to_owned(), Vec::new())];
+    while let Some((directory, mut ancestors)) = directories.pop() {
+        if blacklist.contains(directory.as_path()) {
+            continue;
+        }
+
+        let metadata = match fs::metadata(&directory) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let id = (metadata.dev(), metadata.ino());
+        if let Some((_, ancestor)) = ancestors.iter().find(|(ancestor_id, _)| *ancestor_id == id) {
+            warn_symlink_loop(&directory, ancestor);
+            continue;
+        }
+        ancestors.push((id, directory.clone()));
+
+        let (_, modified) = cache::file_size_and_mtime(&directory).unwrap_or((0, u64::MAX));
+        let contents = match previous.directories.get(&directory) {
+            Some(contents) if modified < previous.watermark => contents.clone(),
+            _ => read_directory(&directory),
+        };
+
+        for path in contents
+            .files
+            .iter()
+            .filter(|path| !blacklist.contains(path.as_path()))
+        {
+            match path.extension().and_then(|os| os.to_str()) {
+                Some("dll") => index.dll_files.push(path.clone()),
+                Some("vst3") => index.vst3_files.push(path.clone()),
+                Some("clap") => index.clap_files.push(path.clone()),
+                Some("lnk") => index.lnk_files.push(path.clone()),
+                Some("zip") => index.zip_files.push(path.clone()),
+                Some("so") if contents.symlinks.contains(path) => {
+                    index.so_files.push(NativeFile::Symlink(path.clone()))
+                }
+                Some("so") => index.so_files.push(NativeFile::Regular(path.clone())),
+                _ => (),
+            }
+        }
+        directories.extend(
+            contents
+                .subdirectories
+                .iter()
+                .rev()
+                .map(|subdirectory| (subdirectory.clone(), ancestors.clone())),
+        );
+
+        scanned.insert(directory, contents);
+    }
+
+    index
+}
+
+/// Print a warning about a symlink to one of its own parent directories that we skipped while
+/// indexing. The same directories get indexed several times during a single sync, so every loop is
+/// only reported once.
+fn warn_symlink_loop(path: &Path, ancestor: &Path) {
+    lazy_static! {
+        static ref REPORTED_LOOPS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+    }
+
+    if !REPORTED_LOOPS.lock().unwrap().insert(path.to_owned()) {
+        return;
+    }
+
+    utils::warnln!(
+        "\n{}",
+        utils::wrap(&format!(
+            "{}: Skipping '{}', since it is a symlink to '{}' which contains the symlink itself. \
+             Following it would cause an infinite loop.",
+            "WARNING".red(),
+            path.display(),
+            ancestor.display()
+        ))
+    );
+}
+
+/// Read the relevant contents of a single directory for [`index_fast()`]. Unreadable entries and
+/// broken symlinks are skipped, just like in [`index()`].
+fn read_directory(directory: &Path) -> ScannedDirectory {
+    let mut contents = ScannedDirectory::default();
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return contents,
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        // This follows symlinks
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            contents.subdirectories.push(path);
+            continue;
+        }
+
+        match path.extension().and_then(|os| os.to_str()) {
+            Some("so") => {
+                if entry
+                    .file_type()
+                    .map(|file_type| file_type.is_symlink())
+                    .unwrap_or(false)
+                {
+                    contents.symlinks.push(path.clone());
+                }
+                contents.files.push(path);
+            }
+            Some("dll") | Some("vst3") | Some("clap") | Some("lnk") | Some("zip") => {
+                contents.files.push(path)
+            }
+            _ => (),
+        }
+    }
+    contents.files.sort();
+    contents.symlinks.sort();
+    contents.subdirectories.sort();
+
+    contents
+}
+
 impl SearchIndex {
-    /// Filter these indexing results down to actual VST2 plugins and VST3 modules. This will skip
+    /// Remove the files that should not be considered as plugins according to [`is_ignored_file()`].
+    /// The `.so` files are kept, since those are only used to find leftover files.
+    pub fn without_ignored_files(mut self, ignored_suffixes: &[String]) -> SearchIndex {
+        for files in [
+            &mut self.dll_files,
+            &mut self.vst3_files,
+            &mut self.clap_files,
+            &mut self.lnk_files,
+            &mut self.zip_files,
+        ]
+        .iter_mut()
+        {
+            files.retain(|path| !is_ignored_file(path, ignored_suffixes));
+        }
+
+        self
+    }
+
+    /// Filter these indexing results down to actual VST2 plugins, VST3 modules, and CLAP plugins.
+    /// This will skip
     /// all invalid files, such as regular `.dll` libraries. Will return an error if `winedump`
-    /// could not be found.
-    pub fn search(self) -> Result<SearchResults> {
+    /// could not be found. Files that haven't changed since they were added to `plugin_index` are
+    /// not classified again. The entries for all classified files are stored in the results.
+    pub fn search(self, plugin_index: &PluginIndex) -> Result<SearchResults> {
         lazy_static! {
             static ref VST2_AUTOMATON: AhoCorasick =
-                AhoCorasick::new_auto_configured(&["VSTPluginMain", "main", "main_plugin"]);
+                AhoCorasick::new_auto_configured(PluginFormat::Vst2.entry_points());
             static ref VST3_AUTOMATON: AhoCorasick =
-                AhoCorasick::new_auto_configured(&["GetPluginFactory"]);
-            static ref DLL32_AUTOMATON: AhoCorasick =
-                AhoCorasick::new_auto_configured(&["Machine:                      014C"]);
+                AhoCorasick::new_auto_configured(PluginFormat::Vst3.entry_points());
+            static ref CLAP_AUTOMATON: AhoCorasick =
+                AhoCorasick::new_auto_configured(PluginFormat::Clap.entry_points());
         }
 
         let winedump = |args: &[&str], path: &Path| {
@@ -395,26 +1068,62 @@ impl SearchIndex {
                 )
                 .map(|output| output.stdout)
         };
-        let pe32_info = |path: &Path| winedump(&[], path);
         let exported_functions = |path: &Path| winedump(&["-j", "export"], path);
+        let classify = |path: &Path, format: PluginFormat| -> Result<IndexedFile> {
+            let (size, modified) = cache::file_size_and_mtime(path)?;
+            let hash = match plugin_index.lookup(path, size, modified) {
+                Ok(indexed) => return Ok(indexed),
+                Err(hash) => hash,
+            };
+
+            // Files that aren't valid Windows libraries won't export any of the entry points, so
+            // the architecture doesn't matter for those
+            let architecture = pe_architecture(path)?.unwrap_or(LibArchitecture::Dll64);
+
+            // Reading the export table ourselves is much faster than running `winedump`, so that's
+            // only used as a fallback
+            let is_plugin = match pe_exports_entry_point(path, format)? {
+                Some(is_plugin) => is_plugin,
+                None => {
+                    let automaton: &AhoCorasick = match format {
+                        PluginFormat::Vst2 => &VST2_AUTOMATON,
+                        PluginFormat::Vst3 => &VST3_AUTOMATON,
+                        PluginFormat::Clap => &CLAP_AUTOMATON,
+                    };
+                    automaton.is_match(exported_functions(path)?)
+                }
+            };
+
+            Ok(IndexedFile {
+                size,
+                modified,
+                hash,
+                architecture,
+                is_plugin,
+            })
+        };
 
         // We'll have to figure out which `.dll` files are VST2 plugins and which should be skipped
         // by checking whether the file contains one of the VST2 entry point functions. This vector
         // will contain an `Err(path)` if `path` was not a valid VST2 plugin.
-        let is_vst2_plugin: Vec<Result<Vst2Plugin, PathBuf>> = self
+        let is_vst2_plugin: Vec<(Result<Vst2Plugin, PathBuf>, IndexedFile)> = self
             .dll_files
             .into_par_iter()
             .map(|path| {
-                let architecture = if DLL32_AUTOMATON.is_match(pe32_info(&path)?) {
-                    LibArchitecture::Dll32
-                } else {
-                    LibArchitecture::Dll64
-                };
+                let indexed = classify(&path, PluginFormat::Vst2)?;
+                let architecture = indexed.architecture;
 
-                if VST2_AUTOMATON.is_match(exported_functions(&path)?) {
-                    Ok(Ok(Vst2Plugin { path, architecture }))
+                if indexed.is_plugin {
+                    Ok((
+                        Ok(Vst2Plugin {
+                            target_path: path.with_extension("so"),
+                            path,
+                            architecture,
+                        }),
+                        indexed,
+                    ))
                 } else {
-                    Ok(Err(path))
+                    Ok((Err(path), indexed))
                 }
             })
             .collect::<Result<_>>()?;
@@ -423,17 +1132,14 @@ impl SearchIndex {
         // to figure out of the `.vst3` file is a legacy standalone VST3 module, or part of a VST
         // 3.6.10 bundle. We also need to know the plugin's architecture because we're going to
         // create a univeral VST3 bundle.
-        let is_vst3_module: Vec<Result<Vst3Module, PathBuf>> = self
+        let is_vst3_module: Vec<(Result<Vst3Module, PathBuf>, IndexedFile)> = self
             .vst3_files
             .into_par_iter()
             .map(|module_path| {
-                let architecture = if DLL32_AUTOMATON.is_match(pe32_info(&module_path)?) {
-                    LibArchitecture::Dll32
-                } else {
-                    LibArchitecture::Dll64
-                };
+                let indexed = classify(&module_path, PluginFormat::Vst3)?;
+                let architecture = indexed.architecture;
 
-                if VST3_AUTOMATON.is_match(exported_functions(&module_path)?) {
+                if indexed.is_plugin {
                     // Now we'll have to figure out if the plugin is part of a VST 3.6.10 style
                     // bundle or a legacy `.vst3` DLL file. A WIndows VST3 bundle contains at least
                     // `<plugin_name>.vst3/Contents/<architecture_string>/<plugin_name>.vst3`, so
@@ -472,54 +1178,891 @@ impl SearchIndex {
 
                     // We want to recreate the original subdirectory structure, so plugins are still
                     // grouped by manufacturer
-                    let vst3_directory = module_home.ancestors().find(|path| {
-                        path.file_name()
-                            .and_then(|name| name.to_str())
-                            .map(|name| name.to_lowercase().as_str() == "vst3")
-                            .unwrap_or(false)
-                    });
-                    let subdirectory = match vst3_directory {
-                        Some(directory) => module_home
-                            .strip_prefix(directory)
-                            .ok()
-                            // We should of coruse pop the `.vst3` directory
-                            .and_then(|suffix| suffix.parent())
-                            .map(|subdirectory| subdirectory.to_owned()),
-                        None => None,
-                    };
+                    let subdirectory = plugin_subdirectory(module_home, "vst3");
+
+                    Ok((
+                        Ok(Vst3Module {
+                            module,
+                            architecture,
+                            subdirectory,
+                            target_home: yabridge_vst3_home(),
+                        }),
+                        indexed,
+                    ))
+                } else {
+                    Ok((Err(module_path), indexed))
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        // CLAP plugins are always single `.clap` files, so these don't need any special treatment
+        let is_clap_plugin: Vec<(Result<ClapPlugin, PathBuf>, IndexedFile)> = self
+            .clap_files
+            .into_par_iter()
+            .map(|path| {
+                let indexed = classify(&path, PluginFormat::Clap)?;
+                let architecture = indexed.architecture;
 
-                    Ok(Ok(Vst3Module {
-                        module,
-                        architecture,
-                        subdirectory,
-                    }))
+                if indexed.is_plugin {
+                    Ok((
+                        Ok(ClapPlugin {
+                            subdirectory: plugin_subdirectory(&path, "clap"),
+                            path,
+                            architecture,
+                            target_home: yabridge_clap_home(),
+                        }),
+                        indexed,
+                    ))
                 } else {
-                    Ok(Err(module_path))
+                    Ok((Err(path), indexed))
                 }
             })
             .collect::<Result<_>>()?;
 
         let mut plugins: Vec<Plugin> = Vec::new();
         let mut skipped_files: Vec<PathBuf> = Vec::new();
+        let mut indexed_files: BTreeMap<PathBuf, IndexedFile> = BTreeMap::new();
 
-        for dandidate in is_vst2_plugin {
+        for (dandidate, indexed) in is_vst2_plugin {
             match dandidate {
-                Ok(plugin) => plugins.push(Plugin::Vst2(plugin)),
-                Err(path) => skipped_files.push(path),
+                Ok(plugin) => {
+                    indexed_files.insert(plugin.path.clone(), indexed);
+                    plugins.push(Plugin::Vst2(plugin));
+                }
+                Err(path) => {
+                    indexed_files.insert(path.clone(), indexed);
+                    skipped_files.push(path);
+                }
             }
         }
 
-        for candidate in is_vst3_module {
+        for (candidate, indexed) in is_vst3_module {
             match candidate {
-                Ok(module) => plugins.push(Plugin::Vst3(module)),
-                Err(path) => skipped_files.push(path),
+                Ok(module) => {
+                    indexed_files.insert(module.original_module_path(), indexed);
+                    plugins.push(Plugin::Vst3(module));
+                }
+                Err(path) => {
+                    indexed_files.insert(path.clone(), indexed);
+                    skipped_files.push(path);
+                }
+            }
+        }
+
+        for (candidate, indexed) in is_clap_plugin {
+            match candidate {
+                Ok(clap_plugin) => {
+                    indexed_files.insert(clap_plugin.path.clone(), indexed);
+                    plugins.push(Plugin::Clap(clap_plugin));
+                }
+                Err(path) => {
+                    indexed_files.insert(path.clone(), indexed);
+                    skipped_files.push(path);
+                }
             }
         }
 
         Ok(SearchResults {
             plugins,
             skipped_files,
+            indexed_files,
+            shortcut_files: self.lnk_files,
+            archive_files: self.zip_files,
             so_files: self.so_files,
         })
     }
 }
+
+/// Find the subdirectory `plugin_path` is in, relative to the closest parent directory called
+/// `format_directory` (e.g. `VST3` or `CLAP`), ignoring capitalization. Returns `None` if there is
+/// no such directory, or if the plugin is directly inside of it.
+fn plugin_subdirectory(plugin_path: &Path, format_directory: &str) -> Option<PathBuf> {
+    let format_directory = plugin_path.ancestors().find(|path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.to_lowercase() == format_directory)
+            .unwrap_or(false)
+    })?;
+
+    plugin_path
+        .strip_prefix(format_directory)
+        .ok()
+        // We should of course pop the plugin itself
+        .and_then(|suffix| suffix.parent())
+        .map(|subdirectory| subdirectory.to_owned())
+}
+
+/// Check whether a plugin matches an entry from a plugin list, such as the file passed to
+/// `yabridgectl sync --plugins-from` or the plugins in a profile. An entry can be an absolute path to
+/// a plugin, a path relative to a plugin directory, a file name, or a plugin name without the file
+/// extension. `plugin_path` is the path to a VST2 plugin's `.dll` file or to a VST3 module. With
+/// `ignore_case` the entry can use different capitalization than the plugin's path, just like file
+/// names on Windows.
+pub fn plugin_matches_entry(plugin_path: &Path, entry: &str, ignore_case: bool) -> bool {
+    if ignore_case {
+        let plugin_path = PathBuf::from(plugin_path.to_string_lossy().to_lowercase());
+        return plugin_matches_entry(&plugin_path, &entry.to_lowercase(), false);
+    }
+
+    // `Path::ends_with()` compares entire path components, so this matches both absolute paths,
+    // relative paths, and file names
+    plugin_path.ends_with(entry)
+        || plugin_path.file_stem().and_then(|name| name.to_str()) == Some(entry)
+}
+
+/// Check whether a plugin matches a wildcard pattern, see [`utils::wildcard_match()`]. The pattern
+/// is matched against the plugin's full path, and relative patterns are also matched against the
+/// path relative to the plugin directory `directory`. Used for the patterns in the blacklist and for
+/// `yabridgectl sync --only`.
+pub fn plugin_matches_pattern(directory: &Path, plugin_path: &Path, pattern: &str) -> bool {
+    let full_path = plugin_path.to_string_lossy();
+    let relative_path = plugin_path
+        .strip_prefix(directory)
+        .unwrap_or(plugin_path)
+        .to_string_lossy();
+
+    utils::wildcard_match(pattern, &full_path)
+        || (Path::new(pattern).is_relative() && utils::wildcard_match(pattern, &relative_path))
+}
+
+/// Check whether a plugin belongs to one of `vendors`. Yabridgectl doesn't read any metadata from
+/// the plugins themselves, so instead this checks whether one of the directories between the
+/// plugin directory `directory` and the plugin starts with one of the vendor names, ignoring
+/// capitalization. Plugins are almost always installed in a directory named after their vendor,
+/// e.g. `VstPlugins/FabFilter/FabFilter Pro-Q 3.dll`.
+pub fn plugin_matches_vendor(directory: &Path, plugin_path: &Path, vendors: &[String]) -> bool {
+    let relative_path = plugin_path.strip_prefix(directory).unwrap_or(plugin_path);
+    relative_path
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().to_lowercase()),
+            _ => None,
+        })
+        .any(|name| {
+            vendors
+                .iter()
+                .any(|vendor| name.starts_with(&vendor.to_lowercase()))
+        })
+}
+
+/// The same as [`read_shortcut_target()`], but this only returns the target if it points to a VST2
+/// or VST3 plugin. Shortcuts to plugins are often added to plugin directories by mistake.
+pub fn read_plugin_shortcut_target(path: &Path) -> Option<String> {
+    read_shortcut_target(path).filter(|target| {
+        let target = target.to_lowercase();
+        target.ends_with(".dll") || target.ends_with(".vst3")
+    })
+}
+
+/// Read the Windows path a Windows shortcut (`.lnk` file) points to, e.g. `C:\Program
+/// Files\Plugin\Plugin.dll`. Returns `None` if the file is not a valid shortcut or if it does not
+/// point to a local file. See the `[MS-SHLLINK]` specification for a description of the format.
+pub fn read_shortcut_target(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    let read_u16 = |offset: usize| -> Option<usize> {
+        let bytes = data.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let read_u32 = |offset: usize| -> Option<usize> {
+        let bytes = data.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    let read_c_string = |offset: usize| -> Option<String> {
+        let bytes = data.get(offset..)?;
+        let length = bytes.iter().position(|&c| c == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    };
+
+    // The header always starts with its own size, and it's followed by the `LinkFlags` field
+    const HEADER_SIZE: usize = 0x4c;
+    const HAS_LINK_TARGET_ID_LIST: usize = 1 << 0;
+    const HAS_LINK_INFO: usize = 1 << 1;
+    const VOLUME_ID_AND_LOCAL_BASE_PATH: usize = 1 << 0;
+    if read_u32(0)? != HEADER_SIZE {
+        return None;
+    }
+    let link_flags = read_u32(0x14)?;
+    if link_flags & HAS_LINK_INFO == 0 {
+        return None;
+    }
+
+    // The `LinkTargetIDList` comes right after the header, and the `LinkInfo` structure containing
+    // the target's path comes after that
+    let link_info = if link_flags & HAS_LINK_TARGET_ID_LIST != 0 {
+        HEADER_SIZE + 2 + read_u16(HEADER_SIZE)?
+    } else {
+        HEADER_SIZE
+    };
+    if read_u32(link_info + 8)? & VOLUME_ID_AND_LOCAL_BASE_PATH == 0 {
+        return None;
+    }
+    let local_base_path = read_c_string(link_info + read_u32(link_info + 16)?)?;
+    let common_path_suffix =
+        read_c_string(link_info + read_u32(link_info + 24)?).unwrap_or_default();
+
+    Some(local_base_path + &common_path_suffix)
+}
+
+/// The first four bytes of every ELF file.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Check whether `header`, the start of a file, contains the ELF magic bytes. Used to make sure
+/// yabridge's libraries are actual shared libraries and not empty files or text placeholders left
+/// behind by an incomplete installation. See [`crate::config::Config::files()`].
+pub fn has_elf_magic(header: &[u8]) -> bool {
+    header.starts_with(ELF_MAGIC)
+}
+
+/// The dynamic dependencies of an ELF executable or shared library. See
+/// [`read_elf_dependencies()`].
+#[derive(Debug, Default)]
+pub struct ElfDependencies {
+    /// Whether this is a 64-bit binary. 64-bit and 32-bit binaries can only use libraries with the
+    /// same architecture.
+    pub is_64_bit: bool,
+    /// The libraries the binary needs, e.g. `libc.so.6`. These are the `DT_NEEDED` entries.
+    pub needed: Vec<String>,
+    /// The binary's library search paths from its `DT_RUNPATH` or `DT_RPATH` entries. These may
+    /// still contain `$ORIGIN`.
+    pub search_paths: Vec<String>,
+}
+
+/// Read the libraries an ELF binary such as `yabridge-host.exe.so` depends on from its dynamic
+/// section. Returns `None` if the file is not a valid little-endian ELF file or if it does not have
+/// a dynamic section. All offsets are checked, so malformed headers also result in `None`. See the
+/// `elf(5)` man page for a description of the format.
+pub fn read_elf_dependencies(path: &Path) -> Option<ElfDependencies> {
+    let data = fs::read(path).ok()?;
+    let read_bytes = |offset: usize, size: usize| -> Option<usize> {
+        let bytes = data.get(offset..offset.checked_add(size)?)?;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0u64, |value, &byte| (value << 8) | byte as u64) as usize,
+        )
+    };
+    // Reads a field at `offset` bytes from the start of a header or an entry at `base`
+    let read_field = |base: usize, offset: usize, size: usize| -> Option<usize> {
+        read_bytes(base.checked_add(offset)?, size)
+    };
+    let read_c_string = |offset: usize| -> Option<String> {
+        let bytes = data.get(offset..)?;
+        let length = bytes.iter().position(|&c| c == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    };
+
+    const ELFCLASS64: u8 = 2;
+    const ELFDATA2LSB: u8 = 1;
+    const PT_LOAD: usize = 1;
+    const PT_DYNAMIC: usize = 2;
+    const DT_NULL: usize = 0;
+    const DT_NEEDED: usize = 1;
+    const DT_STRTAB: usize = 5;
+    const DT_RPATH: usize = 15;
+    const DT_RUNPATH: usize = 29;
+    if !has_elf_magic(&data) || *data.get(5)? != ELFDATA2LSB {
+        return None;
+    }
+
+    // Most fields are twice as large in 64-bit ELF files, and they are placed at different offsets
+    let is_64_bit = *data.get(4)? == ELFCLASS64;
+    let word_size = if is_64_bit { 8 } else { 4 };
+    let (program_headers, program_header_size, num_program_headers) = if is_64_bit {
+        (
+            read_bytes(0x20, 8)?,
+            read_bytes(0x36, 2)?,
+            read_bytes(0x38, 2)?,
+        )
+    } else {
+        (
+            read_bytes(0x1c, 4)?,
+            read_bytes(0x2a, 2)?,
+            read_bytes(0x2c, 2)?,
+        )
+    };
+
+    // These are `(file_offset, virtual_address, file_size)` triples
+    let mut loaded_segments: Vec<(usize, usize, usize)> = Vec::new();
+    let mut dynamic_segment: Option<(usize, usize)> = None;
+    for idx in 0..num_program_headers {
+        let header = program_headers.checked_add(idx.checked_mul(program_header_size)?)?;
+        let (offset, virtual_address, file_size) = if is_64_bit {
+            (
+                read_field(header, 8, 8)?,
+                read_field(header, 16, 8)?,
+                read_field(header, 32, 8)?,
+            )
+        } else {
+            (
+                read_field(header, 4, 4)?,
+                read_field(header, 8, 4)?,
+                read_field(header, 16, 4)?,
+            )
+        };
+        match read_bytes(header, 4)? {
+            PT_LOAD => loaded_segments.push((offset, virtual_address, file_size)),
+            PT_DYNAMIC => dynamic_segment = Some((offset, file_size)),
+            _ => (),
+        }
+    }
+
+    // The dynamic section refers to strings using offsets into the string table, but the string
+    // table's location is stored as a virtual address
+    let (dynamic_offset, dynamic_size) = dynamic_segment?;
+    let mut string_table_address = None;
+    let mut needed_offsets = Vec::new();
+    let mut search_path_offsets = Vec::new();
+    for entry in (dynamic_offset..dynamic_offset.checked_add(dynamic_size)?).step_by(word_size * 2)
+    {
+        let value = read_field(entry, word_size, word_size)?;
+        match read_bytes(entry, word_size)? {
+            DT_NULL => break,
+            DT_NEEDED => needed_offsets.push(value),
+            DT_STRTAB => string_table_address = Some(value),
+            DT_RPATH | DT_RUNPATH => search_path_offsets.push(value),
+            _ => (),
+        }
+    }
+    let string_table_address = string_table_address?;
+    let string_table = loaded_segments
+        .iter()
+        .find_map(|&(offset, address, size)| {
+            if (address..address.checked_add(size)?).contains(&string_table_address) {
+                (string_table_address - address).checked_add(offset)
+            } else {
+                None
+            }
+        })?;
+
+    Some(ElfDependencies {
+        is_64_bit,
+        needed: needed_offsets
+            .into_iter()
+            .filter_map(|offset| read_c_string(string_table.checked_add(offset)?))
+            .collect(),
+        search_paths: search_path_offsets
+            .into_iter()
+            .filter_map(|offset| read_c_string(string_table.checked_add(offset)?))
+            .flat_map(|paths| paths.split(':').map(String::from).collect::<Vec<_>>())
+            .filter(|path| !path.is_empty())
+            .collect(),
+    })
+}
+
+/// The marker yabridge's libraries and host binaries embed in front of yabridge's version, see
+/// `src/common/config/version.h.in`.
+const VERSION_MARKER: &[u8] = b"YABRIDGE_VERSION=";
+
+/// Read the version of yabridge embedded in one of yabridge's libraries or host binaries. Returns
+/// `None` if the file could not be read, or if it was built by an older version of yabridge that did
+/// not embed its version yet. See [`parse_embedded_version()`].
+pub fn read_embedded_version(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+
+    parse_embedded_version(&data).map(String::from)
+}
+
+/// Find the null terminated version string following [`VERSION_MARKER`] in a binary's contents.
+pub fn parse_embedded_version(data: &[u8]) -> Option<&str> {
+    find_marked_string(data, VERSION_MARKER)
+}
+
+/// The marker yabridge's host binaries embed in front of the version of Wine yabridge was compiled
+/// with, see `src/common/config/config.h.in`.
+const WINE_VERSION_MARKER: &[u8] = b"YABRIDGE_WINE_VERSION=";
+
+/// Read the version of Wine one of yabridge's host binaries was compiled with, e.g. `6.4`. Returns
+/// `None` if the file could not be read, if it was built by an older version of yabridge that did
+/// not embed this yet, or if the Wine version could not be determined when yabridge was built.
+pub fn read_embedded_wine_version(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+
+    find_marked_string(&data, WINE_VERSION_MARKER).map(String::from)
+}
+
+/// Find the non-empty null terminated string following `marker` in a binary's contents.
+fn find_marked_string<'a>(data: &'a [u8], marker: &[u8]) -> Option<&'a str> {
+    let start = data
+        .windows(marker.len())
+        .position(|window| window == marker)?
+        + marker.len();
+    let length = data[start..].iter().position(|&c| c == 0)?;
+
+    std::str::from_utf8(&data[start..start + length])
+        .ok()
+        .filter(|string| !string.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Build a minimal little-endian ELF file with a dynamic section containing `DT_NEEDED` entries
+    /// for `needed` and a `DT_RUNPATH` entry for `runpath`. The file is loaded at a nonzero virtual
+    /// address so the string table's address has to be translated to a file offset.
+    fn elf_fixture(is_64_bit: bool, needed: &[&str], runpath: Option<&str>) -> Vec<u8> {
+        const BASE_ADDRESS: usize = 0x1000;
+        let word_size = if is_64_bit { 8 } else { 4 };
+        let (header_size, program_header_size) = if is_64_bit { (64, 56) } else { (52, 32) };
+
+        let mut strings = vec![0u8];
+        let mut string_offset = |string: &str| {
+            let offset = strings.len();
+            strings.extend_from_slice(string.as_bytes());
+            strings.push(0);
+            offset
+        };
+        let mut dynamic_entries: Vec<(usize, usize)> = needed
+            .iter()
+            .map(|library| (1, string_offset(library)))
+            .collect();
+        if let Some(runpath) = runpath {
+            dynamic_entries.push((29, string_offset(runpath)));
+        }
+
+        let string_table_offset = header_size + (2 * program_header_size);
+        let dynamic_offset = (string_table_offset + strings.len() + 7) & !7;
+        dynamic_entries.push((5, BASE_ADDRESS + string_table_offset));
+        dynamic_entries.push((0, 0));
+        let dynamic_size = dynamic_entries.len() * word_size * 2;
+        let file_size = dynamic_offset + dynamic_size;
+
+        let mut data = vec![0u8; file_size];
+        let mut write = |offset: usize, size: usize, value: usize| {
+            data[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        };
+        write(0, 4, 0x464c457f);
+        write(4, 1, if is_64_bit { 2 } else { 1 });
+        write(5, 1, 1);
+        let program_headers = [
+            (1, 0, BASE_ADDRESS, file_size),
+            (
+                2,
+                dynamic_offset,
+                BASE_ADDRESS + dynamic_offset,
+                dynamic_size,
+            ),
+        ];
+        if is_64_bit {
+            write(0x20, 8, header_size);
+            write(0x36, 2, program_header_size);
+            write(0x38, 2, program_headers.len());
+        } else {
+            write(0x1c, 4, header_size);
+            write(0x2a, 2, program_header_size);
+            write(0x2c, 2, program_headers.len());
+        }
+        for (idx, &(kind, offset, address, size)) in program_headers.iter().enumerate() {
+            let header = header_size + (idx * program_header_size);
+            write(header, 4, kind);
+            if is_64_bit {
+                write(header + 8, 8, offset);
+                write(header + 16, 8, address);
+                write(header + 32, 8, size);
+            } else {
+                write(header + 4, 4, offset);
+                write(header + 8, 4, address);
+                write(header + 16, 4, size);
+            }
+        }
+        for (idx, &(tag, value)) in dynamic_entries.iter().enumerate() {
+            let entry = dynamic_offset + (idx * word_size * 2);
+            write(entry, word_size, tag);
+            write(entry + word_size, word_size, value);
+        }
+        data[string_table_offset..string_table_offset + strings.len()].copy_from_slice(&strings);
+
+        data
+    }
+
+    /// A DOS header followed by a PE header for the machine type `machine`. This is all
+    /// [`pe_architecture()`] reads.
+    fn pe_stub(machine: u16) -> Vec<u8> {
+        const PE_HEADER_OFFSET: usize = 0x80;
+        let mut data = vec![0u8; PE_HEADER_OFFSET + 24];
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&(PE_HEADER_OFFSET as u32).to_le_bytes());
+        data[PE_HEADER_OFFSET..PE_HEADER_OFFSET + 4].copy_from_slice(b"PE\0\0");
+        data[PE_HEADER_OFFSET + 4..PE_HEADER_OFFSET + 6].copy_from_slice(&machine.to_le_bytes());
+
+        data
+    }
+
+    #[test]
+    fn pe_architecture_machine_types() {
+        let directory = tempdir().unwrap();
+        for (machine, expected) in [
+            (0x014c, Some(LibArchitecture::Dll32)),
+            (0x8664, Some(LibArchitecture::Dll64)),
+            // ARM64
+            (0xaa64, None),
+        ] {
+            let path = directory.path().join(format!("{:x}.dll", machine));
+            fs::write(&path, pe_stub(machine)).unwrap();
+            assert_eq!(pe_architecture(&path).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn pe_architecture_non_pe_files() {
+        let directory = tempdir().unwrap();
+
+        let text = directory.path().join("text.dll");
+        fs::write(&text, "This is not a library").unwrap();
+        assert_eq!(pe_architecture(&text).unwrap(), None);
+
+        // A DOS executable without a PE header
+        let mut dos_only = pe_stub(0x014c);
+        dos_only.truncate(0x80);
+        let dos_path = directory.path().join("dos.dll");
+        fs::write(&dos_path, dos_only).unwrap();
+        assert_eq!(pe_architecture(&dos_path).unwrap(), None);
+
+        let mut wrong_signature = pe_stub(0x8664);
+        wrong_signature[0x80..0x84].copy_from_slice(b"NE\0\0");
+        let wrong_signature_path = directory.path().join("ne.dll");
+        fs::write(&wrong_signature_path, wrong_signature).unwrap();
+        assert_eq!(pe_architecture(&wrong_signature_path).unwrap(), None);
+
+        let empty = directory.path().join("empty.dll");
+        fs::write(&empty, "").unwrap();
+        assert_eq!(pe_architecture(&empty).unwrap(), None);
+
+        assert!(pe_architecture(&directory.path().join("missing.dll")).is_err());
+    }
+
+    #[test]
+    fn plugin_matches_entry_exact_case() {
+        let plugin = Path::new("/home/user/.wine/drive_c/VST2/FabFilter/Pro-Q 3.dll");
+        assert!(plugin_matches_entry(
+            plugin,
+            "/home/user/.wine/drive_c/VST2/FabFilter/Pro-Q 3.dll",
+            false
+        ));
+        assert!(plugin_matches_entry(plugin, "FabFilter/Pro-Q 3.dll", false));
+        assert!(plugin_matches_entry(plugin, "Pro-Q 3.dll", false));
+        assert!(plugin_matches_entry(plugin, "Pro-Q 3", false));
+        // Entries only match entire path components
+        assert!(!plugin_matches_entry(plugin, "Q 3.dll", false));
+        assert!(!plugin_matches_entry(plugin, "pro-q 3.dll", false));
+        assert!(!plugin_matches_entry(plugin, "PRO-Q 3", false));
+    }
+
+    #[test]
+    fn plugin_matches_entry_ignore_case() {
+        let plugin = Path::new("/home/user/.wine/drive_c/VST2/FabFilter/Pro-Q 3.dll");
+        assert!(plugin_matches_entry(
+            plugin,
+            "/HOME/user/.wine/drive_c/vst2/fabfilter/pro-q 3.DLL",
+            true
+        ));
+        assert!(plugin_matches_entry(plugin, "fabfilter/pro-q 3.dll", true));
+        assert!(plugin_matches_entry(plugin, "PRO-Q 3.DLL", true));
+        assert!(plugin_matches_entry(plugin, "pro-q 3", true));
+        assert!(!plugin_matches_entry(plugin, "q 3.dll", true));
+        assert!(!plugin_matches_entry(plugin, "pro-q 2", true));
+    }
+
+    #[test]
+    fn read_elf_dependencies_64_bit() {
+        let directory = tempdir().unwrap();
+        let path = directory.path().join("yabridge-host.exe.so");
+        fs::write(
+            &path,
+            elf_fixture(
+                true,
+                &["libwine.so.1", "libc.so.6"],
+                Some("$ORIGIN/../lib:/opt/wine/lib"),
+            ),
+        )
+        .unwrap();
+
+        let dependencies = read_elf_dependencies(&path).unwrap();
+        assert!(dependencies.is_64_bit);
+        assert_eq!(dependencies.needed, vec!["libwine.so.1", "libc.so.6"]);
+        assert_eq!(
+            dependencies.search_paths,
+            vec!["$ORIGIN/../lib", "/opt/wine/lib"]
+        );
+    }
+
+    #[test]
+    fn read_elf_dependencies_32_bit() {
+        let directory = tempdir().unwrap();
+        let path = directory.path().join("yabridge-host-32.exe.so");
+        fs::write(&path, elf_fixture(false, &["libc.so.6"], None)).unwrap();
+
+        let dependencies = read_elf_dependencies(&path).unwrap();
+        assert!(!dependencies.is_64_bit);
+        assert_eq!(dependencies.needed, vec!["libc.so.6"]);
+        assert!(dependencies.search_paths.is_empty());
+    }
+
+    #[test]
+    fn read_elf_dependencies_invalid_files() {
+        let directory = tempdir().unwrap();
+        let not_elf = directory.path().join("placeholder.so");
+        fs::write(&not_elf, "This is not a library").unwrap();
+        assert!(read_elf_dependencies(&not_elf).is_none());
+
+        let truncated = directory.path().join("truncated.so");
+        let data = elf_fixture(true, &["libc.so.6"], None);
+        fs::write(&truncated, &data[..data.len() / 2]).unwrap();
+        assert!(read_elf_dependencies(&truncated).is_none());
+
+        assert!(read_elf_dependencies(&directory.path().join("missing.so")).is_none());
+    }
+
+    #[test]
+    fn read_elf_dependencies_overflowing_offsets() {
+        let directory = tempdir().unwrap();
+        let path = directory.path().join("malformed.so");
+        // The offset of the program headers, the loaded segment's size, and the dynamic segment's
+        // size in the 64-bit fixture. Any of these being absurdly large should not cause a panic.
+        for field in [0x20..0x28, 96..104, 152..160] {
+            let mut data = elf_fixture(true, &["libc.so.6"], None);
+            data[field].fill(0xff);
+            fs::write(&path, data).unwrap();
+            assert!(read_elf_dependencies(&path).is_none());
+        }
+    }
+
+    #[test]
+    fn vst3_bundle_resources_dir() {
+        let dir = tempdir().unwrap();
+        let module = |module: Vst3ModuleType| Vst3Module {
+            module,
+            architecture: LibArchitecture::Dll64,
+            subdirectory: Some(PathBuf::from("Vendor")),
+            target_home: PathBuf::from("/target"),
+        };
+
+        let with_resources = dir.path().join("With Resources.vst3");
+        fs::create_dir_all(with_resources.join("Contents/Resources")).unwrap();
+        fs::create_dir_all(with_resources.join("Contents/x86_64-win")).unwrap();
+        let with_resources = module(Vst3ModuleType::Bundle(with_resources.clone()));
+        assert_eq!(
+            with_resources.original_resources_dir(),
+            Some(dir.path().join("With Resources.vst3/Contents/Resources"))
+        );
+        assert_eq!(
+            with_resources.target_resources_dir(),
+            PathBuf::from("/target/Vendor/With Resources.vst3/Contents/Resources")
+        );
+
+        let without_resources = dir.path().join("Without Resources.vst3");
+        fs::create_dir_all(without_resources.join("Contents/x86_64-win")).unwrap();
+        assert_eq!(
+            module(Vst3ModuleType::Bundle(without_resources)).original_resources_dir(),
+            None
+        );
+
+        // Legacy modules are just files, so they can't have any resources
+        let legacy = dir.path().join("Legacy.vst3");
+        fs::write(&legacy, "").unwrap();
+        assert_eq!(
+            module(Vst3ModuleType::Legacy(legacy)).original_resources_dir(),
+            None
+        );
+    }
+
+    /// A plugin directory containing a single plugin, a symlink pointing back to the plugin
+    /// directory, and a symlink pointing to itself.
+    fn symlink_loop_fixture(directory: &Path) {
+        let vendor = directory.join("Vendor");
+        fs::create_dir(&vendor).unwrap();
+        fs::write(vendor.join("Plugin.dll"), "").unwrap();
+        std::os::unix::fs::symlink(directory, vendor.join("Loop")).unwrap();
+        std::os::unix::fs::symlink(vendor.join("Self"), vendor.join("Self")).unwrap();
+    }
+
+    #[test]
+    fn index_skips_symlink_loops() {
+        let directory = tempdir().unwrap();
+        symlink_loop_fixture(directory.path());
+
+        let index = index(directory.path(), &HashSet::new());
+        assert_eq!(
+            index.dll_files,
+            vec![directory.path().join("Vendor/Plugin.dll")]
+        );
+    }
+
+    #[test]
+    fn index_fast_skips_symlink_loops() {
+        let directory = tempdir().unwrap();
+        symlink_loop_fixture(directory.path());
+
+        let mut scanned = BTreeMap::new();
+        let index = index_fast(
+            directory.path(),
+            &HashSet::new(),
+            &ScanCache::default(),
+            &mut scanned,
+        );
+        assert_eq!(
+            index.dll_files,
+            vec![directory.path().join("Vendor/Plugin.dll")]
+        );
+        // The loop itself is skipped, so it's not stored in the cache either
+        assert_eq!(
+            scanned.keys().collect::<Vec<_>>(),
+            vec![
+                &directory.path().to_owned(),
+                &directory.path().join("Vendor")
+            ]
+        );
+    }
+
+    /// Build a PE32 or PE32+ library with a single section starting at RVA `0x1000` containing an
+    /// export directory for `exports`. Without exports the library won't have an export table.
+    fn pe_fixture(is_64_bit: bool, exports: Option<&[&str]>) -> Vec<u8> {
+        const PE_HEADER_OFFSET: usize = 0x80;
+        const SECTION_OFFSET: usize = 0x400;
+        const SECTION_RVA: u32 = 0x1000;
+        let (magic, machine, data_directories_offset): (u16, u16, usize) = if is_64_bit {
+            (0x20b, 0x8664, 112)
+        } else {
+            (0x10b, 0x014c, 96)
+        };
+        let optional_header_size = data_directories_offset + 16 * 8;
+        let optional_header_offset = PE_HEADER_OFFSET + 24;
+        let section_header_offset = optional_header_offset + optional_header_size;
+
+        let mut data = pe_stub(machine);
+        data.resize(SECTION_OFFSET, 0);
+        let put = |data: &mut Vec<u8>, offset: usize, bytes: &[u8]| {
+            if data.len() < offset + bytes.len() {
+                data.resize(offset + bytes.len(), 0);
+            }
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        put(&mut data, PE_HEADER_OFFSET + 6, &1u16.to_le_bytes());
+        put(
+            &mut data,
+            PE_HEADER_OFFSET + 20,
+            &(optional_header_size as u16).to_le_bytes(),
+        );
+        put(&mut data, optional_header_offset, &magic.to_le_bytes());
+        // The number of data directories
+        put(
+            &mut data,
+            optional_header_offset + data_directories_offset - 4,
+            &16u32.to_le_bytes(),
+        );
+
+        // The section's contents are the export directory, followed by the array of name RVAs,
+        // followed by the names themselves
+        let mut section = vec![0u8; 40];
+        if let Some(exports) = exports {
+            let names_rva = SECTION_RVA + 40;
+            let mut name_rva = names_rva + exports.len() as u32 * 4;
+            let mut names = Vec::new();
+            section[24..28].copy_from_slice(&(exports.len() as u32).to_le_bytes());
+            section[32..36].copy_from_slice(&names_rva.to_le_bytes());
+            for export in exports {
+                section.extend_from_slice(&name_rva.to_le_bytes());
+                names.extend_from_slice(export.as_bytes());
+                names.push(0);
+                name_rva += export.len() as u32 + 1;
+            }
+            section.extend(names);
+
+            put(
+                &mut data,
+                optional_header_offset + data_directories_offset,
+                &SECTION_RVA.to_le_bytes(),
+            );
+            put(
+                &mut data,
+                optional_header_offset + data_directories_offset + 4,
+                &(section.len() as u32).to_le_bytes(),
+            );
+        }
+
+        let mut section_header = [0u8; 40];
+        section_header[..6].copy_from_slice(b".edata");
+        section_header[8..12].copy_from_slice(&(section.len() as u32).to_le_bytes());
+        section_header[12..16].copy_from_slice(&SECTION_RVA.to_le_bytes());
+        section_header[16..20].copy_from_slice(&(section.len() as u32).to_le_bytes());
+        section_header[20..24].copy_from_slice(&(SECTION_OFFSET as u32).to_le_bytes());
+        put(&mut data, section_header_offset, &section_header);
+        put(&mut data, SECTION_OFFSET, &section);
+
+        data
+    }
+
+    #[test]
+    fn pe_exported_functions_pe32_and_pe32_plus() {
+        let directory = tempdir().unwrap();
+        let exports = ["GetPluginFactory", "InitDll", "ExitDll"];
+        for is_64_bit in [false, true] {
+            let path = directory.path().join(format!("{}.dll", is_64_bit));
+            fs::write(&path, pe_fixture(is_64_bit, Some(&exports))).unwrap();
+            assert_eq!(
+                pe_exported_functions(&path).unwrap(),
+                Some(exports.iter().map(|name| name.to_string()).collect())
+            );
+        }
+    }
+
+    #[test]
+    fn pe_exports_entry_point_compares_whole_names() {
+        let directory = tempdir().unwrap();
+
+        // These names contain `main`, but they're not VST2 entry points
+        let library = directory.path().join("library.dll");
+        fs::write(
+            &library,
+            pe_fixture(true, Some(&["GetDomainName", "mainloop", "VSTPluginMain2"])),
+        )
+        .unwrap();
+        assert_eq!(
+            pe_exports_entry_point(&library, PluginFormat::Vst2).unwrap(),
+            Some(false)
+        );
+
+        let plugin = directory.path().join("plugin.dll");
+        fs::write(&plugin, pe_fixture(false, Some(&["GetDomainName", "main"]))).unwrap();
+        assert_eq!(
+            pe_exports_entry_point(&plugin, PluginFormat::Vst2).unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            pe_exports_entry_point(&plugin, PluginFormat::Vst3).unwrap(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn pe_exported_functions_without_exports() {
+        let directory = tempdir().unwrap();
+        let path = directory.path().join("no-exports.dll");
+        fs::write(&path, pe_fixture(true, None)).unwrap();
+        assert_eq!(pe_exported_functions(&path).unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn pe_exported_functions_invalid_files() {
+        let directory = tempdir().unwrap();
+
+        let text = directory.path().join("text.dll");
+        fs::write(&text, "This is not a library").unwrap();
+        assert_eq!(pe_exported_functions(&text).unwrap(), None);
+
+        // The names array points past the end of the file, so `winedump` should be used instead
+        let mut truncated = pe_fixture(false, Some(&["VSTPluginMain"]));
+        truncated.truncate(0x400 + 40);
+        let truncated_path = directory.path().join("truncated.dll");
+        fs::write(&truncated_path, truncated).unwrap();
+        assert_eq!(pe_exported_functions(&truncated_path).unwrap(), None);
+
+        // Files that can't be opened are errors
+        assert!(pe_exported_functions(&directory.path().join("missing.dll")).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Serialization for yabridgectl's machine readable JSON output. All commands that can output JSON
+//! should go through [`print()`] so the output is formatted consistently.
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::FromRawFd;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// The original STDOUT after [`redirect_stdout()`] has been called. JSON output is written here
+    /// while everything else printed to STDOUT ends up on STDERR.
+    static ref JSON_OUTPUT: Mutex<Option<File>> = Mutex::new(None);
+}
+
+/// How JSON output should be formatted. This is set through the global `--json-pretty` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// Print everything on a single line. This is the default when using `--json`.
+    Compact,
+    /// Print the output with newlines and two space indentation.
+    Pretty,
+}
+
+/// Serialize a value to a JSON string. The value is converted to a [`serde_json::Value`] first.
+/// Since objects in there are backed by a `BTreeMap`, all keys will always be sorted
+/// alphabetically. This guarantees a stable key ordering, so the output can be diffed across runs.
+/// Fields containing a `None` are serialized as `null`.
+pub fn to_string<T: Serialize>(value: &T, format: JsonFormat) -> Result<String> {
+    let value = serde_json::to_value(value).context("Could not serialize JSON output")?;
+    let output = match format {
+        JsonFormat::Compact => serde_json::to_string(&value),
+        JsonFormat::Pretty => serde_json::to_string_pretty(&value),
+    }
+    .context("Could not serialize JSON output")?;
+
+    Ok(output)
+}
+
+/// Serialize a value to JSON and print it to STDOUT.
+pub fn print<T: Serialize>(value: &T, format: JsonFormat) -> Result<()> {
+    let output = to_string(value, format)?;
+    match JSON_OUTPUT.lock().unwrap().as_mut() {
+        Some(file) => writeln!(file, "{}", output).context("Could not write JSON output")?,
+        None => println!("{}", output),
+    }
+
+    Ok(())
+}
+
+/// Make everything printed to STDOUT go to STDERR instead, except for the JSON printed through
+/// [`print()`]. Commands that make changes print human readable output while they run, and with
+/// `--json` only the summary at the end should end up on STDOUT so it can be parsed by scripts.
+pub fn redirect_stdout() -> Result<()> {
+    io::stdout().flush()?;
+
+    let original_stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if original_stdout == -1 {
+        return Err(io::Error::last_os_error()).context("Could not duplicate STDOUT");
+    }
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } == -1 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(original_stdout) };
+        return Err(err).context("Could not redirect STDOUT to STDERR");
+    }
+
+    *JSON_OUTPUT.lock().unwrap() = Some(unsafe { File::from_raw_fd(original_stdout) });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::Serialize;
+    use std::collections::HashMap;
+
+    #[derive(Serialize)]
+    struct Output {
+        name: &'static str,
+        size: u64,
+        target: Option<&'static str>,
+    }
+
+    #[test]
+    fn keeps_null_and_large_integers() {
+        let output = Output {
+            name: "foo",
+            size: u64::MAX,
+            target: None,
+        };
+
+        assert_eq!(
+            to_string(&output, JsonFormat::Compact).unwrap(),
+            r#"{"name":"foo","size":18446744073709551615,"target":null}"#
+        );
+    }
+
+    #[test]
+    fn sorts_keys() {
+        let mut map = HashMap::new();
+        map.insert("b", 1);
+        map.insert("a", 2);
+
+        assert_eq!(
+            to_string(&map, JsonFormat::Compact).unwrap(),
+            r#"{"a":2,"b":1}"#
+        );
+        assert_eq!(
+            to_string(&map, JsonFormat::Pretty).unwrap(),
+            "{\n  \"a\": 2,\n  \"b\": 1\n}"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// yabridge: a Wine VST bridge
+// Copyright (C) 2020-2021 Robbert van der Helm
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A diagnostic log written with `yabridgectl sync --log-file <path>`. This is completely separate
+//! from the output printed to the terminal. Terminal output is colored and wrapped to the
+//! terminal's width with [`crate::utils::wrap()`], while every line in the log file is written
+//! as-is with a timestamp so the log can be attached to bug reports.
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    /// The log file opened with [`open()`], if any. Operations are applied in parallel, so this is
+    /// locked for every line to prevent lines from different threads from being interleaved.
+    static ref LOG_FILE: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+}
+
+/// Start writing diagnostics to `path`. The file is truncated if it already exists. Everything
+/// logged through [`logln!()`] after this will be written to this file.
+pub fn open(path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Could not create log file '{}'", path.display()))?;
+    *LOG_FILE.lock().unwrap() = Some(LineWriter::new(file));
+
+    write(format_args!(
+        "yabridgectl {} ({})",
+        env!("CARGO_PKG_VERSION"),
+        std::env::args().collect::<Vec<_>>().join(" ")
+    ));
+
+    Ok(())
+}
+
+/// Whether a log file has been opened with [`open()`]. Used to avoid formatting log messages when
+/// they would not be written anywhere.
+pub fn is_enabled() -> bool {
+    LOG_FILE.lock().unwrap().is_some()
+}
+
+/// Write a single timestamped line to the log file, if one has been opened. Use [`logln!()`]
+/// instead. Failing to write to the log file should never cause a sync to fail, so write errors
+/// are ignored.
+pub fn write(message: std::fmt::Arguments) {
+    if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+        let _ = writeln!(file, "[{}] {}", timestamp(), message);
+    }
+}
+
+/// The current local time formatted as `YYYY-MM-DD HH:MM:SS.mmm`.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs() as libc::time_t;
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&seconds, &mut tm) };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        now.subsec_millis()
+    )
+}
+
+/// The same as `println!()`, but the line is written to the log file opened with [`open()`]
+/// instead of to the terminal. Nothing happens when yabridgectl has not been run with
+/// `--log-file`. Messages should not contain any color codes or be wrapped with
+/// [`crate::utils::wrap()`].
+macro_rules! logln {
+    ($($arg:tt)*) => {
+        if $crate::log::is_enabled() {
+            $crate::log::write(format_args!($($arg)*));
+        }
+    };
+}
+pub(crate) use logln;
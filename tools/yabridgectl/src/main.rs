@@ -14,17 +14,26 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use anyhow::Result;
-use clap::{app_from_crate, App, AppSettings, Arg};
+use anyhow::{anyhow, Result};
+use clap::{app_from_crate, App, AppSettings, Arg, ArgMatches, ArgSettings};
 use colored::Colorize;
+use std::collections::BTreeSet;
 use std::env;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use crate::config::Config;
+use crate::files::{LibArchitecture, PluginFormat};
+use crate::json::JsonFormat;
 
 mod actions;
+mod archives;
+mod cache;
+mod completions;
 mod config;
 mod files;
+mod json;
+mod log;
 mod utils;
 
 fn main() -> Result<()> {
@@ -39,7 +48,18 @@ fn main() -> Result<()> {
         },
     );
 
-    let mut config = Config::read()?;
+    // The config has to be read before the command line arguments can be parsed, so the `--config`
+    // option is handled separately
+    if let Some(path) = config_path_arg() {
+        config::set_config_path(env::current_dir().unwrap_or_default().join(path));
+    }
+
+    // `yabridgectl config validate` should be able to report problems with a config file that can't
+    // be parsed, so this error is only returned after parsing the command line arguments
+    let (mut config, config_error) = match Config::read() {
+        Ok(config) => (config, None),
+        Err(err) => (Config::default(), Some(err)),
+    };
 
     // Used for validation in `yabridgectl rm <path>`
     let plugin_directories: Vec<&str> = config
@@ -47,6 +67,20 @@ fn main() -> Result<()> {
         .iter()
         .map(|path| path.to_str().expect("Path contains invalid unicode"))
         .collect();
+    // Used for validation in `yabridgectl set --no-always-copy <path>`
+    let always_copy_entries: Vec<&str> = config
+        .always_copy
+        .iter()
+        .map(|path| path.to_str().expect("Path contains invalid unicode"))
+        .collect();
+    // Used for validation in `yabridgectl --profile <name>` and `yabridgectl profile rm <name>`
+    let profile_names: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+    // Used for validation in `yabridgectl host rm <plugin>`
+    let plugin_host_entries: Vec<&str> = config
+        .plugin_hosts
+        .keys()
+        .map(|path| path.to_str().expect("Path contains invalid unicode"))
+        .collect();
     // Used for validation in `yabridgectl blacklist rm <path>`
     let blacklist_entries: Vec<&str> = config
         .blacklist
@@ -54,19 +88,182 @@ fn main() -> Result<()> {
         .map(|path| path.to_str().expect("Path contains invalid unicode"))
         .collect();
 
-    let matches = app_from_crate!()
+    // The app is also used to generate the completion scripts, so this can't be a temporary
+    let method_long_about = format!(
+        "The installation method to use. \
+         '{}' works in every situation but it requires you to modify your PATH \
+         environment variable so yabridge is able to find 'yabridge-host.exe'. \
+         'yabridgectl sync' whenever you update yabridge. You'll also have to \
+         rerun 'yabridgectl sync' whenever you update yabridge. \
+         '{}' only works for hosts that support individually sandboxed plugins \
+         such as Bitwig Studio, but it does not require setting environment \
+         variables or to manual updates.",
+        "copy".bright_white(),
+        "symlink".bright_white()
+    );
+    // Shared by 'yabridgectl sync' and 'yabridgectl watch'
+    let no_verify_long_about =
+        "Skip post-installation setup checks. This skips checking whether 'yabridge-host.exe' can \
+         be found through your login shell's PATH, whether yabridge works with the installed \
+         version of Wine, and whether the VST2 plugin directories are on the host's search path. \
+         The result of the last successful Wine check is left untouched. This is useful in CI and \
+         in containers where there is no real login shell or Wine installation. Unlike '--force', \
+         which still performs these checks, this does not affect which plugins are set up. Use \
+         'yabridgectl set --no-verify true' to always skip these checks.";
+
+    let app = app_from_crate!()
         .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .about("When to use colored output")
+                .long_about(
+                    "When to use colored output. With 'auto', output is only colored when writing \
+                     to a terminal and when the 'NO_COLOR' environment variable is not set.",
+                )
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .about("Use another config file")
+                .long_about(
+                    "Read the config from and write the config to this file instead of \
+                     '$XDG_CONFIG_HOME/yabridgectl/config.toml'. The file will be created if it \
+                     does not yet exist. This can be used to manage multiple isolated yabridge \
+                     setups.",
+                )
+                .value_name("FILE")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("clean")
+                .long("clean")
+                .about("Write the normalized config file back to disk")
+                .long_about(
+                    "Write the normalized config file back to disk. Paths in the config file are \
+                     always normalized and deduplicated when it is loaded, but the file itself is \
+                     only updated when a setting changes. With this option the cleaned up config \
+                     file is written back even when running a command that doesn't change any \
+                     settings.",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .about("Don't print warnings")
+                .long_about(
+                    "Don't print warnings and other informational messages to STDERR, such as the \
+                     warnings from the post-installation setup checks and the warnings about \
+                     skipped plugins printed during 'yabridgectl sync'. Errors are still printed, \
+                     and the exit code is not affected.",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::new("skip_checks")
+                .long("skip-checks")
+                .about("Skip the PATH and Wine setup checks")
+                .long_about(
+                    "Skip checking whether 'yabridge-host.exe' can be found through your login \
+                     shell's PATH and whether yabridge works with the installed version of Wine, \
+                     for every command that would otherwise perform these checks. This includes \
+                     'yabridgectl sync', 'yabridgectl watch', 'yabridgectl doctor', and \
+                     'yabridgectl healthcheck'. Those checks will be reported as skipped. This is \
+                     useful in CI and in containers where there is no real login shell or Wine \
+                     installation. Unlike 'yabridgectl set --no-verify true', this is not stored \
+                     in the config file.",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::new("json_pretty")
+                .long("json-pretty")
+                .about("Pretty print JSON output")
+                .long_about(
+                    "Pretty print JSON output. This implies '--json' for all commands that support \
+                     JSON output.",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::new("ignore_case")
+                .long("ignore-case")
+                .about("Match plugin names without regard for capitalization")
+                .long_about(
+                    "Match plugin names without regard for capitalization. This applies to the \
+                     entries from 'yabridgectl sync --plugins-from' and the plugins in profiles, \
+                     so 'serum' will also match 'Serum.dll'. Windows file names are case \
+                     insensitive, so this is often how you would expect these names to work.",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .about("Only set up the plugins in this profile")
+                .long_about(
+                    "Only set up the plugins in this profile when running 'yabridgectl sync' or \
+                     'yabridgectl watch'. Existing installations for all other plugins will be \
+                     removed. 'yabridgectl verify-config-against-disk' also only checks the \
+                     plugins in this profile. Profiles can be managed with 'yabridgectl profile'.",
+                )
+                .value_name("NAME")
+                .possible_values(&profile_names)
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("summary_json")
+                .long("summary-json")
+                .about("Also write the summary to a JSON file")
+                .long_about(
+                    "Also write the summary printed at the end of commands that make changes to a \
+                     JSON file, along with how long the command took and the error message if \
+                     the command failed. The normal output is not affected. The file is replaced \
+                     atomically, so other programs will never see a partially written summary.",
+                )
+                .value_name("FILE")
+                .takes_value(true)
+                .global(true),
+        )
         .subcommand(
             App::new("add")
                 .about("Add a plugin install location")
+                .long_about(
+                    "Add one or more plugin install locations. When adding multiple directories, \
+                     either all of them are added or none of them are.",
+                )
                 .display_order(1)
                 .arg(
                     Arg::new("path")
-                        .about("Path to a directory containing Windows VST plugins")
-                        .validator(validate_path)
+                        .about("Paths to directories containing Windows VST plugins")
+                        .validator(validate_expanded_path)
                         .takes_value(true)
+                        .multiple(true)
                         .required(true),
-                ),
+                )
+                .arg(
+                    Arg::new("interactive")
+                        .long("interactive")
+                        .short('i')
+                        .about("Choose which subdirectories to include")
+                        .long_about(
+                            "Search the new directories for plugins, and choose which of their \
+                             subdirectories containing plugins should be included. Only the \
+                             selected subdirectories will be searched for plugins, and plugins \
+                             directly inside of the directories are always included. Use \
+                             'yabridgectl set <path> --all-subdirectories' to include all \
+                             subdirectories again.",
+                        ),
+                )
+                .arg(json_arg()),
         )
         .subcommand(
             App::new("rm")
@@ -78,94 +275,1061 @@ fn main() -> Result<()> {
                         .possible_values(&plugin_directories)
                         .takes_value(true)
                         .required(true),
+                )
+                .arg(
+                    Arg::new("prune")
+                        .long("prune")
+                        .about("Also remove the files set up for this directory's plugins")
+                        .long_about(
+                            "Also remove the files yabridgectl set up for this directory's \
+                             plugins, including the merged VST3 bundles in '~/.vst3/yabridge' and \
+                             the CLAP plugins in '~/.clap/yabridge'. This also works if the \
+                             directory no longer exists.",
+                        ),
+                )
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("rename-directory")
+                .alias("rename")
+                .about("Change the path of a plugin install location")
+                .long_about(
+                    "Change the path of a plugin install location after the directory has been \
+                     moved, while keeping all of the directory's settings and yabridgectl's cached \
+                     hashes for its plugins. Rerun 'yabridgectl sync' afterwards to update the \
+                     installed plugins.",
+                )
+                .display_order(2)
+                .arg(
+                    Arg::new("old_path")
+                        .about("Path to a previously added directory")
+                        .possible_values(&plugin_directories)
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("new_path")
+                        .about("The directory's new location")
+                        .validator(validate_path)
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("import")
+                .about("Add the plugin install locations listed in a file")
+                .long_about(
+                    "Add the plugin install locations listed in a file, for instance one written \
+                     by 'yabridgectl export' on another machine. The file should either contain \
+                     one directory per line, or it should be a TOML file with a 'plugin_dirs' \
+                     array. Directories that don't exist are skipped with a warning, and \
+                     directories that have already been added are skipped.",
+                )
+                .display_order(3)
+                .arg(
+                    Arg::new("file")
+                        .about("The file to read the directories from, or '-' to read from STDIN")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("export")
+                .about("Write the plugin install locations to a file")
+                .long_about(
+                    "Write the plugin install locations to a file, one directory per line. These \
+                     can be added on another machine using 'yabridgectl import'.",
+                )
+                .display_order(3)
+                .arg(
+                    Arg::new("file")
+                        .about("The file to write the directories to, or '-' for STDOUT")
+                        .long_about(
+                            "The file to write the directories to. The directories are printed \
+                             to STDOUT when this is omitted or when this is '-'.",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("toml")
+                        .long("toml")
+                        .about("Write a TOML array instead of one directory per line"),
                 ),
         )
         .subcommand(
             App::new("list")
                 .about("List the plugin install locations")
-                .display_order(3),
+                .display_order(3)
+                .arg(
+                    Arg::new("orphans")
+                        .long("orphans")
+                        .about("List the files 'yabridgectl prune' would remove instead")
+                        .long_about(
+                            "List the '.so' files and merged VST3 bundles yabridgectl set up for \
+                             plugins that have since been uninstalled or moved instead, along with \
+                             the reason why. These are the files 'yabridgectl prune' would remove. \
+                             Nothing is changed.",
+                        ),
+                )
+                .arg(json_arg()),
         )
         .subcommand(
             App::new("status")
                 .about("Show the installation status for all plugins")
+                .display_order(4)
+                .arg(
+                    Arg::new("clean_empty")
+                        .long("clean-empty")
+                        .about("Remove plugin directories without any plugins from the config")
+                        .long_about(
+                            "Remove plugin directories without any plugins from the config. \
+                             This includes directories that no longer exist. You will be asked \
+                             for confirmation first.",
+                        )
+                        .conflicts_with("json"),
+                )
+                .arg(json_arg().long_about(
+                    "Print the installation status for every plugin as JSON. The output is an \
+                     object containing a 'schema_version' and a 'plugins' array. Every plugin has \
+                     a 'path', 'plugin_type', 'architecture', 'installation_method', 'up_to_date', \
+                     and 'target_hash' field. 'installation_method' and 'target_hash' are null \
+                     for plugins that have not yet been set up. The schema version is only \
+                     increased when existing fields change.",
+                )),
+        )
+        .subcommand(
+            App::new("info")
+                .about("Show the versions of yabridgectl and yabridge")
+                .long_about(
+                    "Show the versions of yabridgectl and yabridge\n\
+                     \n\
+                     Prints yabridgectl's version, the paths to the yabridge libraries and to the \
+                     'yabridge-host.exe' that are being used, and the version of yabridge that \
+                     'yabridge-host.exe' reports. This can be used to tell which yabridge \
+                     installation yabridgectl is managing. This starts Wine, so it can take \
+                     several seconds.",
+                )
                 .display_order(4),
         )
+        .subcommand(
+            App::new("inventory")
+                .about("List where every plugin will be set up")
+                .long_about(
+                    "List where every plugin will be set up\n\
+                     \n\
+                     Prints the path to every Windows plugin yabridgectl will set up, followed by \
+                     a tab and the path it will be set up at. For VST3 plugins this is the path \
+                     to the merged bundle. This output is meant to be used by other tools such as \
+                     package managers. Use 'yabridgectl status' to see whether these plugins have \
+                     been set up.",
+                )
+                .display_order(5)
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("healthcheck")
+                .about("Quickly check whether yabridge has been set up correctly")
+                .long_about(
+                    "Quickly check whether yabridge has been set up correctly\n\
+                     \n\
+                     This is meant to be run periodically by monitoring systems. It checks \
+                     whether yabridge's files can be found, whether any of the files set up \
+                     during the last sync contain broken symlinks, and whether the current \
+                     versions of Wine and yabridge have been verified to work together. Wine \
+                     itself is only started when using '--wine'. Exits with exit code 0 if all \
+                     checks passed, and with exit code 1 if any of the checks failed.",
+                )
+                .display_order(7)
+                .arg(json_arg())
+                .arg(
+                    Arg::new("status_file")
+                        .long("status-file")
+                        .about("Also write the results to a JSON file")
+                        .long_about(
+                            "Also write the results to a JSON file, along with the time of the \
+                             check. The file is replaced atomically.",
+                        )
+                        .value_name("FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("wine")
+                        .long("wine")
+                        .about("Also check whether 'yabridge-host.exe' can run")
+                        .long_about(
+                            "Also check whether 'yabridge-host.exe' can run. This starts Wine, \
+                             which can take several seconds.",
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("doctor")
+                .about("Run every check to troubleshoot problems with yabridge")
+                .long_about(
+                    "Run every check to troubleshoot problems with yabridge\n\
+                     \n\
+                     This checks whether yabridge's libraries and 'yabridge-host.exe.so' can be \
+                     read, whether '~/.local/share/yabridge' contains 'yabridge-host.exe', \
+                     whether 'yabridge-host.exe' can be found through your login shell's PATH \
+                     when using copies, whether Wine or yabridge have been updated since the \
+                     last verification, and whether 'yabridge-host.exe' can run. These checks \
+                     are otherwise only performed during 'yabridgectl sync'. This starts Wine, \
+                     so it can take several seconds. Exits with a non-zero exit code if any of \
+                     the critical checks failed.",
+                )
+                .display_order(7),
+        )
+        .subcommand(
+            App::new("verify-config-against-disk")
+                .about("Check whether the installed files match the config")
+                .long_about(
+                    "Check whether the installed files match the config\n\
+                     \n\
+                     For every plugin in the plugin directories, this checks whether it has been \
+                     set up, whether it uses the configured installation method, whether it \
+                     points to the right Windows plugin, and whether it's up to date with the \
+                     installed version of yabridge. Files set up by yabridgectl that don't \
+                     belong to any plugin are listed as stray files. Nothing is changed. Exits \
+                     with exit code 1 if anything doesn't match.",
+                )
+                .display_order(8)
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("verify-prefixes")
+                .about("Check whether yabridge can run in every Wine prefix")
+                .long_about(
+                    "Check whether yabridge can run in every Wine prefix used by the plugin \
+                     directories. The Wine prefix for a directory is detected the same way \
+                     yabridge does it, by searching for a 'drive_c' directory.",
+                )
+                .display_order(6)
+                .arg(json_arg()),
+        )
         .subcommand(
             App::new("sync")
                 .about("Set up or update yabridge for all plugins")
                 .display_order(100)
+                .arg(json_arg())
+                .arg(
+                    Arg::new("apply_plan")
+                        .long("apply-plan")
+                        .about("Apply a plan exported with '--export-plan'")
+                        .long_about(
+                            "Apply a plan previously exported with '--export-plan'. This will \
+                             refuse to make any changes if any of the files affected by the plan \
+                             have changed since the plan was created.",
+                        )
+                        .value_name("FILE")
+                        .validator(validate_path)
+                        .takes_value(true)
+                        .conflicts_with_all(&[
+                            "export_plan",
+                            "exclude_arch",
+                            "fast",
+                            "force",
+                            "max_size",
+                            "plugins_from",
+                            "prefer_newer",
+                            "prune",
+                            "target_prefix",
+                        ]),
+                )
+                .arg(
+                    Arg::new("check_permissions")
+                        .long("check-permissions")
+                        .about("Only check for permission problems without changing anything")
+                        .long_about(
+                            "Only check whether all changes can be made with the current user's \
+                             permissions, without changing anything. This checks whether the \
+                             directories files will be created in are writable and whether \
+                             existing files can be replaced, and it lists all problems at once. \
+                             These checks are also performed automatically before every sync, in \
+                             which case nothing will be changed if there are any problems unless \
+                             '--continue-on-error' is used.",
+                        )
+                        .conflicts_with("export_plan"),
+                )
+                .arg(
+                    Arg::new("confirm")
+                        .long("confirm")
+                        .about("Ask for confirmation before creating new copies")
+                        .long_about(
+                            "Ask for confirmation before creating new copies of yabridge's \
+                             libraries. Every plugin set up using the copy-based installation \
+                             method gets its own copy, so the additional disk space these copies \
+                             will use is always printed before syncing. Copies that replace an \
+                             existing copy and hard links to deduplicated copies are not counted.",
+                        )
+                        .conflicts_with_all(&["apply_plan", "check_permissions", "export_plan", "verify"]),
+                )
+                .arg(
+                    Arg::new("continue_on_error")
+                        .long("continue-on-error")
+                        .about("Keep going when a file could not be set up")
+                        .long_about(
+                            "Keep going when a file could not be set up or removed instead of \
+                             stopping at the first error. All errors will be listed at the end, and \
+                             yabridgectl will exit with a non-zero exit code if anything went \
+                             wrong.",
+                        ),
+                )
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .about("List the changes a sync would make without changing anything")
+                        .long_about(
+                            "Search for plugins and list the plugins that would be set up, \
+                             updated, and removed, without changing anything. This performs the \
+                             same checks as a regular sync, so the listed changes are exactly the \
+                             changes a regular sync would make. The post-installation checks are \
+                             skipped.",
+                        )
+                        .conflicts_with_all(&[
+                            "apply_plan",
+                            "check_permissions",
+                            "confirm",
+                            "export_plan",
+                            "extract",
+                            "verify",
+                        ]),
+                )
+                .arg(
+                    Arg::new("export_plan")
+                        .long("export-plan")
+                        .alias("plan-file")
+                        .about("Write the planned changes to a file instead of applying them")
+                        .long_about(
+                            "Write all planned changes to a file instead of applying them. The \
+                             plan can then be reviewed and applied later with '--apply-plan'. \
+                             '--plan-file' can be used as an alias for this option.",
+                        )
+                        .value_name("FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("exclude_arch")
+                        .long("exclude-arch")
+                        .about("Skip and uninstall plugins with this architecture")
+                        .long_about(
+                            "Skip plugins with this architecture, and remove any existing \
+                             installations for them. This can be specified multiple times. Use \
+                             'yabridgectl set --exclude-arch' to do this permanently.",
+                        )
+                        .possible_values(&["x86", "x86_64"])
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("explain")
+                        .long("explain")
+                        .about("Explain why a plugin would or would not be set up")
+                        .long_about(
+                            "Explain why a sync with the other options passed to this command \
+                             would or would not set up the plugin at this path, without changing \
+                             anything. This reports files that are not plugins, plugins that are \
+                             skipped because of their architecture, format, the blacklist, or any \
+                             of the other filters, and plugins that would be set up at the same \
+                             location as another plugin.",
+                        )
+                        .value_name("PATH")
+                        .validator(validate_path)
+                        .takes_value(true)
+                        .conflicts_with_all(&[
+                            "apply_plan",
+                            "check_permissions",
+                            "export_plan",
+                            "verify",
+                        ]),
+                )
+                .arg(
+                    Arg::new("extract")
+                        .long("extract")
+                        .about("Extract and set up plugins from .zip archives")
+                        .long_about(
+                            "Extract '.zip' archives containing plugins found in the plugin \
+                             directories, and set up the extracted plugins. Archives are \
+                             extracted to '$XDG_DATA_HOME/yabridgectl/archives', and a symlink \
+                             with the archive's name pointing to the extracted files is created \
+                             next to the archive. Archives are only extracted again when they \
+                             change. When an archive \
+                             has been removed, '--prune' will also remove the extracted files. \
+                             This requires 'unzip' to be installed.",
+                        )
+                        .conflicts_with_all(&["apply_plan", "export_plan", "target_prefix"]),
+                )
+                .arg(
+                    Arg::new("fast")
+                        .long("fast")
+                        .about("Skip directories that haven't changed since the last sync")
+                        .long_about(
+                            "Don't read directories that have not been modified since the last \
+                             sync, and reuse their contents from the previous scan instead. This \
+                             can speed up syncing very large plugin directories, but changes can \
+                             be missed when a directory's modification time is set to a time in \
+                             the past, for instance when copying files using 'rsync --times' or \
+                             when restoring a backup. Run 'yabridgectl sync' without this option \
+                             to perform a full scan again.",
+                        ),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .about("Always update files, even not necessary")
+                        .long_about(
+                            "Always update files, even not necessary. This also ignores the cached \
+                             hashes of existing copies, so every copy is read again.",
+                        ),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .long("jobs")
+                        .short('j')
+                        .about("The number of plugins to set up at the same time")
+                        .long_about(
+                            "The number of threads used to hash and set up plugins. This defaults \
+                             to the number of logical CPUs.",
+                        )
+                        .value_name("N")
+                        .validator(|value| match value.parse::<usize>() {
+                            Ok(jobs) if jobs > 0 => Ok(()),
+                            _ => Err(String::from("The number of jobs should be a positive number")),
+                        })
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("log_file")
+                        .long("log-file")
+                        .about("Write a detailed log of the sync to a file")
+                        .long_about(
+                            "Write a timestamped log of every step of the sync to a file, \
+                             including every plugin found while searching, every file that gets \
+                             hashed, copied, symlinked, or removed, and the results of all \
+                             verification steps. This is independent of what's printed to the \
+                             terminal, and it can be attached to bug reports.",
+                        )
+                        .value_name("FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("max_size")
+                        .long("max-size")
+                        .about("Skip copying plugins larger than this size")
+                        .long_about(
+                            "Skip plugins larger than this size when they would be set up using \
+                             copies. Sizes are in bytes, or they can use the K, M, G, and T \
+                             suffixes. This overrides the size set with 'yabridgectl set \
+                             --max-plugin-size' for this sync.",
+                        )
+                        .value_name("SIZE")
+                        .validator(utils::parse_size)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("prefer_newer")
+                        .long("prefer-newer")
+                        .about("Only set up the newest version of duplicate plugins")
+                        .long_about(
+                            "When multiple plugins have the same file name and architecture, only \
+                             set up the plugin that was modified most recently and skip the \
+                             others. This is useful when you have multiple versions of the same \
+                             plugin installed in different locations. Copies with the same \
+                             contents as the newest version are not different versions, so those \
+                             are still set up. Plugins that are skipped for other reasons, like \
+                             an excluded architecture, are not taken into account. Existing VST2 \
+                             plugin installations for the older versions are treated as leftover \
+                             files.",
+                        ),
+                )
+                .arg(
+                    Arg::new("no-verify")
+                        .short('n')
+                        .long("no-verify")
+                        .about("Skip post-installation setup checks")
+                        .long_about(no_verify_long_about),
+                )
+                .arg(
+                    Arg::new("only")
+                        .long("only")
+                        .about("Only set up the plugins matching a wildcard pattern")
+                        .long_about(
+                            "Only set up the plugins whose path matches this wildcard pattern, and \
+                             leave all other plugins and files untouched. Patterns can use '*' and \
+                             '?' wildcards, and they are matched against both the plugin's full \
+                             path and its path relative to the plugin directory, just like the \
+                             patterns in the blacklist. This can be specified multiple times to \
+                             set up the plugins matching any of the patterns.",
+                        )
+                        .value_name("PATTERN")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .conflicts_with("prune"),
+                )
+                .arg(
+                    Arg::new("plugins_from")
+                        .long("plugins-from")
+                        .about("Only set up the plugins listed in a file")
+                        .long_about(
+                            "Only set up the plugins listed in a file, and leave all other plugins \
+                             and files untouched. The file should contain one entry per line. An \
+                             entry can be the path to a plugin, a file name, or a plugin name \
+                             without the file extension. Empty lines and lines starting with '#' \
+                             are ignored.",
+                        )
+                        .value_name("FILE")
+                        .validator(validate_path)
+                        .takes_value(true)
+                        .conflicts_with("prune"),
+                )
+                .arg(
+                    Arg::new("vendor")
+                        .long("vendor")
+                        .about("Only set up the plugins from these vendors")
+                        .long_about(
+                            "Only set up the plugins from these vendors, and leave all other \
+                             plugins and files untouched. Yabridgectl doesn't read the vendor \
+                             from the plugins themselves. Instead, a plugin matches when one of \
+                             the directories between the plugin directory and the plugin starts \
+                             with the vendor's name, ignoring capitalization. For instance, \
+                             'fabfilter' matches 'VstPlugins/FabFilter/FabFilter Pro-Q 3.dll'. \
+                             This can be specified multiple times.",
+                        )
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .conflicts_with("prune"),
+                )
+                .arg(
+                    Arg::new("remove_other_vendors")
+                        .long("remove-other-vendors")
+                        .about("Remove the plugins from all other vendors when using '--vendor'")
+                        .long_about(
+                            "Remove the existing installations for the plugins from all other \
+                             vendors when using '--vendor', just like a profile would. Without \
+                             this option those plugins are left untouched.",
+                        )
+                        .requires("vendor"),
+                )
+                .arg(
+                    Arg::new("prune")
+                        .short('p')
+                        .long("prune")
+                        .about("Remove unrelated or leftover .so files"),
+                )
+                .arg(
+                    Arg::new("strict_wine")
+                        .long("strict-wine")
+                        .about("Fail when Wine can't be verified to work")
+                        .long_about(
+                            "Exit with an error unless yabridge has been verified to work with the \
+                             installed version of Wine, instead of only printing a warning. This \
+                             also fails when 'yabridge-host.exe' is not executable or when some of \
+                             the libraries it needs are missing. The Wine setup will be checked \
+                             even when the post-installation setup checks have been disabled with \
+                             'yabridgectl set --no-verify'.",
+                        )
+                        .conflicts_with_all(&["export_plan", "no-verify", "target_prefix"]),
+                )
+                .arg(
+                    Arg::new("target_prefix")
+                        .long("target-prefix")
+                        .about("Install all files relative to another root directory")
+                        .long_about(
+                            "Install all files relative to another root directory, similar to \
+                             'DESTDIR'. This can be used to set up yabridge in a system image or a \
+                             chroot. Symlinks will still point to the files' locations on the final \
+                             system. Existing files are never moved or removed in this mode, and \
+                             the post-installation setup checks are skipped since those can only \
+                             check the live system.",
+                        )
+                        .value_name("DIR")
+                        .validator(validate_path)
+                        .takes_value(true)
+                        .conflicts_with("prune"),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .about("Print information about plugins being set up or skipped"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .short('y')
+                        .long("yes")
+                        .about("Don't ask for confirmation before removing many plugins")
+                        .long_about(
+                            "Don't ask for confirmation when a sync would remove more plugins than \
+                             the limit set with 'yabridgectl set --removal-limit'. Without this \
+                             option such a sync fails when yabridgectl is not run from a \
+                             terminal.",
+                        ),
+                )
+                .arg(
+                    Arg::new("verify")
+                        .long("verify")
+                        .about("Only check whether all plugins have been set up correctly")
+                        .long_about(
+                            "Only check whether all plugins have been set up correctly, without \
+                             changing anything. This lists the status of every plugin: 'OK', \
+                             'missing' if it has not been set up yet, 'stale' if its copy of \
+                             yabridge's library is out of date, or 'wrong target' if its symlink \
+                             points to the wrong file. Leftover files that would be removed are \
+                             listed as well. This exits with a non-zero exit code if anything is \
+                             out of date, so it can be used in scripts. Unlike '--no-verify', \
+                             this is about the installed plugins and not about the Wine setup.",
+                        )
+                        .conflicts_with_all(&[
+                            "apply_plan",
+                            "check_permissions",
+                            "export_plan",
+                            "extract",
+                            "force",
+                            "target_prefix",
+                        ]),
+                ),
+        )
+        .subcommand(
+            App::new("watch")
+                .about("Keep running and sync whenever plugins or the config change")
+                .long_about(
+                    "Keep running and sync whenever plugins or the config change\n\
+                     \n\
+                     This watches the plugin directories for new, removed or updated plugins, and \
+                     it reruns 'yabridgectl sync' once they have stopped changing for the duration \
+                     of the interval. Changes to yabridgectl's config file are picked up \
+                     automatically. If the config file contains errors, then the last valid config \
+                     will be used until those errors have been fixed.",
+                )
+                .display_order(101)
+                .arg(json_arg())
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .about("The number of seconds to wait for plugins to stop changing")
+                        .value_name("SECONDS")
+                        .default_value("5")
+                        .validator(|value| match value.parse::<u64>() {
+                            Ok(seconds) if seconds > 0 => Ok(()),
+                            _ => Err(String::from("The interval should be a positive number")),
+                        })
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("continue_on_error")
+                        .long("continue-on-error")
+                        .about("Keep going when a file could not be set up"),
+                )
+                .arg(
+                    Arg::new("no-verify")
+                        .short('n')
+                        .long("no-verify")
+                        .about("Skip post-installation setup checks")
+                        .long_about(no_verify_long_about),
+                )
+                .arg(
+                    Arg::new("prune")
+                        .short('p')
+                        .long("prune")
+                        .about("Remove unrelated or leftover .so files")
+                        .long_about(
+                            "Remove unrelated or leftover .so files. This also removes the .so \
+                             files from plugin directories that have been removed from the config.",
+                        ),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .about("Print information about plugins being set up or skipped"),
+                ),
+        )
+        .subcommand(
+            App::new("reload")
+                .about("Make 'yabridgectl watch' reread the config and sync")
+                .long_about(
+                    "Make the running 'yabridgectl watch' instance reread the config file and sync \
+                     right away, without waiting for the next check for changes. This returns an \
+                     error if watch mode is not running.",
+                )
+                .display_order(101),
+        )
+        .subcommand(
+            App::new("generate-service")
+                .about("Print a systemd user service for 'yabridgectl watch'")
+                .long_about(
+                    "Print a systemd user service that runs 'yabridgectl watch' in the \
+                     background. The service uses the same config file and the same Wine \
+                     installation as the current yabridgectl invocation. To install the service, \
+                     save the output to '~/.config/systemd/user/yabridgectl-watch.service' and \
+                     run 'systemctl --user enable --now yabridgectl-watch'.",
+                )
+                .display_order(102),
+        )
+        .subcommand(
+            App::new("hash-manifest")
+                .about("Precompute the hashes for a shared plugin directory")
+                .long_about(
+                    "Hash all plugins in a directory and store the hashes in a manifest file in \
+                     that directory. This is useful for system-wide plugin directories shared by \
+                     multiple users. 'yabridgectl sync' will use these hashes instead of hashing \
+                     the plugins again, and it falls back to hashing plugins that have changed \
+                     since the manifest was generated. Rerun this command after installing or \
+                     updating plugins in the directory.",
+                )
+                .display_order(103)
+                .arg(
+                    Arg::new("path")
+                        .about("Path to a directory containing Windows VST plugins")
+                        .validator(validate_path)
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("prune")
+                .about("Remove yabridge files left behind by uninstalled plugins")
+                .long_about(
+                    "Remove the '.so' files and merged VST3 bundles yabridgectl set up for \
+                     plugins that have since been uninstalled or moved. Unlike 'yabridgectl sync \
+                     --prune', this does not search for plugins. Only copies of and symlinks to \
+                     yabridge's own libraries and broken symlinks are removed, so native Linux \
+                     plugins with the same name as a removed Windows plugin are left alone.",
+                )
+                .display_order(104)
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .about("Only list the files that would be removed"),
+                )
+                .arg(json_arg()),
+        )
+        .subcommand(
+            App::new("set")
+                .about("Change the installation method or yabridge path (advanced)")
+                .display_order(200)
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .arg(json_arg())
+                .arg(
+                    Arg::new("directory")
+                        .about("Change the settings for a single plugin directory")
+                        .long_about(
+                            "Change the settings for a single plugin directory instead of the \
+                             global settings. Only '--target', '--target-auto', \
+                             '--extra-target', '--method', '--method-auto', '--wine', and \
+                             '--wine-auto' can be used together with a directory.",
+                        )
+                        .possible_values(&plugin_directories)
+                        .takes_value(true)
+                        .conflicts_with_all(&[
+                            "path",
+                            "path_auto",
+                            "exclude_arch",
+                            "formats",
+                            "ignore_wine_error",
+                            "ignore_suffix",
+                            "always_copy",
+                            "no_always_copy",
+                            "max_plugin_size",
+                            "removal_limit",
+                            "deduplicate_copies",
+                            "link_host_binaries",
+                            "relative_symlinks",
+                            "no_verify",
+                            "verify_timeout",
+                        ]),
+                )
+                .arg(
+                    Arg::new("method")
+                        .long("method")
+                        .about("The installation method to use")
+                        .long_about(&method_long_about)
+                        .setting(clap::ArgSettings::NextLineHelp)
+                        .possible_values(&["copy", "symlink"])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .about("Path to the directory containing 'libyabridge-{vst2,vst3}.so'")
+                        .long_about(
+                            "Path to the directory containing 'libyabridge-{vst2,vst3}.so'. If this \
+                             is not set, then yabridgectl will look in both '/usr/lib' and \
+                             '~/.local/share/yabridge' by default.",
+                        )
+                        .validator(validate_path)
+                        .takes_value(true).conflicts_with("path_auto"),
+                )
+                .arg(
+                    Arg::new("path_auto")
+                        .long("path-auto")
+                        .about("Automatically locate yabridge's files")
+                        .long_about(
+                            "Automatically locate yabridge's files. This can be used after manually \
+                             setting a path with the '--path' option to revert back to the default \
+                             auto detection behaviour.",
+                        ),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .visible_alias("output-directory")
+                        .about("Set up the directory's plugins in another directory")
+                        .long_about(
+                            "Set up the plugins from a plugin directory in another directory. VST2 \
+                             plugins are normally set up right next to their '.dll' files, and VST3 \
+                             plugins are normally set up in '~/.vst3/yabridge'. With this option \
+                             both will be set up in the target directory instead, keeping the \
+                             directory structure for VST2 plugins intact. This requires a plugin \
+                             directory to be specified. Two plugins being set up at the same \
+                             location results in an error during 'yabridgectl sync'.",
+                        )
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .requires("directory")
+                        .conflicts_with("target_auto"),
+                )
+                .arg(
+                    Arg::new("target_auto")
+                        .long("target-auto")
+                        .about("Set up the directory's plugins in their default locations again")
+                        .requires("directory"),
+                )
+                .arg(
+                    Arg::new("extra_target")
+                        .long("extra-target")
+                        .about("Also make the directory's plugins available in another directory")
+                        .long_about(
+                            "Also make the plugins from a plugin directory available in another \
+                             directory, for hosts that search other directories. The plugins are \
+                             still set up in their normal location or in the directory set with \
+                             '--target', and 'yabridgectl sync' symlinks everything it set up to \
+                             the extra target directories. This can be specified multiple times, \
+                             and it replaces the previously set extra targets. Pass an empty \
+                             string to remove all extra targets. This requires a plugin directory \
+                             to be specified.",
+                        )
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .setting(ArgSettings::AllowEmptyValues)
+                        .multiple_occurrences(true)
+                        .requires("directory"),
+                )
+                .arg(
+                    Arg::new("method_auto")
+                        .long("method-auto")
+                        .about("Use the global installation method for the directory's plugins again")
+                        .requires("directory")
+                        .conflicts_with("method"),
+                )
+                .arg(
+                    Arg::new("wine")
+                        .long("wine")
+                        .about("The Wine binary the directory's plugins are run with")
+                        .long_about(
+                            "The Wine binary the directory's plugins are run with, for instance \
+                             when your DAW is started with '$WINELOADER' pointing to a build of \
+                             wine-staging or Proton for these plugins. 'yabridgectl sync' will then \
+                             also check whether yabridge works with this version of Wine. This \
+                             can be either a path or the name of a binary on the search path. This \
+                             requires a plugin directory to be specified.",
+                        )
+                        .value_name("WINE")
+                        .validator(validate_wine_binary)
+                        .takes_value(true)
+                        .requires("directory")
+                        .conflicts_with("wine_auto"),
+                )
+                .arg(
+                    Arg::new("wine_auto")
+                        .long("wine-auto")
+                        .about("Use the default Wine binary for the directory's plugins again")
+                        .requires("directory"),
+                )
+                .arg(
+                    Arg::new("all_subdirectories")
+                        .long("all-subdirectories")
+                        .about("Search all of the directory's subdirectories for plugins again")
+                        .long_about(
+                            "Search all of the directory's subdirectories for plugins again, after \
+                             only some of them were selected with 'yabridgectl add \
+                             --interactive'. This requires a plugin directory to be specified.",
+                        )
+                        .requires("directory"),
+                )
+                .arg(
+                    Arg::new("exclude_arch")
+                        .long("exclude-arch")
+                        .about("Never set up plugins with these architectures")
+                        .long_about(
+                            "Never set up plugins with these architectures. Existing installations \
+                             for those plugins will be removed during the next sync. This can be \
+                             useful if your Wine installation doesn't support 32-bit applications. \
+                             Use 'none' to set up plugins of every architecture again.",
+                        )
+                        .possible_values(&["x86", "x86_64", "none"])
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("formats")
+                        .long("formats")
+                        .about("Only set up plugins in these formats")
+                        .long_about(
+                            "Only set up plugins in these formats, as a comma separated list. \
+                             Plugins in any other format will be skipped, and their existing \
+                             installations will be removed during the next sync. By default all \
+                             formats are set up.",
+                        )
+                        .value_name("FORMATS")
+                        .possible_values(&["vst2", "vst3", "clap"])
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::new("ignore_wine_error")
+                        .long("ignore-wine-error")
+                        .about("Ignore benign Wine errors matching these patterns")
+                        .long_about(
+                            "Don't treat messages Wine prints that match these patterns as errors \
+                             when checking whether yabridge can run. These are regular \
+                             expressions, and a line is ignored if any part of it matches the \
+                             pattern. Use '^' and '$' to match the start and the end of the line. \
+                             This can be specified multiple times, and it replaces the previously \
+                             set patterns. Pass an empty string to remove all patterns. Be careful \
+                             with overly broad patterns, since those can hide actual problems with \
+                             your Wine installation.",
+                        )
+                        .value_name("PATTERN")
+                        .validator(validate_wine_error_pattern)
+                        .takes_value(true)
+                        .setting(ArgSettings::AllowEmptyValues)
+                        .multiple_occurrences(true),
+                )
                 .arg(
-                    Arg::new("force")
-                        .short('f')
-                        .long("force")
-                        .about("Always update files, even not necessary"),
+                    Arg::new("ignore_suffix")
+                        .long("ignore-suffix")
+                        .about("Never treat files ending with these suffixes as plugins")
+                        .long_about(
+                            "Never treat files whose name ends with one of these suffixes as \
+                             plugins, either with or without the file's extension. This is meant \
+                             for backups and other leftover files like 'Plugin.bak.dll'. Hidden \
+                             files are always skipped. This can be specified multiple times, and \
+                             it replaces the previously set suffixes. Pass an empty string to \
+                             remove all suffixes. Defaults to '.bak', '.old', '.orig', and \
+                             '.backup'.",
+                        )
+                        .value_name("SUFFIX")
+                        .takes_value(true)
+                        .setting(ArgSettings::AllowEmptyValues)
+                        .multiple_occurrences(true),
                 )
                 .arg(
-                    Arg::new("no-verify")
-                        .short('n')
-                        .long("no-verify")
-                        .about("Skip post-installation setup checks"),
+                    Arg::new("always_copy")
+                        .long("always-copy")
+                        .about("Always set up this plugin using a copy")
+                        .long_about(
+                            "Always set up this plugin using a copy, even when using the symlink \
+                             installation method. Some plugins don't work correctly when they are \
+                             set up using symlinks. This should be the path to a VST2 plugin \
+                             '.dll' file or a VST3 module. Rerun 'yabridgectl sync' after changing \
+                             this to replace the existing symlinks with copies. This can be \
+                             specified multiple times.",
+                        )
+                        .value_name("PLUGIN")
+                        .validator(validate_path)
+                        .takes_value(true)
+                        .multiple_occurrences(true),
                 )
                 .arg(
-                    Arg::new("prune")
-                        .short('p')
-                        .long("prune")
-                        .about("Remove unrelated or leftover .so files"),
+                    Arg::new("no_always_copy")
+                        .long("no-always-copy")
+                        .about("Set up this plugin using the normal installation method again")
+                        .value_name("PLUGIN")
+                        .possible_values(&always_copy_entries)
+                        .takes_value(true)
+                        .multiple_occurrences(true),
                 )
                 .arg(
-                    Arg::new("verbose")
-                        .short('v')
-                        .long("verbose")
-                        .about("Print information about plugins being set up or skipped"),
-                ),
-        )
-        .subcommand(
-            App::new("set")
-                .about("Change the installation method or yabridge path (advanced)")
-                .display_order(200)
-                .setting(AppSettings::ArgRequiredElseHelp)
+                    Arg::new("max_plugin_size")
+                        .long("max-plugin-size")
+                        .about("Skip copying plugins larger than this size")
+                        .long_about(
+                            "Skip plugins larger than this size when they would be set up using \
+                             copies. Existing copies of these plugins are left untouched. Sizes \
+                             are in bytes, or they can use the K, M, G, and T suffixes. Use \
+                             'none' to remove the limit again.",
+                        )
+                        .value_name("SIZE")
+                        .validator(|size| match size {
+                            "none" => Ok(0),
+                            size => utils::parse_size(size),
+                        })
+                        .takes_value(true),
+                )
                 .arg(
-                    Arg::new("method")
-                        .long("method")
-                        .about("The installation method to use")
-                        .long_about(&format!(
-                            "The installation method to use. \
-                             '{}' works in every situation but it requires you to modify your PATH \
-                             environment variable so yabridge is able to find 'yabridge-host.exe'. \
-                             'yabridgectl sync' whenever you update yabridge. You'll also have to \
-                             rerun 'yabridgectl sync' whenever you update yabridge. \
-                             '{}' only works for hosts that support individually sandboxed plugins \
-                             such as Bitwig Studio, but it does not require setting environment \
-                             variables or to manual updates.",
-                            "copy".bright_white(),
-                            "symlink".bright_white()
-                        ))
-                        .setting(clap::ArgSettings::NextLineHelp)
-                        .possible_values(&["copy", "symlink"])
+                    Arg::new("removal_limit")
+                        .long("removal-limit")
+                        .about("Ask for confirmation before removing this many plugins")
+                        .long_about(
+                            "Ask for confirmation before a sync removes more than this many \
+                             plugins at once, or more than a quarter of the plugins that have been \
+                             set up before. This usually means that a plugin directory has been \
+                             mistyped or that a drive has not been mounted. Use 'yabridgectl sync \
+                             --yes' to skip the confirmation for a single sync, or set this to 0 \
+                             to disable this check. Defaults to 50 plugins.",
+                        )
+                        .value_name("N")
+                        .validator(|limit| limit.parse::<usize>())
                         .takes_value(true),
                 )
                 .arg(
-                    Arg::new("path")
-                        .long("path")
-                        .about("Path to the directory containing 'libyabridge-{vst2,vst3}.so'")
+                    Arg::new("deduplicate_copies")
+                        .long("deduplicate-copies")
+                        .about("Hard link identical copies instead of storing them separately")
                         .long_about(
-                            "Path to the directory containing 'libyabridge-{vst2,vst3}.so'. If this \
-                             is not set, then yabridgectl will look in both '/usr/lib' and \
-                             '~/.local/share/yabridge' by default.",
+                            "Hard link identical copies of yabridge's libraries to each other \
+                             instead of storing every copy separately. This only affects plugins \
+                             set up using copies, and it falls back to regular copies when the \
+                             files are on different file systems.",
                         )
-                        .validator(validate_path)
-                        .takes_value(true).conflicts_with("path_auto"),
+                        .possible_values(&["true", "false"])
+                        .takes_value(true),
                 )
                 .arg(
-                    Arg::new("path_auto")
-                        .long("path-auto")
-                        .about("Automatically locate yabridge's files")
+                    Arg::new("link_host_binaries")
+                        .long("link-host-binaries")
+                        .about("Symlink yabridge's host binaries to '~/.local/share/yabridge'")
                         .long_about(
-                            "Automatically locate yabridge's files. This can be used after manually \
-                             setting a path with the '--path' option to revert back to the default \
-                             auto detection behaviour.",
-                        ),
-                ).arg(
+                            "Symlink 'yabridge-host.exe' and yabridge's other host binaries to \
+                             '~/.local/share/yabridge' during every sync. Yabridge always searches \
+                             that directory, so plugins set up using copies will be able to find \
+                             the host binaries without adding them to your login shell's search \
+                             path.",
+                        )
+                        .possible_values(&["true", "false"])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("relative_symlinks")
+                        .long("relative-symlinks")
+                        .about("Create relative symlinks instead of absolute ones")
+                        .long_about(
+                            "Create symlinks containing a path relative to the symlink's location \
+                             instead of an absolute path. This keeps plugins set up using symlinks \
+                             working when yabridge and your plugins are stored on the same drive, \
+                             and that drive gets mounted somewhere else. Rerun 'yabridgectl sync' \
+                             after changing this setting to recreate the existing symlinks.",
+                        )
+                        .possible_values(&["true", "false"])
+                        .takes_value(true),
+                )
+                .arg(
                     Arg::new("no_verify")
                         .long("no-verify")
                         .about("Always skip post-installation setup checks")
@@ -175,6 +1339,165 @@ fn main() -> Result<()> {
                         )
                         .possible_values(&["true", "false"])
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::new("verify_timeout")
+                        .long("verify-timeout")
+                        .about("How long the post-installation setup checks may take")
+                        .long_about(
+                            "The number of seconds the post-installation setup checks wait for \
+                             your login shell and for Wine before giving up. The PATH setup check \
+                             is skipped with a warning when the login shell takes longer than \
+                             this, and the Wine setup check fails when 'wine --version' does. \
+                             Defaults to 10 seconds.",
+                        )
+                        .value_name("SECONDS")
+                        .validator(|seconds| match seconds.parse::<u64>() {
+                            Ok(seconds) if seconds > 0 => Ok(seconds),
+                            _ => Err(format!("'{}' is not a positive number of seconds", seconds)),
+                        })
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("config")
+                .about("Check the config file")
+                .display_order(202)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("validate")
+                        .about("Check the config file for problems without changing anything")
+                        .long_about(
+                            "Check whether the config file can be parsed, whether it contains any \
+                             unknown options, whether all plugin directories exist, and whether \
+                             any of the settings conflict with each other. This exits with a \
+                             non-zero exit code if any problems were found.",
+                        )
+                        .arg(json_arg()),
+                ),
+        )
+        .subcommand(
+            App::new("profile")
+                .about("Manage profiles containing a subset of the plugins")
+                .display_order(203)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .long_about(
+                    "Manage profiles containing a subset of the plugins\n\
+                     \n\
+                     A profile contains a subset of the plugin directories and the plugins in \
+                     them. Running 'yabridgectl --profile <name> sync' will only set up the \
+                     plugins in that profile, and it will remove the existing installations for \
+                     all other plugins.",
+                )
+                .subcommand(
+                    App::new("add")
+                        .about("Add plugin directories or plugins to a profile")
+                        .long_about(
+                            "Add plugin directories or plugins to a profile, creating the profile \
+                             if it does not yet exist. A profile without any directories includes \
+                             all plugin directories, and a profile without any plugins includes \
+                             all plugins in its directories. Plugins can be specified the same \
+                             way as with 'yabridgectl sync --plugins-from'.",
+                        )
+                        .display_order(1)
+                        .arg(
+                            Arg::new("name")
+                                .about("The name of the profile")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("directory")
+                                .long("directory")
+                                .about("A plugin directory to include in the profile")
+                                .value_name("PATH")
+                                .possible_values(&plugin_directories)
+                                .takes_value(true)
+                                .multiple_occurrences(true),
+                        )
+                        .arg(
+                            Arg::new("plugin")
+                                .long("plugin")
+                                .about("A plugin to include in the profile")
+                                .value_name("PLUGIN")
+                                .takes_value(true)
+                                .multiple_occurrences(true),
+                        )
+                        .arg(json_arg()),
+                )
+                .subcommand(
+                    App::new("rm")
+                        .about("Remove a profile")
+                        .display_order(2)
+                        .arg(
+                            Arg::new("name")
+                                .about("The name of a previously added profile")
+                                .possible_values(&profile_names)
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(json_arg()),
+                )
+                .subcommand(
+                    App::new("list")
+                        .about("List the profiles")
+                        .display_order(3),
+                ),
+        )
+        .subcommand(
+            App::new("host")
+                .about("Use another build of yabridge for some plugins (advanced)")
+                .display_order(204)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .long_about(
+                    "Use another build of yabridge for some plugins (advanced)\n\
+                     \n\
+                     This lets you use an alternative build of yabridge, such as a patched \
+                     version, for individual plugins. Yabridge looks for 'yabridge-host.exe' next \
+                     to the library a plugin's '.so' file points to, so these plugins will always \
+                     be set up using symlinks to that build's libraries. Rerun 'yabridgectl sync' \
+                     after changing these settings.",
+                )
+                .subcommand(
+                    App::new("set")
+                        .about("Use another build of yabridge for a plugin")
+                        .display_order(1)
+                        .arg(
+                            Arg::new("plugin")
+                                .about("Path to a VST2 plugin '.dll' file or a VST3 module")
+                                .validator(validate_path)
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("directory")
+                                .about(
+                                    "Directory containing yabridge's libraries and \
+                                     'yabridge-host.exe'",
+                                )
+                                .validator(validate_path)
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(json_arg()),
+                )
+                .subcommand(
+                    App::new("rm")
+                        .about("Use the regular build of yabridge for a plugin again")
+                        .display_order(2)
+                        .arg(
+                            Arg::new("plugin")
+                                .about("Path to a plugin with another build of yabridge")
+                                .possible_values(&plugin_host_entries)
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(json_arg()),
+                )
+                .subcommand(
+                    App::new("list")
+                        .about("List the plugins using another build of yabridge")
+                        .display_order(3),
                 ),
         )
         .subcommand(
@@ -186,19 +1509,26 @@ fn main() -> Result<()> {
                     "Manage the indexing blacklist (advanced)\n\
                      \n\
                      This lets you skip over individual files and entire directories in the \
-                     indexing process. You most likely won't have to use this feature.",
+                     indexing process. Entries containing '*' or '?' wildcards are patterns that \
+                     skip every matching plugin during 'yabridgectl sync', and existing \
+                     installations for those plugins will be removed. Relative patterns are \
+                     also matched against the paths relative to the plugin directories, so both \
+                     'FabFilter/*' and '*/FabFilter/*' match FabFilter plugins in every plugin \
+                     directory. You most \
+                     likely won't have to use this feature.",
                 )
                 .subcommand(
                     App::new("add")
-                        .about("Add a path to the blacklist")
+                        .about("Add a path or a wildcard pattern to the blacklist")
                         .display_order(1)
                         .arg(
                             Arg::new("path")
-                                .about("Path to a file or a directory")
-                                .validator(validate_path)
+                                .about("Path to a file or a directory, or a wildcard pattern")
+                                .validator(validate_blacklist_entry)
                                 .takes_value(true)
                                 .required(true),
-                        ),
+                        )
+                        .arg(json_arg()),
                 )
                 .subcommand(
                     App::new("rm")
@@ -210,7 +1540,8 @@ fn main() -> Result<()> {
                                 .possible_values(&blacklist_entries)
                                 .takes_value(true)
                                 .required(true),
-                        ),
+                        )
+                        .arg(json_arg()),
                 )
                 .subcommand(
                     App::new("list")
@@ -220,40 +1551,294 @@ fn main() -> Result<()> {
                 .subcommand(
                     App::new("clear")
                         .about("Clear the entire blacklist")
-                        .display_order(4),
+                        .display_order(4)
+                        .arg(json_arg()),
                 ),
         )
-        .get_matches();
+        .subcommand(
+            App::new("completions")
+                .about("Print a shell completion script")
+                .long_about(
+                    "Print a completion script for yabridgectl's subcommands and options. Save \
+                     the output to your shell's completion directory, or source it from your \
+                     shell's config file.",
+                )
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::new("shell")
+                        .about("The shell to generate the completion script for")
+                        .possible_values(completions::SHELLS)
+                        .takes_value(true)
+                        .required(true),
+                ),
+        );
+    let matches = app.clone().get_matches();
+
+    configure_color(&matches);
+    utils::set_quiet(is_present_anywhere(&matches, "quiet"));
+    utils::set_skip_checks(is_present_anywhere(&matches, "skip_checks"));
+    // With `--json`, STDOUT should only contain JSON. Anything else goes to STDERR instead.
+    if json_format(deepest_subcommand(&matches)).is_some() {
+        json::redirect_stdout()?;
+        utils::set_colors(utils::stderr_colors(), utils::stderr_colors());
+    }
+
+    // The completion scripts don't depend on the config, so these can also be generated when the
+    // config file is invalid
+    if let Some(("completions", options)) = matches.subcommand() {
+        return completions::print(&app, options.value_of("shell").unwrap());
+    }
+    // The app borrows from the config through the possible values, and the config will be modified
+    // below
+    drop(app);
+
+    let validating_config = matches!(
+        matches.subcommand(),
+        Some(("config", config_matches)) if config_matches.subcommand_name() == Some("validate")
+    );
+    if let Some(err) = config_error {
+        // `--clean` would overwrite the invalid config file with the default settings
+        if !validating_config || is_present_anywhere(&matches, "clean") {
+            return Err(err);
+        }
+    }
+
+    // Commands that modify the config or the installed files will hold an exclusive lock until
+    // they finish so that multiple yabridgectl instances cannot interfere with each other
+    let clean = is_present_anywhere(&matches, "clean");
+    let lock = match matches.subcommand() {
+        _ if clean => Some(config::acquire_lock()?),
+        Some(("status", options)) if options.is_present("clean_empty") => {
+            Some(config::acquire_lock()?)
+        }
+        Some(("prune", options)) if options.is_present("dry_run") => None,
+        Some(("list", _))
+        | Some(("export", _))
+        | Some(("status", _))
+        | Some(("info", _))
+        | Some(("inventory", _))
+        | Some(("healthcheck", _))
+        | Some(("verify-config-against-disk", _))
+        | Some(("hash-manifest", _))
+        | Some(("generate-service", _))
+        | Some(("reload", _)) => None,
+        Some(("config", _)) if validating_config => None,
+        Some(("blacklist", blacklist)) if blacklist.subcommand_name() == Some("list") => None,
+        Some(("profile", profile)) if profile.subcommand_name() == Some("list") => None,
+        Some(("host", host)) if host.subcommand_name() == Some("list") => None,
+        // Watch mode acquires the lock separately for every sync
+        Some(("watch", _)) => None,
+        _ => Some(config::acquire_lock()?),
+    };
+
+    // Another yabridgectl instance may have modified the config file after we read it and before we
+    // acquired the lock, so the changes we make should be based on the current version
+    if lock.is_some() {
+        config = Config::read()?;
+    }
+
+    if clean {
+        config.write()?;
+    }
 
     // We're calling canonicalize when adding and setting paths since relative paths would cause
     // some weird behaviour. There's no built-in way to make relative paths absoltue without
     // resolving symlinks, but I don't think this will cause any issues.
     //
     // https://github.com/rust-lang/rust/issues/59117
-    match matches.subcommand() {
-        Some(("add", options)) => actions::add_directory(
+    //
+    // Read-only commands return immediately, while all other commands print a summary at the end
+    let summary_json = deepest_subcommand(&matches)
+        .value_of_t::<PathBuf>("summary_json")
+        .ok();
+    let start_time = Instant::now();
+    let result = match matches.subcommand() {
+        Some(("add", options)) => actions::add_directories(
+            &mut config,
+            options
+                .values_of_t_or_exit::<PathBuf>("path")
+                .into_iter()
+                .map(|path| -> Result<PathBuf> { Ok(utils::expand_path(&path)?.canonicalize()?) })
+                .collect::<Result<_>>()?,
+            options.is_present("interactive"),
+        ),
+        Some(("import", options)) => {
+            actions::import_directories(&mut config, &options.value_of_t_or_exit::<PathBuf>("file"))
+        }
+        Some(("export", options)) => {
+            return actions::export_directories(
+                &config,
+                options.value_of_t::<PathBuf>("file").ok().as_deref(),
+                options.is_present("toml"),
+            )
+        }
+        Some(("rm", options)) => actions::remove_directory(
+            &mut config,
+            &options.value_of_t_or_exit::<PathBuf>("path"),
+            options.is_present("prune"),
+        ),
+        Some(("rename-directory", options)) => actions::rename_directory(
             &mut config,
+            &options.value_of_t_or_exit::<PathBuf>("old_path"),
             options
+                .value_of_t_or_exit::<PathBuf>("new_path")
+                .canonicalize()?,
+        ),
+        Some(("hash-manifest", options)) => actions::generate_hash_manifest(
+            &config,
+            &options
                 .value_of_t_or_exit::<PathBuf>("path")
                 .canonicalize()?,
         ),
-        Some(("rm", options)) => {
-            actions::remove_directory(&mut config, &options.value_of_t_or_exit::<PathBuf>("path"))
+        Some(("prune", options)) => {
+            let dry_run = options.is_present("dry_run");
+            let summary = actions::prune::prune(&config, dry_run);
+            if dry_run {
+                return summary.map(|_| ());
+            }
+
+            summary
+        }
+        Some(("list", options)) if options.is_present("orphans") => {
+            return actions::prune::list_orphans(&config, json_format(options))
         }
-        Some(("list", _)) => actions::list_directories(&config),
-        Some(("status", _)) => actions::show_status(&config),
-        Some(("sync", options)) => actions::do_sync(
+        Some(("list", options)) => return actions::list_directories(&config, json_format(options)),
+        Some(("status", options)) => {
+            if let Some(format) = json_format(options) {
+                return actions::show_status_json(&config, format);
+            }
+
+            let clean_empty = options.is_present("clean_empty");
+            let unused_directories = actions::show_status(&config, clean_empty)?;
+            if !clean_empty {
+                return Ok(());
+            }
+
+            actions::remove_unused_directories(&mut config, unused_directories)
+        }
+        Some(("inventory", options)) => {
+            return actions::show_inventory(&config, json_format(options))
+        }
+        Some(("info", _)) => return actions::show_info(&config),
+        Some(("doctor", _)) => return actions::doctor::doctor(&mut config),
+        Some(("healthcheck", options)) => {
+            return actions::healthcheck::healthcheck(
+                &config,
+                &actions::healthcheck::HealthcheckOptions {
+                    json: json_format(options),
+                    status_file: options.value_of_t::<PathBuf>("status_file").ok(),
+                    wine: options.is_present("wine"),
+                },
+            )
+        }
+        Some(("verify-config-against-disk", options)) => {
+            return actions::reconcile::verify_config_against_disk(
+                &config,
+                options.value_of("profile"),
+                json_format(options),
+            )
+        }
+        Some(("verify-prefixes", _)) => actions::verify_prefixes(&mut config),
+        Some(("sync", options)) => actions::sync::do_sync(
             &mut config,
-            &actions::SyncOptions {
+            &actions::sync::SyncOptions {
+                apply_plan: options.value_of_t::<PathBuf>("apply_plan").ok(),
+                confirm: options.is_present("confirm"),
+                check_permissions: options.is_present("check_permissions"),
+                continue_on_error: options.is_present("continue_on_error"),
+                dry_run: options.is_present("dry_run"),
+                exclude_arch: options
+                    .values_of_t::<LibArchitecture>("exclude_arch")
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                export_plan: options.value_of_t::<PathBuf>("export_plan").ok(),
+                explain: options
+                    .value_of_t::<PathBuf>("explain")
+                    .ok()
+                    .and_then(|path| path.canonicalize().ok()),
+                extract: options.is_present("extract"),
+                fast: options.is_present("fast"),
                 force: options.is_present("force"),
+                ignore_case: options.is_present("ignore_case"),
+                jobs: options.value_of_t::<usize>("jobs").ok(),
+                log_file: options.value_of_t::<PathBuf>("log_file").ok(),
+                max_size: options
+                    .value_of("max_size")
+                    .and_then(|size| utils::parse_size(size).ok()),
                 no_verify: options.is_present("no-verify"),
+                only: options
+                    .values_of("only")
+                    .map(|values| values.map(String::from).collect())
+                    .unwrap_or_default(),
+                plugins_from: options.value_of_t::<PathBuf>("plugins_from").ok(),
+                vendors: options
+                    .values_of("vendor")
+                    .map(|values| values.map(String::from).collect())
+                    .unwrap_or_default(),
+                remove_other_vendors: options.is_present("remove_other_vendors"),
+                prefer_newer: options.is_present("prefer_newer"),
+                profile: options.value_of("profile").map(String::from),
                 prune: options.is_present("prune"),
+                strict_wine: options.is_present("strict_wine"),
+                target_prefix: options
+                    .value_of_t::<PathBuf>("target_prefix")
+                    .ok()
+                    .and_then(|path| path.canonicalize().ok()),
                 verbose: options.is_present("verbose"),
+                verify: options.is_present("verify"),
+                yes: options.is_present("yes"),
             },
         ),
+        Some(("generate-service", _)) => return actions::watch::generate_service(),
+        Some(("reload", _)) => return actions::watch::reload(),
+        Some(("watch", options)) => {
+            // `--clean` will have acquired the lock, but watch mode needs to be able to acquire it
+            // on its own
+            drop(lock);
+
+            return actions::watch::watch(
+                &mut config,
+                &actions::watch::WatchOptions {
+                    interval: Duration::from_secs(options.value_of_t_or_exit("interval")),
+                    json: json_format(options),
+                    sync: actions::sync::SyncOptions {
+                        apply_plan: None,
+                        confirm: false,
+                        check_permissions: false,
+                        continue_on_error: options.is_present("continue_on_error"),
+                        dry_run: false,
+                        exclude_arch: BTreeSet::new(),
+                        export_plan: None,
+                        explain: None,
+                        extract: false,
+                        fast: false,
+                        force: false,
+                        ignore_case: options.is_present("ignore_case"),
+                        jobs: None,
+                        log_file: None,
+                        max_size: None,
+                        no_verify: options.is_present("no-verify"),
+                        only: Vec::new(),
+                        plugins_from: None,
+                        vendors: Vec::new(),
+                        remove_other_vendors: false,
+                        prefer_newer: false,
+                        profile: options.value_of("profile").map(String::from),
+                        prune: options.is_present("prune"),
+                        strict_wine: false,
+                        target_prefix: None,
+                        verbose: options.is_present("verbose"),
+                        verify: false,
+                        yes: false,
+                    },
+                },
+            );
+        }
         Some(("set", options)) => actions::set_settings(
             &mut config,
             &actions::SetOptions {
+                directory: options.value_of_t::<PathBuf>("directory").ok(),
                 method: options.value_of("method"),
                 // We've already verified that the path is valid, so we should only be getting
                 // errors for missing arguments
@@ -262,28 +1847,266 @@ fn main() -> Result<()> {
                     .ok()
                     .and_then(|path| path.canonicalize().ok()),
                 path_auto: options.is_present("path_auto"),
+                // The target directory doesn't have to exist yet, so we can't canonicalize it
+                target: options
+                    .value_of_t::<PathBuf>("target")
+                    .ok()
+                    .map(|path| env::current_dir().unwrap_or_default().join(path)),
+                target_auto: options.is_present("target_auto"),
+                // Just like the target directory, these don't have to exist yet. An empty path
+                // removes all extra targets.
+                extra_targets: options.values_of("extra_target").map(|values| {
+                    values
+                        .filter(|value| !value.is_empty())
+                        .map(|path| env::current_dir().unwrap_or_default().join(path))
+                        .collect()
+                }),
+                method_auto: options.is_present("method_auto"),
+                // This has already been validated, and relative paths are resolved relative to the
+                // current directory
+                wine: options.value_of("wine").and_then(|wine| {
+                    which::which(wine)
+                        .ok()
+                        .map(|path| env::current_dir().unwrap_or_default().join(path))
+                }),
+                wine_auto: options.is_present("wine_auto"),
+                all_subdirectories: options.is_present("all_subdirectories"),
+                // `none` is not a valid architecture, so passing only that value results in an
+                // empty set
+                exclude_arch: options.values_of("exclude_arch").map(|values| {
+                    values
+                        .filter_map(|value| value.parse::<LibArchitecture>().ok())
+                        .collect()
+                }),
+                formats: options
+                    .values_of_t::<PluginFormat>("formats")
+                    .ok()
+                    .map(|formats| formats.into_iter().collect()),
+                // An empty pattern clears the list
+                ignore_wine_errors: options.values_of("ignore_wine_error").map(|values| {
+                    values
+                        .filter(|value| !value.is_empty())
+                        .map(String::from)
+                        .collect()
+                }),
+                ignore_suffixes: options.values_of("ignore_suffix").map(|values| {
+                    values
+                        .filter(|value| !value.is_empty())
+                        .map(String::from)
+                        .collect()
+                }),
+                always_copy: options
+                    .values_of_t::<PathBuf>("always_copy")
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|path| path.canonicalize())
+                    .collect::<std::io::Result<_>>()?,
+                no_always_copy: options
+                    .values_of_t::<PathBuf>("no_always_copy")
+                    .unwrap_or_default(),
+                // `none` removes the limit
+                max_plugin_size: options
+                    .value_of("max_plugin_size")
+                    .map(|size| utils::parse_size(size).ok()),
+                removal_limit: options.value_of_t::<usize>("removal_limit").ok(),
+                deduplicate_copies: options
+                    .value_of("deduplicate_copies")
+                    .map(|value| value == "true"),
+                link_host_binaries: options
+                    .value_of("link_host_binaries")
+                    .map(|value| value == "true"),
+                relative_symlinks: options
+                    .value_of("relative_symlinks")
+                    .map(|value| value == "true"),
                 no_verify: options.value_of("no_verify").map(|value| value == "true"),
+                verify_timeout: options.value_of_t::<u64>("verify_timeout").ok(),
             },
         ),
-        Some(("blacklist", blacklist)) => match blacklist.subcommand() {
-            Some(("add", options)) => actions::blacklist::add_path(
+        Some(("config", config_matches)) => match config_matches.subcommand() {
+            Some(("validate", options)) => return actions::config::validate(json_format(options)),
+            _ => unreachable!(),
+        },
+        Some(("profile", profile)) => match profile.subcommand() {
+            Some(("add", options)) => actions::profile::add(
+                &mut config,
+                options.value_of("name").unwrap(),
+                options
+                    .values_of_t::<PathBuf>("directory")
+                    .unwrap_or_default(),
+                options
+                    .values_of("plugin")
+                    .map(|values| values.map(String::from).collect())
+                    .unwrap_or_default(),
+            ),
+            Some(("rm", options)) => {
+                actions::profile::remove(&mut config, options.value_of("name").unwrap())
+            }
+            Some(("list", _)) => return actions::profile::list(&config),
+            _ => unreachable!(),
+        },
+        Some(("host", host)) => match host.subcommand() {
+            Some(("set", options)) => actions::host::set(
                 &mut config,
                 options
-                    .value_of_t_or_exit::<PathBuf>("path")
+                    .value_of_t_or_exit::<PathBuf>("plugin")
                     .canonicalize()?,
+                options
+                    .value_of_t_or_exit::<PathBuf>("directory")
+                    .canonicalize()?,
+            ),
+            Some(("rm", options)) => actions::host::remove(
+                &mut config,
+                &options.value_of_t_or_exit::<PathBuf>("plugin"),
             ),
+            Some(("list", _)) => return actions::host::list(&config),
+            _ => unreachable!(),
+        },
+        Some(("blacklist", blacklist)) => match blacklist.subcommand() {
+            Some(("add", options)) => {
+                // Patterns can't be canonicalized, and relative patterns should stay relative
+                let path = options.value_of_t_or_exit::<PathBuf>("path");
+                let path = if config::is_blacklist_pattern(&path) {
+                    path
+                } else {
+                    path.canonicalize()?
+                };
+
+                actions::blacklist::add_path(&mut config, path)
+            }
             Some(("rm", options)) => actions::blacklist::remove_path(
                 &mut config,
                 &options.value_of_t_or_exit::<PathBuf>("path"),
             ),
-            Some(("list", _)) => actions::blacklist::list_paths(&config),
+            Some(("list", _)) => return actions::blacklist::list_paths(&config),
             Some(("clear", _)) => actions::blacklist::clear(&mut config),
             _ => unreachable!(),
         },
         _ => unreachable!(),
+    };
+
+    if let Some(path) = summary_json {
+        actions::write_summary_file(&path, &result, start_time.elapsed())?;
+    }
+
+    let summary = result?;
+    summary.print(json_format(deepest_subcommand(&matches)))?;
+    if summary.failed > 0 {
+        Err(anyhow!("{} operation(s) failed", summary.failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the matches for the deepest subcommand that was used, for instance for `yabridgectl blacklist
+/// add`.
+fn deepest_subcommand(matches: &ArgMatches) -> &ArgMatches {
+    match matches.subcommand() {
+        Some((_, subcommand)) => deepest_subcommand(subcommand),
+        None => matches,
+    }
+}
+
+/// Find the value of the global `--config` option before the command line arguments are parsed.
+/// The app's possible values depend on the config, so the config file has to be read before clap
+/// can parse the arguments. Clap will still validate the option afterwards.
+fn config_path_arg() -> Option<PathBuf> {
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+/// Check whether a global flag has been passed. Clap only propagates global arguments down to the
+/// subcommand that was used, so we need to look at the deepest subcommand.
+fn is_present_anywhere(matches: &ArgMatches, name: &str) -> bool {
+    deepest_subcommand(matches).is_present(name)
+}
+
+/// Configure whether the output should be colored based on the global `--color` option. In auto
+/// mode we'll respect the `NO_COLOR`, `CLICOLOR`, and `CLICOLOR_FORCE` environment variables like
+/// the `colored` crate would, and we'll check whether STDOUT and STDERR are terminals separately
+/// since warnings are written to STDERR.
+fn configure_color(options: &ArgMatches) {
+    match options.value_of("color") {
+        Some("always") => utils::set_colors(true, true),
+        Some("never") => utils::set_colors(false, false),
+        _ => {
+            let force = env::var_os("CLICOLOR_FORCE").is_some_and(|value| value != "0");
+            let disabled = env::var_os("NO_COLOR").is_some()
+                || env::var_os("CLICOLOR").is_some_and(|value| value == "0");
+            let colorize = |fd| force || (!disabled && unsafe { libc::isatty(fd) } == 1);
+            utils::set_colors(colorize(libc::STDOUT_FILENO), colorize(libc::STDERR_FILENO));
+        }
+    }
+}
+
+/// The `--json` flag used by all commands that can output JSON. See [`json_format()`].
+fn json_arg<'a>() -> Arg<'a> {
+    Arg::new("json")
+        .long("json")
+        .about("Print the output as JSON")
+        .long_about(
+            "Print the output as JSON. For commands that make changes, only the summary printed \
+             at the end will be formatted as JSON and written to STDOUT. Everything else is \
+             printed to STDERR instead.",
+        )
+}
+
+/// Determine whether a command should print JSON, and if so, how that JSON should be formatted.
+/// Passing the global `--json-pretty` option also implies `--json`.
+fn json_format(options: &ArgMatches) -> Option<JsonFormat> {
+    if options.is_present("json_pretty") {
+        Some(JsonFormat::Pretty)
+    } else if options.is_present("json") {
+        Some(JsonFormat::Compact)
+    } else {
+        None
+    }
+}
+
+/// Like [`validate_path()`], but wildcard patterns don't have to exist. Used for `yabridgectl
+/// blacklist add`.
+fn validate_blacklist_entry(path: &str) -> Result<(), String> {
+    if config::is_blacklist_pattern(Path::new(path)) {
+        Ok(())
+    } else {
+        validate_path(path)
     }
 }
 
+/// Verify that a Wine binary passed to `yabridgectl set --wine` exists. This can be either a path or
+/// the name of a binary in the search path.
+fn validate_wine_binary(wine: &str) -> Result<(), String> {
+    match which::which(wine) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Could not find the Wine binary '{}'", wine)),
+    }
+}
+
+/// The same as [`validate_path()`], but a leading `~` and environment variables are expanded first.
+/// See [`utils::expand_path()`].
+fn validate_expanded_path(path: &str) -> Result<(), String> {
+    let path = utils::expand_path(Path::new(path)).map_err(|err| err.to_string())?;
+
+    validate_path(&path.to_string_lossy())
+}
+
+/// Verify that a pattern passed to `yabridgectl set --ignore-wine-error` is a valid regular
+/// expression. An empty pattern clears the list.
+fn validate_wine_error_pattern(pattern: &str) -> Result<(), String> {
+    utils::ignored_wine_errors([pattern])
+        .map(|_| ())
+        .map_err(|err| format!("{:#}", err))
+}
+
 /// Verify that a path exists, used for validating arguments.
 fn validate_path(path: &str) -> Result<(), String> {
     let path = Path::new(path);
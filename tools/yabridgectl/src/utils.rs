@@ -16,30 +16,152 @@
 
 //! Small helper utilities.
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use colored::Colorize;
 use is_executable::IsExecutable;
+use regex::RegexSet;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fs;
 use std::hash::Hasher;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::os::unix::process::CommandExt;
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use textwrap;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::config::{self, Config, KnownConfig, YABRIDGE_HOST_EXE_NAME};
-use crate::files::NativeFile;
+use crate::config::{
+    self, Config, KnownConfig, YabridgeFiles, YABRIDGE_HOST_32_EXE_NAME, YABRIDGE_HOST_EXE_NAME,
+};
+use crate::files::{self, NativeFile};
+use crate::log::logln;
 
 /// (Part of) the expected output when running `yabridge-host.exe`. Used to verify that everything's
 /// working correctly. We'll only match this prefix so we can modify the exact output at a later
 /// moment without causing issues.
 const YABRIDGE_HOST_EXPECTED_OUTPUT_PREFIX: &str = "Usage: yabridge-";
+/// How many times we'll try to run a host binary in [`verify_wine_setup()`] when it fails because
+/// of a wineserver startup error. When wineserver isn't running yet, the first Wine process can
+/// fail while wineserver is still starting up, and running it again a moment later works fine.
+const HOST_ATTEMPTS: u32 = 3;
+/// How long to wait before the second attempt. Every subsequent attempt waits this much longer.
+const HOST_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+/// Wildcard patterns for the errors Wine prints when it could not start or connect to wineserver.
+/// Only these errors are retried, see [`is_wineserver_startup_error()`].
+const WINESERVER_STARTUP_ERROR_PATTERNS: &[&str] = &[
+    "*wine client error*",
+    "*could not connect to*server*",
+    "*wineserver*",
+];
+
+/// Environment variables used by Linux VST2 hosts to specify additional plugin search paths. These
+/// contain colon separated lists of directories, just like `PATH`.
+const VST2_SEARCH_PATH_ENV_VARS: &[&str] = &["VST_PATH", "LXVST_PATH"];
+/// The directories most Linux VST2 hosts will search through by default. Paths starting with a
+/// tilde are relative to `$HOME`. VST3 modules are installed to `~/.vst3/yabridge`, and `~/.vst3` is
+/// always scanned by every VST3 host, so we don't have to check that.
+const VST2_DEFAULT_SEARCH_PATHS: &[&str] = &[
+    "~/.vst",
+    "~/.lxvst",
+    "/usr/lib/vst",
+    "/usr/lib/lxvst",
+    "/usr/local/lib/vst",
+    "/usr/local/lib/lxvst",
+];
+
+/// Whether warnings should be suppressed, set with the global `--quiet` option. See [`warnln!()`].
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress all output printed through [`warnln!()`]. Used for the global `--quiet` option.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether yabridgectl is run with `--quiet`, see [`set_quiet()`].
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+static SKIP_CHECKS: AtomicBool = AtomicBool::new(false);
+
+/// Skip the PATH and Wine setup checks in every command that would otherwise perform them. Used for
+/// the global `--skip-checks` option.
+pub fn set_skip_checks(skip_checks: bool) {
+    SKIP_CHECKS.store(skip_checks, Ordering::Relaxed);
+}
+
+/// Whether yabridgectl is run with `--skip-checks`, see [`set_skip_checks()`].
+pub fn is_skipping_checks() -> bool {
+    SKIP_CHECKS.load(Ordering::Relaxed)
+}
+
+/// Whether output written to STDOUT and STDERR should be colored, set with the global `--color`
+/// option. See [`set_colors()`].
+static COLOR_STDOUT: AtomicBool = AtomicBool::new(true);
+static COLOR_STDERR: AtomicBool = AtomicBool::new(true);
+
+/// Configure whether output to STDOUT and output to STDERR should be colored. The `colored` crate
+/// only has a single global setting, so that's set to STDOUT's setting. Output to STDERR should be
+/// printed using [`errorln!()`] or [`warnln!()`], which temporarily switch to STDERR's setting.
+pub fn set_colors(stdout: bool, stderr: bool) {
+    COLOR_STDOUT.store(stdout, Ordering::Relaxed);
+    COLOR_STDERR.store(stderr, Ordering::Relaxed);
+    colored::control::set_override(stdout);
+}
+
+/// Whether output to STDERR should be colored. See [`set_colors()`].
+pub fn stderr_colors() -> bool {
+    COLOR_STDERR.load(Ordering::Relaxed)
+}
+
+/// Switches the `colored` crate over to STDERR's color setting until this is dropped, see
+/// [`errorln!()`].
+pub struct StderrColors(());
+
+impl StderrColors {
+    pub fn enable() -> StderrColors {
+        colored::control::set_override(COLOR_STDERR.load(Ordering::Relaxed));
+        StderrColors(())
+    }
+}
+
+impl Drop for StderrColors {
+    fn drop(&mut self) {
+        colored::control::set_override(COLOR_STDOUT.load(Ordering::Relaxed));
+    }
+}
+
+/// The same as `eprintln!()`, but the message is colored based on whether STDERR is a terminal
+/// instead of STDOUT. See [`set_colors()`].
+macro_rules! errorln {
+    ($($arg:tt)*) => {{
+        let _colors = $crate::utils::StderrColors::enable();
+        eprintln!($($arg)*);
+    }};
+}
+pub(crate) use errorln;
+
+/// The same as [`errorln!()`], but nothing gets printed when yabridgectl is run with `--quiet`. This
+/// should be used for warnings and other informational messages printed to STDERR. Hard errors are
+/// returned as `Err` values instead, and those are always printed.
+macro_rules! warnln {
+    ($($arg:tt)*) => {
+        if !$crate::utils::is_quiet() {
+            $crate::utils::errorln!($($arg)*);
+        }
+    };
+}
+pub(crate) use warnln;
 
 /// Wrapper around [`std::fs::copy()`](std::fs::copy) with a human readable error message.
 pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
-    fs::copy(&from, &to).with_context(|| {
+    with_write_context(fs::copy(&from, &to), to.as_ref().parent(), || {
         format!(
             "Error copying '{}' to '{}'",
             from.as_ref().display(),
@@ -48,10 +170,60 @@ pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
     })
 }
 
+/// Add a context message to the result of a file system operation that writes to `directory`, like
+/// `.with_context()` would. If the operation failed because the user is not allowed to write to that
+/// directory, then the message also explains how to get around that. Plugin directories in system
+/// locations like `/opt` are often owned by root, and the OS error alone doesn't say what to do
+/// about that.
+fn with_write_context<T, F: FnOnce() -> String>(
+    result: std::io::Result<T>,
+    directory: Option<&Path>,
+    message: F,
+) -> Result<T> {
+    result.map_err(|err| {
+        let is_permission_error = err.kind() == std::io::ErrorKind::PermissionDenied
+            || err.raw_os_error() == Some(libc::EROFS);
+        let message = match directory {
+            Some(directory) if is_permission_error => format!(
+                "{}\n\n{}",
+                message(),
+                wrap(&format!(
+                    "Yabridgectl is not allowed to write to '{}'. Either run yabridgectl as a \
+                     user that can write to this directory, or use 'yabridgectl set \
+                     <plugin_directory> --target <path>' to set up the plugins from this plugin \
+                     directory in another directory.",
+                    directory.display()
+                ))
+            ),
+            _ => message(),
+        };
+
+        anyhow::Error::new(err).context(message)
+    })
+}
+
+/// The same as [`copy()`], but `from` is first copied to a temporary file next to `to`, which is
+/// then moved over `to`. This way `to` always contains either the old file or the new file, even
+/// when yabridgectl gets interrupted halfway through copying. The temporary file is removed again if
+/// anything goes wrong.
+pub fn copy_atomically<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64> {
+    let to = to.as_ref();
+    let temporary_path = temporary_path_for(to);
+    let result = copy(&from, &temporary_path).and_then(|size| {
+        rename(&temporary_path, to)?;
+        Ok(size)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+
+    result
+}
+
 /// Wrapper around [`std::fs::create_dir_all()`](std::fs::create_dir_all) with a human readable
 /// error message.
 pub fn create_dir_all<P: AsRef<Path>>(path: P) -> Result<()> {
-    fs::create_dir_all(&path).with_context(|| {
+    with_write_context(fs::create_dir_all(&path), Some(path.as_ref()), || {
         format!(
             "Error creating directories for '{}'",
             path.as_ref().display(),
@@ -73,10 +245,133 @@ pub fn remove_file<P: AsRef<Path>>(path: P) -> Result<()> {
         .with_context(|| format!("Could not remove '{}'", path.as_ref().display()))
 }
 
+/// Write `contents` to a temporary file next to `path` and then move it to `path`. This way other
+/// programs reading the file will never see a partially written file.
+pub fn write_atomically<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let path = path.as_ref();
+    let temporary_path = temporary_path_for(path);
+    fs::write(&temporary_path, contents)
+        .with_context(|| format!("Could not write '{}'", temporary_path.display()))?;
+
+    rename(&temporary_path, path)
+}
+
+/// The path of the temporary file used by [`write_atomically()`] and [`copy_atomically()`]. This
+/// needs to be in the same directory as `path` since files can only be renamed atomically within
+/// the same file system.
+fn temporary_path_for(path: &Path) -> PathBuf {
+    let mut temporary_file_name = path.file_name().unwrap_or_default().to_owned();
+    temporary_file_name.push(".tmp");
+
+    path.with_file_name(temporary_file_name)
+}
+
+/// Wrapper around [`std::fs::rename()`](std::fs::rename) with a human readable error message.
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+    with_write_context(fs::rename(&from, &to), to.as_ref().parent(), || {
+        format!(
+            "Error moving '{}' to '{}'",
+            from.as_ref().display(),
+            to.as_ref().display()
+        )
+    })
+}
+
+/// Move a file or a directory from `from` to `to`. This uses [`rename()`] when possible, but renaming
+/// does not work across file systems. In that case `from` is copied to `to` instead, and `from` is
+/// removed once everything has been copied. Symlinks are copied as symlinks, since the merged VST3
+/// bundles yabridgectl creates consist mostly of symlinks.
+pub fn move_path(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+            let result = copy_recursively(from, to);
+            if result.is_err() {
+                // Leave the original files in place if we couldn't copy all of them
+                let _ = fs::remove_dir_all(to).or_else(|_| fs::remove_file(to));
+                return result;
+            }
+
+            match get_file_type(from.to_owned()) {
+                Some(NativeFile::Directory(_)) => remove_dir_all(from),
+                _ => remove_file(from),
+            }
+        }
+        result => with_write_context(result, to.parent(), || {
+            format!("Error moving '{}' to '{}'", from.display(), to.display())
+        }),
+    }
+}
+
+/// Copy `from` to `to` for [`move_path()`]. Directories are copied recursively, and symlinks are
+/// recreated instead of being followed.
+fn copy_recursively(from: &Path, to: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(from)
+        .with_context(|| format!("Could not read '{}'", from.display()))?;
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(from)
+            .with_context(|| format!("Could not read the symlink '{}'", from.display()))?;
+        symlink(target, to)
+    } else if metadata.is_dir() {
+        with_write_context(fs::create_dir(to), to.parent(), || {
+            format!("Error creating '{}'", to.display())
+        })?;
+        for entry in
+            fs::read_dir(from).with_context(|| format!("Could not read '{}'", from.display()))?
+        {
+            let entry = entry.with_context(|| format!("Could not read '{}'", from.display()))?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+
+        Ok(())
+    } else {
+        copy(from, to).map(|_| ())
+    }
+}
+
+/// Check whether the current user can create and remove files in `directory`, without actually
+/// changing anything. This also accounts for read-only file systems.
+pub fn is_writable_dir(directory: &Path) -> bool {
+    let c_path = match CString::new(directory.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+
+    unsafe { libc::access(c_path.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
+
+/// Check whether the current user could remove or replace the file at `path`, assuming its parent
+/// directory is writable. Directories with the sticky bit set, like `/tmp`, only allow files to be
+/// removed by their owner.
+pub fn can_replace(path: &Path) -> bool {
+    let parent_metadata = match path.parent().map(fs::metadata) {
+        Some(Ok(metadata)) => metadata,
+        _ => return false,
+    };
+    if parent_metadata.mode() & libc::S_ISVTX == 0 {
+        return true;
+    }
+
+    let uid = unsafe { libc::geteuid() };
+    uid == 0
+        || parent_metadata.uid() == uid
+        || matches!(fs::symlink_metadata(path), Ok(metadata) if metadata.uid() == uid)
+}
+
+/// Wrapper around [`std::fs::hard_link()`](std::fs::hard_link) with a human readable error message.
+pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
+    with_write_context(fs::hard_link(&src, &dst), dst.as_ref().parent(), || {
+        format!(
+            "Error hard linking '{}' to '{}'",
+            src.as_ref().display(),
+            dst.as_ref().display()
+        )
+    })
+}
+
 /// Wrapper around [`std::os::unix::fs::symlink()`](std::os::unix::fs::symlink) with a human
 /// readable error message.
 pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
-    unix_fs::symlink(&src, &dst).with_context(|| {
+    with_write_context(unix_fs::symlink(&src, &dst), dst.as_ref().parent(), || {
         format!(
             "Error symlinking '{}' to '{}'",
             src.as_ref().display(),
@@ -108,8 +403,230 @@ pub fn hash_file(file: &Path) -> Result<i64> {
         &fs::read(file)
             .with_context(|| format!("Could not read contents of '{}'", file.display()))?,
     );
+    let hash = hasher.finish() as i64;
+    logln!("Hashed '{}': {:016x}", file.display(), hash);
+
+    Ok(hash)
+}
+
+/// Normalize a path without touching the file system. This removes `.` components, resolves `..`
+/// components, and removes redundant and trailing slashes. Unlike
+/// [`Path::canonicalize()`](std::path::Path::canonicalize) this does not resolve symlinks, and the
+/// path doesn't need to exist.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            // `PathBuf::pop()` won't remove the root, and for relative paths we can't resolve
+            // leading `..` components
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            Component::ParentDir if normalized.has_root() => (),
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Expand a leading `~` or `~user` and any `$VAR` or `${VAR}` environment variable references in a
+/// path, like a shell would. Paths in the config file and paths passed to `yabridgectl add` are
+/// expanded this way, so `~/Plugins` doesn't end up as a directory literally called `~`. A `$`
+/// that's not followed by a variable name is kept as is, and so are references to variables that
+/// are not set. Windows paths like `$Recycle.Bin` are common in plugin directories, and those would
+/// otherwise make the config file unusable. Referencing a user that does not exist returns an
+/// error.
+pub fn expand_path(path: &Path) -> Result<PathBuf> {
+    let bytes = path.as_os_str().as_bytes();
+    let mut expanded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    // A tilde is only expanded at the start of the path, and only when it's followed by a user
+    // name or by nothing at all
+    let mut rest = bytes;
+    if let Some(after_tilde) = bytes.strip_prefix(b"~") {
+        let user_len = after_tilde
+            .iter()
+            .position(|&c| c == b'/')
+            .unwrap_or(after_tilde.len());
+        let user = &after_tilde[..user_len];
+        let home = if user.is_empty() {
+            env::var_os("HOME").ok_or_else(|| {
+                anyhow!(
+                    "Could not expand '~' in '{}', $HOME is not set",
+                    path.display()
+                )
+            })?
+        } else {
+            user_home_directory(user).ok_or_else(|| {
+                anyhow!(
+                    "Could not expand '~{}' in '{}', that user does not exist",
+                    String::from_utf8_lossy(user),
+                    path.display()
+                )
+            })?
+        };
+        expanded.extend_from_slice(home.as_bytes());
+        rest = &after_tilde[user_len..];
+    }
+
+    let is_name_char = |c: u8| c.is_ascii_alphanumeric() || c == b'_';
+    let mut idx = 0;
+    while idx < rest.len() {
+        let (name, len) = match &rest[idx..] {
+            [b'$', b'{', tail @ ..] if tail.contains(&b'}') => {
+                let name_len = tail.iter().position(|&c| c == b'}').unwrap();
+                (&tail[..name_len], name_len + 3)
+            }
+            [b'$', first, tail @ ..] if first.is_ascii_alphabetic() || *first == b'_' => {
+                let name_len = 1 + tail.iter().take_while(|&&c| is_name_char(c)).count();
+                (&rest[idx + 1..idx + 1 + name_len], name_len + 1)
+            }
+            [c, ..] => {
+                expanded.push(*c);
+                idx += 1;
+                continue;
+            }
+            [] => unreachable!(),
+        };
+
+        match env::var_os(OsStr::from_bytes(name)) {
+            Some(value) => expanded.extend_from_slice(value.as_bytes()),
+            None => expanded.extend_from_slice(&rest[idx..idx + len]),
+        }
+        idx += len;
+    }
+
+    Ok(PathBuf::from(OsString::from_vec(expanded)))
+}
+
+/// Look up a user's home directory in the password database, for expanding `~user` in
+/// [`expand_path()`].
+fn user_home_directory(user: &[u8]) -> Option<OsString> {
+    let user = CString::new(user).ok()?;
+    let passwd = unsafe { libc::getpwnam(user.as_ptr()) };
+    if passwd.is_null() {
+        return None;
+    }
+
+    let home = unsafe { CStr::from_ptr((*passwd).pw_dir) };
+    Some(OsStr::from_bytes(home.to_bytes()).to_owned())
+}
+
+/// Compute the relative path from the directory `base` to `path`, for creating relative symlinks.
+/// Both paths should be absolute. Just like [`normalize_path()`] this doesn't touch the file system,
+/// so symlinked directories in `base` are not taken into account.
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path = normalize_path(path);
+    let base = normalize_path(base);
+    let common_components = path
+        .components()
+        .zip(base.components())
+        .take_while(|(component, base_component)| component == base_component)
+        .count();
+
+    let mut relative_path = PathBuf::new();
+    for _ in base.components().skip(common_components) {
+        relative_path.push(Component::ParentDir);
+    }
+    for component in path.components().skip(common_components) {
+        relative_path.push(component);
+    }
 
-    Ok(hasher.finish() as i64)
+    relative_path
+}
+
+/// Read the target of a symlink. Relative targets are resolved relative to the directory containing
+/// the symlink, so the result can be compared to an absolute path. The target doesn't need to
+/// exist.
+pub fn read_symlink(path: &Path) -> std::io::Result<PathBuf> {
+    let target = path.read_link()?;
+    match path.parent() {
+        Some(parent) if target.is_relative() => Ok(normalize_path(&parent.join(target))),
+        _ => Ok(target),
+    }
+}
+
+/// Symlink `yabridge-host.exe` and the other host binaries that exist next to it to
+/// `~/.local/share/yabridge`, for `config.link_host_binaries`. Yabridge always searches that
+/// directory, so this makes sure that copies of yabridge's libraries can find the host binaries even
+/// when the DAW was not started from a login shell. Existing symlinks are replaced when they point
+/// somewhere else, but regular files are left alone since those may be from a manual installation.
+pub fn link_host_binaries(files: &YabridgeFiles) -> Result<()> {
+    let data_home = config::yabridge_directories()?.get_data_home();
+    let source_dir = match files.yabridge_host_exe.parent() {
+        Some(directory) => directory,
+        None => return Ok(()),
+    };
+    // If yabridge is installed in `~/.local/share/yabridge`, then there's nothing to link
+    if source_dir == data_home {
+        return Ok(());
+    }
+
+    let mut num_linked = 0;
+    let mut num_up_to_date = 0;
+    for host_name in [
+        "yabridge-host.exe",
+        "yabridge-host-32.exe",
+        "yabridge-group.exe",
+        "yabridge-group-32.exe",
+    ] {
+        for file_name in [host_name.to_owned(), format!("{}.so", host_name)] {
+            let source = source_dir.join(&file_name);
+            if !source.exists() {
+                continue;
+            }
+
+            let target = data_home.join(&file_name);
+            match get_file_type(target.clone()) {
+                Some(NativeFile::Symlink(_))
+                    if fs::read_link(&target).ok().as_ref() == Some(&source) =>
+                {
+                    num_up_to_date += 1;
+                    continue;
+                }
+                Some(NativeFile::Symlink(_)) => remove_file(&target)?,
+                Some(_) => {
+                    warnln!(
+                        "\n{}",
+                        wrap(&format!(
+                            "Warning: Not symlinking '{}' to '{}' since that file already exists \
+                             and it is not a symlink.",
+                            source.display(),
+                            target.display()
+                        ))
+                    );
+                    continue;
+                }
+                None => create_dir_all(&data_home)?,
+            }
+
+            symlink(&source, &target)?;
+            num_linked += 1;
+        }
+    }
+
+    if num_linked > 0 {
+        println!(
+            "Symlinked {} of yabridge's host binaries to '{}'",
+            num_linked,
+            data_home.display()
+        );
+    } else if num_up_to_date > 0 {
+        println!(
+            "Not symlinking yabridge's host binaries, the symlinks in '{}' already point to '{}'",
+            data_home.display(),
+            source_dir.display()
+        );
+    }
+
+    Ok(())
 }
 
 /// Verify that `yabridge-host.exe` can be found when yabridge is run in a host launched from the
@@ -145,7 +662,7 @@ pub fn verify_path_setup(config: &Config) -> Result<bool> {
             let shell = Path::new(&shell_path)
                 .file_name()
                 .and_then(|os_str| os_str.to_str())
-                .unwrap_or_else(|| shell_path.as_str());
+                .unwrap_or(shell_path.as_str());
 
             // We're using the `-l` flag present in most shells to start a login shell, but some
             // shells don't have this option. According the Bash's man page, another method some
@@ -176,7 +693,7 @@ pub fn verify_path_setup(config: &Config) -> Result<bool> {
                     .arg("-c")
                     .arg(format!("which {}", YABRIDGE_HOST_EXE_NAME)),
                 shell => {
-                    eprintln!(
+                    warnln!(
                         "\n{}",
                         wrap(&format!(
                             "WARNING: Yabridgectl does not know how to handle your login shell \
@@ -197,10 +714,12 @@ pub fn verify_path_setup(config: &Config) -> Result<bool> {
                 .env_clear()
                 .env("HOME", env::var("HOME").unwrap_or_default());
 
-            match command.stdout(Stdio::null()).stderr(Stdio::null()).status() {
-                Ok(status) if status.success() => Ok(true),
-                Ok(_) => {
-                    eprintln!(
+            let timeout = Duration::from_secs(config.verify_timeout);
+            match output_with_timeout(command.stdout(Stdio::null()).stderr(Stdio::null()), timeout)
+            {
+                Ok(Some(output)) if output.status.success() => Ok(true),
+                Ok(Some(_)) => {
+                    warnln!(
                         "\n{}",
                         wrap(&format!(
                             "Warning: 'yabridge-host.exe' is not present in your login shell's \
@@ -220,8 +739,22 @@ pub fn verify_path_setup(config: &Config) -> Result<bool> {
 
                     Ok(false)
                 }
+                Ok(None) => {
+                    warnln!(
+                        "\n{}",
+                        wrap(&format!(
+                            "Warning: {} did not exit within {} seconds when started as a login \
+                             shell, skipping PATH setup check. This timeout can be changed with \
+                             'yabridgectl set --verify-timeout <seconds>'.",
+                            shell.bright_white(),
+                            timeout.as_secs()
+                        ))
+                    );
+
+                    Ok(true)
+                }
                 Err(err) => {
-                    eprintln!(
+                    warnln!(
                         "\n{}",
                         wrap(&format!(
                             "Warning: could not run {} as a login shell, skipping PATH setup check: \
@@ -235,7 +768,7 @@ pub fn verify_path_setup(config: &Config) -> Result<bool> {
             }
         }
         Err(_) => {
-            eprintln!("\nWarning: Could not determine login shell, skipping PATH setup check");
+            warnln!("\nWarning: Could not determine login shell, skipping PATH setup check");
 
             Ok(true)
         }
@@ -245,45 +778,674 @@ pub fn verify_path_setup(config: &Config) -> Result<bool> {
 /// Verify that the installed versions of Wine and yabridge will work together properly. This check
 /// is only performed once per combination of Wine and yabridge, and we'll update the config with
 /// the versions we just tested if the check succeeds. Will return `Err` values if either Wine or
-/// `yabridge-host.exe` can't be run.
-pub fn verify_wine_setup(config: &mut Config) -> Result<()> {
-    // These winelib scripts respect `$WINELOADER`, so we'll do the same thing
-    let wine_binary = env::var("WINELOADER").unwrap_or_else(|_| String::from("wine"));
-    let wine_version_output = Command::new(&wine_binary)
-        .arg("--version")
-        .output()
-        .with_context(|| {
-            format!(
-                "Could not run '{}', make sure Wine is installed",
-                wine_binary
-            )
-        })?
-        .stdout;
-    // Strip the trailing newline just to make the config file a bit neater
-    let mut wine_version = String::from_utf8(wine_version_output)?;
-    wine_version.pop().unwrap();
+/// `yabridge-host.exe` can't be run. With `strict`, any problem that would otherwise only cause a
+/// warning to be printed will also return an `Err`. This is used for `yabridgectl sync
+/// --strict-wine`. With `verify_32bit_host`, `yabridge-host-32.exe` is also checked if yabridge has
+/// been built with it. This should only be done when there are 32-bit plugins to set up.
+pub fn verify_wine_setup(config: &mut Config, strict: bool, verify_32bit_host: bool) -> Result<()> {
+    let wine_version = wine_version(Duration::from_secs(config.verify_timeout))?;
 
     let files = config
         .files()
         .context(format!("Could not find '{}'", YABRIDGE_HOST_EXE_NAME))?;
 
-    // Hash the contents of `yabridge-host.exe.so` since `yabridge-host.exe` is only a Wine
-    // generated shell script
-    let yabridge_host_hash = hash_file(&files.yabridge_host_exe_so)?;
+    // This is checked before the cached results below, since the files can lose their executable
+    // bit without their contents changing
+    let is_executable = verify_host_permissions(&files)?;
+    let is_same_build = verify_same_build(&files);
+    if strict && !is_executable {
+        return Err(anyhow!("'{}' is not executable", YABRIDGE_HOST_EXE_NAME));
+    }
+    if strict && !is_same_build {
+        return Err(anyhow!(
+            "yabridge's files come from different versions of yabridge"
+        ));
+    }
 
     // Since these checks can take over a second if wineserver isn't already running we'll only
     // perform them when something has changed
-    let current_config = KnownConfig {
-        wine_version: wine_version.clone(),
-        yabridge_host_hash,
+    let current_config = KnownConfig::current(&files, wine_version.clone(), verify_32bit_host)?;
+    if !config
+        .last_known_config
+        .as_ref()
+        .is_some_and(|known_config| known_config.covers(&current_config))
+    {
+        // Finding the missing libraries runs `ldconfig`, so this is also only checked when
+        // something has changed. `yabridgectl doctor` always performs this check.
+        if !verify_host_libraries(&files) && strict {
+            return Err(anyhow!(
+                "Some of the libraries needed by '{}' are missing",
+                YABRIDGE_HOST_EXE_NAME
+            ));
+        }
+
+        // Wine's own errors for a broken prefix don't mention the prefix at all
+        if let Some(wine_prefix) = env::var_os("WINEPREFIX").filter(|prefix| !prefix.is_empty()) {
+            verify_wine_prefix(Path::new(&wine_prefix));
+        }
+
+        if run_hosts(
+            config,
+            &files,
+            None,
+            &wine_version,
+            strict,
+            verify_32bit_host,
+        )? {
+            config.last_known_config = Some(current_config);
+            config.write()?;
+        }
+    }
+
+    // Plugin directories can use their own Wine binaries, and those are cached separately
+    let wine_loaders: Vec<(PathBuf, Vec<PathBuf>)> = config
+        .wine_loaders()
+        .into_iter()
+        .map(|(loader, directories)| {
+            (
+                loader.to_owned(),
+                directories.into_iter().map(Path::to_owned).collect(),
+            )
+        })
+        .collect();
+    for (loader, directories) in wine_loaders {
+        if !loader.exists() {
+            return Err(anyhow!(
+                "The Wine binary '{}' set for {} does not exist",
+                loader.display(),
+                directories
+                    .iter()
+                    .map(|directory| format!("'{}'", directory.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        let loader_version =
+            wine_loader_version(&loader, Duration::from_secs(config.verify_timeout))?;
+        let current_config =
+            KnownConfig::current(&files, loader_version.clone(), verify_32bit_host)?;
+        if config
+            .known_loader_configs
+            .get(&loader)
+            .is_some_and(|known_config| known_config.covers(&current_config))
+        {
+            continue;
+        }
+
+        if run_hosts(
+            config,
+            &files,
+            Some(&loader),
+            &loader_version,
+            strict,
+            verify_32bit_host,
+        )? {
+            config.known_loader_configs.insert(loader, current_config);
+            config.write()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Try to run `yabridge-host.exe`, and also `yabridge-host-32.exe` when `verify_32bit_host` is set,
+/// using either the default Wine binary or `wine_loader`. Prints a warning for every host that
+/// could not be run, or returns an error with `strict`. Returns whether all hosts could be run, in
+/// which case the results can be cached. Used in [`verify_wine_setup()`].
+fn run_hosts(
+    config: &Config,
+    files: &YabridgeFiles,
+    wine_loader: Option<&Path>,
+    wine_version: &str,
+    strict: bool,
+    verify_32bit_host: bool,
+) -> Result<bool> {
+    // The 32-bit host is only checked when it's actually needed
+    let mut hosts = vec![(YABRIDGE_HOST_EXE_NAME, files.yabridge_host_exe.as_path())];
+    if let (true, Some(host_32_exe)) = (verify_32bit_host, &files.yabridge_host_32_exe) {
+        hosts.push((YABRIDGE_HOST_32_EXE_NAME, host_32_exe.as_path()));
+    }
+
+    // Used to tell whether Wine is outdated or whether it's too new when a host can't be run
+    let build_wine_version = files::read_embedded_wine_version(&files.yabridge_host_exe_so)
+        .and_then(|version| WineVersion::parse(&version));
+
+    let mut all_running = true;
+    for (host_name, host_exe) in hosts {
+        let host_description = match wine_loader {
+            Some(loader) => format!("'{}' using '{}'", host_name, loader.display()),
+            None => format!("'{}'", host_name),
+        };
+        if let HostStatus::Failed { last_error } =
+            run_host_with_retries(host_exe, wine_loader, &config.ignored_wine_errors)?
+        {
+            all_running = false;
+            warnln!(
+                "\n{}",
+                wrap(&format!(
+                    "Warning: Could not run {}. Wine reported the following error: \n\
+                     \n\
+                     {}\n\
+                     \n\
+                     {}\n\
+                     \n\
+                     https://github.com/robbert-vdh/yabridge#troubleshooting-common-issues",
+                    host_description,
+                    last_error
+                        .as_deref()
+                        .unwrap_or("<no_output>")
+                        .bright_white(),
+                    describe_wine_version(wine_version, build_wine_version),
+                ))
+            );
+
+            // The warning above is not shown with `--quiet`, so the error should be self-contained
+            if strict {
+                return Err(anyhow!(
+                    "Could not run {}: {}",
+                    host_description,
+                    last_error.as_deref().unwrap_or("<no_output>")
+                ));
+            }
+        }
+    }
+
+    Ok(all_running)
+}
+
+/// Explain how the installed version of Wine compares to the version of Wine yabridge was compiled
+/// with, for the warning printed by [`run_hosts()`]. If either version is unknown, then we'll assume
+/// that Wine is outdated since that's the most common cause.
+fn describe_wine_version(wine_version: &str, build_wine_version: Option<WineVersion>) -> String {
+    let display_version = wine_version
+        .strip_prefix("wine-")
+        .unwrap_or(wine_version)
+        .bright_white();
+    match (WineVersion::parse(wine_version), build_wine_version) {
+        (Some(installed), Some(build)) if installed < build => format!(
+            "Your current Wine version '{}' is older than Wine {}, which yabridge has been \
+             compiled with. See the troubleshooting section of the readme for more information \
+             on how to upgrade your installation of Wine.",
+            display_version,
+            build.to_string().bright_white()
+        ),
+        (Some(installed), Some(build)) if installed > build => format!(
+            "Your current Wine version '{}' is newer than Wine {}, which yabridge has been \
+             compiled with, so this is not caused by Wine being outdated. Newer versions of Wine \
+             sometimes break compatibility with binaries compiled with older versions. In that \
+             case yabridge needs to be updated or rebuilt using your current version of Wine. See \
+             the troubleshooting section of the readme for more information.",
+            display_version,
+            build.to_string().bright_white()
+        ),
+        (Some(_), Some(build)) => format!(
+            "Your current Wine version '{}' matches Wine {}, which yabridge has been compiled \
+             with, so this is not caused by Wine being outdated. See the troubleshooting section \
+             of the readme for more information.",
+            display_version,
+            build.to_string().bright_white()
+        ),
+        _ => format!(
+            "This can happen when using a version of Wine that is much older than the version \
+             that has been used to compile yabridge with. Your current Wine version is '{}'. See \
+             the troubleshooting section of the readme for more information on how to upgrade \
+             your installation of Wine.",
+            display_version
+        ),
+    }
+}
+
+/// Run a host binary using [`run_host()`], and try again with a short backoff when it failed because
+/// of a wineserver startup error. Other errors, like those caused by an outdated version of Wine, are
+/// returned immediately. This tries at most [`HOST_ATTEMPTS`] times, and the status from the last
+/// attempt is returned.
+fn run_host_with_retries(
+    host_exe: &Path,
+    wine_loader: Option<&Path>,
+    ignored_errors: &[String],
+) -> Result<HostStatus> {
+    let mut attempt = 1;
+    loop {
+        let status = run_host(host_exe, None, wine_loader, ignored_errors)?;
+        match &status {
+            HostStatus::Failed {
+                last_error: Some(error),
+            } if attempt < HOST_ATTEMPTS && is_wineserver_startup_error(error) => {
+                thread::sleep(HOST_RETRY_BACKOFF * attempt);
+                attempt += 1;
+            }
+            _ => return Ok(status),
+        }
+    }
+}
+
+/// Whether an error Wine printed indicates that wineserver could not be started or connected to,
+/// which can happen on a cold start. A wineserver protocol version mismatch also mentions
+/// wineserver, but that's not going to go away by trying again.
+fn is_wineserver_startup_error(error: &str) -> bool {
+    !error.contains("version mismatch")
+        && WINESERVER_STARTUP_ERROR_PATTERNS
+            .iter()
+            .any(|pattern| wildcard_match(pattern, error))
+}
+
+/// Print a warning if `wine_prefix`, which was set through `$WINEPREFIX`, doesn't exist or has not
+/// been initialized yet. An initialized prefix contains a `system.reg` file.
+fn verify_wine_prefix(wine_prefix: &Path) {
+    let problem = if !wine_prefix.is_dir() {
+        "that directory does not exist"
+    } else if !wine_prefix.join("system.reg").is_file() {
+        "that Wine prefix has not been initialized yet since it does not contain a 'system.reg' \
+         file"
+    } else {
+        return;
     };
-    if config.last_known_config.as_ref() == Some(&current_config) {
-        return Ok(());
+
+    warnln!(
+        "\n{}",
+        wrap(&format!(
+            "Warning: The {} environment variable is set to '{}', but {}. Running \
+             'yabridge-host.exe' will likely fail. Run 'wineboot' to create the Wine prefix, or \
+             fix or unset the {} environment variable.",
+            "WINEPREFIX".bright_white(),
+            wine_prefix.display(),
+            problem,
+            "WINEPREFIX".bright_white()
+        ))
+    );
+}
+
+/// Check whether the host binaries have their executable bit set. Copying yabridge's files with the
+/// wrong tools or to the wrong file system can cause these bits to get lost, and Wine will then fail
+/// with errors that don't make it obvious what's wrong. If this is the case, then we'll offer to fix
+/// the permissions. Returns whether the files are executable, or an error if the permissions could
+/// not be changed.
+pub fn verify_host_permissions(files: &YabridgeFiles) -> Result<bool> {
+    let non_executable_files: Vec<&Path> = [
+        files.yabridge_host_exe.as_path(),
+        files.yabridge_host_exe_so.as_path(),
+    ]
+    .iter()
+    .copied()
+    .filter(|path| !path.is_executable())
+    .collect();
+    if non_executable_files.is_empty() {
+        return Ok(true);
     }
 
-    let output = Command::new(&files.yabridge_host_exe)
+    eprintln!(
+        "\n{}",
+        wrap(
+            "Warning: The following files are not executable. Yabridge won't be able to run until \
+             this is fixed:"
+        )
+    );
+    for path in &non_executable_files {
+        eprintln!("- {}", path.display());
+    }
+
+    match promptly::prompt_opt::<String, &str>(
+        "\nWould you like to make these files executable? Entering anything other than YES will \
+         leave these files unchanged",
+    ) {
+        Ok(Some(answer)) if answer == "YES" => {
+            for path in &non_executable_files {
+                let mut permissions = fs::metadata(path)
+                    .with_context(|| format!("Could not read '{}'", path.display()))?
+                    .permissions();
+                // Add the executable bit wherever the file is readable, like `chmod +x` does
+                permissions.set_mode(permissions.mode() | ((permissions.mode() & 0o444) >> 2));
+                fs::set_permissions(path, permissions)
+                    .with_context(|| format!("Could not make '{}' executable", path.display()))?;
+            }
+
+            eprintln!("\nMade {} files executable", non_executable_files.len());
+
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Check whether all of the shared libraries `yabridge-host.exe.so` depends on can be found. When a
+/// library is missing Wine will only print a cryptic error about failing to load the binary, so
+/// we'll print a warning listing the missing libraries instead. This is only a warning since we
+/// can't replicate the dynamic linker's search perfectly. Returns whether all libraries were found.
+pub fn verify_host_libraries(files: &YabridgeFiles) -> bool {
+    let missing_libraries = missing_libraries(&files.yabridge_host_exe_so);
+    if missing_libraries.is_empty() {
+        return true;
+    }
+
+    warnln!(
+        "\n{}",
+        wrap(&format!(
+            "Warning: The following libraries needed by '{}' could not be found. Wine won't be \
+             able to run yabridge until these libraries have been installed:",
+            files.yabridge_host_exe_so.display()
+        ))
+    );
+    for library in &missing_libraries {
+        warnln!("- {}", library);
+    }
+
+    false
+}
+
+/// Check whether all of yabridge's libraries and host binaries come from the same build by comparing
+/// the versions embedded in them. Manually copying yabridge's files around can result in files from
+/// different versions being mixed, which causes subtle failures. Files built by older versions of
+/// yabridge that don't embed their version are not checked. Prints a warning and returns `false` if
+/// the versions don't match.
+pub fn verify_same_build(files: &YabridgeFiles) -> bool {
+    let versions = group_by_version(
+        files
+            .components()
+            .into_iter()
+            .filter_map(|path| files::read_embedded_version(&path).map(|version| (path, version)))
+            .collect(),
+    );
+    if versions.len() <= 1 {
+        return true;
+    }
+
+    warnln!(
+        "\n{}",
+        wrap(&format!(
+            "{}: yabridge's files come from different versions of yabridge. Plugins will likely \
+             fail to load until all of these files come from the same version. Reinstalling \
+             yabridge should fix this:",
+            "WARNING".red()
+        ))
+    );
+    for (version, paths) in &versions {
+        for path in paths {
+            warnln!("- {} ({})", path.display(), version);
+        }
+    }
+
+    false
+}
+
+/// Group files by the version of yabridge embedded in them, see [`verify_same_build()`]. If all
+/// files come from the same build, then this contains at most one entry.
+pub fn group_by_version(versions: Vec<(PathBuf, String)>) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for (path, version) in versions {
+        groups.entry(version).or_default().push(path);
+    }
+
+    groups
+}
+
+/// Find the libraries an ELF binary depends on that cannot be found on this system. This searches
+/// the binary's own search paths, `$LD_LIBRARY_PATH`, the dynamic linker's cache, the default
+/// library directories, and the library directories for the Wine installation that will load the
+/// binary. Returns an empty list if the file could not be parsed.
+pub fn missing_libraries(binary: &Path) -> Vec<String> {
+    let dependencies = match files::read_elf_dependencies(binary) {
+        Some(dependencies) => dependencies,
+        None => return Vec::new(),
+    };
+
+    let origin = binary.parent().unwrap_or_else(|| Path::new("/"));
+    let mut search_paths: Vec<PathBuf> = dependencies
+        .search_paths
+        .iter()
+        .map(|path| {
+            PathBuf::from(
+                path.replace("${ORIGIN}", &origin.to_string_lossy())
+                    .replace("$ORIGIN", &origin.to_string_lossy()),
+            )
+        })
+        .collect();
+    if let Ok(ld_library_path) = env::var("LD_LIBRARY_PATH") {
+        search_paths.extend(
+            ld_library_path
+                .split(':')
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        );
+    }
+    search_paths.extend(
+        if dependencies.is_64_bit {
+            &[
+                "/lib64",
+                "/usr/lib64",
+                "/lib/x86_64-linux-gnu",
+                "/usr/lib/x86_64-linux-gnu",
+                "/lib",
+                "/usr/lib",
+            ][..]
+        } else {
+            &[
+                "/lib32",
+                "/usr/lib32",
+                "/lib/i386-linux-gnu",
+                "/usr/lib/i386-linux-gnu",
+                "/lib",
+                "/usr/lib",
+            ][..]
+        }
+        .iter()
+        .map(PathBuf::from),
+    );
+    // Wine may already have loaded some of these libraries from its own installation directory
+    let wine_binary = env::var("WINELOADER").unwrap_or_else(|_| String::from("wine"));
+    if let Some(wine_prefix) = which::which(wine_binary)
+        .ok()
+        .and_then(|path| path.canonicalize().ok())
+        .and_then(|path| Some(path.parent()?.parent()?.to_owned()))
+    {
+        for directory in &[
+            "lib",
+            "lib64",
+            "lib32",
+            "lib/wine",
+            "lib64/wine",
+            "lib32/wine",
+        ] {
+            search_paths.push(wine_prefix.join(directory));
+        }
+    }
+
+    let cached_libraries = ld_cache_libraries(dependencies.is_64_bit);
+    dependencies
+        .needed
+        .into_iter()
+        .filter(|library| {
+            !cached_libraries.contains(library)
+                && !search_paths
+                    .iter()
+                    .any(|directory| directory.join(library).exists())
+        })
+        .collect()
+}
+
+/// The names of the libraries in the dynamic linker's cache with the matching architecture, as
+/// reported by `ldconfig -p`. Returns an empty set if `ldconfig` could not be run.
+fn ld_cache_libraries(is_64_bit: bool) -> HashSet<String> {
+    // `ldconfig` is often not on the search path for regular users
+    let output = match Command::new("ldconfig")
+        .arg("-p")
         .output()
-        .with_context(|| format!("Could not run '{}'", files.yabridge_host_exe.display()))?;
+        .or_else(|_| Command::new("/sbin/ldconfig").arg("-p").output())
+    {
+        Ok(output) => output,
+        Err(_) => return HashSet::new(),
+    };
+
+    // The lines look like `\tlibc.so.6 (libc6,x86-64) => /lib/x86_64-linux-gnu/libc.so.6`
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (name, rest) = line.trim().split_once(" (")?;
+            let (flags, _) = rest.split_once(')')?;
+            if flags.contains("64") == is_64_bit {
+                Some(name.to_owned())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Run a command and wait for it to exit like [`Command::output()`], but kill the process if it
+/// hasn't exited after `timeout`. Returns `None` in that case. The caller should set up the
+/// command's STDOUT and STDERR. Piped output is only read after the process has exited, so this
+/// should only be used for commands that don't print much.
+pub fn output_with_timeout(command: &mut Command, timeout: Duration) -> Result<Option<Output>> {
+    let mut child = command.spawn()?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        if child.try_wait()?.is_some() {
+            return Ok(Some(child.wait_with_output()?));
+        }
+
+        if Instant::now() >= deadline {
+            // This fails if the process has exited in the meantime, which is fine
+            let _ = child.kill();
+            child.wait()?;
+
+            return Ok(None);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Get the output of `wine --version`, minus the trailing newline. These winelib scripts respect
+/// `$WINELOADER`, so we'll do the same thing. This can stall while wineserver is starting, so Wine
+/// is killed if it hasn't exited after `timeout`.
+pub fn wine_version(timeout: Duration) -> Result<String> {
+    let wine_binary = env::var("WINELOADER").unwrap_or_else(|_| String::from("wine"));
+
+    wine_loader_version(Path::new(&wine_binary), timeout)
+}
+
+/// The same as [`wine_version()`], but for a specific Wine binary. Used for the Wine binaries set
+/// with `yabridgectl set <directory> --wine`.
+pub fn wine_loader_version(wine_binary: &Path, timeout: Duration) -> Result<String> {
+    let output = output_with_timeout(
+        Command::new(wine_binary)
+            .arg("--version")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null()),
+        timeout,
+    )
+    .with_context(|| {
+        format!(
+            "Could not run '{}', make sure Wine is installed",
+            wine_binary.display()
+        )
+    })?
+    .with_context(|| {
+        format!(
+            "'{} --version' did not exit within {} seconds",
+            wine_binary.display(),
+            timeout.as_secs()
+        )
+    })?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "'{} --version' failed with {}",
+            wine_binary.display(),
+            output.status
+        ));
+    }
+
+    // Strip the trailing newline just to make the config file a bit neater
+    let wine_version = String::from_utf8(output.stdout)?.trim_end().to_owned();
+    if wine_version.is_empty() {
+        return Err(anyhow!(
+            "'{} --version' did not print a version",
+            wine_binary.display()
+        ));
+    }
+
+    Ok(wine_version)
+}
+
+/// The major and minor components of a Wine version, used to compare the installed version of Wine
+/// to the version yabridge was compiled with. Other components like the patch version of a stable
+/// release or a staging suffix are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WineVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl WineVersion {
+    /// Parse the output of `wine --version`, like `wine-6.4 (Staging)` or
+    /// `wine-6.4.r0.g7ec998e1 ( TkG Staging Esync Fsync )`, or a plain version like `6.4`. Returns
+    /// `None` if the string does not contain a version number.
+    pub fn parse(version: &str) -> Option<WineVersion> {
+        let start = version.find(|c: char| c.is_ascii_digit())?;
+        let mut components = version[start..].split('.').map(|component| {
+            let length = component
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(component.len());
+            component[..length].parse::<u32>().ok()
+        });
+
+        Some(WineVersion {
+            major: components.next()??,
+            minor: components.next()??,
+        })
+    }
+}
+
+impl std::fmt::Display for WineVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The result of trying to run `yabridge-host.exe`, see [`run_yabridge_host()`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum HostStatus {
+    /// `yabridge-host.exe` ran and printed its usage string.
+    Running,
+    /// `yabridge-host.exe` could not be run. This contains the last non-fixme line Wine printed to
+    /// STDERR, if there was any.
+    Failed { last_error: Option<String> },
+}
+
+/// Run `yabridge-host.exe` without any arguments to check whether it can run. If `wine_prefix` is
+/// set, then the host will be run inside of that Wine prefix instead of the default one. Will return
+/// an `Err` if `yabridge-host.exe` could not be started at all. Lines Wine printed to STDERR that
+/// match one of the regular expressions in `ignored_errors` are not considered to be errors, see
+/// [`ignored_wine_errors()`].
+pub fn run_yabridge_host(
+    files: &YabridgeFiles,
+    wine_prefix: Option<&Path>,
+    ignored_errors: &[String],
+) -> Result<HostStatus> {
+    run_host(&files.yabridge_host_exe, wine_prefix, None, ignored_errors)
+}
+
+/// The implementation of [`run_yabridge_host()`], which can also be used to run
+/// `yabridge-host-32.exe`. The host binaries are Wine generated scripts that respect
+/// `$WINELOADER`, so that's used to run them with `wine_loader` instead of the default Wine binary.
+fn run_host(
+    host_exe: &Path,
+    wine_prefix: Option<&Path>,
+    wine_loader: Option<&Path>,
+    ignored_errors: &[String],
+) -> Result<HostStatus> {
+    let ignored_errors = ignored_wine_errors(ignored_errors)?;
+    let mut command = Command::new(host_exe);
+    if let Some(wine_prefix) = wine_prefix {
+        command.env("WINEPREFIX", wine_prefix);
+    }
+    if let Some(wine_loader) = wine_loader {
+        command.env("WINELOADER", wine_loader);
+    }
+
+    let output = command
+        .output()
+        .with_context(|| format!("Could not run '{}'", host_exe.display()))?;
     let stderr = String::from_utf8(output.stderr)?;
 
     // There are three scenarios here:
@@ -292,50 +1454,422 @@ pub fn verify_wine_setup(config: &mut Config) -> Result<()> {
     //   `002b:err:module:__wine_process_init`
     // - Or the used version of Wine is much newer than what was used to compile yabridge with
     //
-    // I don't know if it's possible to differentiate between the second and the third case, so
-    // we'll always assume it's Wine that's outdated.
-    let mut success = false;
+    // The output doesn't tell the second and the third case apart, so `run_hosts()` compares the
+    // installed Wine version to the version yabridge was compiled with instead.
     let mut last_error: Option<&str> = None;
     for line in stderr.lines() {
         if line.starts_with(YABRIDGE_HOST_EXPECTED_OUTPUT_PREFIX) {
-            success = true;
-            break;
+            return Ok(HostStatus::Running);
         }
 
         // Ignore fixme messages here, since those can be produced by wineserver even after the
-        // application has errored out
-        if line.get(5..10) != Some("fixme") {
+        // application has errored out. Users can also ignore other messages they know are benign.
+        if line.get(5..10) != Some("fixme") && !ignored_errors.is_match(line) {
             last_error = Some(line);
         }
     }
 
-    if success {
-        config.last_known_config = Some(current_config);
-        config.write()?;
+    Ok(HostStatus::Failed {
+        last_error: last_error.map(String::from),
+    })
+}
+
+/// Run `yabridge-host.exe` without any arguments and extract yabridge's version from the usage
+/// string it prints. See [`parse_host_version()`].
+pub fn yabridge_host_version(files: &YabridgeFiles) -> Result<Option<String>> {
+    let output = Command::new(&files.yabridge_host_exe)
+        .output()
+        .with_context(|| format!("Could not run '{}'", files.yabridge_host_exe.display()))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    Ok(parse_host_version(&stderr).map(String::from))
+}
+
+/// Extract yabridge's version from the usage string `yabridge-host.exe` prints to STDERR, which
+/// looks like `Usage: yabridge-host.exe <...> (version 3.1.0)`. Returns `None` if `output` does not
+/// contain the usage string, or if it was printed by an older version of yabridge that did not
+/// include its version yet.
+pub fn parse_host_version(output: &str) -> Option<&str> {
+    let usage = output
+        .lines()
+        .find(|line| line.starts_with(YABRIDGE_HOST_EXPECTED_OUTPUT_PREFIX))?;
+    let (_, version) = usage.trim_end().rsplit_once(" (version ")?;
+
+    version
+        .strip_suffix(')')
+        .filter(|version| !version.is_empty())
+}
+
+/// Parse a file size like `500M` or `2GiB`. Sizes without a suffix are in bytes, and the `K`, `M`,
+/// `G` and `T` suffixes are all powers of 1024.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let number_length = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, suffix) = size.split_at(number_length);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid size", size))?;
+    let multiplier: u64 = match suffix.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("'{}' is not a valid size", size)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("'{}' is too large", size))
+}
+
+/// Format a size in bytes as a human readable string, the inverse of [`parse_size()`].
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} bytes", size);
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Format a Unix timestamp in seconds as the time that has passed since then, like `3 days ago`.
+pub fn format_time_ago(timestamp: u64) -> String {
+    const UNITS: [(&str, u64); 5] = [
+        ("year", 365 * 24 * 60 * 60),
+        ("month", 30 * 24 * 60 * 60),
+        ("day", 24 * 60 * 60),
+        ("hour", 60 * 60),
+        ("minute", 60),
+    ];
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let elapsed = now.saturating_sub(timestamp);
+    match UNITS.iter().find(|(_, seconds)| elapsed >= *seconds) {
+        Some((unit, seconds)) => {
+            let amount = elapsed / seconds;
+            format!(
+                "{} {}{} ago",
+                amount,
+                unit,
+                if amount == 1 { "" } else { "s" }
+            )
+        }
+        None => String::from("just now"),
+    }
+}
+
+/// Compile the regular expressions from `config.ignored_wine_errors`. A line Wine prints to STDERR
+/// is ignored if any part of it matches one of these patterns, so `err:winediag:` ignores every
+/// message from Wine's winediag component. Returns an error if one of the patterns is not a valid
+/// regular expression.
+pub fn ignored_wine_errors<S: AsRef<str>>(
+    patterns: impl IntoIterator<Item = S>,
+) -> Result<RegexSet> {
+    RegexSet::new(patterns).context("Invalid pattern for ignored Wine errors")
+}
+
+/// Check whether `text` matches a simple glob-style wildcard pattern. A `*` in the pattern matches
+/// any number of characters and a `?` matches exactly one character. There are no character classes
+/// or escapes, and every other character only matches itself. The pattern has to match all of
+/// `text`, so to match a substring the pattern should start and end with a `*`.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // The positions in `pattern` and `text` right after the last `*` we encountered, so we can
+    // backtrack and let that `*` match one more character when we run into a mismatch
+    let (mut pattern_idx, mut text_idx) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while text_idx < text.len() {
+        match pattern.get(pattern_idx) {
+            Some('*') => {
+                pattern_idx += 1;
+                last_star = Some((pattern_idx, text_idx));
+            }
+            Some(&c) if c == '?' || c == text[text_idx] => {
+                pattern_idx += 1;
+                text_idx += 1;
+            }
+            _ => match last_star {
+                Some((star_pattern_idx, star_text_idx)) => {
+                    pattern_idx = star_pattern_idx;
+                    text_idx = star_text_idx + 1;
+                    last_star = Some((star_pattern_idx, star_text_idx + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[pattern_idx..].iter().all(|&c| c == '*')
+}
+
+/// Find the Wine prefix a plugin in `path` will be run in. Yabridge does this by searching for a
+/// `drive_c` directory in the plugin's parent directories, and it will fall back to `$WINEPREFIX`
+/// or `~/.wine` if the plugin is not inside of a Wine prefix. We'll do the same thing here.
+pub fn wine_prefix_for(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|directory| directory.file_name().and_then(|name| name.to_str()) == Some("drive_c"))
+        .and_then(|drive_c| drive_c.parent())
+        .map(|prefix| prefix.to_owned())
+        .or_else(|| env::var_os("WINEPREFIX").map(PathBuf::from))
+        .unwrap_or_else(|| Path::new(&env::var("HOME").expect("$HOME is not set")).join(".wine"))
+}
+
+/// Convert a Windows path like `C:\\Program Files\\Plugin.dll` to the corresponding path inside of
+/// a Wine prefix. Returns `None` if `windows_path` doesn't start with a drive letter.
+pub fn windows_path_in_prefix(wine_prefix: &Path, windows_path: &str) -> Option<PathBuf> {
+    let mut chars = windows_path.chars();
+    let drive_letter = chars.next().filter(|c| c.is_ascii_alphabetic())?;
+    if chars.next() != Some(':') {
+        return None;
+    }
+
+    // The C: drive is always stored in `drive_c`, while the other drives are symlinks in
+    // `dosdevices`
+    let drive_letter = drive_letter.to_ascii_lowercase();
+    let mut path = if drive_letter == 'c' {
+        wine_prefix.join("drive_c")
     } else {
-        eprintln!(
-            "\n{}",
-            wrap(&format!(
-                "Warning: Could not run 'yabridge-host.exe'. Wine reported the following error: \n\
-                 \n\
-                 {}\n\
-                 \n\
-                 This can happen when using a version of Wine that is much older than the version \
-                 that has been used to compile yabridge with. Your current Wine version is '{}'. \
-                 See the troubleshooting section of the readme for more information on how to \
-                 upgrade your installation of Wine.\n\
-                 \n\
-                 https://github.com/robbert-vdh/yabridge#troubleshooting-common-issues",
-                last_error.unwrap_or("<no_output>").bright_white(),
-                wine_version
-                    .strip_prefix("wine-")
-                    .unwrap_or(&wine_version)
-                    .bright_white(),
-            ))
-        )
+        wine_prefix
+            .join("dosdevices")
+            .join(format!("{}:", drive_letter))
+    };
+    path.extend(
+        chars
+            .as_str()
+            .split('\\')
+            .filter(|component| !component.is_empty()),
+    );
+
+    Some(path)
+}
+
+/// The file systems Windows installations are usually stored on. `ntfs-3g` mounts show up as
+/// `fuseblk`.
+const WINDOWS_FILE_SYSTEMS: &[&str] = &["ntfs", "ntfs3", "fuseblk", "vfat", "exfat", "msdos"];
+
+/// Information about the file system a path is stored on, read from `/proc/self/mounts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    /// The directory the file system is mounted on.
+    pub mount_point: PathBuf,
+    pub file_system: String,
+    pub read_only: bool,
+}
+
+impl MountInfo {
+    /// Whether this is a file system that's normally used by Windows, such as a Windows partition
+    /// mounted while dual booting.
+    pub fn is_windows_file_system(&self) -> bool {
+        WINDOWS_FILE_SYSTEMS.contains(&self.file_system.as_str())
     }
+}
 
-    Ok(())
+/// Find the file system `path` is stored on. Returns `None` if `/proc/self/mounts` could not be
+/// read or if `path` does not exist.
+pub fn mount_info(path: &Path) -> Option<MountInfo> {
+    let path = path.canonicalize().ok()?;
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+
+    // The last matching entry wins, since later mounts hide earlier mounts on the same directory
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = PathBuf::from(unescape_mount_field(fields.next()?));
+            let file_system = fields.next()?.to_owned();
+            let read_only = fields.next()?.split(',').any(|option| option == "ro");
+
+            Some(MountInfo {
+                mount_point,
+                file_system,
+                read_only,
+            })
+        })
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .fold(None, |best: Option<MountInfo>, mount| match best {
+            Some(best)
+                if best.mount_point.components().count()
+                    > mount.mount_point.components().count() =>
+            {
+                Some(best)
+            }
+            _ => Some(mount),
+        })
+}
+
+/// Undo the octal escapes used for spaces, tabs, newlines, and backslashes in `/proc/self/mounts`.
+fn unescape_mount_field(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let digits: String = chars.clone().take(3).collect();
+            if let Ok(byte) = u8::from_str_radix(&digits, 8) {
+                result.push(byte as char);
+                chars.nth(2);
+                continue;
+            }
+        }
+
+        result.push(c);
+    }
+
+    result
+}
+
+/// Whether `directory` is stored on a case insensitive file system, such as a FAT32 or exFAT
+/// partition. Since `directory` may not exist yet, this probes the closest existing ancestor with a
+/// name containing letters on the same file system by looking up that name with its case swapped.
+/// This doesn't write anything, so it also works on read-only file systems. Returns false if none of
+/// the ancestors can be probed.
+pub fn is_case_insensitive(directory: &Path) -> bool {
+    let device = match directory
+        .ancestors()
+        .find_map(|ancestor| fs::metadata(ancestor).ok())
+    {
+        Some(metadata) => metadata.dev(),
+        None => return false,
+    };
+
+    for ancestor in directory.ancestors() {
+        let metadata = match fs::metadata(ancestor) {
+            Ok(metadata) if metadata.dev() == device => metadata,
+            // We've crossed a mount point, so the remaining ancestors are on another file system
+            Ok(_) => return false,
+            Err(_) => continue,
+        };
+        // The name of a mount point is stored on the parent file system
+        match ancestor
+            .parent()
+            .and_then(|parent| fs::metadata(parent).ok())
+        {
+            Some(parent_metadata) if parent_metadata.dev() == device => (),
+            _ => return false,
+        }
+        let name = match ancestor.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+
+        let swapped_name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_lowercase() {
+                    c.to_uppercase().next().unwrap_or(c)
+                } else {
+                    c.to_lowercase().next().unwrap_or(c)
+                }
+            })
+            .collect();
+        if swapped_name == name {
+            continue;
+        }
+
+        return match fs::metadata(ancestor.with_file_name(swapped_name)) {
+            Ok(swapped_metadata) => {
+                swapped_metadata.dev() == metadata.dev() && swapped_metadata.ino() == metadata.ino()
+            }
+            Err(_) => false,
+        };
+    }
+
+    false
+}
+
+/// Verify that the plugin directories we just set up VST2 plugins in will be picked up by a VST2
+/// host. Unlike VST3 plugins, VST2 plugins are set up right next to the original `.dll` files, so
+/// unless a directory is located inside of one of the default VST2 search paths or one of the paths
+/// from `$VST_PATH` or `$LXVST_PATH`, the user will have to manually add the directory to their
+/// DAW's plugin search paths. There's no way to check the latter, so this will print a warning
+/// reminding the user to do so. Directories inside of a Wine prefix are not checked. Those are the
+/// usual place for Windows VST2 plugins, and yabridge's documentation already tells the user to add
+/// them to their DAW's search paths, so warning about them would only be noise.
+pub fn verify_vst2_search_paths(directories: &[&Path]) {
+    let home = env::var("HOME").unwrap_or_default();
+    let search_paths: Vec<PathBuf> = VST2_DEFAULT_SEARCH_PATHS
+        .iter()
+        .map(|path| match path.strip_prefix("~/") {
+            Some(suffix) => Path::new(&home).join(suffix),
+            None => PathBuf::from(path),
+        })
+        .chain(VST2_SEARCH_PATH_ENV_VARS.iter().flat_map(|var| {
+            env::var(var)
+                .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+                .unwrap_or_default()
+        }))
+        // The plugin directories have been canonicalized when they were added, so we should do
+        // the same thing here in case any of the search paths are symlinks
+        .map(|path| path.canonicalize().unwrap_or(path))
+        .collect();
+
+    let unreachable_directories: Vec<&Path> = directories
+        .iter()
+        .filter(|directory| {
+            !is_in_wine_prefix(directory)
+                && !search_paths
+                    .iter()
+                    .any(|search_path| directory.starts_with(search_path))
+        })
+        .cloned()
+        .collect();
+    if unreachable_directories.is_empty() {
+        return;
+    }
+
+    warnln!(
+        "\n{}",
+        wrap(&format!(
+            "Warning: The following directories contain VST2 plugins but they are not located in \
+             any of the default VST2 search paths, or in any of the paths from {} or {}. Make sure \
+             to add these directories to your DAW's VST2 plugin search paths, or else your DAW \
+             won't be able to find these plugins:",
+            "VST_PATH".bright_white(),
+            "LXVST_PATH".bright_white(),
+        ))
+    );
+    for directory in unreachable_directories {
+        warnln!("- {}", directory.display());
+    }
+}
+
+/// Whether `path` is located inside of a Wine prefix, using the same `drive_c` check as
+/// [`wine_prefix_for()`].
+fn is_in_wine_prefix(path: &Path) -> bool {
+    path.ancestors()
+        .any(|directory| directory.file_name().and_then(|name| name.to_str()) == Some("drive_c"))
+}
+
+/// Shorten `text` to at most `max_width` characters by replacing the middle part with an ellipsis.
+/// This is used for paths, since the start and the file name at the end are the most useful parts.
+pub fn truncate_middle(text: &str, max_width: usize) -> String {
+    let width = text.chars().count();
+    if width <= max_width || max_width == 0 {
+        return text.to_owned();
+    }
+
+    // One character is used for the ellipsis, and the end of the text gets the extra character
+    let head_width = (max_width - 1) / 2;
+    let tail_width = max_width - 1 - head_width;
+    let head: String = text.chars().take(head_width).collect();
+    let tail: String = text.chars().skip(width - tail_width).collect();
+
+    format!("{}…{}", head, tail)
 }
 
 /// Wrap a long paragraph of text to terminal width, or 80 characters if the width of the terminal
@@ -348,3 +1882,365 @@ pub fn wrap(text: &str) -> String {
             .subsequent_indent("    "),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn wildcard_match_literals() {
+        assert!(wildcard_match("", ""));
+        assert!(wildcard_match("err:ole", "err:ole"));
+        assert!(!wildcard_match("err:ole", "err:ole:foo"));
+        assert!(!wildcard_match("err:ole:foo", "err:ole"));
+        assert!(!wildcard_match("", "err"));
+    }
+
+    #[test]
+    fn wildcard_match_wildcards() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("err:*", "err:ole:CoGetClassObject"));
+        assert!(wildcard_match("*:ole:*", "err:ole:CoGetClassObject"));
+        assert!(!wildcard_match("*:ole:*", "err:d3d:wined3d"));
+        assert!(wildcard_match("a*b*c", "aXXbYYbZZc"));
+        assert!(!wildcard_match("a*b*c", "aXXbYYbZZ"));
+        assert!(wildcard_match("err:???:*", "err:ole:foo"));
+        assert!(!wildcard_match("err:???:*", "err:d3d9:foo"));
+        assert!(!wildcard_match("?", ""));
+    }
+
+    #[test]
+    fn wildcard_match_is_not_a_regex() {
+        assert!(!wildcard_match("err:.*", "err:ole"));
+        assert!(wildcard_match("err:.*", "err:.ole"));
+        assert!(!wildcard_match("[ef]rr", "err"));
+        assert!(wildcard_match("[ef]rr", "[ef]rr"));
+    }
+
+    #[test]
+    fn copy_recursively_keeps_symlinks() {
+        let directory = tempdir().unwrap();
+        let bundle = directory.path().join("Plugin.vst3");
+        fs::create_dir_all(bundle.join("Contents/x86_64-linux")).unwrap();
+        fs::write(bundle.join("Contents/x86_64-linux/Plugin.so"), b"library").unwrap();
+        unix_fs::symlink(
+            "/nonexistent/Plugin.vst3",
+            bundle.join("Contents/Plugin.vst3"),
+        )
+        .unwrap();
+
+        let copied_bundle = directory.path().join("Copied.vst3");
+        copy_recursively(&bundle, &copied_bundle).unwrap();
+
+        assert_eq!(
+            fs::read(copied_bundle.join("Contents/x86_64-linux/Plugin.so")).unwrap(),
+            b"library"
+        );
+        assert_eq!(
+            fs::read_link(copied_bundle.join("Contents/Plugin.vst3")).unwrap(),
+            Path::new("/nonexistent/Plugin.vst3")
+        );
+    }
+
+    #[test]
+    fn move_path_moves_directories() {
+        let directory = tempdir().unwrap();
+        let from = directory.path().join("from");
+        let to = directory.path().join("to");
+        fs::create_dir(&from).unwrap();
+        fs::write(from.join("file"), b"contents").unwrap();
+
+        move_path(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(fs::read(to.join("file")).unwrap(), b"contents");
+    }
+
+    #[test]
+    fn parse_host_version_from_usage() {
+        assert_eq!(
+            parse_host_version(
+                "Usage: yabridge-host.exe <plugin_type> <plugin_location> (version 3.1.0)\n"
+            ),
+            Some("3.1.0")
+        );
+        // Wine prints its own messages before the usage string, and the line may end in `\r\n`
+        assert_eq!(
+            parse_host_version(
+                "0009:err:module:import_dll Library foo.dll not found\n\
+                 Usage: yabridge-host-32.exe <plugin_type> <plugin_location> (version 3.2.0-dev)\r\n"
+            ),
+            Some("3.2.0-dev")
+        );
+    }
+
+    #[test]
+    fn parse_host_version_without_version() {
+        // Older versions of yabridge did not include their version in the usage string
+        assert_eq!(
+            parse_host_version("Usage: yabridge-host.exe <plugin_type> <plugin_location>\n"),
+            None
+        );
+        assert_eq!(
+            parse_host_version(
+                "Usage: yabridge-host.exe <plugin_type> <plugin_location> (version )"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_host_version("wine: could not load kernel32.dll\n"),
+            None
+        );
+        assert_eq!(parse_host_version(""), None);
+        // The version has to come from the usage string itself
+        assert_eq!(
+            parse_host_version("Something else (version 3.1.0)\nUsage: yabridge-host.exe"),
+            None
+        );
+    }
+
+    /// Write a fake version of yabridge's files to `directory`, where the libraries and host
+    /// binaries embed the given versions. `None` means that the file was built by an older version
+    /// of yabridge that did not embed its version yet.
+    fn fixture_files(
+        directory: &Path,
+        library_version: Option<&str>,
+        host_version: Option<&str>,
+    ) -> YabridgeFiles {
+        let write = |name: &str, version: Option<&str>| {
+            let path = directory.join(name);
+            let mut contents = b"\x7fELF padding ".to_vec();
+            if let Some(version) = version {
+                contents.extend_from_slice(format!("YABRIDGE_VERSION={}\0", version).as_bytes());
+            }
+            contents.extend_from_slice(b"more padding");
+            fs::write(&path, contents).unwrap();
+
+            path
+        };
+
+        YabridgeFiles {
+            libyabridge_vst2: write("libyabridge-vst2.so", library_version),
+            libyabridge_vst3: Some(write("libyabridge-vst3.so", library_version)),
+            libyabridge_chainloader_vst2: None,
+            libyabridge_chainloader_vst3: None,
+            libyabridge_clap: None,
+            libyabridge_chainloader_clap: None,
+            yabridge_host_exe: directory.join("yabridge-host.exe"),
+            yabridge_host_exe_so: write("yabridge-host.exe.so", host_version),
+            yabridge_host_32_exe: None,
+            yabridge_host_32_exe_so: None,
+        }
+    }
+
+    #[test]
+    fn group_files_by_version() {
+        assert!(group_by_version(Vec::new()).is_empty());
+        assert_eq!(
+            group_by_version(vec![
+                (PathBuf::from("/a.so"), String::from("3.1.0")),
+                (PathBuf::from("/b.so"), String::from("3.2.0")),
+                (PathBuf::from("/c.so"), String::from("3.1.0")),
+            ]),
+            BTreeMap::from([
+                (
+                    String::from("3.1.0"),
+                    vec![PathBuf::from("/a.so"), PathBuf::from("/c.so")]
+                ),
+                (String::from("3.2.0"), vec![PathBuf::from("/b.so")]),
+            ])
+        );
+    }
+
+    #[test]
+    fn same_build() {
+        let directory = tempdir().unwrap();
+        let files = fixture_files(directory.path(), Some("3.1.0"), Some("3.1.0"));
+        assert!(verify_same_build(&files));
+    }
+
+    #[test]
+    fn mixed_builds() {
+        let directory = tempdir().unwrap();
+        let files = fixture_files(directory.path(), Some("3.1.0"), Some("3.0.2"));
+        assert!(!verify_same_build(&files));
+    }
+
+    #[test]
+    fn unversioned_files_are_not_checked() {
+        // Files from older versions of yabridge don't embed their version, so they can't be compared
+        let directory = tempdir().unwrap();
+        let files = fixture_files(directory.path(), Some("3.1.0"), None);
+        assert!(verify_same_build(&files));
+        let files = fixture_files(directory.path(), None, None);
+        assert!(verify_same_build(&files));
+    }
+
+    #[test]
+    fn relative_path_between_directories() {
+        let relative = |path: &str, base: &str| relative_path(Path::new(path), Path::new(base));
+
+        assert_eq!(
+            relative(
+                "/home/user/.vst3/yabridge/Plugin.vst3",
+                "/home/user/.vst3/yabridge"
+            ),
+            PathBuf::from("Plugin.vst3")
+        );
+        assert_eq!(
+            relative(
+                "/home/user/.wine/drive_c/Plugin.dll",
+                "/home/user/.vst/yabridge"
+            ),
+            PathBuf::from("../../.wine/drive_c/Plugin.dll")
+        );
+        assert_eq!(
+            relative("/usr/lib/libyabridge-vst2.so", "/home/user"),
+            PathBuf::from("../../usr/lib/libyabridge-vst2.so")
+        );
+        // The paths are normalized first
+        assert_eq!(
+            relative("/home/user/./plugins/../Plugin.dll", "/home/user/.vst/"),
+            PathBuf::from("../Plugin.dll")
+        );
+    }
+
+    #[test]
+    fn relative_path_symlinks_resolve() {
+        let directory = tempdir().unwrap();
+        let target = directory.path().join("prefix/drive_c/Plugin.dll");
+        let link_directory = directory.path().join("links/vst2");
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::create_dir_all(&link_directory).unwrap();
+        fs::write(&target, "plugin").unwrap();
+
+        let link = link_directory.join("Plugin.dll");
+        std::os::unix::fs::symlink(relative_path(&target, &link_directory), &link).unwrap();
+        assert_eq!(fs::read_to_string(&link).unwrap(), "plugin");
+        assert_eq!(read_symlink(&link).unwrap(), target);
+    }
+
+    #[test]
+    fn wine_version_parse() {
+        let version = |major, minor| Some(WineVersion { major, minor });
+
+        assert_eq!(WineVersion::parse("6.4"), version(6, 4));
+        assert_eq!(WineVersion::parse("wine-6.4"), version(6, 4));
+        assert_eq!(WineVersion::parse("wine-6.4 (Staging)"), version(6, 4));
+        assert_eq!(WineVersion::parse("wine-5.0.3"), version(5, 0));
+        assert_eq!(
+            WineVersion::parse("wine-6.4.r0.g7ec998e1 ( TkG Staging Esync Fsync )"),
+            version(6, 4)
+        );
+        assert_eq!(WineVersion::parse("wine-6.10-rc1"), version(6, 10));
+        assert_eq!(WineVersion::parse("wine-7.0-rc2 (Staging)"), version(7, 0));
+    }
+
+    #[test]
+    fn wine_version_parse_invalid() {
+        assert_eq!(WineVersion::parse(""), None);
+        assert_eq!(WineVersion::parse("wine"), None);
+        assert_eq!(WineVersion::parse("wine-6"), None);
+        assert_eq!(WineVersion::parse("wine-6.x"), None);
+        assert_eq!(WineVersion::parse("wine-99999999999.1"), None);
+    }
+
+    #[test]
+    fn wine_version_ordering() {
+        // The minor version should be compared numerically
+        assert!(WineVersion::parse("6.10") > WineVersion::parse("6.9"));
+        assert!(WineVersion::parse("7.0") > WineVersion::parse("6.22"));
+        assert_eq!(WineVersion::parse("6.4").unwrap().to_string(), "6.4");
+    }
+
+    #[test]
+    fn expand_path_tilde() {
+        let home = PathBuf::from(env::var_os("HOME").expect("$HOME is not set"));
+        assert_eq!(expand_path(Path::new("~")).unwrap(), home);
+        assert_eq!(
+            expand_path(Path::new("~/sub/dir")).unwrap(),
+            home.join("sub/dir")
+        );
+        // A tilde is only expanded at the start of the path
+        assert_eq!(
+            expand_path(Path::new("/plugins/~/sub")).unwrap(),
+            Path::new("/plugins/~/sub")
+        );
+    }
+
+    #[test]
+    fn expand_path_variables() {
+        // These variables are only used by this test, since the environment is shared between tests
+        env::set_var("YABRIDGECTL_TEST_EXPAND_DIR", "/home/user/plugins");
+        assert_eq!(
+            expand_path(Path::new("$YABRIDGECTL_TEST_EXPAND_DIR/vst")).unwrap(),
+            Path::new("/home/user/plugins/vst")
+        );
+        assert_eq!(
+            expand_path(Path::new("/mnt/${YABRIDGECTL_TEST_EXPAND_DIR}-old/vst")).unwrap(),
+            Path::new("/mnt//home/user/plugins-old/vst")
+        );
+        // A `$` that's not followed by a variable name is not a reference
+        assert_eq!(
+            expand_path(Path::new("/plugins/$5/$")).unwrap(),
+            Path::new("/plugins/$5/$")
+        );
+    }
+
+    #[test]
+    fn expand_path_unset_variables() {
+        env::remove_var("YABRIDGECTL_TEST_UNSET");
+        assert_eq!(
+            expand_path(Path::new("/mnt/c/$YABRIDGECTL_TEST_UNSET/plugins")).unwrap(),
+            Path::new("/mnt/c/$YABRIDGECTL_TEST_UNSET/plugins")
+        );
+        assert_eq!(
+            expand_path(Path::new("/mnt/c/${YABRIDGECTL_TEST_UNSET}.Bin")).unwrap(),
+            Path::new("/mnt/c/${YABRIDGECTL_TEST_UNSET}.Bin")
+        );
+        assert_eq!(
+            expand_path(Path::new("/mnt/c/${YABRIDGECTL_TEST_UNSET")).unwrap(),
+            Path::new("/mnt/c/${YABRIDGECTL_TEST_UNSET")
+        );
+    }
+
+    #[test]
+    fn wine_loader_version_checks_output() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempdir().unwrap();
+        let fake_wine = |name: &str, script: &str| {
+            let path = directory.path().join(name);
+            fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let timeout = Duration::from_secs(10);
+
+        let wine = fake_wine("wine", "echo wine-6.4");
+        assert_eq!(wine_loader_version(&wine, timeout).unwrap(), "wine-6.4");
+        let failing = fake_wine("failing", "echo wine-6.4; exit 1");
+        assert!(wine_loader_version(&failing, timeout).is_err());
+        let silent = fake_wine("silent", "true");
+        assert!(wine_loader_version(&silent, timeout).is_err());
+        assert!(wine_loader_version(&directory.path().join("missing"), timeout).is_err());
+    }
+
+    #[test]
+    fn ignored_wine_errors_match_substrings() {
+        let ignored_errors = ignored_wine_errors(["err:winediag:", r"^\d+:err:ole:"]).unwrap();
+        assert!(ignored_errors.is_match("0024:err:winediag:nodrv_CreateWindow Application tried"));
+        assert!(ignored_errors.is_match("0024:err:ole:CoGetClassObject class not registered"));
+        assert!(!ignored_errors.is_match("002b:err:module:__wine_process_init failed"));
+        assert!(!ignored_errors.is_match("wine: err:ole: is not at the start of the line"));
+
+        // Nothing gets ignored by default
+        assert!(!ignored_wine_errors(&[] as &[String])
+            .unwrap()
+            .is_match("0024:err:winediag:"));
+        // Glob-style patterns are not valid regular expressions
+        assert!(ignored_wine_errors(["*err:winediag:*"]).is_err());
+    }
+}